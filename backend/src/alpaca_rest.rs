@@ -2,21 +2,26 @@
 //!
 //! Restful Alpaca Poller
 
-use chrono::Utc;
+use std::time::{Duration, Instant};
 use crossbeam_channel::Sender;
 use common_lib::alpaca_activity::Activity;
 use common_lib::alpaca_position::Position;
-use common_lib::market_hours::{MARKET_CLOSE_EXT, MARKET_OPEN_EXT};
+use common_lib::config::AppConfig;
+use common_lib::market_calendar::MarketCalendar;
+use common_lib::market_hours::MarketHours;
 use common_lib::settings::Settings;
+use tokio::sync::watch;
 use tokio::runtime::Handle;
 use common_lib::account::Account;
 use common_lib::alpaca_transaction_status::AlpacaTransaction;
 use common_lib::db::DbMsg;
+use common_lib::supervisor::Shutdown;
+use common_lib::order_manager::OrderManager;
 
-// see .env first
-const REST_POLL_RATE_OPEN_MILLIS_STR: &str = "3000";
-const REST_POLL_RATE_OPEN_MILLIS: u64 = 3000;
+// the open rate is API_INTERVAL_MILLIS; see common_lib/src/config.rs
 const REST_POLL_RATE_CLOSED_MILLIS: u64 = 10000;
+// reload the Alpaca calendar and clock hourly; holidays and early closes are published well ahead
+const CALENDAR_REFRESH_SECS: u64 = 3600;
 
 // quickly disable pieces of the Alpaca API
 const ENABLE_REST_ACTIVITY: bool = true;
@@ -24,6 +29,8 @@ const ENABLE_REST_POSITION: bool = true;
 // don't need this
 // const ENABLE_REST_ORDER: bool = false;
 const ENABLE_REST_ACCOUNT: bool = true;
+// reprice or cancel limit orders that have sat unfilled too long (see t_order_policy)
+const ENABLE_REST_STALE_ORDERS: bool = true;
 
 
 
@@ -35,8 +42,8 @@ pub  struct AlpacaRest{}
 
 impl AlpacaRest {
 
-    /// Spawn a new thread to poll the Alpaca REST API
-    pub fn run(tx_db_rest: Sender<DbMsg>, _tokio_handle: Handle) {
+    /// Poll the Alpaca REST API until shutdown
+    pub fn run(tx_db_rest: Sender<DbMsg>, mut settings_rx: watch::Receiver<Settings>, _tokio_handle: Handle, shutdown: Shutdown) {
 
         tracing::debug!("[rest_client::run] starting alpaca rest client");
 
@@ -50,65 +57,65 @@ impl AlpacaRest {
 
         let mut alpaca_poll_rate_ms: u64;
 
-        // this is set in all.sh via docker run
-        let time_open_ny = MARKET_OPEN_EXT.clone();
-        let time_close_ny = MARKET_CLOSE_EXT.clone();
-        // Call the API if the market is open in NYC
+        let mut calendar_refreshed: Option<Instant> = None;
 
         loop {
 
-            // let pool3 = pool.clone();
-
-            let time_current_ny = Utc::now().with_timezone(&chrono_tz::America::New_York).time();
+            // poll quickly while the extended session is open per the Alpaca calendar
             alpaca_poll_rate_ms = {
                 // if market is open, set the poll rate to the desired open rate
-
-                // TODO: use new is_open() function
-                if time_current_ny >= time_open_ny && time_current_ny <= time_close_ny {
-                    tracing::info!("[rest_service:loop] NY time: {:?}, open: {:?}, close: {:?}",&time_current_ny,&time_open_ny,&time_close_ny);
-                    std::env::var("API_INTERVAL_MILLIS").unwrap_or_else(|_| REST_POLL_RATE_OPEN_MILLIS_STR.to_string()).parse().unwrap_or(REST_POLL_RATE_OPEN_MILLIS)
+                if MarketHours::is_session_open(true, tx_db_rest.clone()) {
+                    tracing::info!("[rest_service:loop] extended session open");
+                    AppConfig::get().api_interval_millis
 
                 } else {
                     // back off to a slower poll rate.
-                    tracing::debug!("[run] market is closed. NY time: {:?}, open: {:?}, close: {:?}", &time_current_ny, &time_open_ny, &time_close_ny);
+                    tracing::debug!("[run] market is closed");
                     // 30 seconds
                     REST_POLL_RATE_CLOSED_MILLIS
                 }
             };
 
-            // refresh settings from the database
-            let tx_db_1 = tx_db_rest.clone();
+            // the latest settings from the cache
+            let settings = settings_rx.borrow_and_update().clone();
             let tx_db_2 = tx_db_rest.clone();
-            match Settings::load_with_secret(tx_db_1) {
-                Ok(settings) => {
-
-                    tracing::debug!("[run] got settings, running rest API calls");
-
-                    if ENABLE_REST_ACTIVITY {
-                        AlpacaRest::load_activities(&settings, tx_db_2.clone());
-                    }
-
-                    if ENABLE_REST_POSITION {
-                        AlpacaRest::load_positions(&settings, tx_db_2.clone());
-                    }
-
-                    // if ENABLE_REST_ORDER {
-                    //     AlpacaRest::load_orders(&pool3, &settings).await;
-                    // }
-                    //
-                    if ENABLE_REST_ACCOUNT{
-                        Account::load_account(&settings, tx_db_2.clone());
-                    }
-                },
-                Err(e) => {
-                    tracing::error!("[run] couldn't load settings in loop to update activities/positions: {:?}", &e);
+            tracing::debug!("[run] running rest API calls");
+
+            if calendar_refreshed.is_none_or(|t| t.elapsed() >= Duration::from_secs(CALENDAR_REFRESH_SECS)) {
+                match MarketCalendar::refresh_remote(&settings, tx_db_2.clone()) {
+                    Ok(calendar) => {
+                        tracing::info!("[run] market calendar refreshed: {} trading days", calendar.days.len());
+                        calendar_refreshed = Some(Instant::now());
+                    },
+                    Err(e) => tracing::error!("[run] market calendar not refreshed: {:?}", &e),
                 }
             }
 
+            if ENABLE_REST_ACTIVITY {
+                AlpacaRest::load_activities(&settings, tx_db_2.clone());
+            }
+
+            if ENABLE_REST_POSITION {
+                AlpacaRest::load_positions(&settings, tx_db_2.clone());
+            }
+
+            // if ENABLE_REST_ORDER {
+            //     AlpacaRest::load_orders(&pool3, &settings).await;
+            // }
+            //
+            if ENABLE_REST_ACCOUNT{
+                Account::load_account(&settings, tx_db_2.clone());
+            }
+
+            if ENABLE_REST_STALE_ORDERS {
+                OrderManager::enforce_stale_limit_orders(&settings, tx_db_2.clone());
+            }
+
             tracing::debug!("[run] done");
 
-            std::thread::sleep(std::time::Duration::from_millis(alpaca_poll_rate_ms));
-            // tokio::time::sleep(Duration::from_secs(3)).await;
+            if !shutdown.wait_for(std::time::Duration::from_millis(alpaca_poll_rate_ms)) {
+                return;
+            }
 
         }
 
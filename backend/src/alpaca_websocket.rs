@@ -15,16 +15,21 @@
 */
 // use crossbeam_channel::{after, select, tick};
 use common_lib::alpaca_api_structs::{Ping, WebsocketMessage, RequestAuthenticate, RequestListen, RequestListenData, AuthStatus, DataMessage, WebsocketMessageFormat, DataMesgSuccess, AuthAction, MesgOrderUpdate};
+use common_lib::config::AppConfig;
 use common_lib::settings::Settings;
+use tokio::sync::watch;
 use crossbeam::channel::Sender;
 use serde_json::{json};
 use std::time::Duration;
-use chrono::Utc;
+use common_lib::clock;
 use tungstenite::client::IntoClientRequest;
 use tungstenite::Message;
 use common_lib::alpaca_api_structs::RequestAction;
 use common_lib::alpaca_order_log::AlpacaOrderLogEvent;
 use common_lib::db::DbMsg;
+use common_lib::supervisor::Shutdown;
+use crate::websocket_read::{set_read_timeout, timed_out};
+use common_lib::strategy::{MarketEvent, QuoteEvent};
 
 
 pub struct AlpacaWebsocket;
@@ -36,9 +41,12 @@ impl AlpacaWebsocket {
     //     AlpacaWebsocket::ws_connect(tx_db, stream_type, symbols, &settings);
     // }
 
-    pub  fn run(tx_db: Sender<DbMsg>, stream_type: &WebsocketMessageFormat, symbols: Vec<String>, settings: Settings) {
+    /// tx_events, when the strategy runner is on, gets a copy of every trade, bar and fill
+    /// settings_rx is the SettingsCache watch; new credentials close the socket so it reconnects with them
+    /// returns once shutdown is requested
+    pub  fn run(tx_db: Sender<DbMsg>, stream_type: &WebsocketMessageFormat, symbols: Vec<String>, mut settings_rx: watch::Receiver<Settings>, tx_events: Option<Sender<MarketEvent>>, shutdown: Shutdown) {
 
-        let settings = &settings;
+        let mut settings = settings_rx.borrow_and_update().clone();
 
         // ***** a test for times when the websocket feed is down
         // TODO: add a crossbeam_channel timer to simulate an inbound stream
@@ -48,8 +56,8 @@ impl AlpacaWebsocket {
         // let _ = tx_db.send(DbMsg::WsTrade(trade.to_owned()));
 
         let ws_url = match stream_type {
-            WebsocketMessageFormat::TextData => std::env::var("ALPACA_WS_URL_TEXT").expect("ALPACA_WS_URL_TEXT not found"),
-            WebsocketMessageFormat::BinaryUpdates => std::env::var("ALPACA_WS_URL_BIN").expect("ALPACA_WS_URL_BIN not found"),
+            WebsocketMessageFormat::TextData => AppConfig::get().alpaca_ws_url_text.clone(),
+            WebsocketMessageFormat::BinaryUpdates => AppConfig::get().alpaca_ws_url_bin.clone(),
 
         };
 
@@ -71,22 +79,46 @@ impl AlpacaWebsocket {
 
                     // send authentication message
                     ws.write_message(Message::Text(auth_json)).unwrap();
+                    set_read_timeout(&mut ws);
 
                     loop {
 
+                        if shutdown.requested() {
+                            let _ = ws.close(None);
+                            break;
+                        }
+
+                        if let Ok(true) = settings_rx.has_changed() {
+                            let latest = settings_rx.borrow_and_update().clone();
+                            let reauthenticate = !latest.same_credentials(&settings);
+                            settings = latest;
+                            if reauthenticate {
+                                tracing::info!("[ws_connect][{:?}] credentials changed, reconnecting", &stream_type);
+                                let _ = ws.close(None);
+                                break;
+                            }
+                        }
+
                         // non-async tungstenite
-                        if let Ok(msg) = ws.read_message() {
+                        let read = ws.read_message();
+                        if let Err(e) = &read {
+                            if !timed_out(e) {
+                                tracing::debug!("[ws_connect][{:?}] read error, reconnecting: {:?}", &stream_type, e);
+                                break;
+                            }
+                        }
+                        if let Ok(msg) = read {
                             // tracing::debug!("[ws_connect] read websocket...");
 
                             match msg {
                                 Message::Ping(t) => {
                                     tracing::info!("[Alpaca][{:?}][Ping] {:?}", &stream_type, &t);
-                                    let _ = tx_db.send(DbMsg::PingAlpaca(Ping { dtg: chrono::Utc::now() }));
+                                    let _ = tx_db.send(DbMsg::PingAlpaca(Ping { dtg: clock::now() }));
                                 }
 
                                 Message::Pong(t) => {
                                     tracing::info!("[Alpaca][{:?}][Pong] {:?}", &stream_type, &t);
-                                    let _ = tx_db.send(DbMsg::PingAlpaca(Ping { dtg: chrono::Utc::now() }));
+                                    let _ = tx_db.send(DbMsg::PingAlpaca(Ping { dtg: clock::now() }));
                                 }
 
                                 Message::Binary(b_msg) => {
@@ -128,26 +160,32 @@ impl AlpacaWebsocket {
                                         },
 
                                         // decrement the alpaca_transaction_status entry's posn_shares when a sell/fill is received
-                                        Ok(WebsocketMessage::TradeUpdates(MesgOrderUpdate::Fill{timestamp: _t1, price: _p1, qty: _q1, order: o1}))=>{
+                                        Ok(WebsocketMessage::TradeUpdates(MesgOrderUpdate::Fill{timestamp: _t1, price: p1, qty: q1, order: o1}))=>{
                                             tracing::debug!("[ws_connect][binary][TradeUpdates][Fill] order: {:?}", &o1);
-                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: Utc::now(), event: "fill".to_string(), order: o1 };
+                                            if let Some(tx_events) = &tx_events {
+                                                let _ = tx_events.send(MarketEvent::Fill { order: Box::new(o1.clone()), price: p1, qty: q1 });
+                                            }
+                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: clock::now(), event: "fill".to_string(), order: o1 };
                                             let _ = tx_db.send(DbMsg::OrderLogEvent(order_log_evt));
 
                                         },
-                                        Ok(WebsocketMessage::TradeUpdates(MesgOrderUpdate::PartialFill{timestamp: _t1, price: _p1, qty: _q1, order: o1}))=>{
+                                        Ok(WebsocketMessage::TradeUpdates(MesgOrderUpdate::PartialFill{timestamp: _t1, price: p1, qty: q1, order: o1}))=>{
                                             tracing::debug!("[ws_connect][binary][TradeUpdates][PartialFill] order: {:?}", &o1);
-                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: Utc::now(), event: "partial_fill".to_string(), order: o1 };
+                                            if let Some(tx_events) = &tx_events {
+                                                let _ = tx_events.send(MarketEvent::Fill { order: Box::new(o1.clone()), price: p1, qty: q1 });
+                                            }
+                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: clock::now(), event: "partial_fill".to_string(), order: o1 };
                                             let _ = tx_db.send(DbMsg::OrderLogEvent(order_log_evt));
 
                                         },
                                         Ok(WebsocketMessage::TradeUpdates(MesgOrderUpdate::New{order: o1}))=>{
                                             tracing::debug!("[ws_connect][binary][TradeUpdates][New] order: {:?}", &o1);
-                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: Utc::now(), event: "new".to_string(), order: o1 };
+                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: clock::now(), event: "new".to_string(), order: o1 };
                                             let _ = tx_db.send(DbMsg::OrderLogEvent(order_log_evt));
                                         },
                                         Ok(WebsocketMessage::TradeUpdates(MesgOrderUpdate::Accepted{order: o1}))=>{
                                             tracing::debug!("[ws_connect][binary][TradeUpdates][Accepted] order: {:?}", &o1);
-                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: Utc::now(), event: "accepted".to_string(), order: o1 };
+                                            let order_log_evt = AlpacaOrderLogEvent{ dtg: clock::now(), event: "accepted".to_string(), order: o1 };
                                             let _ = tx_db.send(DbMsg::OrderLogEvent(order_log_evt));
                                         },
                                         Ok(WebsocketMessage::TradeUpdates(
@@ -195,12 +233,15 @@ impl AlpacaWebsocket {
                                                                 // subscribe to stock feeds
                                                                 // https://alpaca.markets/docs/api-references/market-data-api/stock-pricing-data/realtime/#subscribe
 
-                                                                let json = json!({
+                                                                let mut json = json!({
                                                                     "action": RequestAction::Subscribe,
                                                                     "trades":  stock_list_to_uppercase(&symbols),
-                                                                    // "quotes": STOCK_LIST_CAPS,
                                                                     "bars": stock_list_to_uppercase(&symbols),
                                                                 });
+                                                                // quotes only go to the strategies; don't ask for them when nobody's listening
+                                                                if tx_events.is_some() {
+                                                                    json["quotes"] = json!(stock_list_to_uppercase(&symbols));
+                                                                }
                                                                 tracing::debug!("[ws_connect] sending subscription request...\n{}", &json);
                                                                 let result = ws.write_message(Message::Text(json.to_string()));
                                                                 tracing::debug!("[ws_connect] subscription request sent: {:?}", &result);
@@ -212,10 +253,24 @@ impl AlpacaWebsocket {
                                                     DataMessage::Trade(trade)=>{
                                                         // trade
                                                         tracing::debug!("[ws_connect][text] trade: {:?}",&trade);
+                                                        if let Some(tx_events) = &tx_events {
+                                                            let _ = tx_events.send(MarketEvent::Trade(trade.clone()));
+                                                        }
                                                         let _ = tx_db.send(DbMsg::TradeAlpaca(trade.to_owned()));
                                                     },
-                                                    DataMessage::Bar=>{},
-                                                    DataMessage::Quote=>{},
+                                                    DataMessage::Bar(bar)=>{
+                                                        tracing::debug!("[ws_connect][text] bar: {:?}",&bar);
+                                                        let _ = tx_db.send(DbMsg::MinuteBar(bar.clone()));
+                                                        if let Some(tx_events) = &tx_events {
+                                                            let _ = tx_events.send(MarketEvent::Bar(bar));
+                                                        }
+                                                    },
+                                                    DataMessage::Quote(quote)=>{
+                                                        // only the strategies want quotes; too many to store
+                                                        if let Some(tx_events) = &tx_events {
+                                                            let _ = tx_events.send(MarketEvent::Quote(QuoteEvent::from(&quote)));
+                                                        }
+                                                    },
                                                     DataMessage::DailyBar=>{},
                                                     DataMessage::Status=>{},
 
@@ -361,7 +416,9 @@ impl AlpacaWebsocket {
                 }
             };
             // 5 second delay if the websocket goes down, then retry
-            std::thread::sleep(Duration::from_millis(5000));
+            if !shutdown.wait_for(Duration::from_millis(5000)) {
+                return;
+            }
         }
     }
 }
@@ -390,8 +447,8 @@ fn generate_ws_authentication_message(settings: &Settings) -> String {
     // {"action": "authenticate","data": {"key_id": "???", "secret_key": "???"}}
 
     // TODO: add database setting "use_paper_or_live_key"
-    let api_key = settings.alpaca_paper_id.clone(); // std::env::var("ALPACA_API_ID").expect("ALPACA_API_ID");
-    let api_secret = settings.alpaca_paper_secret.clone(); //std::env::var("ALPACA_API_SECRET").expect("ALPACA_API_SECRET");
+    let api_key = settings.alpaca_paper_id.expose().to_string(); // std::env::var("ALPACA_API_ID").expect("ALPACA_API_ID");
+    let api_secret = settings.alpaca_paper_secret.expose().to_string(); //std::env::var("ALPACA_API_SECRET").expect("ALPACA_API_SECRET");
 
     let json_obj = RequestAuthenticate {
         action: RequestAction::Auth, //  "auth".to_owned(),
@@ -15,70 +15,89 @@
 
 use crate::alpaca_websocket::{AlpacaWebsocket};
 use crate::finnhub_websocket::FinnhubWebsocket;
-use common_lib::settings::Settings;
+use common_lib::config::AppConfig;
+use common_lib::settings_cache::SettingsCache;
 use common_lib::symbol_list::SymbolList;
-use std::str::FromStr;
+use std::collections::HashMap;
 use std::time::Duration;
 use tokio::runtime::Handle;
 use common_lib::alpaca_api_structs::WebsocketMessageFormat;
 use common_lib::db::{DbActor, DbMsg};
 use crate::alpaca_rest::AlpacaRest;
 use crate::stock_rating;
-
-/// Spawn threads to collect Alpaca and Finnhub websocket feeds into a Postgresql database
-
-
-// associated functions v methods
-// https://doc.rust-lang.org/book/ch05-03-method-syntax.html
-
+use crate::exit_scheduler;
+use crate::profile_scheduler;
+use common_lib::sim_broker::{FillModel, SimRouter, SimulatedBroker};
+use common_lib::strategy::{AlpacaRouter, EmaCross, EmaCrossShort, SellHigh, Strategy, StrategyRunner};
+use common_lib::spool::Spool;
+use common_lib::supervisor::{Shutdown, Supervisor};
+use std::time::Instant;
+
+// how often the supervisor logs each component's state
+const HEALTH_LOG_SECS: u64 = 60;
+// how long the components get to stop before the db actor drains anyway
+const COMPONENT_GRACE_SECS: u64 = 10;
+
+/// Spawn threads to collect Alpaca and Finnhub websocket feeds into a Postgresql database; the Supervisor
+/// restarts any that crash. Returns after SIGTERM/SIGINT once the components have stopped and the db actor
+/// has drained.
 pub async fn run(tokio_handle: Handle) {
 
-    // let mut handles = vec![];
-
-    /****** database actor thread ******/
-    // let tx_db = db_actor.run().await;
-
     tracing::debug!("[run]");
 
+    let shutdown = Shutdown::new();
+    let mut supervisor = Supervisor::new(shutdown.clone());
 
+    // stopped separately, after the components, so their last writes land
+    let db_shutdown = Shutdown::new();
+    let db_shutdown_2 = db_shutdown.clone();
+
+    /****** database actor thread ******/
+    // waits for postgres; until then a signal just ends the process
     let db_actor = DbActor::new().await;
+    shutdown.on_signal(&tokio_handle);
     let tx_db = db_actor.tx.clone();
     let rt = tokio_handle.clone();
+    let runner_handle = tokio_handle.clone();
 
-    std::thread::spawn(move || {
+    let db_thread = std::thread::spawn(move || {
         tracing::debug!("[backend] db thread");
-        db_actor.run(rt);
+        db_actor.run_until(rt, db_shutdown_2);
         tracing::debug!("[backend] db thread done");
     });
     tracing::debug!("[backend] db thread spawned");
 
-
-    // std::thread::sleep(Duration::from_secs(3));
-
-
+    // writes that failed while postgres was unreachable
+    let tx_db_spool = tx_db.clone();
+    supervisor.spawn("spool_replay", move |shutdown| {
+        Spool::replay(tx_db_spool.clone(), shutdown);
+    });
 
     // start the various operational threads
-    // get the settings on startup first
+    // get the settings on startup first; the cache keeps them current for every thread after that
     let tx_db_1 = tx_db.clone();
-    let settings_result = Settings::load_with_secret(tx_db_1);
+    let settings_result = SettingsCache::start(tx_db_1, tokio_handle.clone());
     match settings_result{
 
-        Ok(settings)=>{
+        Ok(settings_rx)=>{
 
+            let settings = settings_rx.borrow().clone();
             tracing::debug!("[run] loaded settings: {:?}", &settings);
+            let config = AppConfig::get();
 
             /****** alpaca rest polling ******/
             // Rest HTTP Service (in/out)
-            let alpaca_rest_on = bool::from_str(std::env::var("ALPACA_REST_ON").unwrap_or_else(|_| "false".to_owned()).as_str()).unwrap_or(false);
+            let alpaca_rest_on = config.alpaca_rest_on;
             tracing::info!("ALPACA_REST_ON is: {}", alpaca_rest_on);
             let tx_db_rest = tx_db.clone();
+            let settings_rx_rest = settings_rx.clone();
 
             if alpaca_rest_on {
                 tracing::debug!("[run] alpaca_rest_on: {}", alpaca_rest_on);
-                std::thread::spawn(||{
+                let rest_handle = tokio_handle.clone();
+                supervisor.spawn("alpaca_rest", move |shutdown| {
                     tracing::debug!("[run] inside spawned rest thread");
-                    AlpacaRest::run(tx_db_rest, tokio_handle);
-
+                    AlpacaRest::run(tx_db_rest.clone(), settings_rx_rest.clone(), rest_handle.clone(), shutdown);
                 });
             } else {
                 tracing::debug!("[run] alpaca_rest_on: {}", alpaca_rest_on);
@@ -86,9 +105,44 @@ pub async fn run(tokio_handle: Handle) {
 
 
 
+            /****** strategy runner ******/
+            // default off; the websocket threads copy trades, bars and fills to it. In shadow mode the
+            // strategies trade a simulated account on the live feed instead of the paper account. A restart
+            // starts the strategies (and the shadow account) over.
+            let strategy_runner_on = config.strategy_runner_on;
+            let strategy_shadow = config.strategy_shadow;
+            tracing::info!("STRATEGY_RUNNER_ON is: {}, STRATEGY_SHADOW is: {}", strategy_runner_on, strategy_shadow);
+            let tx_events = if strategy_runner_on {
+                let (tx_events, rx_events) = crossbeam_channel::unbounded();
+                let tx_db_runner = tx_db.clone();
+                let settings_rx_runner = settings_rx.clone();
+                let runner_handle_2 = runner_handle.clone();
+                if strategy_shadow {
+                    supervisor.spawn("strategy_runner", move |shutdown| {
+                        let settings = settings_rx_runner.borrow().clone();
+                        let strategies: Vec<Box<dyn Strategy>> = vec![Box::new(EmaCross::default()), Box::new(SellHigh::default())];
+                        let broker = SimulatedBroker::new(settings.account_start_value.clone(), FillModel::default());
+                        let runner = StrategyRunner::new(strategies, &settings, SimRouter::new(broker, HashMap::new(), "shadow").with_fill_log());
+                        runner.run(rx_events.clone(), settings_rx_runner.clone(), tx_db_runner.clone(), runner_handle_2.clone(), shutdown);
+                    });
+                } else {
+                    supervisor.spawn("strategy_runner", move |shutdown| {
+                        let settings = settings_rx_runner.borrow().clone();
+                        // shorts only go out when t_order_policy.short_enabled; SellHigh covers them
+                        let strategies: Vec<Box<dyn Strategy>> = vec![Box::new(SellHigh::default()), Box::new(EmaCrossShort::default())];
+                        let runner = StrategyRunner::new(strategies, &settings, AlpacaRouter::new(tx_db_runner.clone(), runner_handle.clone()));
+                        runner.run(rx_events.clone(), settings_rx_runner.clone(), tx_db_runner.clone(), runner_handle_2.clone(), shutdown);
+                    });
+                }
+                Some(tx_events)
+            } else {
+                None
+            };
+
+
             /****** alpaca websocket ******/
             tracing::debug!("[run] db start() complete");
-            let alpaca_ws_on = bool::from_str(std::env::var("ALPACA_WEBSOCKET_ON").unwrap_or_else(|_| "true".to_owned()).as_str()).unwrap_or(false);
+            let alpaca_ws_on = config.alpaca_websocket_on;
             tracing::info!("ALPACA_WEBSOCKET_ON is: {}", &alpaca_ws_on);
             if alpaca_ws_on {
 
@@ -100,26 +154,23 @@ pub async fn run(tokio_handle: Handle) {
                     Ok(symbols) => {
 
                         let symbols2 = symbols.clone();
-                        let settings2 = settings.clone();
-                        let settings3 = settings.clone();
+                        let settings2 = settings_rx.clone();
+                        let settings3 = settings_rx.clone();
                         let tx_db_3 = tx_db.clone();
+                        let tx_events_3 = tx_events.clone();
 
                         // stock data websocket thread
-                        let _join_handle = std::thread::spawn(|| {
+                        supervisor.spawn("alpaca_websocket_data", move |shutdown| {
                             tracing::debug!("[run] starting text data websocket");
-                            AlpacaWebsocket::run(tx_db_3, &WebsocketMessageFormat::TextData, symbols, settings3);
-                            // AlpacaWebsocket::run(tx_db_ws.clone(), &AlpacaData::BinaryUpdates, symbols.clone(), settings2.clone());
+                            AlpacaWebsocket::run(tx_db_3.clone(), &WebsocketMessageFormat::TextData, symbols.clone(), settings3.clone(), tx_events_3.clone(), shutdown);
                         });
-                        // handles.push(join_handle);
 
                         // account and order update websocket thread
                         let tx_db_4 = tx_db.clone();
-                        let _join_handle = std::thread::spawn(|| {
+                        supervisor.spawn("alpaca_websocket_updates", move |shutdown| {
                             tracing::debug!("[run] starting binary data for 'trade_updates'");
-                            // AlpacaWebsocket::run(tx_db_ws, &AlpacaData::TextData, symbols, settings2);
-                            AlpacaWebsocket::run(tx_db_4, &WebsocketMessageFormat::BinaryUpdates, symbols2, settings2);
+                            AlpacaWebsocket::run(tx_db_4.clone(), &WebsocketMessageFormat::BinaryUpdates, symbols2.clone(), settings2.clone(), tx_events.clone(), shutdown);
                         });
-                        // handles.push(join_handle);
                     },
                     Err(e) => tracing::debug!("[start] error getting symbols for websocket: {:?}", &e),
                 }
@@ -130,7 +181,7 @@ pub async fn run(tokio_handle: Handle) {
 
 
             /****** finnhub websocket ******/
-            let finnhub_on = bool::from_str(std::env::var("FINNHUB_ON").unwrap_or_else(|_| "true".to_owned()).as_str()).unwrap_or(true);
+            let finnhub_on = config.finnhub_on;
             tracing::info!("FINNHUB_ON is: {}", &finnhub_on);
 
             if finnhub_on {
@@ -140,16 +191,15 @@ pub async fn run(tokio_handle: Handle) {
                 match SymbolList::get_active_symbols(tx_db_symbols).await{
                     Ok(symbols)=>{
                         let tx_db_ws = tx_db.clone();
-                        let settings2 = settings.clone();
-                        let _join_handle = std::thread::spawn(|| {
+                        let settings2 = settings_rx.clone();
+                        supervisor.spawn("finnhub_websocket", move |shutdown| {
                             tracing::debug!("[finnhub] inside spawned thread...");
-                            FinnhubWebsocket::run(tx_db_ws, symbols, settings2);
+                            FinnhubWebsocket::run(tx_db_ws.clone(), symbols.clone(), settings2.clone(), shutdown);
                         });
                     },
                     Err(e)=>tracing::debug!("[finnhub] could not load symbols, finnhub not started: {:?}", &e),
                 }
 
-                // handles.push(join_handle);
                 tracing::debug!("[run] finnhub_on: {}", finnhub_on);
             } else {
                 tracing::debug!("[run] finnhub_on: {}", finnhub_on);
@@ -157,36 +207,61 @@ pub async fn run(tokio_handle: Handle) {
 
             // start the stock rating system
             // default on
-            let stock_rating_on = bool::from_str(std::env::var("STOCK_RATING_ON").unwrap_or_else(|_| "true".to_owned()).as_str()).unwrap_or(true);
+            let stock_rating_on = config.stock_rating_on;
             tracing::info!("STOCK_RATING_ON is: {}", stock_rating_on);
             let tx_db2 = tx_db.clone();
             if stock_rating_on {
-                let _join_handle = std::thread::spawn(|| {
-                    stock_rating::run(tx_db2);
+                supervisor.spawn("stock_rating", move |shutdown| {
+                    stock_rating::run(tx_db2.clone(), shutdown);
                 });
-                // handles.push(join_handle);
                 tracing::info!("[run] stock_rating_on: {}", stock_rating_on);
             } else {
                 tracing::error!("[run] stock_rating_on: {}", stock_rating_on);
             }
 
+            // age exits and end-of-day flatten; what actually runs is set in t_order_policy
+            let exit_scheduler_on = config.exit_scheduler_on;
+            tracing::info!("EXIT_SCHEDULER_ON is: {}", exit_scheduler_on);
+            let tx_db3 = tx_db.clone();
+            let settings_rx_exit = settings_rx.clone();
+            if exit_scheduler_on {
+                supervisor.spawn("exit_scheduler", move |shutdown| {
+                    exit_scheduler::run(tx_db3.clone(), settings_rx_exit.clone(), shutdown);
+                });
+            }
+
+            // scheduled trade settings profile switches; idle until t_trade_settings_schedule has rows
+            let profile_scheduler_on = config.profile_scheduler_on;
+            tracing::info!("PROFILE_SCHEDULER_ON is: {}", profile_scheduler_on);
+            let tx_db4 = tx_db.clone();
+            if profile_scheduler_on {
+                supervisor.spawn("profile_scheduler", move |shutdown| {
+                    profile_scheduler::run(tx_db4.clone(), shutdown);
+                });
+            }
+
         },
         Err(e)=> tracing::debug!("[run] error getting settings: {:?}", &e),
 
     }
 
 
-    // for h in handles{
-    //     let _ = h.join();
-    // }
-
-
-    loop {
-        std::thread::sleep(Duration::from_secs(3));
+    let mut health_logged = Instant::now();
+    while shutdown.wait_for(Duration::from_secs(3)) {
 
         tracing::debug!("[main] ping send result: {:?}", tx_db.send(DbMsg::PingDb));
 
-        println!("[backend]");
+        if health_logged.elapsed() >= Duration::from_secs(HEALTH_LOG_SECS) {
+            supervisor.log_health();
+            health_logged = Instant::now();
+        }
     }
+
+    // feeds and schedulers first, then whatever they queued for the database
+    tracing::info!("[run] shutting down");
+    supervisor.join(Duration::from_secs(COMPONENT_GRACE_SECS));
+    db_shutdown.request();
+    let _ = db_thread.join();
+    tracing::info!("[run] stopped");
 }
 
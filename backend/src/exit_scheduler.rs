@@ -0,0 +1,32 @@
+//!
+//! exit_scheduler.rs
+//!
+//! Timer for the time-based exits in common_lib::position_exit: positions past their maximum age, and
+//! flattening everything before the close. What runs is controlled by t_order_policy; both are off by default.
+//!
+
+use std::time::Duration;
+use crossbeam_channel::{Sender, tick};
+use common_lib::db::DbMsg;
+use common_lib::supervisor::Shutdown;
+use common_lib::position_exit::PositionExit;
+use common_lib::settings::Settings;
+use tokio::sync::watch;
+
+// flatten steps its limit prices once per pass
+const EXIT_SCHEDULER_SECS:u64=30;
+
+pub fn run(tx: Sender<DbMsg>, mut settings_rx: watch::Receiver<Settings>, shutdown: Shutdown){
+
+    let ticker = tick(Duration::from_secs(EXIT_SCHEDULER_SECS));
+
+    loop {
+
+        let settings = settings_rx.borrow_and_update().clone();
+        PositionExit::run_once(&settings, tx.clone());
+
+        if !shutdown.tick(&ticker) {
+            return;
+        }
+    }
+}
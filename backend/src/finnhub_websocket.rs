@@ -9,13 +9,18 @@
 // use crossbeam_channel::{after, select, tick};
 
 use common_lib::finnhub::{FinnhubPacket, FinnhubPing, FinnhubSubscribe};
+use common_lib::config::AppConfig;
 use common_lib::settings::Settings;
+use tokio::sync::watch;
 use crossbeam::channel::Sender;
 use serde_json::json;
 use std::time::Duration;
 use tungstenite::client::IntoClientRequest;
 use tungstenite::Message;
+use common_lib::clock;
 use common_lib::db::DbMsg;
+use common_lib::supervisor::Shutdown;
+use crate::websocket_read::{set_read_timeout, timed_out};
 
 fn stock_list_to_uppercase(lower_stock: &Vec<String>) -> Vec<String> {
     lower_stock.iter().map(|x| x.to_uppercase()).collect()
@@ -25,21 +30,24 @@ pub struct FinnhubWebsocket;
 
 impl FinnhubWebsocket {
 
-    pub fn run(tx_db: Sender<DbMsg>, symbols: Vec<String>, settings: Settings) {
+    /// settings_rx is the SettingsCache watch; a new key closes the socket so it reconnects with it
+    /// returns once shutdown is requested
+    pub fn run(tx_db: Sender<DbMsg>, symbols: Vec<String>, settings_rx: watch::Receiver<Settings>, shutdown: Shutdown) {
         tracing::debug!("[WsFinnhub::run]");
-        FinnhubWebsocket::connect(tx_db, symbols, &settings);
+        FinnhubWebsocket::connect(tx_db, symbols, settings_rx, shutdown);
     }
 
-    fn connect(tx_db: Sender<DbMsg>, symbols: Vec<String>, settings: &Settings) {
+    fn connect(tx_db: Sender<DbMsg>, symbols: Vec<String>, mut settings_rx: watch::Receiver<Settings>, shutdown: Shutdown) {
 
         // wss://ws.finnhub.io?token=xxxxxxxx
         // .env includes everything except the api key value (xxxxxx); called token here
 
-        let ws_url = std::env::var("FINNHUB_URL").expect("FINNHUB_URL not found");
-        let ws_url = format!("{}{}", ws_url, settings.finnhub_key);
+        let ws_url_base = &AppConfig::get().finnhub_url;
 
         // websocket restart loop
         loop {
+            let finnhub_key = settings_rx.borrow_and_update().finnhub_key.clone();
+            let ws_url = format!("{}{}", ws_url_base, finnhub_key.expose());
             let url = url::Url::parse(&ws_url).unwrap();
             let request = (&url).into_client_request().unwrap();
 
@@ -65,8 +73,21 @@ impl FinnhubWebsocket {
                         tracing::debug!("[WsFinnhub] subscribe: {}", &subscribe.to_string());
                         let _ = ws.write_message(Message::Text(subscribe.to_string()));
                     }
+                    set_read_timeout(&mut ws);
 
                     loop {
+                        if shutdown.requested() {
+                            let _ = ws.close(None);
+                            break;
+                        }
+                        if let Ok(true) = settings_rx.has_changed() {
+                            if settings_rx.borrow().finnhub_key != finnhub_key {
+                                tracing::info!("[WsFinnhub::connect] key changed, reconnecting");
+                                let _ = ws.close(None);
+                                break;
+                            }
+                        }
+
                         // tracing::debug!("[ws_connect] reading websocket...");
                         match ws.read_message() {
                             Ok(msg) => {
@@ -93,7 +114,7 @@ impl FinnhubWebsocket {
 
                                             Ok(FinnhubPacket::Ping) => {
                                                 tracing::info!("[Finnhub] ping");
-                                                let _ = tx_db.send(DbMsg::PingFinnhub(FinnhubPing { dtg: chrono::Utc::now() }));
+                                                let _ = tx_db.send(DbMsg::PingFinnhub(FinnhubPing { dtg: clock::now() }));
                                             }
 
                                             Err(e) => tracing::debug!("[deserialize] FinnhubPacket json error {:?}",&e),
@@ -104,14 +125,20 @@ impl FinnhubWebsocket {
                                     }
                                 }
                             }
-                            Err(e) => tracing::debug!("[ws_finnhub::connect] error reading message: {:?}",&e),
+                            Err(e) if timed_out(&e) => {},
+                            Err(e) => {
+                                tracing::debug!("[ws_finnhub::connect] error reading message, reconnecting: {:?}",&e);
+                                break;
+                            },
                         }
                     }
                 }
             };
 
             // 5 second delay if the websocket goes down, then retry
-            std::thread::sleep(Duration::from_millis(5000));
+            if !shutdown.wait_for(Duration::from_millis(5000)) {
+                return;
+            }
         }
     }
 }
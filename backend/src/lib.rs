@@ -0,0 +1,15 @@
+//! backend/src/lib.rs
+//!
+//! The trading backend: market data websockets, REST polling, strategies and schedulers. `trader backend`
+//! starts it (trader/src/main.rs).
+//!
+#![forbid(unsafe_code)]
+
+pub mod backend;
+pub mod alpaca_rest;
+pub mod alpaca_websocket;
+pub mod finnhub_websocket;
+mod stock_rating;
+mod exit_scheduler;
+mod profile_scheduler;
+mod websocket_read;
@@ -0,0 +1,28 @@
+//!
+//! profile_scheduler.rs
+//!
+//! Timer for the scheduled trade settings profile switches in common_lib::profile_schedule. Nothing happens
+//! until t_trade_settings_schedule has rows.
+//!
+
+use std::time::Duration;
+use crossbeam_channel::{Sender, tick};
+use common_lib::db::DbMsg;
+use common_lib::supervisor::Shutdown;
+use common_lib::profile_schedule::ProfileSchedule;
+
+// switches land within this long of their session time
+const PROFILE_SCHEDULER_SECS:u64=30;
+
+pub fn run(tx: Sender<DbMsg>, shutdown: Shutdown){
+
+    let ticker = tick(Duration::from_secs(PROFILE_SCHEDULER_SECS));
+
+    loop {
+        ProfileSchedule::run_once(tx.clone());
+
+        if !shutdown.tick(&ticker) {
+            return;
+        }
+    }
+}
@@ -13,25 +13,28 @@
 use std::time::Duration;
 use crossbeam_channel::{Sender, tick};
 use common_lib::db::DbMsg;
+use common_lib::supervisor::Shutdown;
 use common_lib::market_hours::MarketHours;
 
 const RATING_REFRESH_SECS:u64=30;
 
-pub fn run(tx: Sender<DbMsg>){
+pub fn run(tx: Sender<DbMsg>, shutdown: Shutdown){
 
     // tracing::debug!("[stock_rating::run]");
     let ticker = tick(Duration::from_secs(RATING_REFRESH_SECS));
 
     loop {
 
-        if MarketHours::is_open(){
+        if MarketHours::is_open(tx.clone()){
             tracing::debug!("[run] sending DbMsg::RefreshRating");
             let _send_result = tx.send(DbMsg::RefreshRating);
         }else{
             tracing::error!("[run] not sending DbMsg::RefreshRating, market closed");
         }
 
-        ticker.recv().unwrap();
+        if !shutdown.tick(&ticker) {
+            return;
+        }
     }
 }
 
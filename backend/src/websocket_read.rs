@@ -0,0 +1,30 @@
+//! websocket_read.rs
+//!
+//! tungstenite blocks in read_message until something arrives, which overnight can be a long time. A read
+//! timeout lets the websocket loops look at settings and shutdown between messages.
+//!
+
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::WebSocket;
+
+const READ_TIMEOUT_MILLIS: u64 = 1000;
+
+pub fn set_read_timeout(ws: &mut WebSocket<MaybeTlsStream<TcpStream>>) {
+    let timeout = Some(Duration::from_millis(READ_TIMEOUT_MILLIS));
+    let result = match ws.get_mut() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(timeout),
+        MaybeTlsStream::Rustls(stream) => stream.sock.set_read_timeout(timeout),
+        _ => Ok(()),
+    };
+    if let Err(e) = result {
+        tracing::error!("[set_read_timeout] {:?}", &e);
+    }
+}
+
+/// the read timeout ran out with nothing to read; any other error means the socket is gone
+pub fn timed_out(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut)
+}
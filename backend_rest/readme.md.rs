@@ -1,3 +0,0 @@
-## Under Construction
-
-Placeholder for if/when I remove the rest API thread from "backend" and make it a microservice.
\ No newline at end of file
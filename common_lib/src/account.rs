@@ -229,7 +229,6 @@ pub struct AccountWithDate {
 #[cfg(test)]
 mod tests{
     use crate::account::Account;
-    use crate::common::account::Account;
 
     #[test]
     /// confirm parsing from json to struct
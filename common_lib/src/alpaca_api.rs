@@ -4,7 +4,7 @@
 //!
 //!
 
-use bigdecimal::{BigDecimal, FromPrimitive};
+use bigdecimal::BigDecimal;
 use crossbeam_channel::Sender;
 
 use crate::account::Account;
@@ -14,16 +14,17 @@ use crate::db::DbMsg;
 use crate::error::TradeWebError;
 use crate::market_hours::{BUY_EXTENDED_HOURS, SELL_EXTENDED_HOURS};
 use crate::order_log_entry::OrderLogEntry;
+use crate::order_manager::OrderManager;
+use crate::risk::RiskManager;
 use crate::settings::Settings;
 use crate::symbol::Symbol;
 use crate::trade_struct::{JsonTrade, OrderType, TimeInForce, TradeSide};
-
-const QTY_SIZE_SAFETY_LIMIT:usize=1001;
-
+use crate::alpaca_asset::Asset;
+use crate::order_sizing::{OrderSize, SizingMode};
 
 
 /// Submit a sell order without doing any checking if there's already any sell orders in place, or even a position to sell.
-/// Assumes short sells setting is turned off since we don't want short sells for now.
+/// Short sales go through sell_short instead, behind t_order_policy.short_enabled.
 /// market, normal hours
 /// TODO: need to get the position to get the correct number of shares to sell
 ///
@@ -38,11 +39,27 @@ pub async fn sell(symbol: &str, qty_to_sell: BigDecimal, limit_price:Option<BigD
                   settings: &Settings, tx_db:Sender<DbMsg>) -> Option<String> {
 
     tracing::info!("[alpaca_api::sell] ************** SELL ************** {}, {} shares for {:?}", symbol, qty_to_sell, limit_price);
+    post_simple_order(symbol, TradeSide::Sell, qty_to_sell, limit_price, settings, tx_db)
+}
+
+/// Buy back shares of a short position; the mirror image of sell. qty_to_cover is a positive number of shares
+/// (see SellPosition::list_to_cover).
+pub async fn buy_to_cover(symbol: &str, qty_to_cover: BigDecimal, limit_price:Option<BigDecimal>,
+                  settings: &Settings, tx_db:Sender<DbMsg>) -> Option<String> {
+
+    tracing::info!("[alpaca_api::buy_to_cover] ************** COVER ************** {}, {} shares for {:?}", symbol, qty_to_cover, limit_price);
+    post_simple_order(symbol, TradeSide::Buy, qty_to_cover, limit_price, settings, tx_db)
+}
+
+/// a plain day order for qty shares, limit if limit_price is given, otherwise market; sell and buy_to_cover
+/// are the usual callers, but strategies use it for sized buys too
+pub fn post_simple_order(symbol: &str, side: TradeSide, qty_to_sell: BigDecimal, limit_price:Option<BigDecimal>,
+                  settings: &Settings, tx_db:Sender<DbMsg>) -> Option<String> {
 
     // generate a new order and save to the order log
     // not the TransactionLog (which prevents duplicates)
     // TODO: move this to after the sell order is successful; or even after it fills(? requires monitoring websocket and more error prone)
-    let order_log_entry = OrderLogEntry::new(symbol.to_string().to_uppercase(), TradeSide::Sell, qty_to_sell.clone());
+    let order_log_entry = OrderLogEntry::new(symbol.to_string().to_uppercase(), side.clone(), qty_to_sell.clone());
     let tx_db1 = tx_db.clone();
     let _result = order_log_entry.save(tx_db1);
 
@@ -52,13 +69,20 @@ pub async fn sell(symbol: &str, qty_to_sell: BigDecimal, limit_price:Option<BigD
             // Limit order
             let json = JsonTrade {
                 symbol: order_log_entry.symbol(), // to uppercase
-                side: TradeSide::Sell,
+                side: side.clone(),
                 time_in_force: TimeInForce::Day,
-                qty: qty_to_sell,
+                qty: Some(qty_to_sell),
+                notional: None,
                 order_type: OrderType::Limit,
                 limit_price: Some(limit_price.clone()),
                 extended_hours: Some(SELL_EXTENDED_HOURS),
                 client_order_id: order_log_entry.id_client(),
+                stop_price: None,
+                trail_percent: None,
+                trail_price: None,
+                order_class: None,
+                take_profit: None,
+                stop_loss: None,
             };
 
             (json,Some(order_log_entry.id_client()))
@@ -69,13 +93,20 @@ pub async fn sell(symbol: &str, qty_to_sell: BigDecimal, limit_price:Option<BigD
 
             let json = JsonTrade {
                 symbol: order_log_entry.symbol(), // to uppercase
-                side: TradeSide::Sell,
+                side: side.clone(),
                 time_in_force: TimeInForce::Day,
-                qty: qty_to_sell,
+                qty: Some(qty_to_sell),
+                notional: None,
                 order_type: OrderType::Market,
                 limit_price: None,
                 extended_hours: None,
-                client_order_id: order_log_entry.id_client()
+                client_order_id: order_log_entry.id_client(),
+                stop_price: None,
+                trail_percent: None,
+                trail_price: None,
+                order_class: None,
+                take_profit: None,
+                stop_loss: None,
             };
 
             (json, None)
@@ -104,6 +135,103 @@ pub async fn sell(symbol: &str, qty_to_sell: BigDecimal, limit_price:Option<BigD
 }
 
 
+/// Open a short position in stock_symbol, if t_order_policy allows shorting and Alpaca can borrow the shares.
+///
+/// Sized like a whole-share buy (Alpaca doesn't short fractional shares) with the same cash and trade_size
+/// limits; exits go through SellPosition::list_to_cover and buy_to_cover.
+pub async fn sell_short(stock_symbol: &Symbol, settings: &Settings, tx_db:Sender<DbMsg>) {
+
+    tracing::info!("[sell_short] ***** SELL SHORT {}: {}", &stock_symbol.symbol, &stock_symbol.trade_size);
+
+    let policy = match OrderManager::policy(tx_db.clone()) {
+        Ok(policy) if policy.short_enabled => policy,
+        Ok(_) => {
+            tracing::info!("[sell_short] ***** shorting disabled in t_order_policy");
+            return;
+        },
+        Err(e) => {
+            tracing::info!("[sell_short] ***** no order policy: {:?}", &e);
+            return;
+        }
+    };
+
+    match Asset::get_remote(&stock_symbol.symbol, settings, tx_db.clone()) {
+        Ok(asset) if !asset.shortable => {
+            tracing::info!("[sell_short] ***** {} not shortable", &stock_symbol.symbol);
+            return;
+        },
+        Ok(asset) if policy.short_require_easy_to_borrow && !asset.easy_to_borrow => {
+            tracing::info!("[sell_short] ***** {} not easy to borrow", &stock_symbol.symbol);
+            return;
+        },
+        Ok(_) => {},
+        Err(e) => {
+            tracing::info!("[sell_short] ***** asset lookup failed: {:?}", &e);
+            return;
+        }
+    }
+
+    // one order or position per symbol, long or short
+    match AlpacaTransaction::buy_check(&stock_symbol.symbol, tx_db.clone()) {
+        BuyResult::NotAllowed { error } => {
+            tracing::debug!("[sell_short] ***** already a position or order: {:?}", &error);
+        },
+        BuyResult::Allowed => {
+            let size = match Account::max_buy_possible(&stock_symbol.symbol, tx_db.clone()).await {
+                Ok(buy_possible) => OrderSize::for_buy(&SizingMode::WholeShares, false, &buy_possible.price, &buy_possible.cash_available, &stock_symbol.trade_size),
+                Err(e) => {
+                    tracing::info!("[sell_short] ***** cannot short; error: {:?}", e);
+                    OrderSize::Qty(BigDecimal::from(0))
+                }
+            };
+
+            let next_step = match size {
+                OrderSize::Qty(qty) if qty > BigDecimal::from(0) => {
+                    let order_log_entry = OrderLogEntry::new(stock_symbol.symbol.clone(), TradeSide::SellShort, qty.clone());
+                    order_log_entry.save(tx_db.clone());
+
+                    let json_trade = JsonTrade {
+                        symbol: order_log_entry.symbol(), // to uppercase
+                        side: TradeSide::SellShort,
+                        time_in_force: TimeInForce::Day,
+                        qty: Some(qty),
+                        notional: None,
+                        order_type: OrderType::Market,
+                        limit_price: None,
+                        extended_hours: None,
+                        client_order_id: order_log_entry.id_client(),
+                        stop_price: None,
+                        trail_percent: None,
+                        trail_price: None,
+                        order_class: None,
+                        take_profit: None,
+                        stop_loss: None,
+                    };
+
+                    match post_order(json_trade, settings, tx_db.clone()) {
+                        Ok(order) => {
+                            order.save(tx_db.clone());
+                            TransactionNextStep::Continue
+                        },
+                        Err(e) => {
+                            tracing::info!("[sell_short] ***** order not posted: {:?}", &e);
+                            TransactionNextStep::DeleteTransaction
+                        }
+                    }
+                },
+                _ => {
+                    tracing::info!("[sell_short] ***** shorting zero");
+                    TransactionNextStep::DeleteTransaction
+                }
+            };
+
+            if next_step == TransactionNextStep::DeleteTransaction {
+                AlpacaTransaction::delete_one(&stock_symbol.symbol, tx_db.clone());
+            }
+        }
+    }
+}
+
 /// buy
 pub async fn buy(stock_symbol: &Symbol, settings: &Settings, tx_db:Sender<DbMsg>) {
 
@@ -117,10 +245,9 @@ pub async fn buy(stock_symbol: &Symbol, settings: &Settings, tx_db:Sender<DbMsg>
     // old, no longer refreshing order table from API
     // let count_result = Position::check_position_and_order(&stock_symbol.symbol, pool).await;
 
-    // TODO: safety checks to prevent buying when data is bad and
-    // 1. check t_settings.allow_buy
-    // 2. check available cash: t_settings.max - account.equity > one share price at least
-    // 3. check account, transaction_status, and trade table updates are recent otherwise default to not buying (websocket probably down)
+    // account-level limits (trade_enable_buy, cash, exposure, position count, daily loss) are enforced by
+    // RiskManager::approve inside post_order
+    // TODO: check account, transaction_status, and trade table updates are recent otherwise default to not buying (websocket probably down)
 
 
     // start_buy checks if there's already an order in play; if there is it returns an error
@@ -140,66 +267,99 @@ pub async fn buy(stock_symbol: &Symbol, settings: &Settings, tx_db:Sender<DbMsg>
             // TODO: get current price at the same time as the current cash available
             let max_buy_result = Account::max_buy_possible(&stock_symbol.symbol, tx_db.clone()).await;
             tracing::info!("[buy] ***** max shares: {:?}", &max_buy_result);
-            let qty = match max_buy_result {
+            let policy = OrderManager::policy(tx_db.clone()).ok();
+            let size = match max_buy_result {
                 Err(_e)=> {
                     tracing::info!("[buy] ***** cannot buy; error: {:?}", _e);
-                    BigDecimal::from(0)
+                    None
                 },
                 Ok(buy_possible) => {
                     // minimum of the max possible and max qty allowed
                     tracing::info!("[buy] ***** cash available: {:?}", &buy_possible);
-                    std::cmp::min(buy_possible.qty_possible, stock_symbol.trade_size.clone())
+                    let sizing_mode = policy.as_ref().map(|p| p.sizing_mode.clone()).unwrap_or(SizingMode::WholeShares);
+                    let fractionable = sizing_mode != SizingMode::WholeShares
+                        && Asset::get_remote(&stock_symbol.symbol, settings, tx_db.clone()).map(|a| a.fractionable).unwrap_or(false);
+                    let size = OrderSize::for_buy(&sizing_mode, fractionable, &buy_possible.price, &buy_possible.cash_available, &stock_symbol.trade_size);
+                    Some((size, buy_possible.price))
                 }
             };
-            tracing::info!("[buy] ***** shares to attempt to buy: {}", &qty);
-
-            if qty <= BigDecimal::from(0) {
-                // qty we can buy is zero
-                tracing::info!("[buy] ***** buying zero: {}", &qty);
-            } else {
-
-
-                // buy the quantity pertaining to the specific stock in the t_symbol table
-                tracing::info!("[buy] BUY (minimum of std size and max possible) {}:{}", &stock_symbol.symbol, &qty);
-
-                // catch whatever was causing us to buy 65000 shares
-                assert!(qty <= BigDecimal::from_usize(QTY_SIZE_SAFETY_LIMIT).unwrap_or_else(|| BigDecimal::from(300)), "{}",
-                        format!("[alpaca_api::buy quantity to sell is not less than {}", QTY_SIZE_SAFETY_LIMIT));
-
-                // generate a new order and save to the order log
-                let order_log_entry = OrderLogEntry::new(stock_symbol.symbol.clone(), TradeSide::Buy, qty.clone());
-
-                let tx_db1 = tx_db.clone();
-                let _result = order_log_entry.save(tx_db1);
-
-                // JSON for alpaca API
-                let json_trade = JsonTrade {
-                    symbol: order_log_entry.symbol(), // to uppercase,
-                    side: TradeSide::Buy,
-                    time_in_force: TimeInForce::Day,
-                    qty: qty.clone(),
-                    order_type: OrderType::Market,
-                    limit_price: None,
-                    extended_hours: Some(BUY_EXTENDED_HOURS),
-                    client_order_id: order_log_entry.id_client(),
-                };
-
-                // Delete the new transaction in alpaca_transaction_status if posting an order fails
-                // We know the order was newly created and currently set to 0.0 shares since it allowed
-                // creating a new order above.
-                let tx_rest1 = tx_db.clone();
-                let next_step = match post_order(json_trade, settings, tx_rest1) {
-                    Ok(order) => {
-                        let tx_db1 = tx_db.clone();
-                        order.save(tx_db1);
-                        TransactionNextStep::Continue
-                    },
-                    Err(_) => TransactionNextStep::DeleteTransaction
-                };
-
-                if next_step == TransactionNextStep::DeleteTransaction {
-                    let tx_db2 = tx_db.clone();
-                    AlpacaTransaction::delete_one(&stock_symbol.symbol, tx_db2);
+            tracing::info!("[buy] ***** size to attempt to buy: {:?}", &size);
+
+            match size {
+                None => {},
+                Some((size, _)) if size.is_zero() => {
+                    // qty we can buy is zero
+                    tracing::info!("[buy] ***** buying zero: {:?}", &size);
+                },
+                Some((size, price)) => {
+
+                    // buy the quantity pertaining to the specific stock in the t_symbol table
+                    tracing::info!("[buy] BUY (minimum of std size and max possible) {}:{:?}", &stock_symbol.symbol, &size);
+
+                    // generate a new order and save to the order log
+                    let order_log_entry = OrderLogEntry::new(stock_symbol.symbol.clone(), TradeSide::Buy, size.qty_estimate(&price));
+
+                    let tx_db1 = tx_db.clone();
+                    order_log_entry.save(tx_db1);
+
+                    let (qty, notional) = match &size {
+                        OrderSize::Qty(qty) => (Some(qty.clone()), None),
+                        OrderSize::Notional(dollars) => (None, Some(dollars.clone())),
+                    };
+
+                    // JSON for alpaca API; fractional and notional orders are market/day only, no extended hours
+                    let mut json_trade = JsonTrade {
+                        symbol: order_log_entry.symbol(), // to uppercase,
+                        side: TradeSide::Buy,
+                        time_in_force: TimeInForce::Day,
+                        qty,
+                        notional,
+                        order_type: OrderType::Market,
+                        limit_price: None,
+                        extended_hours: if size.is_whole_shares() { Some(BUY_EXTENDED_HOURS) } else { None },
+                        client_order_id: order_log_entry.id_client(),
+                        stop_price: None,
+                        trail_percent: None,
+                        trail_price: None,
+                        order_class: None,
+                        take_profit: None,
+                        stop_loss: None,
+                    };
+
+                    // attach the exits up front if t_order_policy says so; Alpaca doesn't allow brackets in
+                    // extended hours or on fractional quantities
+                    let exits = match (&policy, size.is_whole_shares()) {
+                        (Some(policy), true) => OrderManager::entry_exits(&price, policy),
+                        _ => None,
+                    };
+                    if let Some(exits) = exits {
+                        tracing::info!("[buy] ***** {} exits: {:?} {:?}", &exits.order_class, &exits.take_profit, &exits.stop_loss);
+                        json_trade.order_class = Some(exits.order_class);
+                        json_trade.take_profit = exits.take_profit;
+                        json_trade.stop_loss = exits.stop_loss;
+                        json_trade.extended_hours = None;
+                    }
+
+                    // Delete the new transaction in alpaca_transaction_status if posting an order fails
+                    // We know the order was newly created and currently set to 0.0 shares since it allowed
+                    // creating a new order above.
+                    let tx_rest1 = tx_db.clone();
+                    let next_step = match post_order(json_trade, settings, tx_rest1) {
+                        Ok(order) => {
+                            let tx_db1 = tx_db.clone();
+                            order.save(tx_db1);
+                            TransactionNextStep::Continue
+                        },
+                        Err(e) => {
+                            tracing::info!("[buy] ***** order not posted: {:?}", &e);
+                            TransactionNextStep::DeleteTransaction
+                        }
+                    };
+
+                    if next_step == TransactionNextStep::DeleteTransaction {
+                        let tx_db2 = tx_db.clone();
+                        AlpacaTransaction::delete_one(&stock_symbol.symbol, tx_db2);
+                    }
                 }
             }
         }
@@ -209,7 +369,11 @@ pub async fn buy(stock_symbol: &Symbol, settings: &Settings, tx_db:Sender<DbMsg>
 
 /// do the REST part of the orders POST API
 /// see documentation above
+///
+/// Every order passes the pre-trade risk checks first; a rejection is returned as TradeWebError::RiskRejected.
 pub fn post_order(json_trade: JsonTrade, settings: &Settings, tx_db:crossbeam_channel::Sender<DbMsg>) -> Result<Order, TradeWebError> {
+    RiskManager::approve(&json_trade, settings, tx_db.clone())?;
+
     let (tx, rx) = crossbeam_channel::unbounded();
     let _ = tx_db.send(DbMsg::RestPostOrder {json_trade: json_trade.clone(), settings:settings.clone(), sender: tx});
     match rx.recv(){
@@ -46,7 +46,7 @@ impl fmt::Display for RequestAction {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Ping {
     pub dtg: DateTime<Utc>,
 }
@@ -132,10 +132,10 @@ pub enum DataMessage{
     Trade(AlpacaTradeWs),
 
     #[serde(rename = "b")]
-    Bar,
+    Bar(MinuteBar),
 
     #[serde(rename = "q")]
-    Quote,
+    Quote(AlpWsQuote),
 
     #[serde(rename = "d")]
     DailyBar,
@@ -230,29 +230,8 @@ pub struct AlpacaTradeWs {
     // pub dtg_updated: DateTime<Utc>,
 }
 
-/*
-
-
-    {
-        "ev": "Q",
-        "T": "SPY",
-        "x": 17,
-        "p": 283.35,
-        "s": 1,
-        "X": 17,
-        "P": 283.4,
-        "S": 1,
-        "c": [1],
-        "t": 1587407015152775000
-    }
-// #[derive(Deserialize, Serialize, Debug, Clone)]
-// struct AlpacaStreamQuote {
-//     stream: String,
-//     data: AlpWsQuote,
-// }
-
+/// {"T":"q","S":"AMD","bx":"U","bp":87.66,"bs":1,"ax":"Q","ap":87.68,"as":4,"t":"2021-02-22T15:51:45.335689322Z","c":["R"],"z":"C"}
 #[derive(Deserialize, Serialize, Debug, Clone)]
-
 pub struct AlpWsQuote {
 
     /*
@@ -265,52 +244,68 @@ pub struct AlpWsQuote {
         bx 	string 	bid exchange code
         bp 	number 	bid price
         bs 	int 	bid size
-        s 	int 	trade size
         t 	string 	RFC-3339 formatted timestamp with nanosecond precision
         c 	array 	quote condition
         z 	string 	tape
     */
-    #[serde(rename = "T")]
-    pub event: String,
+    // not needed with #[serde(tag="T")] in DataMessage
+    // #[serde(rename = "T")]
+    // pub event: String,
 
     #[serde(rename = "S")]
     pub symbol: String,
 
-    #[serde(rename = "s")]
-    pub size_trade: usize,
-
     // exchange code for bid quote
     #[serde(rename = "bx")]
-    pub exchange_bid: usize,
+    pub exchange_bid: String,
 
     #[serde(rename = "bp")]
     pub price_bid: BigDecimal,
 
     #[serde(rename = "bs")]
-    pub size_bid: usize,
+    pub size_bid: BigDecimal,
 
     // exchange code for ask quote
     #[serde(rename = "ax")]
-    pub exchange_ask: usize,
+    pub exchange_ask: String,
 
     #[serde(rename = "ap")]
     pub price_ask: BigDecimal,
 
     #[serde(rename = "as")]
-    pub size_ask: usize,
+    pub size_ask: BigDecimal,
 
     // condition flags
     // pub c:Vec<usize>,
 
-    // timestamp nanoseconds
-    // #[serde(with = "ts_nanoseconds")]
     #[serde(rename = "t")]
-    pub dtg: String, // DateTime<Utc>,
+    pub dtg: DateTime<Utc>,
 
-    #[serde(default = "Utc::now")]
-    pub dtg_updated: DateTime<Utc>,
+    #[serde(rename = "z")]
+    pub id_tape: String,
 }
 
+/*
+
+
+    {
+        "ev": "Q",
+        "T": "SPY",
+        "x": 17,
+        "p": 283.35,
+        "s": 1,
+        "X": 17,
+        "P": 283.4,
+        "S": 1,
+        "c": [1],
+        "t": 1587407015152775000
+    }
+// #[derive(Deserialize, Serialize, Debug, Clone)]
+// struct AlpacaStreamQuote {
+//     stream: String,
+//     data: AlpWsQuote,
+// }
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Quote {
     pub status: Status,
@@ -354,7 +349,8 @@ pub struct Quote {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MinuteBar {
-    #[serde(rename = "T")]
+    // absent when parsed through DataMessage, which consumes "T" as the tag
+    #[serde(rename = "T", default)]
     msg_type: String,
     #[serde(rename = "S")]
     pub symbol: String,
@@ -372,6 +368,13 @@ pub struct MinuteBar {
     pub dtg: DateTime<Utc>,
 }
 
+impl MinuteBar {
+    /// a bar not parsed off the websocket, e.g. replayed from bar_minute
+    pub fn new(symbol: String, dtg: DateTime<Utc>, price_open: BigDecimal, price_high: BigDecimal, price_low: BigDecimal, price_close: BigDecimal, volume: usize) -> MinuteBar {
+        MinuteBar { msg_type: "b".to_string(), symbol, price_open, price_high, price_low, price_close, volume, dtg }
+    }
+}
+
 
 
 /*
@@ -437,7 +440,7 @@ pub struct MinuteBar {
 // [{"T":"t","S":"TSLA","i":5471,"x":"V","p":286.39,"s":100,"c":["@"],"z":"C","t":"2023-07-17T15:21:55.787214158Z"}]
 // [{"T":"t","S":"T","i":54191554971494,"x":"V","p":13.77,"s":41,"c":[" ","I"],"z":"A","t":"2023-07-17T15:21:55.585613056Z"},{"T":"t","S":"T","i":54191554971509,"x":"V","p":13.77,"s":100,"c":[" "],"z":"A","t":"2023-07-17T15:21:55.585622016Z"},{"T":"t","S":"T","i":54191554971717,"x":"V","p":13.77,"s":59,"c":[" ","I"],"z":"A","t":"2023-07-17T15:21:55.58563712Z"}]
 
-#[derive(Debug, Display, Clone)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Serialize)]
 pub enum CrossStatus{
     Up,
     Down,
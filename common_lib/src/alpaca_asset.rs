@@ -0,0 +1,34 @@
+//! alpaca_asset.rs
+//!
+//! Per-symbol trading flags from the Alpaca assets API.
+//!
+
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+
+/// GET https://paper-api.alpaca.markets/v2/assets/{symbol}
+///
+/// {"id":"b0b6dd9d-8b9b-48a9-ba46-b9d54906e415","class":"us_equity","exchange":"NASDAQ","symbol":"AAPL","name":"Apple Inc. Common Stock","status":"active","tradable":true,"marginable":true,"maintenance_margin_requirement":30,"shortable":true,"easy_to_borrow":true,"fractionable":true,"attributes":[]}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Asset {
+    pub id: String,
+    pub symbol: String,
+    pub status: String,
+    pub tradable: bool,
+    pub marginable: bool,
+    pub shortable: bool,
+    pub easy_to_borrow: bool,
+    pub fractionable: bool,
+}
+
+impl Asset {
+
+    pub fn get_remote(symbol: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<Asset, TradeWebError> {
+        let (resp_tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::AssetGetRemote { symbol: symbol.to_uppercase(), settings: settings.clone(), resp_tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
@@ -101,6 +101,8 @@ pub struct Order {
     pub symbol: String,
     pub asset_class: Option<String>,
     pub notional: Option<BigDecimal>,
+    /// null on a notional order until it fills; read as zero
+    #[serde(deserialize_with = "null_as_zero")]
     pub qty: BigDecimal,
     pub filled_qty: Option<BigDecimal>,
     pub filled_avg_price: Option<BigDecimal>,
@@ -122,14 +124,19 @@ pub struct Order {
 
 }
 
+/// Alpaca sends "qty": null for notional orders
+fn null_as_zero<'de, D>(deserializer: D) -> Result<BigDecimal, D::Error> where D: serde::Deserializer<'de> {
+    Ok(Option::<BigDecimal>::deserialize(deserializer)?.unwrap_or_else(|| BigDecimal::from(0)))
+}
+
 impl Order {
 
     /// Get all outstanding orders from Alpaca API
     pub async fn remote(settings: &Settings) -> Result<Vec<Order>, TradeWebError> {
 
         let mut headers = HeaderMap::new();
-        let api_key = settings.alpaca_paper_id.clone();
-        let api_secret = settings.alpaca_paper_secret.clone();
+        let api_key = settings.alpaca_paper_id.expose().to_string();
+        let api_secret = settings.alpaca_paper_secret.expose().to_string();
         headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
         headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
         let client = reqwest::Client::new();
@@ -237,3 +244,15 @@ impl fmt::Display for Order {
 
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notional_order_with_null_qty_deserializes() {
+        let json = r#"{"id":"61e69015-8549-4bfd-b9c3-01e75843f47d","client_order_id":"eb9e2aaa-f71a-4f51-b5b4-52a6c565dad4","created_at":"2021-03-16T18:38:01.942282Z","updated_at":"2021-03-16T18:38:01.942282Z","submitted_at":"2021-03-16T18:38:01.937734Z","filled_at":null,"expired_at":null,"canceled_at":null,"failed_at":null,"replaced_at":null,"replaced_by":null,"replaces":null,"asset_id":"b0b6dd9d-8b9b-48a9-ba46-b9d54906e415","symbol":"AAPL","asset_class":"us_equity","notional":"500","qty":null,"filled_qty":"0","filled_avg_price":null,"order_class":"","order_type":"market","type":"market","side":"buy","time_in_force":"day","limit_price":null,"stop_price":null,"status":"accepted","extended_hours":false,"legs":null,"trail_percent":null,"trail_price":null,"hwm":null}"#;
+        let order: Order = serde_json::from_str(json).unwrap();
+        assert_eq!(order.qty, BigDecimal::from(0));
+        assert_eq!(order.notional, Some(BigDecimal::from(500)));
+    }
+}
@@ -2,9 +2,10 @@
 
 
 use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use crate::alpaca_order::Order;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct AlpacaOrderLogEvent{
     pub dtg:DateTime<Utc>,
     pub event: String,
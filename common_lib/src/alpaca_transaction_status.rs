@@ -89,11 +89,13 @@ impl AlpacaTransaction{
 
     /// TODO: move to database; for now only called from within database crossbeam message anyway
     /// delete all "orders" without positions; start_buy() relies on the non-existence of a symbol to start an order
+    ///
+    /// Short positions are stored as negative posn_shares so only exactly zero means no position.
     pub async fn clean(pool:&PgPool)->Result<(), TradeWebError>{
-        match sqlx::query!(
+        match sqlx::query(
             r#"
                 delete from alpaca_transaction_status
-                where posn_shares <= 0.0
+                where posn_shares = 0.0
             "#
         ).execute(pool).await{
             Ok(_)=>Ok(()),
@@ -102,21 +104,38 @@ impl AlpacaTransaction{
     }
 
     /// TODO: move to database; for now only called from within database crossbeam message anyway
-    /// insert a new transaction if one doesn't currently exist, otherwise error
+    /// reduce a long position after a sell fill; never crosses zero into a short
     pub async fn decrement(symbol:&str, shares_to_decrement:BigDecimal, pool:&PgPool)->Result<(), TradeWebError>{
-        match sqlx::query!(
+        match sqlx::query(
             r#"
                 update alpaca_transaction_status
-                set posn_shares = posn_shares - $1 where symbol=$2
-            "#,
-            shares_to_decrement,
-            symbol.to_lowercase()
-        ).execute(pool).await{
+                set posn_shares = greatest(posn_shares - $1, 0.0) where symbol=$2 and posn_shares > 0.0
+            "#
+        )
+            .bind(shares_to_decrement)
+            .bind(symbol.to_lowercase())
+            .execute(pool).await{
             Ok(_)=>Ok(()),
             Err(_e)=>Err(TradeWebError::DeleteFailed), // or db error
         }
     }
 
+    /// reduce a short position after a buy-to-cover fill; true if there was a short to reduce
+    pub async fn increment_short(symbol:&str, shares_to_increment:BigDecimal, pool:&PgPool)->Result<bool, TradeWebError>{
+        match sqlx::query(
+            r#"
+                update alpaca_transaction_status
+                set posn_shares = least(posn_shares + $1, 0.0) where symbol=$2 and posn_shares < 0.0
+            "#
+        )
+            .bind(shares_to_increment)
+            .bind(symbol.to_lowercase())
+            .execute(pool).await{
+            Ok(result)=>Ok(result.rows_affected() > 0),
+            Err(_e)=>Err(TradeWebError::DeleteFailed), // or db error
+        }
+    }
+
     /// create a new entry or update the position's shares with the current timestamp
     pub fn insert_existing_position(position:&Position, tx_db:crossbeam_channel::Sender<DbMsg>){
         let _ = tx_db.send(DbMsg::TransactionInsertPosition { position: position.clone() });
@@ -0,0 +1,78 @@
+//! backfill.rs
+//!
+//! Fill bar_minute from Alpaca's historical bars (GET /v2/stocks/{symbol}/bars) for days the websocket
+//! wasn't recording, so backtests have something to replay. Each symbol's range is replaced rather than
+//! added to, so running it twice doesn't double up.
+//!
+
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::Deserialize;
+use crate::alpaca_api_structs::MinuteBar;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+
+/// one page of GET /v2/stocks/{symbol}/bars
+#[derive(Debug, Deserialize)]
+pub struct BarsPage {
+    /// null when the range has no trading
+    pub bars: Option<Vec<HistoricalBar>>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoricalBar {
+    pub t: DateTime<Utc>,
+    pub o: BigDecimal,
+    pub h: BigDecimal,
+    pub l: BigDecimal,
+    pub c: BigDecimal,
+    pub v: u64,
+}
+
+impl BarsPage {
+    /// Pure; the REST bars don't carry the symbol, the websocket ones do
+    pub fn minute_bars(self, symbol: &str) -> Vec<MinuteBar> {
+        self.bars.unwrap_or_default().into_iter()
+            .map(|bar| MinuteBar::new(symbol.to_uppercase(), bar.t, bar.o, bar.h, bar.l, bar.c, bar.v as usize))
+            .collect()
+    }
+}
+
+pub struct Backfill;
+
+impl Backfill {
+
+    /// Replace symbol's bar_minute rows in [start, end) with Alpaca's; feed is iex (free) or sip. Returns
+    /// how many bars were saved.
+    pub fn minute_bars(symbol: &str, start: DateTime<Utc>, end: DateTime<Utc>, feed: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<u64, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RestGetBars { symbol: symbol.to_string(), start, end, feed: feed.to_string(), settings: settings.clone(), sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        let bars = rx.recv().map_err(|_| TradeWebError::ChannelError)??;
+
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::BarMinuteReplace { symbol: symbol.to_string(), start, end, bars, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bars_page_to_minute_bars() {
+        let json = r#"{"bars":[{"t":"2023-06-07T13:30:00Z","o":180.1,"h":180.5,"l":179.9,"c":180.2,"v":1200,"n":40,"vw":180.21}],"symbol":"AAPL","next_page_token":"QUFQTHxNfDIwMjM="}"#;
+        let page: BarsPage = serde_json::from_str(json).unwrap();
+        assert_eq!(page.next_page_token.as_deref(), Some("QUFQTHxNfDIwMjM="));
+        let bars = page.minute_bars("aapl");
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].symbol, "AAPL");
+        assert_eq!(bars[0].volume, 1200);
+
+        let empty: BarsPage = serde_json::from_str(r#"{"bars":null,"symbol":"AAPL","next_page_token":null}"#).unwrap();
+        assert!(empty.minute_bars("aapl").is_empty());
+    }
+}
@@ -0,0 +1,441 @@
+//! backtest.rs
+//!
+//! Replays stored history (trade_alp or trade_fh, plus bar_minute) in time order through the same
+//! StrategyRunner and indicator engine the live backend uses, with a SimulatedBroker (sim_broker.rs)
+//! standing in for Alpaca. Each replay has its own SimulatedClock, set to each event's time and handed to the
+//! broker; the process clock is left alone, so a backtest can run next to live trading. Results go to the
+//! backtest_* tables; the frontend shows them at /backtest.
+//!
+//! Run it with `trader backtest`, or `trader replay` to run a saved one again (trader/src/backtest.rs).
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use crate::alpaca_api_structs::{AlpacaTradeWs, MinuteBar};
+use crate::clock::SimulatedClock;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+use crate::sim_broker::{FillModel, SimRouter, SimulatedBroker};
+use crate::strategy::{MarketEvent, OrderRouter, Strategy, StrategyRunner};
+
+/// history is loaded a day at a time so a long range doesn't have to fit in memory
+const HISTORY_CHUNK_HOURS: i64 = 24;
+
+/// which table the trades come from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum TradeSource {
+    Alpaca,
+    Finnhub,
+}
+
+impl TradeSource {
+    pub fn table(&self) -> &'static str {
+        match self {
+            TradeSource::Alpaca => "trade_alp",
+            TradeSource::Finnhub => "trade_fh",
+        }
+    }
+
+    /// trade_alp calls it size, trade_fh volume
+    pub fn volume_column(&self) -> &'static str {
+        match self {
+            TradeSource::Alpaca => "size",
+            TradeSource::Finnhub => "volume",
+        }
+    }
+}
+
+impl FromStr for TradeSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "alp" | "alpaca" | "trade_alp" => Ok(TradeSource::Alpaca),
+            "fh" | "finnhub" | "trade_fh" => Ok(TradeSource::Finnhub),
+            _ => Err(format!("unknown trade source: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktestConfig {
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// uppercase
+    pub symbols: Vec<String>,
+    pub source: TradeSource,
+    pub cash_start: BigDecimal,
+    /// adverse price move on market fills, in basis points of the trade price
+    pub slippage_bps: BigDecimal,
+    /// an order can't fill on any trade earlier than this long after it's sent
+    pub latency_ms: i64,
+    /// no fill bigger than the trade it's matched against
+    pub partial_fills: bool,
+}
+
+impl BacktestConfig {
+    /// Pure; a saved run's range, symbols, source and fill model under a new name, for `trader replay`.
+    /// backtest_run doesn't record partial_fills, so that's given.
+    pub fn from_run(run: &BacktestRun, name: &str, partial_fills: bool) -> Result<BacktestConfig, String> {
+        Ok(BacktestConfig {
+            name: name.to_string(),
+            start: run.dtg_start,
+            end: run.dtg_end,
+            symbols: run.symbols.split(',').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect(),
+            source: TradeSource::from_str(&run.source)?,
+            cash_start: run.cash_start.clone(),
+            slippage_bps: run.slippage_bps.clone(),
+            latency_ms: run.latency_ms,
+            partial_fills,
+        })
+    }
+}
+
+/// one trade or one finished minute bar, in the shape both tables can be read into
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct HistoryRow {
+    pub kind: String,
+    pub dtg: DateTime<Utc>,
+    pub symbol: String,
+    pub price_open: BigDecimal,
+    pub price_high: BigDecimal,
+    pub price_low: BigDecimal,
+    pub price_close: BigDecimal,
+    pub volume: BigDecimal,
+}
+
+impl HistoryRow {
+
+    pub fn to_event(&self) -> MarketEvent {
+        match self.kind.as_str() {
+            "bar" => MarketEvent::Bar(MinuteBar::new(
+                self.symbol.clone(),
+                // bar_minute is keyed by the minute's start; the row's dtg is when it finished
+                self.dtg - Duration::minutes(1),
+                self.price_open.clone(),
+                self.price_high.clone(),
+                self.price_low.clone(),
+                self.price_close.clone(),
+                self.volume.to_string().parse::<f64>().map(|v| v as usize).unwrap_or(0),
+            )),
+            _ => MarketEvent::Trade(AlpacaTradeWs {
+                symbol: self.symbol.clone(),
+                id_trade: 0,
+                exchange: "".to_string(),
+                price: self.price_close.clone(),
+                size: self.volume.clone(),
+                dtg: self.dtg,
+                id_tape: "".to_string(),
+            }),
+        }
+    }
+}
+
+/// a closed round trip
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct BacktestTrade {
+    pub symbol: String,
+    pub dtg_open: DateTime<Utc>,
+    pub dtg_close: DateTime<Utc>,
+    pub qty: BigDecimal,
+    pub price_open: BigDecimal,
+    pub price_close: BigDecimal,
+    pub pnl: BigDecimal,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct EquityPoint {
+    pub dtg: DateTime<Utc>,
+    pub equity: BigDecimal,
+    /// below the running peak, in dollars
+    pub drawdown: BigDecimal,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SymbolResult {
+    pub symbol: String,
+    pub trades: i64,
+    pub wins: i64,
+    pub pnl: BigDecimal,
+}
+
+/// one row of backtest_run; id is 0 until saved
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct BacktestRun {
+    pub id: i64,
+    pub dtg: DateTime<Utc>,
+    pub name: String,
+    pub dtg_start: DateTime<Utc>,
+    pub dtg_end: DateTime<Utc>,
+    pub symbols: String,
+    pub source: String,
+    pub slippage_bps: BigDecimal,
+    pub latency_ms: i64,
+    pub cash_start: BigDecimal,
+    pub equity_end: BigDecimal,
+    pub pnl: BigDecimal,
+    pub max_drawdown: BigDecimal,
+    pub max_drawdown_per_cent: BigDecimal,
+    pub trade_count: i64,
+    pub win_rate: BigDecimal,
+    /// the settings the run used as JSON, minus credentials
+    pub settings: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BacktestReport {
+    pub run: BacktestRun,
+    pub trades: Vec<BacktestTrade>,
+    pub equity: Vec<EquityPoint>,
+    pub symbols: Vec<SymbolResult>,
+}
+
+impl BacktestReport {
+
+    /// Pure; the largest peak-to-trough drop in dollars and as a percent of that peak.
+    pub fn max_drawdown(equity: &[EquityPoint]) -> (BigDecimal, BigDecimal) {
+        let mut peak: Option<BigDecimal> = None;
+        let mut worst = (BigDecimal::zero(), BigDecimal::zero());
+        for point in equity {
+            if peak.as_ref().is_none_or(|p| point.equity > *p) {
+                peak = Some(point.equity.clone());
+            }
+            let peak = peak.as_ref().unwrap();
+            let drop = peak - &point.equity;
+            if drop > worst.0 {
+                let per_cent = if *peak > BigDecimal::zero() { (&drop * BigDecimal::from(100) / peak).round(4) } else { BigDecimal::zero() };
+                worst = (drop, per_cent);
+            }
+        }
+        worst
+    }
+
+    /// Pure; winning round trips as a percent of all of them
+    pub fn win_rate(trades: &[BacktestTrade]) -> BigDecimal {
+        if trades.is_empty() {
+            return BigDecimal::zero();
+        }
+        let wins = trades.iter().filter(|t| t.pnl > BigDecimal::zero()).count();
+        (BigDecimal::from(wins as i64) * BigDecimal::from(100) / BigDecimal::from(trades.len() as i64)).round(2)
+    }
+
+    /// Pure; P&L and win count per symbol, alphabetical
+    pub fn by_symbol(trades: &[BacktestTrade]) -> Vec<SymbolResult> {
+        let mut by_symbol: HashMap<String, SymbolResult> = HashMap::new();
+        for trade in trades {
+            let result = by_symbol.entry(trade.symbol.clone()).or_insert_with(|| SymbolResult {
+                symbol: trade.symbol.clone(),
+                trades: 0,
+                wins: 0,
+                pnl: BigDecimal::zero(),
+            });
+            result.trades += 1;
+            if trade.pnl > BigDecimal::zero() {
+                result.wins += 1;
+            }
+            result.pnl += &trade.pnl;
+        }
+        let mut results: Vec<SymbolResult> = by_symbol.into_values().collect();
+        results.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        results
+    }
+}
+
+/// one replay in progress: the runner and its simulated account, plus the equity curve so far
+struct Replay<'a> {
+    settings: &'a Settings,
+    sim_clock: SimulatedClock,
+    runner: StrategyRunner<SimRouter>,
+    equity: Vec<EquityPoint>,
+    peak: BigDecimal,
+    minute: Option<DateTime<Utc>>,
+}
+
+impl<'a> Replay<'a> {
+
+    fn new(config: &BacktestConfig, settings: &'a Settings, strategies: Vec<Box<dyn Strategy>>, trade_sizes: HashMap<String, BigDecimal>) -> Replay<'a> {
+        let sim_clock = SimulatedClock::new(config.start);
+        let fill_model = FillModel { slippage_bps: config.slippage_bps.clone(), latency: Duration::milliseconds(config.latency_ms), partial_fills: config.partial_fills };
+        let broker = SimulatedBroker::new(config.cash_start.clone(), fill_model).with_clock(Arc::new(sim_clock.clone()));
+        let router = SimRouter::new(broker, trade_sizes, "backtest");
+        Replay {
+            settings,
+            sim_clock,
+            runner: StrategyRunner::new(strategies, settings, router),
+            equity: vec![],
+            peak: config.cash_start.clone(),
+            minute: None,
+        }
+    }
+
+    /// the next rows in time order
+    fn feed(&mut self, rows: &[HistoryRow]) {
+        for row in rows.iter() {
+            self.sim_clock.set(row.dtg);
+
+            // once a minute: mark to market and show the strategies their positions
+            let row_minute = row.dtg.duration_trunc(Duration::minutes(1)).unwrap_or(row.dtg);
+            if self.minute.is_some_and(|m| m != row_minute) {
+                let point_equity = self.runner.router().broker().equity();
+                if point_equity > self.peak {
+                    self.peak = point_equity.clone();
+                }
+                self.equity.push(EquityPoint { dtg: row_minute, drawdown: &self.peak - &point_equity, equity: point_equity });
+                let positions = self.runner.router().positions(row.dtg).unwrap_or_default();
+                self.runner.dispatch(&MarketEvent::Positions(positions), self.settings, row.dtg);
+                let short_positions = self.runner.router().short_positions(row.dtg).unwrap_or_default();
+                self.runner.dispatch(&MarketEvent::ShortPositions(short_positions), self.settings, row.dtg);
+            }
+            self.minute = Some(row_minute);
+
+            // the router fills pending orders on the event before the strategies see it
+            self.runner.dispatch(&row.to_event(), self.settings, row.dtg);
+        }
+    }
+
+    fn finish(mut self, config: &BacktestConfig) -> BacktestReport {
+        let equity_end = self.runner.router().broker().equity();
+        if let Some(last) = self.minute {
+            self.equity.push(EquityPoint { dtg: last, drawdown: std::cmp::max(&self.peak - &equity_end, BigDecimal::zero()), equity: equity_end.clone() });
+        }
+
+        let trades = self.runner.router().broker().closed_trades().to_vec();
+        let (max_drawdown, max_drawdown_per_cent) = BacktestReport::max_drawdown(&self.equity);
+        let run = BacktestRun {
+            id: 0,
+            dtg: Utc::now(),
+            name: config.name.clone(),
+            dtg_start: config.start,
+            dtg_end: config.end,
+            symbols: config.symbols.join(","),
+            source: config.source.table().to_string(),
+            slippage_bps: config.slippage_bps.clone(),
+            latency_ms: config.latency_ms,
+            cash_start: config.cash_start.clone(),
+            pnl: (&equity_end - &config.cash_start).round(4),
+            equity_end: equity_end.round(4),
+            max_drawdown: max_drawdown.round(4),
+            max_drawdown_per_cent,
+            trade_count: trades.len() as i64,
+            win_rate: BacktestReport::win_rate(&trades),
+            settings: self.settings.to_json_no_secret().to_string(),
+        };
+
+        BacktestReport { run, symbols: BacktestReport::by_symbol(&trades), trades, equity: self.equity }
+    }
+}
+
+pub struct Backtest {}
+
+impl Backtest {
+
+    /// Replay config's range through the strategies, on simulated time that starts at config.start.
+    pub fn run(config: &BacktestConfig, settings: &Settings, strategies: Vec<Box<dyn Strategy>>, trade_sizes: HashMap<String, BigDecimal>, tx_db: Sender<DbMsg>) -> Result<BacktestReport, TradeWebError> {
+        let mut replay = Replay::new(config, settings, strategies, trade_sizes);
+        let mut chunk_start = config.start;
+        while chunk_start < config.end {
+            let chunk_end = std::cmp::min(chunk_start + Duration::hours(HISTORY_CHUNK_HOURS), config.end);
+            let rows = Backtest::history(&config.source, &config.symbols, chunk_start, chunk_end, tx_db.clone())?;
+            tracing::info!("[Backtest::run] {} to {}: {} rows", &chunk_start, &chunk_end, rows.len());
+            replay.feed(&rows);
+            chunk_start = chunk_end;
+        }
+        Ok(replay.finish(config))
+    }
+
+    /// Like run, but over rows already in memory (oldest first) instead of config's range in the database;
+    /// the optimizer loads its history once and replays it for every candidate.
+    pub fn run_rows(config: &BacktestConfig, settings: &Settings, strategies: Vec<Box<dyn Strategy>>, trade_sizes: HashMap<String, BigDecimal>, rows: &[HistoryRow]) -> BacktestReport {
+        let mut replay = Replay::new(config, settings, strategies, trade_sizes);
+        replay.feed(rows);
+        replay.finish(config)
+    }
+
+    /// trades and finished bars for symbols in [start, end), oldest first
+    pub fn history(source: &TradeSource, symbols: &[String], start: DateTime<Utc>, end: DateTime<Utc>, tx_db: Sender<DbMsg>) -> Result<Vec<HistoryRow>, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::BacktestHistory { source: source.clone(), symbols: symbols.to_vec(), start, end, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// write a report to the backtest tables; returns the new run id
+    pub fn save(report: BacktestReport, tx_db: Sender<DbMsg>) -> Result<i64, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::BacktestSave { report: Box::new(report), sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// saved runs, newest first, without their trades or equity
+    pub fn list_runs(tx_db: Sender<DbMsg>) -> Result<Vec<BacktestRun>, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::BacktestRunList { sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// one saved run with everything
+    pub fn load(run_id: i64, tx_db: Sender<DbMsg>) -> Result<BacktestReport, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::BacktestLoad { run_id, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn dtg(minute: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2023-11-22T14:30:00Z").unwrap().with_timezone(&Utc) + Duration::minutes(minute)
+    }
+
+    fn trade(symbol: &str, pnl: &str) -> BacktestTrade {
+        BacktestTrade { symbol: symbol.to_string(), dtg_open: dtg(0), dtg_close: dtg(5), qty: dec("1"), price_open: dec("10"), price_close: dec("10") + dec(pnl), pnl: dec(pnl) }
+    }
+
+    #[test]
+    fn config_from_saved_run() {
+        let run = BacktestRun {
+            id: 7, dtg: dtg(60), name: "june".to_string(), dtg_start: dtg(0), dtg_end: dtg(30), symbols: "AAPL,MSFT".to_string(),
+            source: "trade_fh".to_string(), slippage_bps: dec("5"), latency_ms: 250, cash_start: dec("100000"),
+            equity_end: dec("100010"), pnl: dec("10"), max_drawdown: dec("0"), max_drawdown_per_cent: dec("0"),
+            trade_count: 1, win_rate: dec("100"), settings: "{}".to_string(),
+        };
+        let config = BacktestConfig::from_run(&run, "replay of 7", false).unwrap();
+        assert_eq!(config.symbols, vec!["AAPL".to_string(), "MSFT".to_string()]);
+        assert_eq!(config.source, TradeSource::Finnhub);
+        assert_eq!((config.start, config.end, config.latency_ms), (dtg(0), dtg(30), 250));
+    }
+
+    #[test]
+    fn drawdown_is_peak_to_trough() {
+        let equity: Vec<EquityPoint> = ["100", "110", "99", "105", "120", "108"].iter().enumerate()
+            .map(|(i, e)| EquityPoint { dtg: dtg(i as i64), equity: dec(e), drawdown: BigDecimal::zero() })
+            .collect();
+        let (dollars, per_cent) = BacktestReport::max_drawdown(&equity);
+        assert_eq!(dollars, dec("12"));
+        assert_eq!(per_cent, dec("10.0000"));
+    }
+
+    #[test]
+    fn win_rate_and_per_symbol() {
+        let trades = vec![trade("AAPL", "1.5"), trade("AAPL", "-0.5"), trade("MSFT", "2"), trade("AAPL", "0")];
+        assert_eq!(BacktestReport::win_rate(&trades), dec("50.00"));
+
+        let by_symbol = BacktestReport::by_symbol(&trades);
+        assert_eq!(by_symbol.len(), 2);
+        assert_eq!((by_symbol[0].symbol.as_str(), by_symbol[0].trades, by_symbol[0].wins), ("AAPL", 3, 1));
+        assert_eq!(by_symbol[0].pnl, dec("1.0"));
+        assert_eq!(by_symbol[1].pnl, dec("2"));
+    }
+}
@@ -0,0 +1,90 @@
+//! clock.rs
+//!
+//! Where the current time comes from. Production uses the system clock; tests install a SimulatedClock and
+//! move it forward themselves, so time-dependent behavior (market session, stale orders, order timestamps)
+//! can be driven through a trading day deterministically. Backtests don't install one: each replay hands
+//! its own SimulatedClock to its SimulatedBroker.
+//!
+
+use std::sync::{Arc, Mutex, RwLock};
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// wall-clock time
+#[derive(Debug, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Time that only moves when told to. Clones share the same time, so a test can keep one handle and install
+/// another with clock::set.
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl SimulatedClock {
+
+    pub fn new(start: DateTime<Utc>) -> SimulatedClock {
+        SimulatedClock { now: Arc::new(Mutex::new(start)) }
+    }
+
+    pub fn set(&self, dtg: DateTime<Utc>) {
+        *self.now.lock().unwrap() = dtg;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+static CLOCK: Lazy<RwLock<Arc<dyn Clock>>> = Lazy::new(|| RwLock::new(Arc::new(SystemClock)));
+
+/// the current time from the installed clock; use instead of Utc::now() for anything time-dependent
+pub fn now() -> DateTime<Utc> {
+    CLOCK.read().unwrap().now()
+}
+
+/// install a clock for the whole process, e.g. a SimulatedClock at the start of a backtest
+pub fn set(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+/// back to the system clock
+pub fn reset() {
+    set(Arc::new(SystemClock));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated_clock_only_moves_when_told() {
+        let start = DateTime::parse_from_rfc3339("2023-11-22T14:30:00Z").unwrap().with_timezone(&Utc);
+        let clock = SimulatedClock::new(start);
+        let handle = clock.clone();
+        assert_eq!(clock.now(), start);
+
+        handle.advance(Duration::minutes(90));
+        assert_eq!(clock.now(), start + Duration::minutes(90));
+
+        handle.set(start);
+        assert_eq!(clock.now(), start);
+    }
+}
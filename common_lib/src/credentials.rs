@@ -0,0 +1,65 @@
+//! credentials.rs
+//!
+//! The credential columns of t_settings: which are set, setting new ones (sealed with CREDENTIALS_KEY
+//! before they leave the process), and re-sealing every row for `trader credentials encrypt` and
+//! `trader credentials rotate`. Values never come back out of here; Settings::load_with_secret is the only reader.
+//!
+
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use crate::db::DbMsg;
+use crate::error::{SecretError, TradeWebError};
+use crate::secret::{is_sealed, MasterKey, Secret, CREDENTIALS_KEY_ENV};
+use crate::settings::SECRET_FIELDS;
+use crate::settings_history::{ChangeOrigin, SOURCE_CREDENTIALS};
+
+/// what the frontend shows instead of the value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CredentialStatus {
+    pub field: String,
+    pub is_set: bool,
+    pub is_encrypted: bool,
+}
+
+impl CredentialStatus {
+    /// Pure
+    pub fn from_stored(field: &str, stored: &str) -> CredentialStatus {
+        CredentialStatus { field: field.to_string(), is_set: !stored.is_empty(), is_encrypted: is_sealed(stored) }
+    }
+}
+
+pub struct Credentials;
+
+impl Credentials {
+
+    /// every credential field of the current settings
+    pub fn status(tx_db: Sender<DbMsg>) -> Result<Vec<CredentialStatus>, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::CredentialsStored { sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        let stored = rx.recv().map_err(|_| TradeWebError::ChannelError)?;
+        Ok(stored.iter().map(|(field, stored)| CredentialStatus::from_stored(field, stored)).collect())
+    }
+
+    /// Seal and save the given credentials; fields not in SECRET_FIELDS are ignored. Needs CREDENTIALS_KEY:
+    /// nothing is written in plaintext.
+    pub fn set(values: &[(String, Secret)], changed_by: &str, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+        let key = MasterKey::from_env(CREDENTIALS_KEY_ENV).map_err(TradeWebError::Secret)?.ok_or(TradeWebError::Secret(SecretError::NoKey))?;
+        let values: Vec<(String, String)> = values.iter()
+            .filter(|(field, _)| SECRET_FIELDS.contains(&field.as_str()))
+            .map(|(field, secret)| (field.clone(), key.seal(secret.expose())))
+            .collect();
+        let fields: Vec<&str> = values.iter().map(|(field, _)| field.as_str()).collect();
+        let origin = ChangeOrigin::new(changed_by, SOURCE_CREDENTIALS, &fields.join(", "));
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::CredentialsSet { values, origin, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?
+    }
+
+    /// Every stored credential under new: plaintext sealed, values under old rewrapped. Returns how many
+    /// values changed.
+    pub fn reseal(old: Option<MasterKey>, new: MasterKey, tx_db: Sender<DbMsg>) -> Result<u64, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::CredentialsReseal { old, new, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?
+    }
+}
@@ -7,12 +7,16 @@
 use bigdecimal::BigDecimal;
 use sqlx::postgres::PgQueryResult;
 use sqlx::{Error, PgPool};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use crossbeam_channel::Sender;
 use reqwest::header::HeaderMap;
 use tokio::runtime::Handle;
 use tokio::sync::oneshot;
-use crate::settings::Settings;
+use uuid::Uuid;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::settings::{Settings, SECRET_FIELDS};
+use crate::secret::{is_sealed, MasterKey, CREDENTIALS_KEY_ENV};
 
 // trade imports
 // use crate::account::{Account, AccountWithDate};
@@ -23,6 +27,19 @@ use crate::settings::Settings;
 // use crate::alpaca_position::{Position, TempPosition};
 // use crate::error::TradeWebError;
 // use crate::finnhub::{FinnhubPing, FinnhubTrade};
+use crate::alpaca_asset::Asset;
+use crate::kill_switch::KillSwitch;
+use crate::order_manager::{OrderChange, OrderPolicy};
+use crate::market_calendar::{CalendarDay, MarketClock};
+use crate::trade_setting_profile::{ProfileActivation, SettingsProfile};
+use crate::profile_schedule::ProfileSchedule;
+use crate::settings_history::{ChangeOrigin, SettingsChange};
+use crate::backfill::BarsPage;
+use crate::feed_monitor::FeedHeartbeat;
+use crate::supervisor::Shutdown;
+use crate::spool::{is_unavailable, Spool, SpoolRecord};
+use crate::trade_batch::{latest_per_symbol, TradeBatch, TradeMetrics, TradeRow, TRADE_BATCH_SIZE, TRADE_FLUSH_MILLIS, TRADE_METRICS_SECS};
+use crate::backtest::{BacktestReport, BacktestRun, BacktestTrade, EquityPoint, HistoryRow, SymbolResult, TradeSource};
 // use crate::symbol_list::QrySymbol;
 // use crate::trade_struct::TradeSide;
 // use crate::alpaca_transaction_status::*;
@@ -38,11 +55,12 @@ use crate::alpaca_position::{Position, TempPosition};
 use crate::error::{PollerError, TradeWebError};
 use crate::finnhub::{FinnhubPing, FinnhubTrade};
 use crate::symbol_list::QrySymbol;
-use crate::trade_struct::{JsonTrade, OrderType, TimeInForce, TradeSide};
+use crate::trade_struct::{JsonReplace, JsonTrade, OrderType, TimeInForce, TradeSide};
 use crate::alpaca_transaction_status::*;
 use crate::diff_calc::DiffCalc;
 use crate::order_log_entry::OrderLogEntry;
 use crate::position_local::PositionLocal;
+use crate::risk::{RiskEvent, RiskLimits, RiskSnapshot};
 use crate::sell_position::SellPosition;
 use crate::sqlx_pool::create_sqlx_pg_pool;
 use crate::symbol::Symbol;
@@ -66,7 +84,7 @@ pub enum DbMsg {
     TransactionDeleteOne{ symbol:String },
     TransactionDeleteAll,
     AccountGet{ resp_tx: Sender<AccountWithDate> },
-    // TODO: pass settings to others that'd need updated settings while running
+    // settings come from the caller's SettingsCache watch, so they're current
     AccountGetRemote{ settings:Settings, resp_tx: Sender<Account> },
     AccountSaveToDb{ account:Account},
     AcctCashAvailable { symbol:String, sender_tx: oneshot::Sender<MaxBuyPossible> },
@@ -87,9 +105,13 @@ pub enum DbMsg {
     OrderLocal{ sender_tx: Sender<Vec<Order>> },
 
     PositionGetRemote{ settings:Settings, resp_tx: Sender<Vec<Position>> },
+    AssetGetRemote{ symbol:String, settings:Settings, resp_tx: Sender<Asset> },
     PositionDeleteAll,
     PositionSaveToDb { position:Position },
     PositionListShowingProfit{ pl_filter: BigDecimal, sender_tx: Sender<Vec<SellPosition>>},
+    PositionListToCover{ pl_filter: BigDecimal, sender_tx: Sender<Vec<SellPosition>>},
+    PositionListShowingAge{ age_filter_minutes: BigDecimal, sender_tx: Sender<Vec<SellPosition>>},
+    PositionListToCoverShowingAge{ age_filter_minutes: BigDecimal, sender_tx: Sender<Vec<SellPosition>>},
 
     OrderLogEntrySave{entry: OrderLogEntry},
 
@@ -99,6 +121,57 @@ pub enum DbMsg {
 
     PositionLocalGet{sender: oneshot::Sender<Vec<PositionLocal>>},
 
+    RiskLimitsGet{ sender: Sender<RiskLimits> },
+    RiskSnapshotGet{ symbol:String, sender: Sender<RiskSnapshot> },
+    RiskEventSave{ event: RiskEvent },
+
+    KillSwitchGet{ sender: Sender<KillSwitch> },
+    KillSwitchSet{ state: KillSwitch, sender: Sender<KillSwitch> },
+    RestCancelAllOrders{ settings:Settings, sender: Sender<usize> },
+    RestCloseAllPositions{ settings:Settings, sender: Sender<usize> },
+
+    RestGetOrders{ settings:Settings, sender: Sender<Vec<Order>> },
+    RestCancelOrder{ order_id:String, settings:Settings, sender: Sender<String> },
+    RestReplaceOrder{ order_id:String, json_replace:JsonReplace, settings:Settings, sender: Sender<Order> },
+    OrderChangeSave{ change: OrderChange },
+    OrderPolicyGet{ sender: Sender<OrderPolicy> },
+    PriceLastGet{ symbol:String, sender: Sender<BigDecimal> },
+
+    RestGetCalendar{ start:NaiveDate, end:NaiveDate, settings:Settings, sender: Sender<Vec<CalendarDay>> },
+    RestGetClock{ settings:Settings, sender: Sender<MarketClock> },
+    CalendarSave{ days: Vec<CalendarDay> },
+    CalendarGet{ start:NaiveDate, end:NaiveDate, sender: Sender<Vec<CalendarDay>> },
+    ClockSave{ clock: MarketClock },
+    ClockGet{ sender: Sender<MarketClock> },
+
+    BacktestHistory{ source:TradeSource, symbols:Vec<String>, start:DateTime<Utc>, end:DateTime<Utc>, sender: Sender<Vec<HistoryRow>> },
+    BacktestSave{ report:Box<BacktestReport>, sender: Sender<i64> },
+    BacktestRunList{ sender: Sender<Vec<BacktestRun>> },
+    BacktestLoad{ run_id:i64, sender: Sender<BacktestReport> },
+    SettingsProfileList{ sender: Sender<Vec<SettingsProfile>> },
+    SettingsProfileLoad{ id:i64, sender: Sender<SettingsProfile> },
+    SettingsProfileSave{ profile:Box<SettingsProfile>, sender: Sender<i64> },
+    SettingsProfileUpdate{ profile:Box<SettingsProfile>, sender: Sender<()> },
+    SettingsProfileDelete{ id:i64, sender: Sender<()> },
+    SettingsProfileActivate{ name:String, origin:ChangeOrigin, sender: Sender<Settings> },
+    SettingsProfileActivations{ limit:i64, sender: Sender<Vec<ProfileActivation>> },
+    SettingsUpdate{ settings:Box<Settings>, expected_dtg:DateTime<Utc>, origin:ChangeOrigin, sender: Sender<Result<Settings, TradeWebError>> },
+    SettingsHistoryList{ limit:i64, sender: Sender<Vec<SettingsChange>> },
+    SettingsHistoryLoad{ id:i64, sender: Sender<SettingsChange> },
+    CredentialsStored{ sender: Sender<Vec<(String, String)>> },
+    CredentialsSet{ values:Vec<(String, String)>, origin:ChangeOrigin, sender: Sender<Result<(), TradeWebError>> },
+    CredentialsReseal{ old:Option<MasterKey>, new:MasterKey, sender: Sender<Result<u64, TradeWebError>> },
+    ProfileScheduleList{ sender: Sender<Vec<ProfileSchedule>> },
+    ProfileScheduleAdd{ session_time:String, weekdays:String, profile_name:String, sender: Sender<i64> },
+    ProfileScheduleDelete{ id:i64, sender: Sender<()> },
+    ProfileScheduleApplied{ id:i64, dtg:DateTime<Utc> },
+    UserAdd{ username:String, password_hash:String, sender: Sender<Result<Uuid, TradeWebError>> },
+    UserSetPassword{ username:String, password_hash:String, sender: Sender<Result<(), TradeWebError>> },
+    FeedHeartbeat{ sender: Sender<Result<FeedHeartbeat, TradeWebError>> },
+    RestGetBars{ symbol:String, start:DateTime<Utc>, end:DateTime<Utc>, feed:String, settings:Settings, sender: Sender<Result<Vec<MinuteBar>, TradeWebError>> },
+    BarMinuteReplace{ symbol:String, start:DateTime<Utc>, end:DateTime<Utc>, bars:Vec<MinuteBar>, sender: Sender<Result<u64, TradeWebError>> },
+    SpoolReplay{ records:Vec<SpoolRecord>, sender: Sender<usize> },
+
 }
 
 #[derive(Debug)]
@@ -110,6 +183,9 @@ pub struct MaxBuyPossible {
 
 }
 
+// how long a stopping DbActor waits on the writes it has started
+const DRAIN_SECS: u64 = 10;
+
 pub struct DbActor {
     pub pool: PgPool,
     pub tx:crossbeam_channel::Sender<DbMsg>,
@@ -133,47 +209,103 @@ impl DbActor {
     /// Other threads can send DbMsg messages via crossbeam to perform inserts into the database cross-thread.
     ///
     /// Each db network call takes 150-300ms on LAN/wifi
-    pub fn run(&self, rt: Handle) /*-> JoinHandle<()> */{
+    pub fn run(&self, rt: Handle) {
+        self.run_until(rt, Shutdown::new());
+    }
+
+    /// run() until shutdown is requested, then process whatever is queued and wait up to DRAIN_SECS for the
+    /// messages in flight to finish; the backend stops its components first so nothing new arrives.
+    ///
+    /// Websocket trades are batched rather than written one at a time; see trade_batch.rs.
+    pub fn run_until(&self, rt: Handle, shutdown: Shutdown) {
 
         let rx = self.rx.clone();
         let pool = self.pool.clone();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let mut batch = TradeBatch::default();
+        let metrics = Arc::new(TradeMetrics::default());
+        let flush_ticker = crossbeam_channel::tick(std::time::Duration::from_millis(TRADE_FLUSH_MILLIS));
+        let mut metrics_logged = std::time::Instant::now();
 
         tracing::debug!("[run]");
 
-        loop {
-
-            // tracing::debug!("[run] loop");
-
+        let process = |msg: DbMsg| {
             let pool = pool.clone();
-            crossbeam::channel::select! {
-
-                // TODO: potentially handle a separate channel that controls startup and shutdown of this db actor
-                // https://ryhl.io/blog/actors-with-tokio/
+            let guard = InFlight::new(in_flight.clone());
+            // blocking not required for sqlx and reqwest async libraries
+            let _x = rt.spawn(async move {
+                tracing::debug!("[run] tokio spawn process_message: {:?}", &msg);
+                process_message(msg, pool).await;
+                drop(guard);
+            });
+        };
+
+        let flush = |batch: TradeBatch| {
+            let pool = pool.clone();
+            let metrics = metrics.clone();
+            let guard = InFlight::new(in_flight.clone());
+            let _x = rt.spawn(async move {
+                let started = std::time::Instant::now();
+                let trades = batch.len();
+                let failed = trade_batch_save(batch, &pool).await;
+                metrics.record(trades, started.elapsed(), failed);
+                drop(guard);
+            });
+        };
 
+        loop {
+            crossbeam::channel::select! {
                 recv(rx) -> result => {
                     match result {
-                        Ok(msg)=>{
-                            // tracing::debug!("[run] msg: {:?}", &msg);
-
-                            // blocking not required for sqlx and reqwest async libraries
-                            let _x = rt.spawn(async {
-                                tracing::debug!("[run] tokio spawn process_message: {:?}", &msg);
-                                process_message(msg, pool).await;
-                            });
-
+                        Ok(msg)=> match batch.add(msg) {
+                            Some(msg) => process(msg),
+                            None if batch.len() >= TRADE_BATCH_SIZE => flush(batch.take()),
+                            None => {},
                         },
                         Err(e)=>{
                             tracing::error!("[run] select error: {:?}", &e);
+                            break;
                         }
                     }
                 },
-                default=>{
-                    // potentially do something if no message is coming in, like check a
-                    // semaphore to shutdown, for example.
-                    // tracing::debug!("[run] nothing to receive");
-                }
+                recv(flush_ticker) -> _ => {
+                    if !batch.is_empty() {
+                        flush(batch.take());
+                    }
+                    if metrics_logged.elapsed() >= std::time::Duration::from_secs(TRADE_METRICS_SECS) {
+                        metrics.report(metrics_logged.elapsed(), rx.len());
+                        metrics_logged = std::time::Instant::now();
+                    }
+                },
+                recv(shutdown.receiver()) -> _ => break,
             }
         }
+
+        let queued = rx.try_iter().filter_map(|msg| batch.add(msg)).map(&process).count() + batch.len();
+        if !batch.is_empty() {
+            flush(batch.take());
+        }
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(DRAIN_SECS);
+        while in_flight.load(Ordering::SeqCst) > 0 && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        tracing::info!("[run] db actor stopped; drained {} queued, {} still in flight", queued, in_flight.load(Ordering::SeqCst));
+    }
+}
+
+/// counts a spawned process_message until it finishes, panic or not
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn new(count: Arc<AtomicUsize>) -> InFlight {
+        count.fetch_add(1, Ordering::SeqCst);
+        InFlight(count)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
     }
 }
 
@@ -233,7 +365,7 @@ async fn process_message(msg:DbMsg, pool: PgPool){
 
         DbMsg::SettingsNoSecret {sender_tx}=>{
             tracing::debug!("[db] received DbMsg::SettingsNoSecret");
-            match settings_load_with_secret(pool).await {
+            match load_no_secret(pool).await {
                 Ok(settings) => { let _ = sender_tx.send(settings); },
                 Err(_e)=> tracing::debug!("[db] received DbMsg::GetSettingsWithSecret error: {:?}", &_e),
             }
@@ -285,6 +417,15 @@ async fn process_message(msg:DbMsg, pool: PgPool){
             }
         },
 
+        DbMsg::AssetGetRemote{symbol, settings, resp_tx} =>{
+            match asset_get_remote(&symbol, &settings).await {
+                Ok(asset) => {
+                    let _ = resp_tx.send(asset);
+                },
+                Err(e) => tracing::error!("[DbMsg::AssetGetRemote] {}: {:?}", &symbol, &e),
+            }
+        },
+
         DbMsg::PositionDeleteAll =>{
             let _ = position_delete_all(&pool).await;
         },
@@ -299,6 +440,24 @@ async fn process_message(msg:DbMsg, pool: PgPool){
             }
         }
 
+        DbMsg::PositionListShowingAge{ age_filter_minutes, sender_tx} => {
+            if let Ok(position_list) = SellPosition::list_showing_age(age_filter_minutes, &pool).await {
+                let _ = sender_tx.send(position_list);
+            }
+        }
+
+        DbMsg::PositionListToCover{ pl_filter, sender_tx} => {
+            if let Ok(position_list) = position_list_to_cover(pl_filter, &pool).await {
+                let _ = sender_tx.send(position_list);
+            }
+        }
+
+        DbMsg::PositionListToCoverShowingAge{ age_filter_minutes, sender_tx} => {
+            if let Ok(position_list) = position_list_to_cover_showing_age(age_filter_minutes, &pool).await {
+                let _ = sender_tx.send(position_list);
+            }
+        }
+
 
 
         DbMsg::TransactionInsertPosition{ position }=>{
@@ -349,24 +508,6 @@ async fn process_message(msg:DbMsg, pool: PgPool){
         },
 
 
-        DbMsg::TradeAlpaca(t) => {
-            tracing::debug!("[db_thread, DbMsg::WsTrade] trade: {:?}", &t);
-
-            // old; uses tokio::postgres w/o a connection pool
-            // crate::db::insert_alpaca_websocket_trade(&client, &t).await;
-
-            match insert_alpaca_trade(&t, &pool).await{
-                Ok(_)=> tracing::debug!("[db_thread, DbMsg::WsTrade] alpaca trade inserted"),
-                Err(e) => tracing::debug!("[db_thread, DbMsg::WsTrade] alpaca trade not inserted: {:?}", &e),
-            }
-
-            // also save a copy to the latest table; saves .5-3 seconds on lookup
-            match insert_alpaca_trade_latest(&t, &pool).await {
-                Ok(_)=> tracing::debug!("[db_thread, DbMsg::WsTrade] latest alpaca trade inserted"),
-                Err(e) => tracing::debug!("[db_thread, DbMsg::WsTrade] latest alpaca trade not inserted: {:?}", &e),
-            }
-        },
-
         DbMsg::OrderLocal{ sender_tx }=>{
 
             if let Ok(result) = order_local(pool).await{
@@ -377,13 +518,14 @@ async fn process_message(msg:DbMsg, pool: PgPool){
 
         DbMsg::OrderLogEvent(event)=>{
             tracing::debug!("[db] received DbMsg::OrderLogEvent: {:?}", &event);
-            let _ = insert_order_log_entry(&event, &pool).await;
-            if event.event=="fill" && event.order.side== TradeSide::Sell {
-                tracing::info!("[DbMsg::OrderLogEvent][Fill][Sell] {}:{:?}", &event.order.symbol, &event.order.filled_qty);
-                if let Some(qty) = event.order.filled_qty{
-                    let _ = AlpacaTransaction::decrement(&event.order.symbol.clone(), qty, &pool).await;
-                    let _ = AlpacaTransaction::clean(&pool).await;
-                }
+            if let Err(e) = order_log_event_save(&event, &pool).await {
+                Spool::on_error(&e, SpoolRecord::OrderLogEvent(Box::new(event)));
+            }
+        },
+
+        DbMsg::MinuteBar(bar)=>{
+            if let Err(e) = insert_minute_bar(&bar, &pool).await {
+                Spool::on_error(&e, SpoolRecord::MinuteBar(bar));
             }
         },
 
@@ -392,29 +534,270 @@ async fn process_message(msg:DbMsg, pool: PgPool){
             refresh_rating(&pool).await;
         },
 
-        DbMsg::TradeFinnhub(finnhub_trade) => {
+        DbMsg::PingFinnhub(ping) => {
+            if let Err(e) = insert_finnhub_ping(&ping, &pool).await {
+                Spool::on_error(&e, SpoolRecord::PingFinnhub(ping));
+            }
+        },
 
-            // tracing::debug!("[db_thread, DbMsg::FhTrade] finnhub_trade received by db thread: {:?}", &finnhub_trade);
-            match insert_finnhub_trade(&finnhub_trade, &pool).await {
-                Ok(_)=> tracing::debug!("[db_thread, DbMsg::FhTrade] finnhub trade inserted"),
-                Err(e) => tracing::debug!("[db_thread, DbMsg::FhTrade] finnhub trade not inserted: {:?}", &e),
+        DbMsg::PingAlpaca(ping) => {
+            if let Err(e) = insert_alpaca_ping(&ping, &pool).await {
+                Spool::on_error(&e, SpoolRecord::PingAlpaca(ping));
             }
+        },
 
-            // also save a copy to the latest table; saves .5-3 seconds on lookup (could also just save it in memory
-            // client-side later when I convert to using RabbitMq
-            match insert_finnhub_trade_latest(&finnhub_trade, &pool).await {
-                Ok(_)=> tracing::debug!("[db_thread, DbMsg::FhTrade] finnhub trade inserted"),
-                Err(e) => tracing::debug!("[db_thread, DbMsg::FhTrade] finnhub trade not inserted: {:?}", &e),
+        DbMsg::RiskLimitsGet{ sender }=>{
+            if let Ok(limits) = risk_limits_get(&pool).await {
+                let _ = sender.send(limits);
             }
         },
 
-        DbMsg::PingFinnhub(ping) => {
-            let _ = insert_finnhub_ping(&ping, &pool).await;
+        DbMsg::RiskSnapshotGet{ symbol, sender }=>{
+            if let Ok(snapshot) = risk_snapshot_get(&symbol, &pool).await {
+                let _ = sender.send(snapshot);
+            }
+        },
 
+        DbMsg::RiskEventSave{ event }=>{
+            let _ = risk_event_save(&event, &pool).await;
         },
 
-        DbMsg::PingAlpaca(ping) => {
-            let _ = insert_alpaca_ping(&ping, &pool).await;
+        DbMsg::KillSwitchGet{ sender }=>{
+            if let Ok(state) = kill_switch_get(&pool).await {
+                let _ = sender.send(state);
+            }
+        },
+
+        DbMsg::KillSwitchSet{ state, sender }=>{
+            if let Ok(state) = kill_switch_set(&state, &pool).await {
+                let _ = sender.send(state);
+            }
+        },
+
+        // TODO: move to a REST handler, not the database
+        DbMsg::RestCancelAllOrders{ settings, sender }=>{
+            if let Ok(count) = rest_cancel_all_orders(&settings).await {
+                let _ = sender.send(count);
+            }
+        },
+
+        DbMsg::RestCloseAllPositions{ settings, sender }=>{
+            if let Ok(count) = rest_close_all_positions(&settings).await {
+                let _ = sender.send(count);
+            }
+        },
+
+        DbMsg::RestGetOrders{ settings, sender }=>{
+            if let Ok(orders) = Order::remote(&settings).await {
+                let _ = sender.send(orders);
+            }
+        },
+
+        DbMsg::RestCancelOrder{ order_id, settings, sender }=>{
+            if let Ok(()) = rest_cancel_order(&order_id, &settings).await {
+                let _ = sender.send(order_id);
+            }
+        },
+
+        DbMsg::RestReplaceOrder{ order_id, json_replace, settings, sender }=>{
+            if let Ok(order) = rest_replace_order(&order_id, &json_replace, &settings).await {
+                let _ = sender.send(order);
+            }
+        },
+
+        DbMsg::OrderChangeSave{ change }=>{
+            let _ = order_change_save(&change, &pool).await;
+        },
+
+        DbMsg::OrderPolicyGet{ sender }=>{
+            if let Ok(policy) = order_policy_get(&pool).await {
+                let _ = sender.send(policy);
+            }
+        },
+
+        DbMsg::PriceLastGet{ symbol, sender }=>{
+            if let Ok(price) = price_last_get(&symbol, &pool).await {
+                let _ = sender.send(price);
+            }
+        },
+
+        DbMsg::RestGetCalendar{ start, end, settings, sender }=>{
+            match calendar_get_remote(start, end, &settings).await {
+                Ok(days) => { let _ = sender.send(days); },
+                Err(e) => tracing::error!("[DbMsg::RestGetCalendar] {:?}", &e),
+            }
+        },
+
+        DbMsg::RestGetClock{ settings, sender }=>{
+            match clock_get_remote(&settings).await {
+                Ok(clock) => { let _ = sender.send(clock); },
+                Err(e) => tracing::error!("[DbMsg::RestGetClock] {:?}", &e),
+            }
+        },
+
+        DbMsg::CalendarSave{ days }=>{
+            let _ = calendar_save(&days, &pool).await;
+        },
+
+        DbMsg::CalendarGet{ start, end, sender }=>{
+            if let Ok(days) = calendar_get(start, end, &pool).await {
+                let _ = sender.send(days);
+            }
+        },
+
+        DbMsg::ClockSave{ clock }=>{
+            let _ = clock_save(&clock, &pool).await;
+        },
+
+        DbMsg::ClockGet{ sender }=>{
+            if let Ok(clock) = clock_get(&pool).await {
+                let _ = sender.send(clock);
+            }
+        },
+
+        DbMsg::BacktestHistory{ source, symbols, start, end, sender }=>{
+            if let Ok(rows) = backtest_history(&source, &symbols, start, end, &pool).await {
+                let _ = sender.send(rows);
+            }
+        },
+
+        DbMsg::BacktestSave{ report, sender }=>{
+            if let Ok(run_id) = backtest_save(&report, &pool).await {
+                let _ = sender.send(run_id);
+            }
+        },
+
+        DbMsg::BacktestRunList{ sender }=>{
+            if let Ok(runs) = backtest_run_list(&pool).await {
+                let _ = sender.send(runs);
+            }
+        },
+
+        DbMsg::BacktestLoad{ run_id, sender }=>{
+            if let Ok(report) = backtest_load(run_id, &pool).await {
+                let _ = sender.send(report);
+            }
+        },
+
+        DbMsg::SettingsProfileList{ sender }=>{
+            if let Ok(profiles) = settings_profile_list(&pool).await {
+                let _ = sender.send(profiles);
+            }
+        },
+
+        DbMsg::SettingsProfileLoad{ id, sender }=>{
+            if let Ok(profile) = settings_profile_load(id, &pool).await {
+                let _ = sender.send(profile);
+            }
+        },
+
+        DbMsg::SettingsProfileSave{ profile, sender }=>{
+            if let Ok(id) = settings_profile_save(&profile, &pool).await {
+                let _ = sender.send(id);
+            }
+        },
+
+        DbMsg::SettingsProfileUpdate{ profile, sender }=>{
+            if let Ok(()) = settings_profile_update(&profile, &pool).await {
+                let _ = sender.send(());
+            }
+        },
+
+        DbMsg::SettingsProfileDelete{ id, sender }=>{
+            if let Ok(()) = settings_profile_delete(id, &pool).await {
+                let _ = sender.send(());
+            }
+        },
+
+        DbMsg::SettingsProfileActivate{ name, origin, sender }=>{
+            if let Ok(settings) = settings_profile_activate(&name, &origin, &pool).await {
+                let _ = sender.send(settings);
+            }
+        },
+
+        DbMsg::SettingsProfileActivations{ limit, sender }=>{
+            if let Ok(activations) = settings_profile_activations(limit, &pool).await {
+                let _ = sender.send(activations);
+            }
+        },
+
+        DbMsg::SettingsUpdate{ settings, expected_dtg, origin, sender }=>{
+            let _ = sender.send(settings_update(&settings, expected_dtg, &origin, &pool).await);
+        },
+
+        DbMsg::SettingsHistoryLoad{ id, sender }=>{
+            if let Ok(change) = settings_history_load(id, &pool).await {
+                let _ = sender.send(change);
+            }
+        },
+
+        DbMsg::CredentialsStored{ sender }=>{
+            if let Ok(stored) = credentials_stored(&pool).await {
+                let _ = sender.send(stored);
+            }
+        },
+
+        DbMsg::CredentialsSet{ values, origin, sender }=>{
+            let _ = sender.send(credentials_set(&values, &origin, &pool).await);
+        },
+
+        DbMsg::CredentialsReseal{ old, new, sender }=>{
+            let _ = sender.send(credentials_reseal(old.as_ref(), &new, &pool).await);
+        },
+
+        DbMsg::SettingsHistoryList{ limit, sender }=>{
+            if let Ok(changes) = settings_history_list(limit, &pool).await {
+                let _ = sender.send(changes);
+            }
+        },
+
+        DbMsg::ProfileScheduleList{ sender }=>{
+            if let Ok(schedules) = profile_schedule_list(&pool).await {
+                let _ = sender.send(schedules);
+            }
+        },
+
+        DbMsg::ProfileScheduleAdd{ session_time, weekdays, profile_name, sender }=>{
+            if let Ok(id) = profile_schedule_add(&session_time, &weekdays, &profile_name, &pool).await {
+                let _ = sender.send(id);
+            }
+        },
+
+        DbMsg::ProfileScheduleDelete{ id, sender }=>{
+            if let Ok(()) = profile_schedule_delete(id, &pool).await {
+                let _ = sender.send(());
+            }
+        },
+
+        DbMsg::ProfileScheduleApplied{ id, dtg }=>{
+            let _ = profile_schedule_applied(id, dtg, &pool).await;
+        },
+
+        DbMsg::UserAdd{ username, password_hash, sender }=>{
+            let _ = sender.send(user_add(&username, &password_hash, &pool).await);
+        },
+
+        DbMsg::UserSetPassword{ username, password_hash, sender }=>{
+            let _ = sender.send(user_set_password(&username, &password_hash, &pool).await);
+        },
+
+        DbMsg::FeedHeartbeat{ sender }=>{
+            let _ = sender.send(feed_heartbeat(&pool).await);
+        },
+
+        DbMsg::RestGetBars{ symbol, start, end, feed, settings, sender }=>{
+            let _ = sender.send(bars_get_remote(&symbol, start, end, &feed, &settings).await.map_err(|e| {
+                tracing::error!("[DbMsg::RestGetBars] {}: {:?}", &symbol, &e);
+                TradeWebError::ReqwestError
+            }));
+        },
+
+        DbMsg::BarMinuteReplace{ symbol, start, end, bars, sender }=>{
+            let _ = sender.send(bar_minute_replace(&symbol, start, end, &bars, &pool).await);
+        },
+
+        DbMsg::SpoolReplay{ records, sender }=>{
+            let _ = sender.send(spool_replay(records, &pool).await);
         },
 
         _ => { }
@@ -505,7 +888,7 @@ async fn refresh_rating(pool:&PgPool){
 // }
 
 /// insert a single FinnHub trade into the trade_fh table
-async fn insert_finnhub_trade(trade: &FinnhubTrade, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
+pub(crate) async fn insert_finnhub_trade(trade: &FinnhubTrade, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query!(
         r#"
             insert into trade_fh (dtg,symbol, price, volume) values ($1, $2, $3, $4)
@@ -520,7 +903,7 @@ async fn insert_finnhub_trade(trade: &FinnhubTrade, pool: &PgPool) -> Result<PgQ
 }
 
 /// Continuously overwrite only the latest trade for a given symbol so we have a fast way of getting the most recent price.
-async fn insert_finnhub_trade_latest(
+pub(crate) async fn insert_finnhub_trade_latest(
     trade: &FinnhubTrade,
     pool: &PgPool,
 ) -> Result<PgQueryResult, sqlx::Error> {
@@ -540,7 +923,7 @@ async fn insert_finnhub_trade_latest(
 }
 
 /// Insert an Alpaca trade received on the websocket
-async fn insert_alpaca_trade(t: &AlpacaTradeWs, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
+pub(crate) async fn insert_alpaca_trade(t: &AlpacaTradeWs, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query!(
         r#"
             insert into trade_alp (dtg, symbol, price, size)
@@ -551,7 +934,7 @@ async fn insert_alpaca_trade(t: &AlpacaTradeWs, pool: &PgPool) -> Result<PgQuery
 }
 
 /// Continuously overwrite only the latest trade for a given symbol so we have a fast way of getting the most recent price.
-async fn insert_alpaca_trade_latest(trade: &AlpacaTradeWs, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
+pub(crate) async fn insert_alpaca_trade_latest(trade: &AlpacaTradeWs, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query!(
         r#"insert into trade_alp_latest(dtg,symbol,price,size)
             values ($1, $2, $3, $4)
@@ -564,6 +947,89 @@ async fn insert_alpaca_trade_latest(trade: &AlpacaTradeWs, pool: &PgPool) -> Res
     ).execute(pool).await
 }
 
+/// A batch of websocket trades in one transaction: a multi-row insert into each history table and one upsert
+/// per latest table that only moves a symbol's row forward in time.
+pub(crate) async fn trade_batch_write(batch: &TradeBatch, pool: &PgPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for (rows, table, qty) in [(&batch.alpaca, "trade_alp", "size"), (&batch.finnhub, "trade_fh", "volume")] {
+        if rows.is_empty() {
+            continue;
+        }
+        let latest = latest_per_symbol(rows);
+        let all: Vec<&TradeRow> = rows.iter().collect();
+        for (rows, sql) in [
+            (all, format!(r#"
+                insert into {table} (dtg, symbol, price, {qty})
+                select * from unnest($1::timestamp[], $2::varchar[], $3::numeric[], $4::numeric[])
+            "#)),
+            (latest, format!(r#"
+                insert into {table}_latest (dtg, symbol, price, {qty})
+                select * from unnest($1::timestamp[], $2::varchar[], $3::numeric[], $4::numeric[])
+                on conflict (symbol) do update set dtg=excluded.dtg, price=excluded.price, {qty}=excluded.{qty}
+                where {table}_latest.dtg <= excluded.dtg
+            "#)),
+        ] {
+            sqlx::query(&sql)
+                .bind(rows.iter().map(|r| r.dtg.naive_utc()).collect::<Vec<_>>())
+                .bind(rows.iter().map(|r| r.symbol.clone()).collect::<Vec<_>>())
+                .bind(rows.iter().map(|r| r.price.clone()).collect::<Vec<_>>())
+                .bind(rows.iter().map(|r| r.qty.clone()).collect::<Vec<_>>())
+                .execute(&mut tx).await?;
+        }
+    }
+    tx.commit().await
+}
+
+/// trade_batch_write, retried a row at a time if the database turns the batch down for anything but being
+/// unreachable, so only the bad rows are dropped. Whatever's left when the database is unreachable is spooled.
+/// Returns how many trades weren't saved.
+async fn trade_batch_save(batch: TradeBatch, pool: &PgPool) -> usize {
+    let e = match trade_batch_write(&batch, pool).await {
+        Ok(()) => return 0,
+        Err(e) => e,
+    };
+    if is_unavailable(&e) || batch.len() == 1 {
+        let failed = batch.len();
+        Spool::on_error(&e, SpoolRecord::Trades(batch));
+        return failed;
+    }
+
+    tracing::warn!("[trade_batch_save] batch of {} turned down, retrying row by row: {:?}", batch.len(), &e);
+    let mut failed = 0;
+    let mut rows = batch.into_rows().into_iter();
+    while let Some(row) = rows.next() {
+        match trade_batch_write(&row, pool).await {
+            Ok(()) => {},
+            Err(e) if is_unavailable(&e) => {
+                let rest = rows.by_ref().fold(row, |mut rest, row| { rest.append(row); rest });
+                failed += rest.len();
+                Spool::on_error(&e, SpoolRecord::Trades(rest));
+            },
+            Err(e) => {
+                failed += 1;
+                Spool::on_error(&e, SpoolRecord::Trades(row));
+            },
+        }
+    }
+    failed
+}
+
+/// A bar from the websocket into bar_minute, which backfill also fills
+async fn insert_minute_bar(bar: &MinuteBar, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query(r#"
+        insert into bar_minute (dtg, symbol, price_open, price_high, price_low, price_close, volume)
+        values ($1, $2, $3, $4, $5, $6, $7)
+    "#)
+        .bind(bar.dtg.naive_utc())
+        .bind(&bar.symbol)
+        .bind(&bar.price_open)
+        .bind(&bar.price_high)
+        .bind(&bar.price_low)
+        .bind(&bar.price_close)
+        .bind(i32::try_from(bar.volume).unwrap_or(i32::MAX))
+        .execute(pool).await
+}
+
 /// Append a timestamp to the ping table whenever the Finnhub websocket pings. Use it to determine if the websocket goes down.
 async fn insert_finnhub_ping(ping: &FinnhubPing, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query!(
@@ -661,6 +1127,27 @@ async fn symbols_get_active(pool:&PgPool) -> Result<Vec<String>, TradeWebError>
     }
 }
 
+/// Log the order event, then on a fill adjust the transaction slate; an error means the event itself wasn't saved
+async fn order_log_event_save(event: &AlpacaOrderLogEvent, pool: &PgPool) -> Result<(), Error> {
+    insert_order_log_entry(event, pool).await?;
+    if event.event=="fill" && event.order.side== TradeSide::Sell {
+        tracing::info!("[DbMsg::OrderLogEvent][Fill][Sell] {}:{:?}", &event.order.symbol, &event.order.filled_qty);
+        if let Some(qty) = event.order.filled_qty.clone(){
+            let _ = AlpacaTransaction::decrement(&event.order.symbol.clone(), qty, pool).await;
+            let _ = AlpacaTransaction::clean(pool).await;
+        }
+    } else if event.event=="fill" && event.order.side== TradeSide::Buy {
+        // a buy against a short position is a cover
+        if let Some(qty) = event.order.filled_qty.clone(){
+            if let Ok(true) = AlpacaTransaction::increment_short(&event.order.symbol.clone(), qty, pool).await {
+                tracing::info!("[DbMsg::OrderLogEvent][Fill][Cover] {}", &event.order.symbol);
+                let _ = AlpacaTransaction::clean(pool).await;
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn insert_order_log_entry(event: &AlpacaOrderLogEvent, pool:&PgPool) -> Result<PgQueryResult, Error> {
     /*
 
@@ -745,8 +1232,8 @@ async fn insert_order_log_entry(event: &AlpacaOrderLogEvent, pool:&PgPool) -> Re
 async fn account_get_remote(settings:&Settings) -> Result<Account, TradeWebError> {
     // tracing::debug!("[account_get_remote] ************** settings: {:?}", settings);
 
-    let api_key = settings.alpaca_paper_id.clone();
-    let api_secret = settings.alpaca_paper_secret.clone();
+    let api_key = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
     let mut headers = HeaderMap::new();
     headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
     headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
@@ -847,8 +1334,8 @@ async fn account_save_to_db(account:&Account, pool:&PgPool) -> Result<(), TradeW
 async fn position_get_remote(settings: &Settings) -> Result<Vec<Position>, reqwest::Error> {
 
     let mut headers = reqwest::header::HeaderMap::new();
-    let api_key_id = settings.alpaca_paper_id.clone();
-    let api_secret = settings.alpaca_paper_secret.clone();
+    let api_key_id = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
     headers.insert("APCA-API-KEY-ID", api_key_id.parse().unwrap());
     headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
 
@@ -871,6 +1358,25 @@ async fn position_get_remote(settings: &Settings) -> Result<Vec<Position>, reqwe
     Ok(remote_positions)
 }
 
+async fn asset_get_remote(symbol: &str, settings: &Settings) -> Result<Asset, reqwest::Error> {
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    let api_key_id = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
+    headers.insert("APCA-API-KEY-ID", api_key_id.parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
+
+    let client = reqwest::Client::new();
+    client
+        .get(format!("https://paper-api.alpaca.markets/v2/assets/{}", symbol))
+        .headers(headers)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
 async fn position_delete_all(pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query!(r#"delete from alpaca_position"#)
         .execute(pool)
@@ -931,8 +1437,8 @@ async fn activity_latest_dtg(pool:PgPool)->Result<DateTime<Utc>, TradeWebError>{
 
 async fn activity_get_remote(since_filter:Option<DateTime<Utc>>, settings: Settings) -> Result<Vec<Activity>, TradeWebError> {
     let mut headers = HeaderMap::new();
-    let api_key = settings.alpaca_paper_id.clone();
-    let api_secret = settings.alpaca_paper_secret.clone();
+    let api_key = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
     headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
     headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
 
@@ -1120,6 +1626,54 @@ pub async fn position_list_showing_profit(pl_filter:BigDecimal, pool: PgPool) ->
     }
 }
 
+/// short positions in the same shape as position_list_showing_profit; see fn_positions_to_cover
+async fn position_list_to_cover(pl_filter:BigDecimal, pool:&PgPool) ->Result<Vec<SellPosition>, TradeWebError>{
+    match sqlx::query_as::<_, SellPosition>(r#"
+            select
+                stock_symbol as symbol
+                , price as avg_entry_price
+                , sell_qty as qty
+                , sell_qty_available as qty_available
+                , unrealized_pl_per_share
+                , cost as cost_basis
+                , unrealized_pl_total
+                , coalesce(trade_size,0.0) as trade_size
+                , coalesce(age_min,0.0) as age_minute
+            from fn_positions_to_cover($1) a
+            left join t_symbol b on upper(a.stock_symbol) = upper(b.symbol)
+        "#).bind(pl_filter).fetch_all(pool).await {
+        Ok(positions)=>Ok(positions),
+        Err(e)=>{
+            tracing::error!("[position_list_to_cover] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// short positions held longer than age_filter_minutes, in the same shape as position_list_to_cover; see fn_positions_to_cover_old
+async fn position_list_to_cover_showing_age(age_filter_minutes:BigDecimal, pool:&PgPool) ->Result<Vec<SellPosition>, TradeWebError>{
+    match sqlx::query_as::<_, SellPosition>(r#"
+            select
+                stock_symbol as symbol
+                , price as avg_entry_price
+                , sell_qty as qty
+                , sell_qty_available as qty_available
+                , unrealized_pl_per_share
+                , cost as cost_basis
+                , unrealized_pl_total
+                , coalesce(trade_size,0.0) as trade_size
+                , coalesce(age_min,0.0) as age_minute
+            from fn_positions_to_cover_old($1) a
+            left join t_symbol b on upper(a.stock_symbol) = upper(b.symbol)
+        "#).bind(age_filter_minutes).fetch_all(pool).await {
+        Ok(positions)=>Ok(positions),
+        Err(e)=>{
+            tracing::error!("[position_list_to_cover_showing_age] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
 /// save a new order before it's submitted
 pub async fn order_log_entry_save(entry: OrderLogEntry, pool:PgPool) -> Result<PgQueryResult, Error> {
     tracing::debug!("[order_log_entry_save]: {:?}", &entry);
@@ -1242,8 +1796,8 @@ pub async fn symbol_load_one(symbol: &str, pool: PgPool) -> Result<Symbol, Trade
 async fn rest_post_order(json_trade: &JsonTrade, settings: &Settings) -> Result<Order, TradeWebError> {
     let mut headers = HeaderMap::new();
 
-    let api_key = settings.alpaca_paper_id.clone();
-    let api_secret = settings.alpaca_paper_secret.clone();
+    let api_key = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
 
     headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
     headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
@@ -1374,97 +1928,73 @@ pub async fn order_local(pool:PgPool) -> Result<Vec<Order>, TradeWebError> {
 
 /// return blank secret for front-end type uses
 pub async fn load_no_secret(pool: PgPool) -> Result<Settings, TradeWebError> {
-    let settings_result = sqlx::query_as!(Settings,
-        r#"
-            SELECT
-                dtg as "dtg!",
-                alpaca_paper_id as "alpaca_paper_id!:String",
-                '' as "alpaca_paper_secret!:String",
-                alpaca_live_id as "alpaca_live_id!:String",
-                '' as "alpaca_live_secret!:String",
-                trade_size as "trade_size!",
-                trade_enable_buy as "trade_enable_buy!",
-                trade_ema_small_size as "trade_ema_small_size!",
-                trade_ema_large_size as "trade_ema_large_size!",
-                trade_sell_high_per_cent_multiplier as "trade_sell_high_per_cent_multiplier!",
-                trade_sell_high_upper_limit_cents as "trade_sell_high_upper_limit_cents!"
-                ,finnhub_key as "finnhub_key!:String"
-                ,coalesce(account_start_value,0.0) as "account_start_value!"
-                ,coalesce(max_position_age_minute,0.0) as "max_position_age_minute!"
-                ,coalesce(upgrade_min_profit,0.0) as "upgrade_min_profit!"
-                ,coalesce(upgrade_sell_elapsed_minutes_min,60.0) as "upgrade_sell_elapsed_minutes_min!"
-                ,coalesce(upgrade_posn_max_elapsed_minutes,60.0) as "upgrade_posn_max_elapsed_minutes!"
-                ,coalesce(upgrade_posn_loss_allowed_dollars,10.0) as "upgrade_posn_loss_allowed_dollars!"
-                ,coalesce(acct_max_position_market_value,10.0) as "acct_max_position_market_value!"
-                ,coalesce(acct_min_cash_dollars,10.0) as "acct_min_cash_dollars!"
-            FROM t_settings
-            ORDER BY t_settings.dtg DESC
-            LIMIT 1
-        "#
-    ).fetch_one(&pool).await;
+    let settings_result = sqlx::query_as::<_, Settings>(SETTINGS_NO_SECRET_SELECT).fetch_one(&pool).await;
     match settings_result{
         Ok(result)=>Ok(result),
         Err(_e)=>Err(TradeWebError::SqlxError),
     }
 }
 
+/// the latest settings with the credentials decrypted
 async fn settings_load_with_secret(pool:PgPool) -> Result<Settings, TradeWebError> {
 
-    let settings_result = sqlx::query_as!(
-        Settings,
-        r#"
-            SELECT
-                dtg as "dtg!",
-                alpaca_paper_id as "alpaca_paper_id!:String",
-                alpaca_paper_secret as "alpaca_paper_secret!:String",
-                alpaca_live_id as "alpaca_live_id!:String",
-                alpaca_live_secret as "alpaca_live_secret!:String",
-                trade_size as "trade_size!",
-                trade_enable_buy as "trade_enable_buy!",
-                trade_ema_small_size as "trade_ema_small_size!",
-                trade_ema_large_size as "trade_ema_large_size!",
-                trade_sell_high_per_cent_multiplier as "trade_sell_high_per_cent_multiplier!",
-                trade_sell_high_upper_limit_cents as "trade_sell_high_upper_limit_cents!"
-                ,finnhub_key as "finnhub_key!:String"
-                ,coalesce(account_start_value,0.0) as "account_start_value!"
-                ,coalesce(max_position_age_minute,0.0) as "max_position_age_minute!"
-                ,coalesce(upgrade_min_profit,0.0) as "upgrade_min_profit!"
-                ,coalesce(upgrade_sell_elapsed_minutes_min,60.0) as "upgrade_sell_elapsed_minutes_min!"
-                ,coalesce(upgrade_posn_max_elapsed_minutes,60.0) as "upgrade_posn_max_elapsed_minutes!"
-                ,coalesce(upgrade_posn_loss_allowed_dollars,10.0) as "upgrade_posn_loss_allowed_dollars!"
-                ,coalesce(acct_max_position_market_value,10.0) as "acct_max_position_market_value!"
-                ,coalesce(acct_min_cash_dollars,10.0) as "acct_min_cash_dollars!"
-            FROM t_settings
-            ORDER BY t_settings.dtg DESC
-            LIMIT 1
-        "#
-    ).fetch_one(&pool).await;
-
-    match settings_result{
-        Ok(result)=>Ok(result),
-        Err(_e)=>Err(TradeWebError::SqlxError),
+    let settings_result = sqlx::query_as::<_, Settings>(r#"
+        select
+            dtg, alpaca_paper_id, alpaca_paper_secret, alpaca_live_id, alpaca_live_secret, trade_size, trade_enable_buy
+            , trade_ema_small_size, trade_ema_large_size, trade_sell_high_per_cent_multiplier, trade_sell_high_upper_limit_cents, finnhub_key
+            , coalesce(account_start_value,0.0) as account_start_value
+            , coalesce(max_position_age_minute,0.0) as max_position_age_minute
+            , coalesce(upgrade_min_profit,0.0) as upgrade_min_profit
+            , coalesce(upgrade_sell_elapsed_minutes_min,60.0) as upgrade_sell_elapsed_minutes_min
+            , coalesce(upgrade_posn_max_elapsed_minutes,60.0) as upgrade_posn_max_elapsed_minutes
+            , coalesce(upgrade_posn_loss_allowed_dollars,10.0) as upgrade_posn_loss_allowed_dollars
+            , coalesce(acct_max_position_market_value,10.0) as acct_max_position_market_value
+            , coalesce(acct_min_cash_dollars,10.0) as acct_min_cash_dollars
+        from t_settings
+        order by dtg desc
+        limit 1
+    "#).fetch_one(&pool).await;
+
+    let settings = match settings_result{
+        Ok(result)=>result,
+        Err(_e)=>return Err(TradeWebError::SqlxError),
+    };
+    let key = MasterKey::from_env(CREDENTIALS_KEY_ENV).map_err(TradeWebError::Secret)?;
+    if key.is_none() && [&settings.alpaca_paper_id, &settings.alpaca_paper_secret, &settings.alpaca_live_id, &settings.alpaca_live_secret, &settings.finnhub_key].iter().any(|s| !s.is_empty() && !is_sealed(s.expose())) {
+        tracing::warn!("[settings_load_with_secret] credentials are stored in plaintext; set {} and run `trader credentials encrypt`", CREDENTIALS_KEY_ENV);
     }
-
+    settings.unseal_credentials(key.as_ref()).map_err(|e| {
+        tracing::error!("[settings_load_with_secret] couldn't decrypt the credentials: {:?}", &e);
+        TradeWebError::Secret(e)
+    })
 }
 
 /// get the positions from the local database, filtered and P/L computed to display on the web frontend
 async fn position_local_get(pool:PgPool)->Result<Vec<PositionLocal>, TradeWebError>{
 
-    let result = sqlx::query_as!(PositionLocal, r#"
+    // alpaca_position has the side; short qty is negative, so (price - entry) * qty is the P/L either way
+    // and pl_per_share is per share held. The age counts from the latest opening fill.
+    let result = sqlx::query_as::<_, PositionLocal>(r#"
         select
-            a.symbol as "symbol!"
-            , coalesce(qty,0) as "qty!"
-            , a.basis + b.price*a.qty as "pl!"
-            , (a.basis + b.price*a.qty)/a.qty as "pl_per_share!"
-            , a.basis as "basis!"
-            , b.price*a.qty as "market_value!"
-            , b.price as "price!"
-            , a.dtg as "dtg!"
-            , a.posn_age_sec as "posn_age_sec!"
-        from fn_transaction('%') a
-        left join trade_alp_latest b on a.symbol = b.symbol
-        where posn_age_sec is not null
-        order by a.symbol, a.dtg desc
+            p.symbol
+            , p.qty
+            , p.side
+            , (coalesce(b.price, p.current_price) - p.avg_entry_price) * p.qty as pl
+            , (coalesce(b.price, p.current_price) - p.avg_entry_price) * sign(p.qty) as pl_per_share
+            , p.cost_basis as basis
+            , coalesce(b.price, p.current_price) * p.qty as market_value
+            , coalesce(b.price, p.current_price) as price
+            , coalesce(a.dtg, p.dtg) as dtg
+            , coalesce(extract(epoch from now() - a.dtg), 0.0)::numeric as posn_age_sec
+        from alpaca_position p
+        left join trade_alp_latest b on upper(b.symbol) = upper(p.symbol)
+        left join lateral (
+            select max(x.dtg) as dtg
+            from alpaca_activity x
+            where upper(x.symbol) = upper(p.symbol) and x.side = case when p.qty < 0.0 then 'sell_short' else 'buy' end
+        ) a on true
+        where p.qty <> 0.0
+        order by p.symbol
     "#).fetch_all(&pool).await;
 
     match result {
@@ -1504,4 +2034,1092 @@ async fn acct_cash_available(symbol:&str, pool: PgPool) ->Result<MaxBuyPossible,
             Err(TradeWebError::SqlxError)
         }
     }
-}
\ No newline at end of file
+}
+/// latest row of t_risk_limits
+async fn risk_limits_get(pool: &PgPool) -> Result<RiskLimits, TradeWebError> {
+    match sqlx::query_as::<_, RiskLimits>(r#"
+        select dtg, symbol_notional_cap, max_open_positions, daily_loss_limit_dollars
+        from t_risk_limits
+        order by dtg desc
+        limit 1
+    "#).fetch_one(pool).await {
+        Ok(limits) => Ok(limits),
+        Err(e) => {
+            tracing::error!("[risk_limits_get] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// Everything RiskManager::check needs in one query.
+///
+/// Realized P/L today: today's (New York) sell proceeds minus the same quantity valued at the average buy
+/// price over the last five days. Close enough for a circuit breaker without replaying lots FIFO.
+async fn risk_snapshot_get(symbol: &str, pool: &PgPool) -> Result<RiskSnapshot, TradeWebError> {
+    match sqlx::query_as::<_, RiskSnapshot>(r#"
+        with sells as (
+            select upper(symbol) as symbol, sum(qty) as qty, sum(qty * price) as proceeds
+            from alpaca_activity
+            where side = 'sell'
+                and dtg >= (date_trunc('day', now() at time zone 'America/New_York') at time zone 'America/New_York')
+            group by upper(symbol)
+        ), buys as (
+            select upper(symbol) as symbol, sum(qty * price) / nullif(sum(qty), 0) as price_avg
+            from alpaca_activity
+            where side = 'buy' and dtg >= now() - interval '5 days'
+            group by upper(symbol)
+        ), shorts as (
+            select upper(symbol) as symbol, sum(qty) as qty, sum(qty * price) / nullif(sum(qty), 0) as price_avg
+            from alpaca_activity
+            where side = 'sell_short' and dtg >= now() - interval '5 days'
+            group by upper(symbol)
+        ), covers as (
+            -- today's buys that came after a short sale of the symbol, no more than was sold short
+            select upper(a.symbol) as symbol, sum(a.qty) as qty, sum(a.qty * a.price) / nullif(sum(a.qty), 0) as price_avg
+            from alpaca_activity a
+            where a.side = 'buy'
+                and a.dtg >= (date_trunc('day', now() at time zone 'America/New_York') at time zone 'America/New_York')
+                and exists(select 1 from alpaca_activity x where upper(x.symbol) = upper(a.symbol) and x.side = 'sell_short'
+                    and x.dtg < a.dtg and x.dtg >= now() - interval '5 days')
+            group by upper(a.symbol)
+        )
+        select
+            coalesce((select cash from alpaca_account order by dtg desc limit 1), 0.0)::numeric as cash
+            , coalesce((select sum(abs(market_value)) from alpaca_position), 0.0)::numeric as position_market_value
+            , (select count(*) from alpaca_position where qty <> 0.0) as open_positions
+            , coalesce((select sum(abs(market_value)) from alpaca_position where upper(symbol) = upper($1)), 0.0)::numeric as symbol_market_value
+            , exists(select 1 from alpaca_position where upper(symbol) = upper($1) and qty <> 0.0) as symbol_has_position
+            , coalesce((select sum(qty) from alpaca_position where upper(symbol) = upper($1)), 0.0)::numeric as symbol_qty
+            , coalesce((select price from trade_alp_latest where upper(symbol) = upper($1)), 0.0)::numeric as price_last
+            , (coalesce((select sum(s.proceeds - s.qty * coalesce(b.price_avg, s.proceeds / nullif(s.qty, 0))) from sells s left join buys b on s.symbol = b.symbol), 0.0)
+                + coalesce((select sum(least(c.qty, s.qty) * (s.price_avg - c.price_avg)) from covers c join shorts s on c.symbol = s.symbol), 0.0))::numeric as realized_pl_today
+            , coalesce((select engaged from t_kill_switch order by dtg desc, id desc limit 1), false) as kill_switch_engaged
+    "#).bind(symbol).fetch_one(pool).await {
+        Ok(snapshot) => Ok(snapshot),
+        Err(e) => {
+            tracing::error!("[risk_snapshot_get] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// record an order the risk manager refused
+async fn risk_event_save(event: &RiskEvent, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
+    let result = sqlx::query(r#"
+        insert into risk_event(dtg, symbol, side, qty, notional, reason, detail)
+        values ($1, $2, $3, $4, $5, $6, $7)
+    "#)
+        .bind(event.dtg)
+        .bind(event.symbol.to_lowercase())
+        .bind(event.side.to_string())
+        .bind(&event.qty)
+        .bind(&event.notional)
+        .bind(event.rejection.code())
+        .bind(event.rejection.to_string())
+        .execute(pool).await;
+
+    if let Err(e) = &result {
+        tracing::error!("[risk_event_save] sqlx error: {:?}", e);
+    }
+    result
+}
+
+/// latest kill switch state; cleared if it has never been set
+async fn kill_switch_get(pool: &PgPool) -> Result<KillSwitch, TradeWebError> {
+    match sqlx::query_as::<_, KillSwitch>(r#"
+        select dtg, engaged, flatten, reason, changed_by
+        from t_kill_switch
+        order by dtg desc, id desc
+        limit 1
+    "#).fetch_optional(pool).await {
+        Ok(state) => Ok(state.unwrap_or_else(KillSwitch::cleared)),
+        Err(e) => {
+            tracing::error!("[kill_switch_get] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// append a new kill switch state and return it as stored
+async fn kill_switch_set(state: &KillSwitch, pool: &PgPool) -> Result<KillSwitch, TradeWebError> {
+    match sqlx::query_as::<_, KillSwitch>(r#"
+        insert into t_kill_switch(dtg, engaged, flatten, reason, changed_by)
+        values ($1, $2, $3, $4, $5)
+        returning dtg, engaged, flatten, reason, changed_by
+    "#)
+        .bind(state.dtg)
+        .bind(state.engaged)
+        .bind(state.flatten)
+        .bind(&state.reason)
+        .bind(&state.changed_by)
+        .fetch_one(pool).await {
+        Ok(state) => Ok(state),
+        Err(e) => {
+            tracing::error!("[kill_switch_set] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// DELETE https://paper-api.alpaca.markets/v2/orders
+///
+/// Cancels every open order. Alpaca answers 207 Multi-Status with one entry per order.
+async fn rest_cancel_all_orders(settings: &Settings) -> Result<usize, TradeWebError> {
+    let mut headers = HeaderMap::new();
+    let api_key = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
+    headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
+
+    let client = reqwest::Client::new();
+    rest_delete_multi_status(client.delete("https://paper-api.alpaca.markets/v2/orders").headers(headers), "rest_cancel_all_orders").await
+}
+
+/// DELETE https://paper-api.alpaca.markets/v2/positions?cancel_orders=true
+///
+/// Liquidates every position at market.
+async fn rest_close_all_positions(settings: &Settings) -> Result<usize, TradeWebError> {
+    let mut headers = HeaderMap::new();
+    let api_key = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
+    headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
+
+    let client = reqwest::Client::new();
+    rest_delete_multi_status(client.delete("https://paper-api.alpaca.markets/v2/positions?cancel_orders=true").headers(headers), "rest_close_all_positions").await
+}
+
+/// send a bulk DELETE and count the entries in the 207 Multi-Status body
+async fn rest_delete_multi_status(request: reqwest::RequestBuilder, caller: &str) -> Result<usize, TradeWebError> {
+    match request.send().await {
+        Ok(resp) => {
+            let status = resp.status();
+            tracing::info!("[{}] response code: {:?}", caller, &status);
+            if !status.is_success() {
+                tracing::error!("[{}] body: {:?}", caller, &resp.text().await);
+                return Err(TradeWebError::ReqwestError);
+            }
+            match resp.json::<Vec<serde_json::Value>>().await {
+                Ok(entries) => Ok(entries.len()),
+                Err(e) => {
+                    tracing::error!("[{}] json error: {:?}", caller, &e);
+                    Err(TradeWebError::JsonError)
+                }
+            }
+        },
+        Err(e) => {
+            tracing::error!("[{}] reqwest error: {:?}", caller, &e);
+            Err(TradeWebError::ReqwestError)
+        }
+    }
+}
+
+/// DELETE https://paper-api.alpaca.markets/v2/orders/{order_id}
+///
+/// 204 on success; 422 if the order is no longer cancelable (already filled or canceled)
+async fn rest_cancel_order(order_id: &str, settings: &Settings) -> Result<(), TradeWebError> {
+    let mut headers = HeaderMap::new();
+    let api_key = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
+    headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
+
+    let client = reqwest::Client::new();
+    let url = format!("https://paper-api.alpaca.markets/v2/orders/{}", order_id);
+    match client.delete(url).headers(headers).send().await {
+        Ok(resp) => {
+            let status = resp.status();
+            tracing::info!("[rest_cancel_order] {} response code: {:?}", order_id, &status);
+            match status {
+                reqwest::StatusCode::NO_CONTENT | reqwest::StatusCode::OK => Ok(()),
+                reqwest::StatusCode::UNPROCESSABLE_ENTITY => {
+                    tracing::error!("[rest_cancel_order:422] body: {:?}", &resp.text().await);
+                    Err(TradeWebError::Alpaca422)
+                },
+                _ => {
+                    tracing::error!("[rest_cancel_order] body: {:?}", &resp.text().await);
+                    Err(TradeWebError::ReqwestError)
+                }
+            }
+        },
+        Err(e) => {
+            tracing::error!("[rest_cancel_order] reqwest error: {:?}", &e);
+            Err(TradeWebError::ReqwestError)
+        }
+    }
+}
+
+/// PATCH https://paper-api.alpaca.markets/v2/orders/{order_id}
+///
+/// Returns the replacement order, which has a new id.
+async fn rest_replace_order(order_id: &str, json_replace: &JsonReplace, settings: &Settings) -> Result<Order, TradeWebError> {
+    let mut headers = HeaderMap::new();
+    let api_key = settings.alpaca_paper_id.expose().to_string();
+    let api_secret = settings.alpaca_paper_secret.expose().to_string();
+    headers.insert("APCA-API-KEY-ID", api_key.parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", api_secret.parse().unwrap());
+
+    let client = reqwest::Client::new();
+    let url = format!("https://paper-api.alpaca.markets/v2/orders/{}", order_id);
+    match client.patch(url).headers(headers).json(json_replace).send().await {
+        Ok(resp) => {
+            let status = resp.status();
+            tracing::info!("[rest_replace_order] {} response code: {:?}", order_id, &status);
+            match status {
+                reqwest::StatusCode::OK => match resp.json::<Order>().await {
+                    Ok(order) => Ok(order),
+                    Err(e) => {
+                        tracing::error!("[rest_replace_order] json error: {:?}", &e);
+                        Err(TradeWebError::JsonError)
+                    }
+                },
+                reqwest::StatusCode::UNPROCESSABLE_ENTITY => {
+                    tracing::error!("[rest_replace_order:422] body: {:?}", &resp.text().await);
+                    Err(TradeWebError::Alpaca422)
+                },
+                _ => {
+                    tracing::error!("[rest_replace_order] body: {:?}", &resp.text().await);
+                    Err(TradeWebError::ReqwestError)
+                }
+            }
+        },
+        Err(e) => {
+            tracing::error!("[rest_replace_order] reqwest error: {:?}", &e);
+            Err(TradeWebError::ReqwestError)
+        }
+    }
+}
+
+async fn order_change_save(change: &OrderChange, pool: &PgPool) -> Result<(), TradeWebError> {
+    match sqlx::query(r#"
+        insert into log_order_change(dtg, action, order_id, client_order_id, symbol, side, qty, limit_price_old, limit_price_new, order_id_new, reason)
+        values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+    "#)
+        .bind(change.dtg)
+        .bind(change.action.to_string())
+        .bind(&change.order_id)
+        .bind(&change.client_order_id)
+        .bind(&change.symbol)
+        .bind(change.side.as_ref().map(|s| s.to_string()))
+        .bind(&change.qty)
+        .bind(&change.limit_price_old)
+        .bind(&change.limit_price_new)
+        .bind(&change.order_id_new)
+        .bind(&change.reason)
+        .execute(pool).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("[order_change_save] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn order_policy_get(pool: &PgPool) -> Result<OrderPolicy, TradeWebError> {
+    match sqlx::query_as::<_, OrderPolicy>(r#"
+        select dtg, stale_limit_enabled, stale_limit_age_seconds, stale_limit_action
+            , bracket_enabled, bracket_take_profit_per_cent::numeric, bracket_stop_loss_per_cent::numeric
+            , sizing_mode
+            , short_enabled
+            , short_require_easy_to_borrow
+            , age_exit_enabled, eod_flatten_enabled, eod_flatten_minutes
+            , eod_flatten_max_concession_per_cent::numeric, eod_flatten_market_minutes
+        from t_order_policy
+        order by dtg desc
+        limit 1
+    "#).fetch_one(pool).await {
+        Ok(policy) => Ok(policy),
+        Err(e) => {
+            tracing::error!("[order_policy_get] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// latest trade price for a symbol
+async fn price_last_get(symbol: &str, pool: &PgPool) -> Result<BigDecimal, TradeWebError> {
+    match sqlx::query_scalar::<_, BigDecimal>(r#"
+        select price::numeric from trade_alp_latest where upper(symbol) = upper($1)
+    "#).bind(symbol).fetch_one(pool).await {
+        Ok(price) => Ok(price),
+        Err(e) => {
+            tracing::error!("[price_last_get] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// GET /v2/calendar for start..=end (New York dates)
+async fn calendar_get_remote(start: NaiveDate, end: NaiveDate, settings: &Settings) -> Result<Vec<CalendarDay>, reqwest::Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert("APCA-API-KEY-ID", settings.alpaca_paper_id.expose().parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", settings.alpaca_paper_secret.expose().parse().unwrap());
+
+    let client = reqwest::Client::new();
+    client
+        .get(format!("https://paper-api.alpaca.markets/v2/calendar?start={}&end={}", start.format("%Y-%m-%d"), end.format("%Y-%m-%d")))
+        .headers(headers)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+/// GET /v2/clock
+async fn clock_get_remote(settings: &Settings) -> Result<MarketClock, reqwest::Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert("APCA-API-KEY-ID", settings.alpaca_paper_id.expose().parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", settings.alpaca_paper_secret.expose().parse().unwrap());
+
+    let client = reqwest::Client::new();
+    client
+        .get("https://paper-api.alpaca.markets/v2/clock")
+        .headers(headers)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+}
+
+/// upsert each trading day; a day that turns into a holiday or early close after the fact gets the new times
+async fn calendar_save(days: &[CalendarDay], pool: &PgPool) -> Result<(), TradeWebError> {
+    for day in days {
+        if let Err(e) = sqlx::query(r#"
+            insert into alpaca_calendar(date, open, close, session_open, session_close, dtg)
+            values ($1, $2, $3, $4, $5, now())
+            on conflict (date) do update
+                set open = $2, close = $3, session_open = $4, session_close = $5, dtg = now()
+        "#)
+            .bind(day.date)
+            .bind(day.open)
+            .bind(day.close)
+            .bind(day.session_open)
+            .bind(day.session_close)
+            .execute(pool).await {
+            tracing::error!("[calendar_save] sqlx error: {:?}", &e);
+            return Err(TradeWebError::SqlxError);
+        }
+    }
+    Ok(())
+}
+
+async fn calendar_get(start: NaiveDate, end: NaiveDate, pool: &PgPool) -> Result<Vec<CalendarDay>, TradeWebError> {
+    match sqlx::query_as::<_, CalendarDay>(r#"
+        select date, open, close, session_open, session_close
+        from alpaca_calendar
+        where date between $1 and $2
+        order by date
+    "#).bind(start).bind(end).fetch_all(pool).await {
+        Ok(days) => Ok(days),
+        Err(e) => {
+            tracing::error!("[calendar_get] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// only the latest clock is kept
+async fn clock_save(clock: &MarketClock, pool: &PgPool) -> Result<(), TradeWebError> {
+    let result = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query(r#"delete from alpaca_clock"#).execute(&mut tx).await?;
+        sqlx::query(r#"
+            insert into alpaca_clock(dtg, timestamp, is_open, next_open, next_close)
+            values (now(), $1, $2, $3, $4)
+        "#)
+            .bind(clock.timestamp)
+            .bind(clock.is_open)
+            .bind(clock.next_open)
+            .bind(clock.next_close)
+            .execute(&mut tx).await?;
+        tx.commit().await
+    }.await;
+
+    result.map_err(|e| {
+        tracing::error!("[clock_save] sqlx error: {:?}", &e);
+        TradeWebError::SqlxError
+    })
+}
+
+async fn clock_get(pool: &PgPool) -> Result<MarketClock, TradeWebError> {
+    match sqlx::query_as::<_, MarketClock>(r#"
+        select timestamp, is_open, next_open, next_close
+        from alpaca_clock
+        order by dtg desc
+        limit 1
+    "#).fetch_one(pool).await {
+        Ok(clock) => Ok(clock),
+        Err(e) => {
+            tracing::error!("[clock_get] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// trades and finished minute bars, merged oldest first; bars are stamped with the end of their minute so
+/// a replay never sees a bar before it could have closed
+async fn backtest_history(source: &TradeSource, symbols: &[String], start: DateTime<Utc>, end: DateTime<Utc>, pool: &PgPool) -> Result<Vec<HistoryRow>, TradeWebError> {
+    let sql = format!(r#"
+        select kind, dtg, symbol, price_open, price_high, price_low, price_close, volume
+        from (
+            select 'trade' as kind, dtg at time zone 'utc' as dtg, upper(symbol) as symbol
+                , price as price_open, price as price_high, price as price_low, price as price_close
+                , coalesce({volume}, 0)::numeric as volume
+            from {table}
+            where dtg >= $1 and dtg < $2 and upper(symbol) = any($3)
+            union all
+            select 'bar', (dtg + interval '1 minute') at time zone 'utc', upper(symbol)
+                , price_open, price_high, price_low, price_close
+                , coalesce(volume, 0)::numeric
+            from bar_minute
+            where dtg + interval '1 minute' >= $1 and dtg + interval '1 minute' < $2 and upper(symbol) = any($3)
+        ) h
+        order by dtg, kind
+    "#, table = source.table(), volume = source.volume_column());
+
+    match sqlx::query_as::<_, HistoryRow>(&sql)
+        .bind(start.naive_utc())
+        .bind(end.naive_utc())
+        .bind(symbols)
+        .fetch_all(pool).await {
+        Ok(rows) => Ok(rows),
+        Err(e) => {
+            tracing::error!("[backtest_history] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn backtest_save(report: &BacktestReport, pool: &PgPool) -> Result<i64, TradeWebError> {
+    let result: Result<i64, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let run = &report.run;
+        let run_id: i64 = sqlx::query_scalar(r#"
+            insert into backtest_run(dtg, name, dtg_start, dtg_end, symbols, source, slippage_bps, latency_ms, cash_start
+                , equity_end, pnl, max_drawdown, max_drawdown_per_cent, trade_count, win_rate, settings)
+            values (now(), $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+            returning id
+        "#)
+            .bind(&run.name)
+            .bind(run.dtg_start)
+            .bind(run.dtg_end)
+            .bind(&run.symbols)
+            .bind(&run.source)
+            .bind(&run.slippage_bps)
+            .bind(run.latency_ms)
+            .bind(&run.cash_start)
+            .bind(&run.equity_end)
+            .bind(&run.pnl)
+            .bind(&run.max_drawdown)
+            .bind(&run.max_drawdown_per_cent)
+            .bind(run.trade_count)
+            .bind(&run.win_rate)
+            .bind(&run.settings)
+            .fetch_one(&mut tx).await?;
+
+        for trade in report.trades.iter() {
+            sqlx::query(r#"
+                insert into backtest_trade(run_id, symbol, dtg_open, dtg_close, qty, price_open, price_close, pnl)
+                values ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#)
+                .bind(run_id)
+                .bind(&trade.symbol)
+                .bind(trade.dtg_open)
+                .bind(trade.dtg_close)
+                .bind(&trade.qty)
+                .bind(&trade.price_open)
+                .bind(&trade.price_close)
+                .bind(&trade.pnl)
+                .execute(&mut tx).await?;
+        }
+
+        // one row a minute adds up over a long run, so the equity curve goes in as arrays
+        let dtgs: Vec<DateTime<Utc>> = report.equity.iter().map(|p| p.dtg).collect();
+        let equities: Vec<BigDecimal> = report.equity.iter().map(|p| p.equity.clone()).collect();
+        let drawdowns: Vec<BigDecimal> = report.equity.iter().map(|p| p.drawdown.clone()).collect();
+        sqlx::query(r#"
+            insert into backtest_equity(run_id, dtg, equity, drawdown)
+            select $1, dtg, equity, drawdown from unnest($2::timestamptz[], $3::numeric[], $4::numeric[]) as e(dtg, equity, drawdown)
+        "#)
+            .bind(run_id)
+            .bind(dtgs)
+            .bind(equities)
+            .bind(drawdowns)
+            .execute(&mut tx).await?;
+
+        for symbol in report.symbols.iter() {
+            sqlx::query(r#"
+                insert into backtest_symbol(run_id, symbol, trades, wins, pnl)
+                values ($1, $2, $3, $4, $5)
+            "#)
+                .bind(run_id)
+                .bind(&symbol.symbol)
+                .bind(symbol.trades)
+                .bind(symbol.wins)
+                .bind(&symbol.pnl)
+                .execute(&mut tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(run_id)
+    }.await;
+
+    result.map_err(|e| {
+        tracing::error!("[backtest_save] sqlx error: {:?}", &e);
+        TradeWebError::SqlxError
+    })
+}
+
+const BACKTEST_RUN_COLUMNS: &str = r#"
+    id, dtg, name, dtg_start, dtg_end, symbols, source, slippage_bps, latency_ms, cash_start
+    , equity_end, pnl, max_drawdown, max_drawdown_per_cent, trade_count, win_rate, settings
+"#;
+
+async fn backtest_run_list(pool: &PgPool) -> Result<Vec<BacktestRun>, TradeWebError> {
+    let sql = format!("select {} from backtest_run order by dtg desc", BACKTEST_RUN_COLUMNS);
+    match sqlx::query_as::<_, BacktestRun>(&sql).fetch_all(pool).await {
+        Ok(runs) => Ok(runs),
+        Err(e) => {
+            tracing::error!("[backtest_run_list] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn backtest_load(run_id: i64, pool: &PgPool) -> Result<BacktestReport, TradeWebError> {
+    let result: Result<BacktestReport, sqlx::Error> = async {
+        let sql = format!("select {} from backtest_run where id = $1", BACKTEST_RUN_COLUMNS);
+        let run = sqlx::query_as::<_, BacktestRun>(&sql).bind(run_id).fetch_one(pool).await?;
+        let trades = sqlx::query_as::<_, BacktestTrade>(r#"
+            select symbol, dtg_open, dtg_close, qty, price_open, price_close, pnl
+            from backtest_trade where run_id = $1 order by dtg_close
+        "#).bind(run_id).fetch_all(pool).await?;
+        let equity = sqlx::query_as::<_, EquityPoint>(r#"
+            select dtg, equity, drawdown from backtest_equity where run_id = $1 order by dtg
+        "#).bind(run_id).fetch_all(pool).await?;
+        let symbols = sqlx::query_as::<_, SymbolResult>(r#"
+            select symbol, trades, wins, pnl from backtest_symbol where run_id = $1 order by symbol
+        "#).bind(run_id).fetch_all(pool).await?;
+        Ok(BacktestReport { run, trades, equity, symbols })
+    }.await;
+
+    result.map_err(|e| {
+        tracing::error!("[backtest_load] sqlx error: {:?}", &e);
+        TradeWebError::SqlxError
+    })
+}
+
+const SETTINGS_PROFILE_COLUMNS: &str = r#"
+    id, name, dtg, source, notes, trade_size, trade_enable_buy, trade_ema_small_size, trade_ema_large_size
+    , trade_sell_high_per_cent_multiplier, trade_sell_high_upper_limit_cents, max_position_age_minute, upgrade_min_profit
+    , upgrade_sell_elapsed_minutes_min, upgrade_posn_max_elapsed_minutes, upgrade_posn_loss_allowed_dollars
+    , acct_max_position_market_value, acct_min_cash_dollars
+"#;
+
+async fn settings_profile_list(pool: &PgPool) -> Result<Vec<SettingsProfile>, TradeWebError> {
+    let sql = format!("select {} from t_trade_settings_profile order by name", SETTINGS_PROFILE_COLUMNS);
+    match sqlx::query_as::<_, SettingsProfile>(&sql).fetch_all(pool).await {
+        Ok(profiles) => Ok(profiles),
+        Err(e) => {
+            tracing::error!("[settings_profile_list] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn settings_profile_load(id: i64, pool: &PgPool) -> Result<SettingsProfile, TradeWebError> {
+    let sql = format!("select {} from t_trade_settings_profile where id = $1", SETTINGS_PROFILE_COLUMNS);
+    match sqlx::query_as::<_, SettingsProfile>(&sql).bind(id).fetch_one(pool).await {
+        Ok(profile) => Ok(profile),
+        Err(e) => {
+            tracing::error!("[settings_profile_load] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn settings_profile_save(profile: &SettingsProfile, pool: &PgPool) -> Result<i64, TradeWebError> {
+    let result = sqlx::query_scalar::<_, i64>(r#"
+        insert into t_trade_settings_profile(name, dtg, source, notes, trade_size, trade_enable_buy, trade_ema_small_size
+            , trade_ema_large_size, trade_sell_high_per_cent_multiplier, trade_sell_high_upper_limit_cents, max_position_age_minute
+            , upgrade_min_profit, upgrade_sell_elapsed_minutes_min, upgrade_posn_max_elapsed_minutes, upgrade_posn_loss_allowed_dollars
+            , acct_max_position_market_value, acct_min_cash_dollars)
+        values ($1, now(), $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
+        returning id
+    "#)
+        .bind(&profile.name)
+        .bind(&profile.source)
+        .bind(&profile.notes)
+        .bind(&profile.trade_size)
+        .bind(profile.trade_enable_buy)
+        .bind(profile.trade_ema_small_size)
+        .bind(profile.trade_ema_large_size)
+        .bind(&profile.trade_sell_high_per_cent_multiplier)
+        .bind(&profile.trade_sell_high_upper_limit_cents)
+        .bind(&profile.max_position_age_minute)
+        .bind(&profile.upgrade_min_profit)
+        .bind(&profile.upgrade_sell_elapsed_minutes_min)
+        .bind(&profile.upgrade_posn_max_elapsed_minutes)
+        .bind(&profile.upgrade_posn_loss_allowed_dollars)
+        .bind(&profile.acct_max_position_market_value)
+        .bind(&profile.acct_min_cash_dollars)
+        .fetch_one(pool).await;
+    match result {
+        Ok(id) => Ok(id),
+        Err(e) => {
+            tracing::error!("[settings_profile_save] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn settings_profile_update(profile: &SettingsProfile, pool: &PgPool) -> Result<(), TradeWebError> {
+    let result = sqlx::query(r#"
+        update t_trade_settings_profile set name = $2, dtg = now(), source = $3, notes = $4, trade_size = $5, trade_enable_buy = $6
+            , trade_ema_small_size = $7, trade_ema_large_size = $8, trade_sell_high_per_cent_multiplier = $9
+            , trade_sell_high_upper_limit_cents = $10, max_position_age_minute = $11, upgrade_min_profit = $12
+            , upgrade_sell_elapsed_minutes_min = $13, upgrade_posn_max_elapsed_minutes = $14, upgrade_posn_loss_allowed_dollars = $15
+            , acct_max_position_market_value = $16, acct_min_cash_dollars = $17
+        where id = $1
+    "#)
+        .bind(profile.id)
+        .bind(&profile.name)
+        .bind(&profile.source)
+        .bind(&profile.notes)
+        .bind(&profile.trade_size)
+        .bind(profile.trade_enable_buy)
+        .bind(profile.trade_ema_small_size)
+        .bind(profile.trade_ema_large_size)
+        .bind(&profile.trade_sell_high_per_cent_multiplier)
+        .bind(&profile.trade_sell_high_upper_limit_cents)
+        .bind(&profile.max_position_age_minute)
+        .bind(&profile.upgrade_min_profit)
+        .bind(&profile.upgrade_sell_elapsed_minutes_min)
+        .bind(&profile.upgrade_posn_max_elapsed_minutes)
+        .bind(&profile.upgrade_posn_loss_allowed_dollars)
+        .bind(&profile.acct_max_position_market_value)
+        .bind(&profile.acct_min_cash_dollars)
+        .execute(pool).await;
+    match result {
+        Ok(done) if done.rows_affected() == 1 => Ok(()),
+        Ok(_) => Err(TradeWebError::SqlxError),
+        Err(e) => {
+            tracing::error!("[settings_profile_update] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn settings_profile_delete(id: i64, pool: &PgPool) -> Result<(), TradeWebError> {
+    match sqlx::query("delete from t_trade_settings_profile where id = $1").bind(id).execute(pool).await {
+        Ok(done) if done.rows_affected() == 1 => Ok(()),
+        Ok(_) => Err(TradeWebError::DeleteFailed),
+        Err(e) => {
+            tracing::error!("[settings_profile_delete] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// copy the profile's values over the current settings row and log the switch and the change, in one transaction
+async fn settings_profile_activate(name: &str, origin: &ChangeOrigin, pool: &PgPool) -> Result<Settings, TradeWebError> {
+    let result: Result<Option<Settings>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let old = sqlx::query_as::<_, Settings>(&format!("{} for update", SETTINGS_NO_SECRET_SELECT)).fetch_one(&mut tx).await?;
+        let updated = sqlx::query(r#"
+            update t_settings s set dtg = now(), trade_size = p.trade_size, trade_enable_buy = p.trade_enable_buy
+                , trade_ema_small_size = p.trade_ema_small_size, trade_ema_large_size = p.trade_ema_large_size
+                , trade_sell_high_per_cent_multiplier = p.trade_sell_high_per_cent_multiplier
+                , trade_sell_high_upper_limit_cents = p.trade_sell_high_upper_limit_cents
+                , max_position_age_minute = p.max_position_age_minute, upgrade_min_profit = p.upgrade_min_profit
+                , upgrade_sell_elapsed_minutes_min = p.upgrade_sell_elapsed_minutes_min
+                , upgrade_posn_max_elapsed_minutes = p.upgrade_posn_max_elapsed_minutes
+                , upgrade_posn_loss_allowed_dollars = p.upgrade_posn_loss_allowed_dollars
+                , acct_max_position_market_value = p.acct_max_position_market_value, acct_min_cash_dollars = p.acct_min_cash_dollars
+            from t_trade_settings_profile p
+            where p.name = $1 and s.dtg = $2
+        "#).bind(name).bind(old.dtg).execute(&mut tx).await?.rows_affected();
+        if updated != 1 {
+            return Ok(None);
+        }
+        sqlx::query("insert into t_trade_settings_profile_activation(dtg, profile_name, changed_by) values (now(), $1, $2)")
+            .bind(name)
+            .bind(&origin.changed_by)
+            .execute(&mut tx).await?;
+        let new = sqlx::query_as::<_, Settings>(SETTINGS_NO_SECRET_SELECT).fetch_one(&mut tx).await?;
+        settings_history_insert(&mut tx, origin, &old, &new).await?;
+        tx.commit().await?;
+        Ok(Some(new))
+    }.await;
+    match result {
+        Ok(Some(settings)) => Ok(settings),
+        Ok(None) => {
+            tracing::error!("[settings_profile_activate] no profile named {}", name);
+            Err(TradeWebError::SqlxError)
+        },
+        Err(e) => {
+            tracing::error!("[settings_profile_activate] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn settings_profile_activations(limit: i64, pool: &PgPool) -> Result<Vec<ProfileActivation>, TradeWebError> {
+    let result = sqlx::query_as::<_, ProfileActivation>(r#"
+        select dtg, profile_name, changed_by from t_trade_settings_profile_activation order by dtg desc limit $1
+    "#).bind(limit).fetch_all(pool).await;
+    match result {
+        Ok(activations) => Ok(activations),
+        Err(e) => {
+            tracing::error!("[settings_profile_activations] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn profile_schedule_list(pool: &PgPool) -> Result<Vec<ProfileSchedule>, TradeWebError> {
+    let result = sqlx::query_as::<_, ProfileSchedule>(r#"
+        select id, enabled, session_time, weekdays, profile_name, dtg_last_applied from t_trade_settings_schedule order by session_time, id
+    "#).fetch_all(pool).await;
+    match result {
+        Ok(schedules) => Ok(schedules),
+        Err(e) => {
+            tracing::error!("[profile_schedule_list] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn profile_schedule_add(session_time: &str, weekdays: &str, profile_name: &str, pool: &PgPool) -> Result<i64, TradeWebError> {
+    let result = sqlx::query_scalar::<_, i64>(r#"
+        insert into t_trade_settings_schedule(session_time, weekdays, profile_name) values ($1, $2, $3) returning id
+    "#)
+        .bind(session_time)
+        .bind(weekdays)
+        .bind(profile_name)
+        .fetch_one(pool).await;
+    match result {
+        Ok(id) => Ok(id),
+        Err(e) => {
+            tracing::error!("[profile_schedule_add] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn profile_schedule_delete(id: i64, pool: &PgPool) -> Result<(), TradeWebError> {
+    match sqlx::query("delete from t_trade_settings_schedule where id = $1").bind(id).execute(pool).await {
+        Ok(done) if done.rows_affected() == 1 => Ok(()),
+        Ok(_) => Err(TradeWebError::DeleteFailed),
+        Err(e) => {
+            tracing::error!("[profile_schedule_delete] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn profile_schedule_applied(id: i64, dtg: DateTime<Utc>, pool: &PgPool) -> Result<(), TradeWebError> {
+    match sqlx::query("update t_trade_settings_schedule set dtg_last_applied = $2 where id = $1").bind(id).bind(dtg).execute(pool).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("[profile_schedule_applied] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// the latest settings row with blank credentials
+const SETTINGS_NO_SECRET_SELECT: &str = r#"
+    select
+        dtg, '' as alpaca_paper_id, '' as alpaca_paper_secret, '' as alpaca_live_id, '' as alpaca_live_secret, trade_size, trade_enable_buy
+        , trade_ema_small_size, trade_ema_large_size, trade_sell_high_per_cent_multiplier, trade_sell_high_upper_limit_cents, '' as finnhub_key
+        , coalesce(account_start_value,0.0) as account_start_value
+        , coalesce(max_position_age_minute,0.0) as max_position_age_minute
+        , coalesce(upgrade_min_profit,0.0) as upgrade_min_profit
+        , coalesce(upgrade_sell_elapsed_minutes_min,60.0) as upgrade_sell_elapsed_minutes_min
+        , coalesce(upgrade_posn_max_elapsed_minutes,60.0) as upgrade_posn_max_elapsed_minutes
+        , coalesce(upgrade_posn_loss_allowed_dollars,10.0) as upgrade_posn_loss_allowed_dollars
+        , coalesce(acct_max_position_market_value,10.0) as acct_max_position_market_value
+        , coalesce(acct_min_cash_dollars,10.0) as acct_min_cash_dollars
+    from t_settings
+    order by dtg desc
+    limit 1
+"#;
+
+/// write the editable fields if the latest row's dtg is still expected_dtg, and record the change
+async fn settings_update(settings: &Settings, expected_dtg: DateTime<Utc>, origin: &ChangeOrigin, pool: &PgPool) -> Result<Settings, TradeWebError> {
+    let result: Result<Option<Settings>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let old = sqlx::query_as::<_, Settings>(&format!("{} for update", SETTINGS_NO_SECRET_SELECT)).fetch_one(&mut tx).await?;
+        if old.dtg != expected_dtg {
+            return Ok(None);
+        }
+        sqlx::query(r#"
+            update t_settings set dtg = now(), trade_size = $2, trade_enable_buy = $3, trade_ema_small_size = $4, trade_ema_large_size = $5
+                , trade_sell_high_per_cent_multiplier = $6, trade_sell_high_upper_limit_cents = $7, account_start_value = $8
+                , max_position_age_minute = $9, upgrade_min_profit = $10, upgrade_sell_elapsed_minutes_min = $11
+                , upgrade_posn_max_elapsed_minutes = $12, upgrade_posn_loss_allowed_dollars = $13, acct_max_position_market_value = $14
+                , acct_min_cash_dollars = $15
+            where dtg = $1
+        "#)
+            .bind(expected_dtg)
+            .bind(&settings.trade_size)
+            .bind(settings.trade_enable_buy)
+            .bind(settings.trade_ema_small_size)
+            .bind(settings.trade_ema_large_size)
+            .bind(&settings.trade_sell_high_per_cent_multiplier)
+            .bind(&settings.trade_sell_high_upper_limit_cents)
+            .bind(&settings.account_start_value)
+            .bind(&settings.max_position_age_minute)
+            .bind(&settings.upgrade_min_profit)
+            .bind(&settings.upgrade_sell_elapsed_minutes_min)
+            .bind(&settings.upgrade_posn_max_elapsed_minutes)
+            .bind(&settings.upgrade_posn_loss_allowed_dollars)
+            .bind(&settings.acct_max_position_market_value)
+            .bind(&settings.acct_min_cash_dollars)
+            .execute(&mut tx).await?;
+        let new = sqlx::query_as::<_, Settings>(SETTINGS_NO_SECRET_SELECT).fetch_one(&mut tx).await?;
+        settings_history_insert(&mut tx, origin, &old, &new).await?;
+        tx.commit().await?;
+        Ok(Some(new))
+    }.await;
+    match result {
+        Ok(Some(settings)) => Ok(settings),
+        Ok(None) => Err(TradeWebError::Conflict),
+        Err(e) => {
+            tracing::error!("[settings_update] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// one t_settings_history row, inside the transaction that made the change
+async fn settings_history_insert(tx: &mut sqlx::Transaction<'_, sqlx::Postgres>, origin: &ChangeOrigin, old: &Settings, new: &Settings) -> Result<(), sqlx::Error> {
+    sqlx::query("insert into t_settings_history(dtg, changed_by, source, note, old_values, new_values) values (now(), $1, $2, $3, $4, $5)")
+        .bind(&origin.changed_by)
+        .bind(&origin.source)
+        .bind(&origin.note)
+        .bind(old.to_json_no_secret().to_string())
+        .bind(new.to_json_no_secret().to_string())
+        .execute(tx).await?;
+    Ok(())
+}
+
+async fn settings_history_load(id: i64, pool: &PgPool) -> Result<SettingsChange, TradeWebError> {
+    let result = sqlx::query_as::<_, SettingsChange>(r#"
+        select id, dtg, changed_by, source, note, old_values, new_values from t_settings_history where id = $1
+    "#).bind(id).fetch_one(pool).await;
+    match result {
+        Ok(change) => Ok(change),
+        Err(e) => {
+            tracing::error!("[settings_history_load] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+async fn settings_history_list(limit: i64, pool: &PgPool) -> Result<Vec<SettingsChange>, TradeWebError> {
+    let result = sqlx::query_as::<_, SettingsChange>(r#"
+        select id, dtg, changed_by, source, note, old_values, new_values from t_settings_history order by id desc limit $1
+    "#).bind(limit).fetch_all(pool).await;
+    match result {
+        Ok(changes) => Ok(changes),
+        Err(e) => {
+            tracing::error!("[settings_history_list] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// the latest row's credential columns as stored, in SECRET_FIELDS order
+async fn credentials_stored(pool: &PgPool) -> Result<Vec<(String, String)>, TradeWebError> {
+    let result = sqlx::query_as::<_, (String, String, String, String, String)>(r#"
+        select alpaca_paper_id, alpaca_paper_secret, alpaca_live_id, alpaca_live_secret, finnhub_key from t_settings order by dtg desc limit 1
+    "#).fetch_one(pool).await;
+    match result {
+        Ok((a, b, c, d, e)) => Ok(SECRET_FIELDS.iter().map(|f| f.to_string()).zip([a, b, c, d, e]).collect()),
+        Err(e) => {
+            tracing::error!("[credentials_stored] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// Write already-sealed credentials into the latest row and record that they changed (the history holds no
+/// credential values, so old and new only differ by dtg).
+async fn credentials_set(values: &[(String, String)], origin: &ChangeOrigin, pool: &PgPool) -> Result<(), TradeWebError> {
+    let result: Result<(), sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let old = sqlx::query_as::<_, Settings>(&format!("{} for update", SETTINGS_NO_SECRET_SELECT)).fetch_one(&mut tx).await?;
+        // field names come from SECRET_FIELDS, never from the caller
+        for field in SECRET_FIELDS {
+            if let Some((_, value)) = values.iter().find(|(f, _)| f == field) {
+                sqlx::query(&format!("update t_settings set {} = $1 where dtg = $2", field)).bind(value).bind(old.dtg).execute(&mut tx).await?;
+            }
+        }
+        sqlx::query("update t_settings set dtg = now() where dtg = $1").bind(old.dtg).execute(&mut tx).await?;
+        let new = sqlx::query_as::<_, Settings>(SETTINGS_NO_SECRET_SELECT).fetch_one(&mut tx).await?;
+        settings_history_insert(&mut tx, origin, &old, &new).await?;
+        tx.commit().await?;
+        Ok(())
+    }.await;
+    result.map_err(|e| {
+        tracing::error!("[credentials_set] sqlx error: {:?}", &e);
+        TradeWebError::SqlxError
+    })
+}
+
+/// Re-seal the credentials in every t_settings row under new (see MasterKey::reseal), all or nothing.
+/// Returns how many values changed.
+async fn credentials_reseal(old: Option<&MasterKey>, new: &MasterKey, pool: &PgPool) -> Result<u64, TradeWebError> {
+    let result: Result<Result<u64, TradeWebError>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        let rows = sqlx::query_as::<_, (DateTime<Utc>, String, String, String, String, String)>(r#"
+            select dtg, alpaca_paper_id, alpaca_paper_secret, alpaca_live_id, alpaca_live_secret, finnhub_key from t_settings for update
+        "#).fetch_all(&mut tx).await?;
+        let mut changed = 0;
+        for (dtg, a, b, c, d, e) in rows {
+            for (field, stored) in SECRET_FIELDS.iter().zip([a, b, c, d, e]) {
+                match MasterKey::reseal(&stored, old, new) {
+                    Ok(Some(sealed)) => {
+                        sqlx::query(&format!("update t_settings set {} = $1 where dtg = $2", field)).bind(sealed).bind(dtg).execute(&mut tx).await?;
+                        changed += 1;
+                    },
+                    Ok(None) => {},
+                    // dropping tx rolls back
+                    Err(e) => return Ok(Err(TradeWebError::Secret(e))),
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(Ok(changed))
+    }.await;
+    result.unwrap_or_else(|e| {
+        tracing::error!("[credentials_reseal] sqlx error: {:?}", &e);
+        Err(TradeWebError::SqlxError)
+    })
+}
+
+async fn user_add(username: &str, password_hash: &str, pool: &PgPool) -> Result<Uuid, TradeWebError> {
+    sqlx::query_scalar::<_, Uuid>("insert into users (user_id, username, password) values (gen_random_uuid(), $1, $2) returning user_id")
+        .bind(username)
+        .bind(password_hash)
+        .fetch_one(pool).await
+        .map_err(|e| {
+            tracing::error!("[user_add] sqlx error: {:?}", &e);
+            TradeWebError::SqlxError
+        })
+}
+
+async fn user_set_password(username: &str, password_hash: &str, pool: &PgPool) -> Result<(), TradeWebError> {
+    match sqlx::query("update users set password = $2 where username = $1").bind(username).bind(password_hash).execute(pool).await {
+        Ok(result) if result.rows_affected() == 0 => Err(TradeWebError::NotFound),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("[user_set_password] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// the ping columns are UTC without a time zone
+async fn feed_heartbeat(pool: &PgPool) -> Result<FeedHeartbeat, TradeWebError> {
+    let result = sqlx::query_as::<_, (Option<DateTime<Utc>>, Option<DateTime<Utc>>)>(r#"
+        select (select max(ping) from ping_alpaca) at time zone 'utc', (select max(ping) from ping_finnhub) at time zone 'utc'
+    "#).fetch_one(pool).await;
+    match result {
+        Ok((alpaca, finnhub)) => Ok(FeedHeartbeat { alpaca, finnhub }),
+        Err(e) => {
+            tracing::error!("[feed_heartbeat] sqlx error: {:?}", &e);
+            Err(TradeWebError::SqlxError)
+        }
+    }
+}
+
+/// GET /v2/stocks/{symbol}/bars, every page of minute bars in [start, end)
+async fn bars_get_remote(symbol: &str, start: DateTime<Utc>, end: DateTime<Utc>, feed: &str, settings: &Settings) -> Result<Vec<MinuteBar>, reqwest::Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert("APCA-API-KEY-ID", settings.alpaca_paper_id.expose().parse().unwrap());
+    headers.insert("APCA-API-SECRET-KEY", settings.alpaca_paper_secret.expose().parse().unwrap());
+
+    let client = reqwest::Client::new();
+    let (start, end) = (start.to_rfc3339(), end.to_rfc3339());
+    let mut bars = vec![];
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut query = vec![("timeframe", "1Min"), ("start", start.as_str()), ("end", end.as_str()), ("feed", feed), ("adjustment", "raw"), ("limit", "10000")];
+        if let Some(token) = &page_token {
+            query.push(("page_token", token.as_str()));
+        }
+        let page: BarsPage = client
+            .get(format!("https://data.alpaca.markets/v2/stocks/{}/bars", symbol.to_uppercase()))
+            .headers(headers.clone())
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        page_token = page.next_page_token.clone();
+        bars.extend(page.minute_bars(symbol));
+        if page_token.is_none() {
+            return Ok(bars);
+        }
+    }
+}
+
+/// swap symbol's bars in [start, end) for these, all or nothing
+async fn bar_minute_replace(symbol: &str, start: DateTime<Utc>, end: DateTime<Utc>, bars: &[MinuteBar], pool: &PgPool) -> Result<u64, TradeWebError> {
+    let result: Result<u64, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
+        sqlx::query("delete from bar_minute where upper(symbol) = $1 and dtg >= $2 and dtg < $3")
+            .bind(symbol.to_uppercase())
+            .bind(start.naive_utc())
+            .bind(end.naive_utc())
+            .execute(&mut tx).await?;
+        for bar in bars {
+            sqlx::query(r#"
+                insert into bar_minute (dtg, symbol, price_open, price_high, price_low, price_close, volume)
+                values ($1, $2, $3, $4, $5, $6, $7)
+            "#)
+                .bind(bar.dtg.naive_utc())
+                .bind(&bar.symbol)
+                .bind(&bar.price_open)
+                .bind(&bar.price_high)
+                .bind(&bar.price_low)
+                .bind(&bar.price_close)
+                .bind(i32::try_from(bar.volume).unwrap_or(i32::MAX))
+                .execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(bars.len() as u64)
+    }.await;
+    result.map_err(|e| {
+        tracing::error!("[bar_minute_replace] sqlx error: {:?}", &e);
+        TradeWebError::SqlxError
+    })
+}
+
+/// Spooled records back into the database in order; how many were done (or dropped as bad rows) before the
+/// database became unreachable again
+async fn spool_replay(records: Vec<SpoolRecord>, pool: &PgPool) -> usize {
+    let mut written = 0;
+    for record in records {
+        let result = match &record {
+            SpoolRecord::Trades(batch) => trade_batch_write(batch, pool).await,
+            SpoolRecord::MinuteBar(bar) => insert_minute_bar(bar, pool).await.map(|_| ()),
+            SpoolRecord::PingAlpaca(ping) => insert_alpaca_ping(ping, pool).await.map(|_| ()),
+            SpoolRecord::PingFinnhub(ping) => insert_finnhub_ping(ping, pool).await.map(|_| ()),
+            SpoolRecord::OrderLogEvent(event) => order_log_event_save(event, pool).await,
+        };
+        match result {
+            Err(e) if is_unavailable(&e) => break,
+            Err(e) => tracing::error!("[spool_replay] dropping {:?}: {:?}", &record, &e),
+            Ok(()) => {},
+        }
+        written += 1;
+    }
+    written
+}
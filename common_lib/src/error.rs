@@ -3,6 +3,8 @@
 //!
 //!
 
+use crate::risk::RiskRejection;
+
 #[derive(Debug, PartialEq)]
 pub enum TradeWebError {
     ReqwestError,
@@ -17,6 +19,25 @@ pub enum TradeWebError {
     PositionExists,
     DeleteFailed,
     NoSharesFound,
+    RiskRejected(RiskRejection),
+    /// optimistic concurrency: the row changed after it was read
+    Conflict,
+    Secret(SecretError),
+    /// nothing to update
+    NotFound,
+}
+
+/// sealing and opening credentials; see secret.rs
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretError {
+    /// the key isn't 32 bytes of base64
+    BadKey,
+    /// a credential is encrypted but CREDENTIALS_KEY isn't set
+    NoKey,
+    /// not something seal() wrote
+    Malformed,
+    /// wrong key, or the value was tampered with
+    Decrypt,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,55 @@
+//! feed_monitor.rs
+//!
+//! Whether the market data websockets are up, going by when each last pinged (ping_alpaca, ping_finnhub).
+//! `trader monitor` checks it on a timer.
+//!
+
+use chrono::{DateTime, Duration, Utc};
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+
+/// the latest ping from each feed; None if it never pinged
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FeedHeartbeat {
+    pub alpaca: Option<DateTime<Utc>>,
+    pub finnhub: Option<DateTime<Utc>>,
+}
+
+impl FeedHeartbeat {
+
+    pub fn load(tx_db: Sender<DbMsg>) -> Result<FeedHeartbeat, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::FeedHeartbeat { sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?
+    }
+
+    /// Pure; the feeds checked that haven't pinged within max_age of now
+    pub fn stale(&self, now: DateTime<Utc>, max_age: Duration, alpaca: bool, finnhub: bool) -> Vec<&'static str> {
+        let is_stale = |last: Option<DateTime<Utc>>| !matches!(last, Some(last) if now - last <= max_age);
+        let mut stale = vec![];
+        if alpaca && is_stale(self.alpaca) {
+            stale.push("alpaca");
+        }
+        if finnhub && is_stale(self.finnhub) {
+            stale.push("finnhub");
+        }
+        stale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    #[test]
+    fn stale_feeds() {
+        let now = Utc.with_ymd_and_hms(2023, 6, 7, 15, 0, 0).unwrap();
+        let heartbeat = FeedHeartbeat { alpaca: Some(now - Duration::seconds(30)), finnhub: Some(now - Duration::minutes(10)) };
+        assert_eq!(heartbeat.stale(now, Duration::minutes(5), true, true), vec!["finnhub"]);
+        assert!(heartbeat.stale(now, Duration::minutes(5), true, false).is_empty());
+        assert_eq!(FeedHeartbeat::default().stale(now, Duration::minutes(5), true, true), vec!["alpaca", "finnhub"]);
+    }
+}
@@ -14,7 +14,7 @@ pub enum FinnhubPacket {
     Trade(Vec<FinnhubTrade>),
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct FinnhubPing {
     pub dtg: DateTime<Utc>,
 }
@@ -0,0 +1,367 @@
+//! indicator.rs
+//!
+//! Streaming indicators kept in memory per symbol and fed from the Alpaca data websocket by the StrategyRunner, so a
+//! crossover is seen on the trade that causes it rather than after the next poll of v_alpaca_diff.
+//!
+//! EMA and SMA advance once per minute bar (periods are in bars, Settings.trade_ema_small_size and
+//! trade_ema_large_size). A minute is closed by whichever comes first: the websocket bar for it, or the
+//! first trade of the next minute. Between closes each trade is folded in as a provisional close, which is
+//! what the crossover check uses. VWAP is for the current New York trading day; the 30s/1m/3m/5m diffs are
+//! the last price minus the price that long ago, like DiffCalc.
+//!
+
+use std::collections::{HashMap, VecDeque};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, DurationRound, NaiveDate, Utc};
+use serde::Serialize;
+use crate::alpaca_api_structs::{AlpacaTradeWs, CrossStatus, MinuteBar};
+
+/// indicators are rounded to this many places so BigDecimal scale doesn't grow without bound
+const INDICATOR_SCALE: i64 = 10;
+
+/// keep enough trade history for the longest diff
+const HISTORY_SECONDS: i64 = 5 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Ema {
+    alpha: BigDecimal,
+    value: Option<BigDecimal>,
+}
+
+impl Ema {
+
+    pub fn new(period: usize) -> Ema {
+        Ema { alpha: BigDecimal::from(2) / BigDecimal::from(period.max(1) as u64 + 1), value: None }
+    }
+
+    /// fold in a closed bar; the first value seeds the average
+    pub fn update(&mut self, price: &BigDecimal) -> BigDecimal {
+        let value = self.peek(price);
+        self.value = Some(value.clone());
+        value
+    }
+
+    /// what the average would be if price closed the current bar, without advancing it
+    pub fn peek(&self, price: &BigDecimal) -> BigDecimal {
+        match &self.value {
+            None => price.clone(),
+            Some(value) => (&self.alpha * price + (BigDecimal::from(1) - &self.alpha) * value).with_scale(INDICATOR_SCALE),
+        }
+    }
+
+    pub fn value(&self) -> Option<&BigDecimal> {
+        self.value.as_ref()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sma {
+    period: usize,
+    window: VecDeque<BigDecimal>,
+    sum: BigDecimal,
+}
+
+impl Sma {
+
+    pub fn new(period: usize) -> Sma {
+        Sma { period: period.max(1), window: VecDeque::new(), sum: BigDecimal::from(0) }
+    }
+
+    pub fn update(&mut self, price: &BigDecimal) -> BigDecimal {
+        self.window.push_back(price.clone());
+        self.sum += price;
+        if self.window.len() > self.period {
+            if let Some(oldest) = self.window.pop_front() {
+                self.sum -= oldest;
+            }
+        }
+        self.value()
+    }
+
+    /// average of what's been seen so far, even before the window is full
+    pub fn value(&self) -> BigDecimal {
+        if self.window.is_empty() {
+            BigDecimal::from(0)
+        } else {
+            (&self.sum / BigDecimal::from(self.window.len() as u64)).with_scale(INDICATOR_SCALE)
+        }
+    }
+}
+
+/// volume weighted average price, reset at the start of each New York trading day
+#[derive(Debug, Clone, Default)]
+pub struct Vwap {
+    date: Option<NaiveDate>,
+    price_volume: BigDecimal,
+    volume: BigDecimal,
+}
+
+impl Vwap {
+
+    pub fn update(&mut self, dtg: DateTime<Utc>, price: &BigDecimal, size: &BigDecimal) -> Option<BigDecimal> {
+        let date = dtg.with_timezone(&chrono_tz::America::New_York).date_naive();
+        if self.date != Some(date) {
+            *self = Vwap { date: Some(date), ..Default::default() };
+        }
+        self.price_volume += price * size;
+        self.volume += size;
+        self.value()
+    }
+
+    pub fn value(&self) -> Option<BigDecimal> {
+        if self.volume > BigDecimal::from(0) {
+            Some((&self.price_volume / &self.volume).with_scale(INDICATOR_SCALE))
+        } else {
+            None
+        }
+    }
+}
+
+fn minute_start(dtg: DateTime<Utc>) -> DateTime<Utc> {
+    dtg.duration_trunc(Duration::minutes(1)).unwrap_or(dtg)
+}
+
+/// a crossover of the small EMA through the large one
+#[derive(Debug, Clone, Serialize)]
+pub struct CrossEvent {
+    pub symbol: String,
+    pub dtg: DateTime<Utc>,
+    pub status: CrossStatus,
+    pub price: BigDecimal,
+    pub ema_small: BigDecimal,
+    pub ema_large: BigDecimal,
+}
+
+/// everything known about one symbol right now
+#[derive(Debug, Clone, Serialize)]
+pub struct IndicatorSnapshot {
+    pub symbol: String,
+    pub dtg_last: DateTime<Utc>,
+    pub price_last: BigDecimal,
+    pub ema_small: BigDecimal,
+    pub ema_large: BigDecimal,
+    pub sma_small: BigDecimal,
+    pub sma_large: BigDecimal,
+    pub vwap: Option<BigDecimal>,
+    pub diff_30s: Option<BigDecimal>,
+    pub diff_1m: Option<BigDecimal>,
+    pub diff_3m: Option<BigDecimal>,
+    pub diff_5m: Option<BigDecimal>,
+}
+
+#[derive(Debug, Clone)]
+struct SymbolIndicators {
+    ema_small: Ema,
+    ema_large: Ema,
+    sma_small: Sma,
+    sma_large: Sma,
+    vwap: Vwap,
+    history: VecDeque<(DateTime<Utc>, BigDecimal)>,
+    /// start of the minute being built from trades, and its latest price
+    bar_open: Option<(DateTime<Utc>, BigDecimal)>,
+    /// start of the latest minute already folded into the averages
+    closed_through: Option<DateTime<Utc>>,
+    /// sign of ema_small - ema_large at the last check: true above, false below
+    small_above: Option<bool>,
+}
+
+impl SymbolIndicators {
+
+    fn new(small: usize, large: usize) -> SymbolIndicators {
+        SymbolIndicators {
+            ema_small: Ema::new(small),
+            ema_large: Ema::new(large),
+            sma_small: Sma::new(small),
+            sma_large: Sma::new(large),
+            vwap: Vwap::default(),
+            history: VecDeque::new(),
+            bar_open: None,
+            closed_through: None,
+            small_above: None,
+        }
+    }
+
+    /// fold a finished minute into the averages, once
+    fn close_bar(&mut self, minute: DateTime<Utc>, price: &BigDecimal) {
+        if self.closed_through.is_some_and(|closed| closed >= minute) {
+            return;
+        }
+        self.ema_small.update(price);
+        self.ema_large.update(price);
+        self.sma_small.update(price);
+        self.sma_large.update(price);
+        self.closed_through = Some(minute);
+    }
+
+    /// Up/Down only when the order of the two averages flips
+    fn check_cross(&mut self, ema_small: &BigDecimal, ema_large: &BigDecimal) -> CrossStatus {
+        if ema_small == ema_large {
+            return CrossStatus::None;
+        }
+        let above = ema_small > ema_large;
+        let status = match self.small_above {
+            Some(false) if above => CrossStatus::Up,
+            Some(true) if !above => CrossStatus::Down,
+            _ => CrossStatus::None,
+        };
+        self.small_above = Some(above);
+        status
+    }
+
+    fn price_ago(&self, dtg: DateTime<Utc>, seconds: i64) -> Option<&BigDecimal> {
+        let then = dtg - Duration::seconds(seconds);
+        self.history.iter().rev().find(|(t, _)| *t <= then).map(|(_, price)| price)
+    }
+}
+
+/// per-symbol indicators for every symbol on the feed
+#[derive(Debug, Clone)]
+pub struct IndicatorEngine {
+    ema_small_size: usize,
+    ema_large_size: usize,
+    symbols: HashMap<String, SymbolIndicators>,
+}
+
+impl IndicatorEngine {
+
+    /// sizes usually from Settings.trade_ema_small_size and trade_ema_large_size
+    pub fn new(ema_small_size: i32, ema_large_size: i32) -> IndicatorEngine {
+        IndicatorEngine {
+            ema_small_size: ema_small_size.max(1) as usize,
+            ema_large_size: ema_large_size.max(1) as usize,
+            symbols: HashMap::new(),
+        }
+    }
+
+    fn symbol_mut(&mut self, symbol: &str) -> &mut SymbolIndicators {
+        let (small, large) = (self.ema_small_size, self.ema_large_size);
+        self.symbols.entry(symbol.to_uppercase()).or_insert_with(|| SymbolIndicators::new(small, large))
+    }
+
+    /// a trade from the websocket; returns a crossover if this trade caused one
+    pub fn on_trade(&mut self, trade: &AlpacaTradeWs) -> Option<CrossEvent> {
+        let indicators = self.symbol_mut(&trade.symbol);
+        indicators.vwap.update(trade.dtg, &trade.price, &trade.size);
+        indicators.history.push_back((trade.dtg, trade.price.clone()));
+        while let Some((dtg, _)) = indicators.history.front() {
+            if trade.dtg - *dtg > Duration::seconds(HISTORY_SECONDS + 60) {
+                indicators.history.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let minute = minute_start(trade.dtg);
+        if let Some((open, price)) = indicators.bar_open.clone() {
+            if minute > open {
+                indicators.close_bar(open, &price);
+            }
+        }
+        indicators.bar_open = Some((minute, trade.price.clone()));
+
+        let ema_small = indicators.ema_small.peek(&trade.price);
+        let ema_large = indicators.ema_large.peek(&trade.price);
+        match indicators.check_cross(&ema_small, &ema_large) {
+            CrossStatus::None => None,
+            status => Some(CrossEvent {
+                symbol: trade.symbol.to_uppercase(),
+                dtg: trade.dtg,
+                status,
+                price: trade.price.clone(),
+                ema_small,
+                ema_large,
+            }),
+        }
+    }
+
+    /// a minute bar (t is the start of the minute); also all a backtest over bar_minute has
+    pub fn on_bar(&mut self, bar: &MinuteBar) -> Option<CrossEvent> {
+        let indicators = self.symbol_mut(&bar.symbol);
+        indicators.close_bar(minute_start(bar.dtg), &bar.price_close);
+        let ema_small = indicators.ema_small.value()?.clone();
+        let ema_large = indicators.ema_large.value()?.clone();
+        match indicators.check_cross(&ema_small, &ema_large) {
+            CrossStatus::None => None,
+            status => Some(CrossEvent {
+                symbol: bar.symbol.to_uppercase(),
+                dtg: bar.dtg,
+                status,
+                price: bar.price_close.clone(),
+                ema_small,
+                ema_large,
+            }),
+        }
+    }
+
+    pub fn snapshot(&self, symbol: &str) -> Option<IndicatorSnapshot> {
+        let indicators = self.symbols.get(&symbol.to_uppercase())?;
+        let (dtg_last, price_last) = indicators.history.back()?.clone();
+        let diff = |seconds| indicators.price_ago(dtg_last, seconds).map(|then| &price_last - then);
+        Some(IndicatorSnapshot {
+            symbol: symbol.to_uppercase(),
+            dtg_last,
+            ema_small: indicators.ema_small.peek(&price_last),
+            ema_large: indicators.ema_large.peek(&price_last),
+            sma_small: indicators.sma_small.value(),
+            sma_large: indicators.sma_large.value(),
+            vwap: indicators.vwap.value(),
+            diff_30s: diff(30),
+            diff_1m: diff(60),
+            diff_3m: diff(180),
+            diff_5m: diff(300),
+            price_last,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn trade(seconds: i64, price: &str) -> AlpacaTradeWs {
+        let start = DateTime::parse_from_rfc3339("2023-11-22T15:00:00Z").unwrap().with_timezone(&Utc);
+        serde_json::from_value(serde_json::json!({
+            "S": "AAPL", "i": seconds, "x": "V", "p": BigDecimal::from_str(price).unwrap(), "s": 100,
+            "t": start + Duration::seconds(seconds), "z": "C"
+        })).unwrap()
+    }
+
+    #[test]
+    fn ema_seeds_then_smooths() {
+        let mut ema = Ema::new(3);
+        assert_eq!(ema.update(&dec("10")), dec("10"));
+        assert_eq!(ema.update(&dec("20")), dec("15"));
+        assert_eq!(ema.peek(&dec("15")), dec("15"));
+    }
+
+    #[test]
+    fn crossover_up_then_down() {
+        // one trade a minute; each closes the previous minute's bar
+        let mut engine = IndicatorEngine::new(2, 5);
+        assert!(engine.on_trade(&trade(0, "10")).is_none());
+        // a falling price puts the small average below the large
+        assert!(engine.on_trade(&trade(60, "9")).is_none());
+        let up = engine.on_trade(&trade(120, "12")).unwrap();
+        assert!(matches!(up.status, CrossStatus::Up));
+        let down = engine.on_trade(&trade(180, "8")).unwrap();
+        assert!(matches!(down.status, CrossStatus::Down));
+    }
+
+    #[test]
+    fn diffs_and_vwap() {
+        let mut engine = IndicatorEngine::new(2, 5);
+        engine.on_trade(&trade(0, "10"));
+        engine.on_trade(&trade(40, "11"));
+        engine.on_trade(&trade(70, "12"));
+        let snapshot = engine.snapshot("aapl").unwrap();
+        assert_eq!(snapshot.diff_30s, Some(dec("1")));
+        assert_eq!(snapshot.diff_1m, Some(dec("2")));
+        assert_eq!(snapshot.diff_3m, None);
+        assert_eq!(snapshot.vwap, Some(dec("11")));
+    }
+}
@@ -5,8 +5,15 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{fmt, EnvFilter};
 use crate::common_structs::ConfigLocation;
+use crate::config::{AppConfig, Service};
 
+/// the command line tools; see init_service
 pub fn init(package_name: &str) {
+    init_service(package_name, Service::Tool, &[]);
+}
+
+/// .env, tracing, then the configuration from the file, the environment and args; exits if it isn't valid
+pub fn init_service(package_name: &str, service: Service, args: &[String]) {
     // load .env
     // if docker, load .env from the root directory, otherwise use the cargo workspace directory
     let config_location:ConfigLocation = ConfigLocation::from_str(&std::env::var("CONFIG_LOCATION").unwrap_or_else(|_| "not_docker".to_owned())).expect("CONFIG_LOCATION");
@@ -33,5 +40,16 @@ pub fn init(package_name: &str) {
     tracing_subscriber::registry().with(fmt::layer()).with(EnvFilter::from_default_env()).init();
     tracing::info!("[init] .env file: {}", &dot_env_path);
 
-
+    match AppConfig::load(package_name, args).and_then(|config| config.validate(service).map(|_| config)) {
+        Ok(config) => {
+            tracing::info!("[init] configuration:\n{}", &config);
+            AppConfig::set(config);
+        },
+        Err(errors) => {
+            for error in errors {
+                eprintln!("[init] configuration: {}", error);
+            }
+            std::process::exit(2);
+        },
+    }
 }
@@ -0,0 +1,102 @@
+//! kill_switch.rs
+//!
+//! Global trading halt. While engaged, RiskManager rejects every new order (buy or sell). Engaging it
+//! also cancels all open orders on Alpaca (DELETE /v2/orders) and optionally flattens every position
+//! (DELETE /v2/positions). The state lives in t_kill_switch so it stays engaged across restarts until
+//! someone clears it from the frontend or the command line.
+//!
+
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use crate::clock;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+
+/// reflects the latest row in t_kill_switch
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct KillSwitch {
+    pub dtg: DateTime<Utc>,
+    pub engaged: bool,
+    pub flatten: bool,
+    pub reason: Option<String>,
+    pub changed_by: Option<String>,
+}
+
+/// what engaging the switch did on the Alpaca side
+#[derive(Debug, Serialize)]
+pub struct KillSwitchResult {
+    pub state: KillSwitch,
+    pub orders_canceled: Option<usize>,
+    pub positions_closed: Option<usize>,
+}
+
+impl KillSwitch {
+
+    /// never engaged yet; also what's assumed before the table has a row
+    pub fn cleared() -> KillSwitch {
+        KillSwitch {
+            dtg: clock::now(),
+            engaged: false,
+            flatten: false,
+            reason: None,
+            changed_by: None,
+        }
+    }
+
+    /// current state from the database
+    pub fn status(tx_db: Sender<DbMsg>) -> Result<KillSwitch, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::KillSwitchGet { sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// Halt trading: persist the engaged state first so buy/sell stop immediately, then cancel every
+    /// open order and, if asked, close every position.
+    pub fn engage(flatten: bool, reason: &str, changed_by: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<KillSwitchResult, TradeWebError> {
+        tracing::warn!("[engage] ***** KILL SWITCH ENGAGED by {} (flatten: {}): {}", changed_by, flatten, reason);
+
+        let state = KillSwitch {
+            dtg: clock::now(),
+            engaged: true,
+            flatten,
+            reason: Some(reason.to_string()),
+            changed_by: Some(changed_by.to_string()),
+        };
+        let state = KillSwitch::save(state, tx_db.clone())?;
+
+        let (sender, rx) = crossbeam_channel::unbounded();
+        let _ = tx_db.send(DbMsg::RestCancelAllOrders { settings: settings.clone(), sender });
+        let orders_canceled = rx.recv().ok();
+        tracing::warn!("[engage] orders canceled: {:?}", &orders_canceled);
+
+        let positions_closed = if flatten {
+            let (sender, rx) = crossbeam_channel::unbounded();
+            let _ = tx_db.send(DbMsg::RestCloseAllPositions { settings: settings.clone(), sender });
+            let closed = rx.recv().ok();
+            tracing::warn!("[engage] positions closed: {:?}", &closed);
+            closed
+        } else {
+            None
+        };
+
+        Ok(KillSwitchResult { state, orders_canceled, positions_closed })
+    }
+
+    /// resume trading
+    pub fn clear(changed_by: &str, tx_db: Sender<DbMsg>) -> Result<KillSwitch, TradeWebError> {
+        tracing::warn!("[clear] ***** kill switch cleared by {}", changed_by);
+        let state = KillSwitch {
+            changed_by: Some(changed_by.to_string()),
+            ..KillSwitch::cleared()
+        };
+        KillSwitch::save(state, tx_db)
+    }
+
+    fn save(state: KillSwitch, tx_db: Sender<DbMsg>) -> Result<KillSwitch, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::KillSwitchSet { state, sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
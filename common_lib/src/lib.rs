@@ -10,6 +10,7 @@ pub mod alpaca_api_structs;
 pub mod alpaca_order;
 pub mod alpaca_position;
 pub mod common_structs;
+pub mod config;
 pub mod error;
 pub mod finnhub;
 pub mod http;
@@ -25,3 +26,27 @@ pub mod alpaca_transaction_status;
 pub mod db;
 pub mod symbol;
 pub mod position_local;
+pub mod risk;
+pub mod kill_switch;
+pub mod order_manager;
+pub mod alpaca_asset;
+pub mod order_sizing;
+pub mod market_calendar;
+pub mod clock;
+pub mod position_exit;
+pub mod indicator;
+pub mod strategy;
+pub mod backtest;
+pub mod sim_broker;
+pub mod optimizer;
+pub mod profile_schedule;
+pub mod settings_history;
+pub mod secret;
+pub mod credentials;
+pub mod settings_cache;
+pub mod user;
+pub mod feed_monitor;
+pub mod backfill;
+pub mod supervisor;
+pub mod trade_batch;
+pub mod spool;
@@ -0,0 +1,259 @@
+//! market_calendar.rs
+//!
+//! Trading days and session times from the Alpaca calendar and clock APIs, cached in alpaca_calendar and
+//! alpaca_clock. Unlike the fixed times in market_hours this knows about weekends, exchange holidays and
+//! early closes.
+//!
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::America::New_York;
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::db::DbMsg;
+use crate::clock;
+use crate::error::TradeWebError;
+use crate::market_hours::Session;
+use crate::settings::Settings;
+
+/// how far back and ahead of today to load and cache
+const CALENDAR_DAYS_BEFORE: i64 = 7;
+const CALENDAR_DAYS_AFTER: i64 = 30;
+
+/// GET https://paper-api.alpaca.markets/v2/calendar?start=2023-11-20&end=2023-11-24
+///
+/// [{"date":"2023-11-22","open":"09:30","close":"16:00","session_open":"0400","session_close":"2000","settlement_date":"2023-11-27"},
+///  {"date":"2023-11-24","open":"09:30","close":"13:00","session_open":"0400","session_close":"1700","settlement_date":"2023-11-28"}]
+///
+/// All times are New York local.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CalendarDay {
+    pub date: NaiveDate,
+    #[serde(deserialize_with = "time_from_hh_colon_mm")]
+    pub open: NaiveTime,
+    #[serde(deserialize_with = "time_from_hh_colon_mm")]
+    pub close: NaiveTime,
+    #[serde(deserialize_with = "time_from_hhmm")]
+    pub session_open: NaiveTime,
+    #[serde(deserialize_with = "time_from_hhmm")]
+    pub session_close: NaiveTime,
+}
+
+/// GET https://paper-api.alpaca.markets/v2/clock
+///
+/// {"timestamp":"2023-11-22T10:01:32.190584317-05:00","is_open":true,"next_open":"2023-11-24T09:30:00-05:00","next_close":"2023-11-22T16:00:00-05:00"}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MarketClock {
+    pub timestamp: DateTime<Utc>,
+    pub is_open: bool,
+    pub next_open: DateTime<Utc>,
+    pub next_close: DateTime<Utc>,
+}
+
+fn time_from_hh_colon_mm<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H:%M").map_err(serde::de::Error::custom)
+}
+
+fn time_from_hhmm<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&s, "%H%M").map_err(serde::de::Error::custom)
+}
+
+impl CalendarDay {
+
+    /// session start in UTC; extended includes pre-market
+    pub fn open_utc(&self, extended: bool) -> Option<DateTime<Utc>> {
+        let time = if extended { self.session_open } else { self.open };
+        New_York.from_local_datetime(&self.date.and_time(time)).single().map(|dtg| dtg.with_timezone(&Utc))
+    }
+
+    /// session end in UTC; extended includes after-hours
+    pub fn close_utc(&self, extended: bool) -> Option<DateTime<Utc>> {
+        let time = if extended { self.session_close } else { self.close };
+        New_York.from_local_datetime(&self.date.and_time(time)).single().map(|dtg| dtg.with_timezone(&Utc))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarketCalendar {
+    /// trading days in date order; days the market is closed are absent
+    pub days: Vec<CalendarDay>,
+}
+
+impl MarketCalendar {
+
+    pub fn new(mut days: Vec<CalendarDay>) -> MarketCalendar {
+        days.sort_by_key(|d| d.date);
+        MarketCalendar { days }
+    }
+
+    /// the trading days around today, from the database cache
+    pub fn load(tx_db: Sender<DbMsg>) -> Result<MarketCalendar, TradeWebError> {
+        let (start, end) = MarketCalendar::range(clock::now());
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::CalendarGet { start, end, sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map(MarketCalendar::new).map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// fetch the calendar and clock from Alpaca and cache both in the database
+    pub fn refresh_remote(settings: &Settings, tx_db: Sender<DbMsg>) -> Result<MarketCalendar, TradeWebError> {
+        let (start, end) = MarketCalendar::range(clock::now());
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RestGetCalendar { start, end, settings: settings.clone(), sender }).map_err(|_| TradeWebError::ChannelError)?;
+        let days = rx.recv().map_err(|_| TradeWebError::ChannelError)?;
+        tx_db.send(DbMsg::CalendarSave { days: days.clone() }).map_err(|_| TradeWebError::ChannelError)?;
+
+        if let Ok(clock) = MarketClock::get_remote(settings, tx_db.clone()) {
+            tx_db.send(DbMsg::ClockSave { clock }).map_err(|_| TradeWebError::ChannelError)?;
+        }
+        Ok(MarketCalendar::new(days))
+    }
+
+    fn range(now: DateTime<Utc>) -> (NaiveDate, NaiveDate) {
+        let today = now.with_timezone(&New_York).date_naive();
+        (today - Duration::days(CALENDAR_DAYS_BEFORE), today + Duration::days(CALENDAR_DAYS_AFTER))
+    }
+
+    /// true if now falls inside the cached range, so a missing day really means closed
+    pub fn covers(&self, now: DateTime<Utc>) -> bool {
+        let today = now.with_timezone(&New_York).date_naive();
+        match (self.days.first(), self.days.last()) {
+            (Some(first), Some(last)) => first.date <= today && today <= last.date,
+            _ => false,
+        }
+    }
+
+    pub fn day(&self, date: NaiveDate) -> Option<&CalendarDay> {
+        self.days.iter().find(|d| d.date == date)
+    }
+
+    /// which part of the trading day now falls in; Closed all day on weekends and holidays
+    pub fn session_at(&self, now: DateTime<Utc>) -> Session {
+        let today = now.with_timezone(&New_York).date_naive();
+        let day = match self.day(today) {
+            Some(day) => day,
+            None => return Session::Closed,
+        };
+        match (day.open_utc(true), day.open_utc(false), day.close_utc(false), day.close_utc(true)) {
+            (Some(session_open), Some(open), Some(close), Some(session_close)) => {
+                if now < session_open || now >= session_close {
+                    Session::Closed
+                } else if now < open {
+                    Session::PreMarket
+                } else if now < close {
+                    Session::Regular
+                } else {
+                    Session::AfterHours
+                }
+            },
+            _ => Session::Closed,
+        }
+    }
+
+    /// is the regular (or, if extended, the pre-market through after-hours) session open at now
+    pub fn is_open_at(&self, now: DateTime<Utc>, extended: bool) -> bool {
+        self.session_at(now).is_open(extended)
+    }
+
+    /// the first session open strictly after now
+    pub fn next_open(&self, now: DateTime<Utc>, extended: bool) -> Option<DateTime<Utc>> {
+        self.days.iter().filter_map(|d| d.open_utc(extended)).find(|open| *open > now)
+    }
+
+    /// the first session close strictly after now; today's close while the market is open
+    pub fn next_close(&self, now: DateTime<Utc>, extended: bool) -> Option<DateTime<Utc>> {
+        self.days.iter().filter_map(|d| d.close_utc(extended)).find(|close| *close > now)
+    }
+}
+
+impl MarketClock {
+
+    pub fn get_remote(settings: &Settings, tx_db: Sender<DbMsg>) -> Result<MarketClock, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RestGetClock { settings: settings.clone(), sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// the last clock saved by MarketCalendar::refresh_remote
+    pub fn load(tx_db: Sender<DbMsg>) -> Result<MarketClock, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::ClockGet { sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// Pure; whether the regular session is open at now, or None once now is past the next open or close
+    /// this clock knew about and it's out of date
+    pub fn is_open_at(&self, now: DateTime<Utc>) -> Option<bool> {
+        if now < self.timestamp {
+            return None;
+        }
+        match self.is_open {
+            true if now < self.next_close => Some(true),
+            false if now < self.next_open => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar() -> MarketCalendar {
+        // Thanksgiving week 2023: closed Thursday, early close Friday
+        let json = r#"[
+            {"date":"2023-11-22","open":"09:30","close":"16:00","session_open":"0400","session_close":"2000","settlement_date":"2023-11-27"},
+            {"date":"2023-11-24","open":"09:30","close":"13:00","session_open":"0400","session_close":"1700","settlement_date":"2023-11-28"}
+        ]"#;
+        MarketCalendar::new(serde_json::from_str(json).unwrap())
+    }
+
+    fn ny(s: &str) -> DateTime<Utc> {
+        let local = chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        New_York.from_local_datetime(&local).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn holiday_and_early_close() {
+        let calendar = calendar();
+        assert!(calendar.is_open_at(ny("2023-11-22 10:00"), false));
+        assert!(!calendar.is_open_at(ny("2023-11-23 10:00"), false));
+        assert!(!calendar.is_open_at(ny("2023-11-23 10:00"), true));
+        assert!(!calendar.is_open_at(ny("2023-11-24 14:00"), false));
+        assert!(calendar.is_open_at(ny("2023-11-24 14:00"), true));
+        assert!(!calendar.is_open_at(ny("2023-11-24 17:30"), true));
+    }
+
+    #[test]
+    fn sessions_through_the_day() {
+        let calendar = calendar();
+        assert_eq!(calendar.session_at(ny("2023-11-22 03:59")), Session::Closed);
+        assert_eq!(calendar.session_at(ny("2023-11-22 04:00")), Session::PreMarket);
+        assert_eq!(calendar.session_at(ny("2023-11-22 09:30")), Session::Regular);
+        assert_eq!(calendar.session_at(ny("2023-11-22 16:00")), Session::AfterHours);
+        assert_eq!(calendar.session_at(ny("2023-11-22 20:00")), Session::Closed);
+        assert_eq!(calendar.session_at(ny("2023-11-23 12:00")), Session::Closed);
+    }
+
+    #[test]
+    fn clock_is_current_until_the_next_open_or_close() {
+        let closed = MarketClock { timestamp: ny("2023-11-22 17:00"), is_open: false, next_open: ny("2023-11-24 09:30"), next_close: ny("2023-11-24 13:00") };
+        assert_eq!(closed.is_open_at(ny("2023-11-23 10:00")), Some(false));
+        assert_eq!(closed.is_open_at(ny("2023-11-24 09:30")), None);
+        assert_eq!(closed.is_open_at(ny("2023-11-22 16:59")), None);
+
+        let open = MarketClock { timestamp: ny("2023-11-24 10:00"), is_open: true, next_open: ny("2023-11-27 09:30"), next_close: ny("2023-11-24 13:00") };
+        assert_eq!(open.is_open_at(ny("2023-11-24 12:59")), Some(true));
+        assert_eq!(open.is_open_at(ny("2023-11-24 13:00")), None);
+    }
+
+    #[test]
+    fn next_open_and_close() {
+        let calendar = calendar();
+        assert_eq!(calendar.next_open(ny("2023-11-22 17:00"), false), Some(ny("2023-11-24 09:30")));
+        assert_eq!(calendar.next_close(ny("2023-11-24 10:00"), false), Some(ny("2023-11-24 13:00")));
+        assert_eq!(calendar.next_open(ny("2023-11-24 10:00"), false), None);
+        assert!(calendar.covers(ny("2023-11-23 10:00")));
+        assert!(!calendar.covers(ny("2023-11-25 10:00")));
+    }
+}
@@ -1,7 +1,16 @@
 //! market_hours.rs
+//!
+//! Is the market open; see market_calendar for the trading days behind the answer.
 
-use chrono::{NaiveTime, Utc};
+use chrono::{DateTime, NaiveTime, Utc};
+use crossbeam_channel::Sender;
 use once_cell::sync::Lazy;
+use serde::Serialize;
+use strum::Display;
+use crate::clock;
+use crate::config::AppConfig;
+use crate::db::DbMsg;
+use crate::market_calendar::{MarketCalendar, MarketClock};
 
 pub const OPERATE_API_AFTER_HOURS: bool = false;
 pub const BUY_EXTENDED_HOURS: bool = false;
@@ -14,40 +23,139 @@ pub static MARKET_CLOSE_TIME: Lazy<NaiveTime> = Lazy::new(|| NaiveTime::from_hms
 pub static MARKET_OPEN_EXT: Lazy<NaiveTime> = Lazy::new(|| NaiveTime::from_hms_opt(4, 0, 0).unwrap()); // 4am Eastern
 pub static MARKET_CLOSE_EXT: Lazy<NaiveTime> = Lazy::new(|| NaiveTime::from_hms_opt(20, 0, 0).unwrap()); // 4pm
 
+/// where in the trading day we are, New York time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Session {
+    PreMarket,
+    Regular,
+    AfterHours,
+    Closed,
+}
+
+impl Session {
+    /// regular hours are always open; pre-market and after-hours only count if extended
+    pub fn is_open(&self, extended: bool) -> bool {
+        match self {
+            Session::Regular => true,
+            Session::PreMarket | Session::AfterHours => extended,
+            Session::Closed => false,
+        }
+    }
+}
+
 pub struct MarketHours {
 }
 
 
 impl MarketHours{
-    pub fn is_open() -> bool{
 
-        let time_current_ny = Utc::now().with_timezone(&chrono_tz::America::New_York).time();
+    /// Is the market open now: the regular session, or the extended session if OPERATE_API_AFTER_HOURS.
+    pub fn is_open(tx_db: Sender<DbMsg>) -> bool{
+        MarketHours::is_session_open(OPERATE_API_AFTER_HOURS, tx_db)
+    }
 
+    /// Regular (09:30-16:00) or extended (04:00-20:00) session now.
+    pub fn is_session_open(extended: bool, tx_db: Sender<DbMsg>) -> bool{
 
-        let open_for_testing = std::env::var("PRETEND_TO_BE_OPEN").unwrap_or_else(|_| "false".to_string()).parse().unwrap_or_else(|_| false);
-        if open_for_testing{
+        if AppConfig::get().pretend_to_be_open{
             return true;
         }
 
-        let are_we_open = match OPERATE_API_AFTER_HOURS {
-            false => {
-                if time_current_ny > *MARKET_OPEN_TIME /* *MARKET_NORMAL_OPEN_TIME */ &&
-                    time_current_ny < *MARKET_CLOSE_TIME /* *MARKET_NORMAL_CLOSE_TIME */ {
-                    (true, "Operating inside normal hours (0930-1600)")
-                } else {
-                    (false, "Outside normal hours (0930-1600)")
-                }
-            },
-            true =>{
-                if time_current_ny > *MARKET_OPEN_EXT /* *MARKET_EXTENDED_OPEN_TIME */ &&
-                    time_current_ny < *MARKET_CLOSE_EXT /* *MARKET_EXTENDED_CLOSE_TIME */ {
-                    (true, "Operating inside extended hours (0400-2000)")
-                } else {
-                    (false, "Outside extended hours (0400-2000)")
-                }
+        MarketHours::session(tx_db).is_open(extended)
+    }
+
+    /// The session at clock::now() per the cached Alpaca calendar, so weekends, holidays and early closes
+    /// are Closed. Falls back to the fixed times above if the calendar hasn't been loaded (see
+    /// MarketCalendar::refresh_remote). The cached Alpaca clock, while it's current, has the last word on
+    /// whether the regular session is open, which also catches unscheduled closures.
+    pub fn session(tx_db: Sender<DbMsg>) -> Session{
+        let now = clock::now();
+        let session = match MarketCalendar::load(tx_db.clone()) {
+            Ok(calendar) if calendar.covers(now) => calendar.session_at(now),
+            _ => {
+                tracing::debug!("[session] no market calendar for today; using fixed hours");
+                MarketHours::session_fixed(now)
             }
         };
-        // tracing::debug!("[are_we_open] Time in NYC: {:?}; OPERATE_API_AFTER_HOURS: {}; are_we_open: {}:{}", &time_current_ny, OPERATE_API_AFTER_HOURS, &are_we_open.0, &are_we_open.1);
-        are_we_open.0
+        match MarketClock::load(tx_db).ok().and_then(|market_clock| market_clock.is_open_at(now)) {
+            Some(true) => Session::Regular,
+            Some(false) if session == Session::Regular => Session::Closed,
+            _ => session,
+        }
     }
-}
\ No newline at end of file
+
+    /// today's regular close (13:00 on early-close days), or None on days the market doesn't open
+    pub fn regular_close(tx_db: Sender<DbMsg>) -> Option<DateTime<Utc>>{
+        let now = clock::now();
+        match MarketCalendar::load(tx_db) {
+            Ok(calendar) if calendar.covers(now) => calendar
+                .day(now.with_timezone(&chrono_tz::America::New_York).date_naive())
+                .and_then(|day| day.close_utc(false)),
+            _ => now.with_timezone(&chrono_tz::America::New_York)
+                .date_naive()
+                .and_time(*MARKET_CLOSE_TIME)
+                .and_local_timezone(chrono_tz::America::New_York)
+                .single()
+                .map(|close| close.with_timezone(&Utc)),
+        }
+    }
+
+    /// the original fixed-time check; knows nothing about weekends or holidays
+    pub fn session_fixed(now: DateTime<Utc>) -> Session{
+
+        let time_current_ny = now.with_timezone(&chrono_tz::America::New_York).time();
+
+        if time_current_ny < *MARKET_OPEN_EXT || time_current_ny >= *MARKET_CLOSE_EXT {
+            Session::Closed
+        } else if time_current_ny < *MARKET_OPEN_TIME {
+            Session::PreMarket
+        } else if time_current_ny < *MARKET_CLOSE_TIME {
+            Session::Regular
+        } else {
+            Session::AfterHours
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, SimulatedClock};
+
+    #[test]
+    fn simulated_day_without_a_calendar() {
+        // 2023-11-22 03:00 New York
+        let sim = SimulatedClock::new(DateTime::parse_from_rfc3339("2023-11-22T08:00:00Z").unwrap().with_timezone(&Utc));
+        let mut seen = vec![];
+        for _ in 0..20 {
+            let session = MarketHours::session_fixed(sim.now());
+            if seen.last() != Some(&session) {
+                seen.push(session);
+            }
+            sim.advance(chrono::Duration::hours(1));
+        }
+        assert_eq!(seen, vec![Session::Closed, Session::PreMarket, Session::Regular, Session::AfterHours, Session::Closed]);
+        assert!(Session::AfterHours.is_open(true));
+        assert!(!Session::AfterHours.is_open(false));
+    }
+
+    #[test]
+    fn session_follows_the_installed_clock() {
+        // nothing answers on tx_db, so there's no calendar or Alpaca clock and the fixed hours apply
+        let (tx_db, rx_db) = crossbeam_channel::unbounded();
+        drop(rx_db);
+        // 2023-11-22 10:00 New York
+        let sim = SimulatedClock::new(DateTime::parse_from_rfc3339("2023-11-22T15:00:00Z").unwrap().with_timezone(&Utc));
+        clock::set(std::sync::Arc::new(sim.clone()));
+
+        let regular = MarketHours::session(tx_db.clone());
+        let close = MarketHours::regular_close(tx_db.clone());
+        sim.advance(chrono::Duration::hours(7));
+        let after_hours = MarketHours::session(tx_db.clone());
+        clock::reset();
+
+        assert_eq!((regular, after_hours), (Session::Regular, Session::AfterHours));
+        assert_eq!(close, Some(DateTime::parse_from_rfc3339("2023-11-22T21:00:00Z").unwrap().with_timezone(&Utc)));
+    }
+}
@@ -0,0 +1,410 @@
+//! optimizer.rs
+//!
+//! Parameter sweeps over the Settings tunables, scored by replaying history through the backtester.
+//!
+//! A SearchSpace lists the values to try for each parameter; a grid search tries every combination and a
+//! random search a sample of them. With walk-forward on, the range is cut into rolling train/test windows:
+//! every candidate is scored on each train window, the best on train is replayed on the following test
+//! window, and the test scores show how well picking on past data held up. Candidates are ranked by their
+//! mean train score.
+//!
+//! The score is return over drawdown: percent return divided by the max drawdown percent, with the
+//! drawdown floored at 1% so a run that never dipped doesn't score infinitely.
+//!
+//! Run it with `trader optimize` (trader/src/optimize.rs), which can save the winner as a named
+//! trade settings profile.
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, Duration, Utc};
+use crate::backtest::{Backtest, BacktestConfig, BacktestRun, HistoryRow};
+use crate::settings::Settings;
+use crate::strategy::Strategy;
+
+/// the drawdown percent a score divides by is at least this
+const MIN_DRAWDOWN_PER_CENT: f64 = 1.0;
+
+/// the Settings fields a sweep can vary; the upgrade_* and acct_* ones act in the database and the risk
+/// check, which a replay doesn't run, so they're left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Param {
+    TradeSize,
+    EmaSmallSize,
+    EmaLargeSize,
+    SellHighPerCentMultiplier,
+    SellHighUpperLimitCents,
+    MaxPositionAgeMinute,
+}
+
+impl Param {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Param::TradeSize => "trade_size",
+            Param::EmaSmallSize => "trade_ema_small_size",
+            Param::EmaLargeSize => "trade_ema_large_size",
+            Param::SellHighPerCentMultiplier => "trade_sell_high_per_cent_multiplier",
+            Param::SellHighUpperLimitCents => "trade_sell_high_upper_limit_cents",
+            Param::MaxPositionAgeMinute => "max_position_age_minute",
+        }
+    }
+
+    /// set this parameter in settings; EMA sizes are truncated to whole bars
+    pub fn apply(&self, settings: &mut Settings, value: &BigDecimal) {
+        match self {
+            Param::TradeSize => settings.trade_size = value.clone(),
+            Param::EmaSmallSize => settings.trade_ema_small_size = value.with_scale(0).to_string().parse().unwrap_or(0),
+            Param::EmaLargeSize => settings.trade_ema_large_size = value.with_scale(0).to_string().parse().unwrap_or(0),
+            Param::SellHighPerCentMultiplier => settings.trade_sell_high_per_cent_multiplier = value.clone(),
+            Param::SellHighUpperLimitCents => settings.trade_sell_high_upper_limit_cents = value.clone(),
+            Param::MaxPositionAgeMinute => settings.max_position_age_minute = value.clone(),
+        }
+    }
+}
+
+impl FromStr for Param {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Param::TradeSize, Param::EmaSmallSize, Param::EmaLargeSize, Param::SellHighPerCentMultiplier, Param::SellHighUpperLimitCents, Param::MaxPositionAgeMinute]
+            .into_iter()
+            .find(|p| p.name() == s.trim())
+            .ok_or_else(|| format!("unknown parameter: {}", s))
+    }
+}
+
+/// the values to try for one parameter
+#[derive(Debug, Clone)]
+pub struct ParamRange {
+    pub param: Param,
+    pub values: Vec<BigDecimal>,
+}
+
+impl FromStr for ParamRange {
+    type Err = String;
+
+    /// "name=1,2,5" for a list or "name=10:60:10" for first:last:step, last included
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, values) = s.split_once('=').ok_or_else(|| format!("expected name=values: {}", s))?;
+        let param = Param::from_str(name)?;
+        let number = |v: &str| BigDecimal::from_str(v.trim()).map_err(|_| format!("not a number in {}: {}", name, v));
+
+        let values = match values.split(':').collect::<Vec<&str>>()[..] {
+            [first, last, step] => {
+                let (first, last, step) = (number(first)?, number(last)?, number(step)?);
+                if step <= BigDecimal::zero() || first > last {
+                    return Err(format!("bad range for {}: {}", name, values));
+                }
+                let mut range = vec![];
+                let mut value = first;
+                while value <= last {
+                    range.push(value.clone());
+                    value += &step;
+                }
+                range
+            },
+            [_] => values.split(',').map(number).collect::<Result<Vec<BigDecimal>, String>>()?,
+            _ => return Err(format!("bad range for {}: {}", name, values)),
+        };
+        if values.is_empty() {
+            return Err(format!("no values for {}", name));
+        }
+        Ok(ParamRange { param, values })
+    }
+}
+
+/// one combination of parameter values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub values: Vec<(Param, BigDecimal)>,
+}
+
+impl Candidate {
+
+    /// base with this candidate's values in place
+    pub fn apply(&self, base: &Settings) -> Settings {
+        let mut settings = base.clone();
+        for (param, value) in self.values.iter() {
+            param.apply(&mut settings, value);
+        }
+        settings
+    }
+
+    /// Pure; whether these settings make sense to replay: positive sizes and a small EMA below the large one
+    pub fn is_valid(settings: &Settings) -> bool {
+        settings.trade_size > BigDecimal::zero()
+            && settings.trade_ema_small_size > 0
+            && settings.trade_ema_small_size < settings.trade_ema_large_size
+            && settings.trade_sell_high_per_cent_multiplier > BigDecimal::zero()
+            && settings.trade_sell_high_upper_limit_cents > BigDecimal::zero()
+            && settings.max_position_age_minute >= BigDecimal::zero()
+    }
+
+    pub fn label(&self) -> String {
+        self.values.iter().map(|(param, value)| format!("{}={}", param.name(), value)).collect::<Vec<String>>().join(" ")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Search {
+    Grid,
+    /// this many distinct combinations, picked with the seed
+    Random { samples: usize, seed: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchSpace {
+    pub ranges: Vec<ParamRange>,
+}
+
+impl SearchSpace {
+
+    /// combinations in the full grid
+    pub fn size(&self) -> usize {
+        self.ranges.iter().map(|r| r.values.len()).product()
+    }
+
+    /// the index'th combination of the grid, first parameter varying slowest
+    fn point(&self, mut index: usize) -> Candidate {
+        let mut values = vec![];
+        for range in self.ranges.iter().rev() {
+            values.push((range.param, range.values[index % range.values.len()].clone()));
+            index /= range.values.len();
+        }
+        values.reverse();
+        Candidate { values }
+    }
+
+    /// Pure; the candidates a search tries, in order. A random search asking for at least the grid's size
+    /// gets the whole grid.
+    pub fn candidates(&self, search: &Search) -> Vec<Candidate> {
+        let size = self.size();
+        match search {
+            Search::Random { samples, seed } if *samples < size => {
+                // xorshift64; reproducible for a given seed and needs no extra crate
+                let mut state = std::cmp::max(*seed, 1);
+                let mut picked: Vec<usize> = vec![];
+                while picked.len() < *samples {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let index = (state % size as u64) as usize;
+                    if !picked.contains(&index) {
+                        picked.push(index);
+                    }
+                }
+                picked.into_iter().map(|i| self.point(i)).collect()
+            },
+            _ => (0..size).map(|i| self.point(i)).collect(),
+        }
+    }
+}
+
+/// train on [train_start, train_end), then test on [train_end, test_end); no test when they're equal
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    pub train_start: DateTime<Utc>,
+    pub train_end: DateTime<Utc>,
+    pub test_end: DateTime<Utc>,
+}
+
+impl Window {
+
+    /// the whole range as training, without a test window
+    pub fn whole(start: DateTime<Utc>, end: DateTime<Utc>) -> Window {
+        Window { train_start: start, train_end: end, test_end: end }
+    }
+
+    pub fn has_test(&self) -> bool {
+        self.test_end > self.train_end
+    }
+
+    /// Pure; rolling windows over [start, end): each trains on train then tests on the test that follows,
+    /// and the next one starts a test later. Empty if one doesn't fit.
+    pub fn walk_forward(start: DateTime<Utc>, end: DateTime<Utc>, train: Duration, test: Duration) -> Vec<Window> {
+        let mut windows = vec![];
+        if train <= Duration::zero() || test <= Duration::zero() {
+            return windows;
+        }
+        let mut train_start = start;
+        while train_start + train + test <= end {
+            windows.push(Window { train_start, train_end: train_start + train, test_end: train_start + train + test });
+            train_start += test;
+        }
+        windows
+    }
+}
+
+/// Pure; percent return over max drawdown percent, the drawdown floored at MIN_DRAWDOWN_PER_CENT
+pub fn risk_adjusted(run: &BacktestRun) -> f64 {
+    if run.cash_start <= BigDecimal::zero() {
+        return 0.0;
+    }
+    let return_per_cent = (&run.pnl * BigDecimal::from(100) / &run.cash_start).to_string().parse::<f64>().unwrap_or(0.0);
+    let drawdown_per_cent = run.max_drawdown_per_cent.to_string().parse::<f64>().unwrap_or(0.0);
+    return_per_cent / drawdown_per_cent.max(MIN_DRAWDOWN_PER_CENT)
+}
+
+#[derive(Debug, Clone)]
+pub struct CandidateResult {
+    pub candidate: Candidate,
+    pub settings: Settings,
+    /// one per window, in window order
+    pub train_runs: Vec<BacktestRun>,
+    pub train_scores: Vec<f64>,
+    pub mean_train_score: f64,
+}
+
+/// the best candidate on one window's training range, and how it did on the test range after it
+#[derive(Debug, Clone)]
+pub struct WindowResult {
+    pub window: Window,
+    /// index into SweepReport.ranked
+    pub best: usize,
+    pub train_score: f64,
+    pub test_run: Option<BacktestRun>,
+    pub test_score: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SweepReport {
+    /// best mean train score first
+    pub ranked: Vec<CandidateResult>,
+    pub windows: Vec<WindowResult>,
+}
+
+impl SweepReport {
+
+    /// mean of the out-of-sample scores, if there were test windows
+    pub fn mean_test_score(&self) -> Option<f64> {
+        let scores: Vec<f64> = self.windows.iter().filter_map(|w| w.test_score).collect();
+        if scores.is_empty() {
+            None
+        } else {
+            Some(scores.iter().sum::<f64>() / scores.len() as f64)
+        }
+    }
+}
+
+/// a sweep over history already loaded into memory
+pub struct Optimizer<'a> {
+    /// range, symbols and fill model; start and end are overridden per window
+    pub config: BacktestConfig,
+    /// the settings the candidates vary
+    pub settings: Settings,
+    /// shares per buy by symbol; ignored when trade_size is swept
+    pub trade_sizes: HashMap<String, BigDecimal>,
+    /// fresh strategies for each replay
+    pub strategies: &'a dyn Fn() -> Vec<Box<dyn Strategy>>,
+}
+
+impl<'a> Optimizer<'a> {
+
+    /// Score every valid candidate on every window; rows are the history for the whole range, oldest first.
+    /// Each replay keeps its own simulated time, like Backtest::run.
+    pub fn run(&self, candidates: Vec<Candidate>, windows: &[Window], rows: &[HistoryRow]) -> SweepReport {
+        let sweeps_trade_size = candidates.iter().any(|c| c.values.iter().any(|(p, _)| *p == Param::TradeSize));
+        let trade_sizes = if sweeps_trade_size { HashMap::new() } else { self.trade_sizes.clone() };
+
+        let mut ranked: Vec<CandidateResult> = vec![];
+        for (number, candidate) in candidates.into_iter().enumerate() {
+            let settings = candidate.apply(&self.settings);
+            if !Candidate::is_valid(&settings) {
+                tracing::info!("[Optimizer::run] skipping invalid candidate {}", candidate.label());
+                continue;
+            }
+            let train_runs: Vec<BacktestRun> = windows.iter()
+                .map(|w| self.replay(&settings, &trade_sizes, w.train_start, w.train_end, rows))
+                .collect();
+            let train_scores: Vec<f64> = train_runs.iter().map(risk_adjusted).collect();
+            let mean_train_score = train_scores.iter().sum::<f64>() / std::cmp::max(train_scores.len(), 1) as f64;
+            tracing::info!("[Optimizer::run] candidate {}: {} mean train score {:.4}", number + 1, candidate.label(), mean_train_score);
+            ranked.push(CandidateResult { candidate, settings, train_runs, train_scores, mean_train_score });
+        }
+        ranked.sort_by(|a, b| b.mean_train_score.total_cmp(&a.mean_train_score));
+
+        let mut window_results = vec![];
+        for (index, window) in windows.iter().enumerate() {
+            let best = match (0..ranked.len()).max_by(|a, b| ranked[*a].train_scores[index].total_cmp(&ranked[*b].train_scores[index])) {
+                Some(best) => best,
+                None => continue,
+            };
+            let test_run = window.has_test()
+                .then(|| self.replay(&ranked[best].settings, &trade_sizes, window.train_end, window.test_end, rows));
+            window_results.push(WindowResult {
+                window: window.clone(),
+                best,
+                train_score: ranked[best].train_scores[index],
+                test_score: test_run.as_ref().map(risk_adjusted),
+                test_run,
+            });
+        }
+
+        SweepReport { ranked, windows: window_results }
+    }
+
+    fn replay(&self, settings: &Settings, trade_sizes: &HashMap<String, BigDecimal>, start: DateTime<Utc>, end: DateTime<Utc>, rows: &[HistoryRow]) -> BacktestRun {
+        let from = rows.partition_point(|r| r.dtg < start);
+        let to = rows.partition_point(|r| r.dtg < end);
+        let config = BacktestConfig { start, end, ..self.config.clone() };
+        Backtest::run_rows(&config, settings, (self.strategies)(), trade_sizes.clone(), &rows[from..to]).run
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn day(d: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2023-06-01T00:00:00Z").unwrap().with_timezone(&Utc) + Duration::days(d)
+    }
+
+    #[test]
+    fn ranges_parse_as_lists_and_steps() {
+        let range = ParamRange::from_str("trade_ema_small_size=3,5,8").unwrap();
+        assert_eq!(range.param, Param::EmaSmallSize);
+        assert_eq!(range.values, vec![dec("3"), dec("5"), dec("8")]);
+
+        let range = ParamRange::from_str("max_position_age_minute=30:60:15").unwrap();
+        assert_eq!(range.values, vec![dec("30"), dec("45"), dec("60")]);
+
+        assert!(ParamRange::from_str("upgrade_min_profit=1,2").is_err());
+        assert!(ParamRange::from_str("trade_size=5:1:1").is_err());
+        assert!(ParamRange::from_str("trade_size=a").is_err());
+    }
+
+    #[test]
+    fn grid_and_random_candidates() {
+        let space = SearchSpace { ranges: vec![
+            ParamRange::from_str("trade_ema_small_size=3,5").unwrap(),
+            ParamRange::from_str("trade_ema_large_size=20,30,40").unwrap(),
+        ] };
+        let grid = space.candidates(&Search::Grid);
+        assert_eq!(grid.len(), 6);
+        assert_eq!(grid[0].values, vec![(Param::EmaSmallSize, dec("3")), (Param::EmaLargeSize, dec("20"))]);
+        assert_eq!(grid[5].values, vec![(Param::EmaSmallSize, dec("5")), (Param::EmaLargeSize, dec("40"))]);
+
+        let random = space.candidates(&Search::Random { samples: 4, seed: 42 });
+        assert_eq!(random.len(), 4);
+        assert!(random.iter().all(|c| grid.contains(c)));
+        assert_eq!(random, space.candidates(&Search::Random { samples: 4, seed: 42 }));
+        assert_eq!(space.candidates(&Search::Random { samples: 10, seed: 42 }).len(), 6);
+    }
+
+    #[test]
+    fn walk_forward_windows_roll_by_the_test_length() {
+        let windows = Window::walk_forward(day(0), day(10), Duration::days(5), Duration::days(2));
+        assert_eq!(windows, vec![
+            Window { train_start: day(0), train_end: day(5), test_end: day(7) },
+            Window { train_start: day(2), train_end: day(7), test_end: day(9) },
+        ]);
+        assert!(Window::walk_forward(day(0), day(3), Duration::days(5), Duration::days(2)).is_empty());
+        assert!(!Window::whole(day(0), day(3)).has_test());
+    }
+}
@@ -4,6 +4,7 @@ use bigdecimal::BigDecimal;
 use chrono::{DateTime, Utc};
 use crossbeam_channel::Sender;
 use sqlx::types::Uuid;
+use crate::clock;
 use crate::db::DbMsg;
 use crate::trade_struct::TradeSide;
 
@@ -29,7 +30,7 @@ impl OrderLogEntry{
 
         // TODO: let this be assignable since we want to assign the same group to sales subsequent to buys
         let id_group = Uuid::new_v4();
-        let dtg = Utc::now();
+        let dtg = clock::now();
 
         tracing::debug!("[new] group: {}, uuid: {}", &id_group, &id);
 
@@ -0,0 +1,594 @@
+//! order_manager.rs
+//!
+//! Cancel and replace for orders already on Alpaca, plus the stale limit order policy. Limit sells from
+//! alpaca_api::sell that never fill hold their shares (qty_available drops) until end of day; the
+//! policy in t_order_policy reprices or cancels them once they're older than stale_limit_age_seconds. It's
+//! off until the operator sets stale_limit_enabled. Every cancel and replace is recorded in log_order_change.
+//!
+
+use std::fmt;
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use crate::alpaca_api::post_order;
+use crate::alpaca_order::Order;
+use crate::alpaca_position::{Position, PositionSide};
+use crate::clock;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::order_sizing::SizingMode;
+use crate::risk::RiskManager;
+use crate::settings::Settings;
+use crate::trade_struct::{JsonReplace, JsonTrade, OrderClass, OrderType, StopLoss, TakeProfit, TimeInForce, TradeSide};
+
+/// what to do with a limit order that has sat too long
+#[derive(sqlx::Type, Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[sqlx(type_name = "VARCHAR", rename_all = "lowercase")]
+pub enum StaleLimitAction {
+    #[serde(rename = "reprice")]
+    Reprice,
+    #[serde(rename = "cancel")]
+    Cancel,
+}
+
+/// exits attached to a buy; see OrderManager::entry_exits
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryExits {
+    pub order_class: OrderClass,
+    pub take_profit: Option<TakeProfit>,
+    pub stop_loss: Option<StopLoss>,
+}
+
+/// reflects the latest row in t_order_policy
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct OrderPolicy {
+    pub dtg: DateTime<Utc>,
+    pub stale_limit_enabled: bool,
+    pub stale_limit_age_seconds: i32,
+    pub stale_limit_action: StaleLimitAction,
+    /// attach take-profit and stop-loss legs to every buy
+    pub bracket_enabled: bool,
+    pub bracket_take_profit_per_cent: BigDecimal,
+    pub bracket_stop_loss_per_cent: BigDecimal,
+    pub sizing_mode: SizingMode,
+    /// allow sell_short; off by default
+    pub short_enabled: bool,
+    /// only short what Alpaca marks easy_to_borrow
+    pub short_require_easy_to_borrow: bool,
+    /// sell positions older than Settings.max_position_age_minute
+    pub age_exit_enabled: bool,
+    /// close every position in the last eod_flatten_minutes of the regular session
+    pub eod_flatten_enabled: bool,
+    pub eod_flatten_minutes: i32,
+    /// how far below (above, for a cover) the last price the limit may step by eod_flatten_market_minutes
+    pub eod_flatten_max_concession_per_cent: BigDecimal,
+    /// switch to market orders this close to the close
+    pub eod_flatten_market_minutes: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderChangeAction {
+    Cancel,
+    Replace,
+}
+
+impl fmt::Display for OrderChangeAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderChangeAction::Cancel => write!(f, "cancel"),
+            OrderChangeAction::Replace => write!(f, "replace"),
+        }
+    }
+}
+
+/// one row of log_order_change
+#[derive(Debug, Clone)]
+pub struct OrderChange {
+    pub dtg: DateTime<Utc>,
+    pub action: OrderChangeAction,
+    pub order_id: String,
+    pub client_order_id: Option<String>,
+    pub symbol: Option<String>,
+    pub side: Option<TradeSide>,
+    pub qty: Option<BigDecimal>,
+    pub limit_price_old: Option<BigDecimal>,
+    pub limit_price_new: Option<BigDecimal>,
+    pub order_id_new: Option<String>,
+    pub reason: String,
+}
+
+impl OrderChange {
+    fn new(action: OrderChangeAction, order: &Order, reason: &str) -> OrderChange {
+        OrderChange {
+            dtg: clock::now(),
+            action,
+            order_id: order.id.clone(),
+            client_order_id: Some(order.client_order_id.clone()),
+            symbol: Some(order.symbol.clone()),
+            side: Some(order.side.clone()),
+            qty: Some(order.qty.clone()),
+            limit_price_old: order.limit_price.clone(),
+            limit_price_new: None,
+            order_id_new: None,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// what the stale order policy decided for one open order
+#[derive(Debug, Clone, PartialEq)]
+pub enum StaleOrderDecision {
+    Cancel,
+    Reprice { limit_price: BigDecimal },
+}
+
+pub struct OrderManager {}
+
+impl OrderManager {
+
+    /// DELETE /v2/orders/{id}
+    pub fn cancel(order: &Order, reason: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+        tracing::info!("[cancel] {} {} {} {}: {}", &order.symbol, &order.side, &order.qty, &order.id, reason);
+
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RestCancelOrder { order_id: order.id.clone(), settings: settings.clone(), sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?;
+
+        let _ = tx_db.send(DbMsg::OrderChangeSave { change: OrderChange::new(OrderChangeAction::Cancel, order, reason) });
+        Ok(())
+    }
+
+    /// Cancel by id when only the id is known (frontend, command line). Looks the order up first so the
+    /// log has the symbol and price.
+    pub fn cancel_by_id(order_id: &str, reason: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+        let orders = OrderManager::open_orders(settings, tx_db.clone())?;
+        match orders.iter().find(|o| o.id == order_id) {
+            Some(order) => OrderManager::cancel(order, reason, settings, tx_db),
+            None => Err(TradeWebError::TransactionNotFound),
+        }
+    }
+
+    /// Cancel every open order for a symbol; returns how many were canceled.
+    pub fn cancel_by_symbol(symbol: &str, reason: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<usize, TradeWebError> {
+        let orders = OrderManager::open_orders(settings, tx_db.clone())?;
+        let mut canceled = 0;
+        for order in orders.iter().filter(|o| o.symbol.eq_ignore_ascii_case(symbol)) {
+            match OrderManager::cancel(order, reason, settings, tx_db.clone()) {
+                Ok(()) => canceled += 1,
+                Err(e) => tracing::error!("[cancel_by_symbol] {} not canceled: {:?}", &order.id, &e),
+            }
+        }
+        Ok(canceled)
+    }
+
+    /// PATCH /v2/orders/{id}
+    ///
+    /// Alpaca cancels the original and returns the new order, which gets a new id. The order as replaced goes
+    /// through the same pre-trade check as a new one, so nothing is replaced while the kill switch is engaged.
+    pub fn replace(order: &Order, json_replace: JsonReplace, reason: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<Order, TradeWebError> {
+        tracing::info!("[replace] {} {} {} {}: {:?} ({})", &order.symbol, &order.side, &order.qty, &order.id, &json_replace, reason);
+        RiskManager::approve(&OrderManager::replaced_trade(order, &json_replace), settings, tx_db.clone())?;
+
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RestReplaceOrder { order_id: order.id.clone(), json_replace, settings: settings.clone(), sender }).map_err(|_| TradeWebError::ChannelError)?;
+        let new_order = rx.recv().map_err(|_| TradeWebError::ChannelError)?;
+
+        let change = OrderChange {
+            limit_price_new: new_order.limit_price.clone(),
+            order_id_new: Some(new_order.id.clone()),
+            qty: Some(new_order.qty.clone()),
+            ..OrderChange::new(OrderChangeAction::Replace, order, reason)
+        };
+        let _ = tx_db.send(DbMsg::OrderChangeSave { change });
+        new_order.save(tx_db);
+        Ok(new_order)
+    }
+
+    /// Pure; order with json_replace applied, as the JsonTrade the risk check sees
+    pub fn replaced_trade(order: &Order, json_replace: &JsonReplace) -> JsonTrade {
+        JsonTrade {
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            time_in_force: json_replace.time_in_force.clone().unwrap_or_else(|| order.time_in_force.clone()),
+            qty: Some(json_replace.qty.clone().unwrap_or_else(|| order.qty.clone())),
+            notional: None,
+            order_type: order.order_type_v2.clone(),
+            limit_price: json_replace.limit_price.clone().or_else(|| order.limit_price.clone()),
+            extended_hours: Some(order.extended_hours),
+            client_order_id: json_replace.client_order_id.clone().unwrap_or_else(|| order.client_order_id.clone()),
+            stop_price: order.stop_price.clone(),
+            trail_percent: order.trail_percent.clone(),
+            trail_price: order.trail_price.clone(),
+            order_class: None,
+            take_profit: None,
+            stop_loss: None,
+        }
+    }
+
+    /// replace by id; see cancel_by_id
+    pub fn replace_by_id(order_id: &str, json_replace: JsonReplace, reason: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<Order, TradeWebError> {
+        let orders = OrderManager::open_orders(settings, tx_db.clone())?;
+        match orders.iter().find(|o| o.id == order_id) {
+            Some(order) => OrderManager::replace(order, json_replace, reason, settings, tx_db),
+            None => Err(TradeWebError::TransactionNotFound),
+        }
+    }
+
+    /// Apply t_order_policy to every open limit order. Called from the REST poller.
+    pub fn enforce_stale_limit_orders(settings: &Settings, tx_db: Sender<DbMsg>) {
+        let policy = match OrderManager::policy(tx_db.clone()) {
+            Ok(policy) => policy,
+            Err(e) => {
+                tracing::error!("[enforce_stale_limit_orders] no order policy: {:?}", &e);
+                return;
+            }
+        };
+        if !policy.stale_limit_enabled {
+            return;
+        }
+
+        let orders = match OrderManager::open_orders(settings, tx_db.clone()) {
+            Ok(orders) => orders,
+            Err(e) => {
+                tracing::error!("[enforce_stale_limit_orders] couldn't get open orders: {:?}", &e);
+                return;
+            }
+        };
+
+        let now = clock::now();
+        for order in orders.iter() {
+            let price_last = OrderManager::price_last(&order.symbol, tx_db.clone()).ok();
+            let reason = format!("limit order older than {}s", policy.stale_limit_age_seconds);
+            let result = match OrderManager::stale_decision(order, now, &policy, price_last.as_ref()) {
+                None => continue,
+                Some(StaleOrderDecision::Cancel) => OrderManager::cancel(order, &reason, settings, tx_db.clone()),
+                Some(StaleOrderDecision::Reprice { limit_price }) => {
+                    let json_replace = JsonReplace { limit_price: Some(limit_price), ..Default::default() };
+                    OrderManager::replace(order, json_replace, &reason, settings, tx_db.clone()).map(|_| ())
+                }
+            };
+            if let Err(e) = result {
+                tracing::error!("[enforce_stale_limit_orders] {} {}: {:?}", &order.symbol, &order.id, &e);
+            }
+        }
+    }
+
+    /// Pure decision; no database or network access so it can be unit tested.
+    ///
+    /// Only open, simple limit orders past the age limit are touched; bracket/oto legs are left to
+    /// Alpaca so an exit isn't pulled out from under its position. A reprice moves the limit to the latest
+    /// trade price; with no price, or if the limit is already there, the order is canceled instead.
+    pub fn stale_decision(order: &Order, now: DateTime<Utc>, policy: &OrderPolicy, price_last: Option<&BigDecimal>) -> Option<StaleOrderDecision> {
+        if order.order_type_v2 != OrderType::Limit || order.filled_at.is_some() {
+            return None;
+        }
+        if let Some(order_class) = order.order_class.as_deref() {
+            if !order_class.is_empty() && order_class != "simple" {
+                return None;
+            }
+        }
+        let age_seconds = (now - order.created_at).num_seconds();
+        if age_seconds < policy.stale_limit_age_seconds as i64 {
+            return None;
+        }
+
+        match policy.stale_limit_action {
+            StaleLimitAction::Cancel => Some(StaleOrderDecision::Cancel),
+            StaleLimitAction::Reprice => match (price_last, order.limit_price.as_ref()) {
+                (Some(price), Some(limit)) if *price > BigDecimal::from(0) && price != limit => {
+                    Some(StaleOrderDecision::Reprice { limit_price: price.round(2) })
+                },
+                _ => Some(StaleOrderDecision::Cancel),
+            },
+        }
+    }
+
+    /// Exit legs for a buy entered near entry_price, or None if brackets are off. A zero percentage leaves
+    /// that leg out: both legs make a bracket, one makes an oto.
+    pub fn entry_exits(entry_price: &BigDecimal, policy: &OrderPolicy) -> Option<EntryExits> {
+        if !policy.bracket_enabled {
+            return None;
+        }
+        let (take_profit, stop_loss) = OrderManager::exit_legs(entry_price, &PositionSide::Long, policy);
+        let order_class = match (&take_profit, &stop_loss) {
+            (Some(_), Some(_)) => OrderClass::Bracket,
+            (None, None) => return None,
+            _ => OrderClass::Oto,
+        };
+        Some(EntryExits { order_class, take_profit, stop_loss })
+    }
+
+    /// Pure; an oco exit for a position that's already open, e.g. one bought before brackets were turned on:
+    /// a take-profit limit and a stop-loss for the shares not already held by an order, priced from the
+    /// position's entry with the bracket percentages. None unless both percentages are set.
+    pub fn oco_exit(position: &Position, policy: &OrderPolicy) -> Option<JsonTrade> {
+        let qty = position.qty_available.abs();
+        if qty <= BigDecimal::from(0) {
+            return None;
+        }
+        let (Some(take_profit), Some(stop_loss)) = OrderManager::exit_legs(&position.avg_entry_price, &position.side, policy) else {
+            return None;
+        };
+        Some(JsonTrade {
+            symbol: position.symbol.to_uppercase(),
+            side: match position.side {
+                PositionSide::Long => TradeSide::Sell,
+                PositionSide::Short => TradeSide::Buy,
+            },
+            time_in_force: TimeInForce::Gtc,
+            qty: Some(qty),
+            notional: None,
+            order_type: OrderType::Limit,
+            limit_price: None,
+            extended_hours: None,
+            client_order_id: uuid::Uuid::new_v4().to_string(),
+            stop_price: None,
+            trail_percent: None,
+            trail_price: None,
+            order_class: Some(OrderClass::Oco),
+            take_profit: Some(take_profit),
+            stop_loss: Some(stop_loss),
+        })
+    }
+
+    /// post an oco exit for the open position in symbol; see oco_exit
+    pub fn protect(symbol: &str, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<Order, TradeWebError> {
+        let policy = OrderManager::policy(tx_db.clone())?;
+        let positions = Position::get_remote(settings, tx_db.clone())?;
+        let position = positions.iter().find(|p| p.symbol.eq_ignore_ascii_case(symbol)).ok_or(TradeWebError::NotFound)?;
+        let json_trade = OrderManager::oco_exit(position, &policy).ok_or(TradeWebError::NoSharesFound)?;
+        tracing::info!("[protect] {} {:?} {:?} {:?}", &json_trade.symbol, &json_trade.qty, &json_trade.take_profit, &json_trade.stop_loss);
+        post_order(json_trade, settings, tx_db)
+    }
+
+    /// Pure; take-profit and stop-loss for a position entered at entry_price, from the bracket percentages:
+    /// above and below the entry for a long, the other way round for a short. A zero percentage leaves that
+    /// leg out.
+    ///
+    /// Alpaca rejects a take-profit that isn't at least a cent past the entry (or a stop a cent short of it),
+    /// so both are rounded to cents and pushed out by a cent if the percentage is too small.
+    fn exit_legs(entry_price: &BigDecimal, side: &PositionSide, policy: &OrderPolicy) -> (Option<TakeProfit>, Option<StopLoss>) {
+        let zero = BigDecimal::from(0);
+        if *entry_price <= zero {
+            return (None, None);
+        }
+        let hundred = BigDecimal::from(100);
+        let cent = match side {
+            PositionSide::Long => BigDecimal::new(1.into(), 2),
+            PositionSide::Short => BigDecimal::new((-1).into(), 2),
+        };
+        let direction = match side {
+            PositionSide::Long => BigDecimal::from(1),
+            PositionSide::Short => BigDecimal::from(-1),
+        };
+
+        let take_profit = if policy.bracket_take_profit_per_cent > zero {
+            let take_profit = (entry_price * (&hundred + &direction * &policy.bracket_take_profit_per_cent) / &hundred).round(2);
+            let take_profit = if &direction * (&take_profit - entry_price) < cent.abs() { (entry_price + &cent).round(2) } else { take_profit };
+            Some(TakeProfit { limit_price: take_profit })
+        } else {
+            None
+        };
+
+        let stop_loss = if policy.bracket_stop_loss_per_cent > zero {
+            let stop_loss = (entry_price * (&hundred - &direction * &policy.bracket_stop_loss_per_cent) / &hundred).round(2);
+            let stop_loss = if &direction * (entry_price - &stop_loss) < cent.abs() { (entry_price - &cent).round(2) } else { stop_loss };
+            Some(StopLoss { stop_price: stop_loss, limit_price: None })
+        } else {
+            None
+        };
+        (take_profit, stop_loss)
+    }
+
+    /// open orders straight from Alpaca
+    pub fn open_orders(settings: &Settings, tx_db: Sender<DbMsg>) -> Result<Vec<Order>, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RestGetOrders { settings: settings.clone(), sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    pub fn policy(tx_db: Sender<DbMsg>) -> Result<OrderPolicy, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::OrderPolicyGet { sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    fn price_last(symbol: &str, tx_db: Sender<DbMsg>) -> Result<BigDecimal, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::PriceLastGet { symbol: symbol.to_string(), sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use chrono::Duration;
+    use crate::trade_struct::TimeInForce;
+    use super::*;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn limit_sell(created_at: DateTime<Utc>, limit_price: &str) -> Order {
+        Order {
+            id: "order-1".to_string(),
+            client_order_id: "client-1".to_string(),
+            created_at,
+            updated_at: created_at,
+            submitted_at: created_at,
+            filled_at: None,
+            expired_at: None,
+            canceled_at: None,
+            failed_at: None,
+            replaced_at: None,
+            replaced_by: None,
+            replaces: None,
+            asset_id: None,
+            symbol: "AAPL".to_string(),
+            asset_class: None,
+            notional: None,
+            qty: dec("7"),
+            filled_qty: None,
+            filled_avg_price: None,
+            order_class: None,
+            order_type_v2: OrderType::Limit,
+            side: TradeSide::Sell,
+            time_in_force: TimeInForce::Day,
+            limit_price: Some(dec(limit_price)),
+            stop_price: None,
+            status: "new".to_string(),
+            extended_hours: false,
+            trail_percent: None,
+            trail_price: None,
+            hwm: None,
+        }
+    }
+
+    fn policy(action: StaleLimitAction) -> OrderPolicy {
+        OrderPolicy {
+            dtg: Utc::now(),
+            stale_limit_enabled: true,
+            stale_limit_age_seconds: 300,
+            stale_limit_action: action,
+            bracket_enabled: true,
+            bracket_take_profit_per_cent: dec("0.5"),
+            bracket_stop_loss_per_cent: dec("1.0"),
+            sizing_mode: SizingMode::WholeShares,
+            short_enabled: false,
+            short_require_easy_to_borrow: true,
+            age_exit_enabled: false,
+            eod_flatten_enabled: false,
+            eod_flatten_minutes: 15,
+            eod_flatten_max_concession_per_cent: dec("0.5"),
+            eod_flatten_market_minutes: 2,
+        }
+    }
+
+    #[test]
+    fn young_orders_are_left_alone() {
+        let now = Utc::now();
+        let order = limit_sell(now - Duration::seconds(299), "101.00");
+        assert_eq!(OrderManager::stale_decision(&order, now, &policy(StaleLimitAction::Cancel), Some(&dec("100"))), None);
+    }
+
+    #[test]
+    fn stale_order_is_repriced_to_last_trade() {
+        let now = Utc::now();
+        let order = limit_sell(now - Duration::seconds(301), "101.00");
+        assert_eq!(
+            OrderManager::stale_decision(&order, now, &policy(StaleLimitAction::Reprice), Some(&dec("100.123"))),
+            Some(StaleOrderDecision::Reprice { limit_price: dec("100.12") })
+        );
+    }
+
+    #[test]
+    fn stale_order_without_price_is_canceled() {
+        let now = Utc::now();
+        let order = limit_sell(now - Duration::seconds(301), "101.00");
+        assert_eq!(OrderManager::stale_decision(&order, now, &policy(StaleLimitAction::Reprice), None), Some(StaleOrderDecision::Cancel));
+        assert_eq!(OrderManager::stale_decision(&order, now, &policy(StaleLimitAction::Cancel), Some(&dec("100"))), Some(StaleOrderDecision::Cancel));
+    }
+
+    #[test]
+    fn replaced_trade_applies_the_replace_to_the_order() {
+        let order = limit_sell(Utc::now(), "101.00");
+        let trade = OrderManager::replaced_trade(&order, &JsonReplace { limit_price: Some(dec("100.50")), ..Default::default() });
+        assert_eq!((trade.side, trade.qty, trade.limit_price), (TradeSide::Sell, Some(dec("7")), Some(dec("100.50"))));
+        assert_eq!(trade.client_order_id, "client-1");
+    }
+
+    #[test]
+    fn entry_exits_bracket_the_entry() {
+        let exits = OrderManager::entry_exits(&dec("100"), &policy(StaleLimitAction::Cancel)).unwrap();
+        assert_eq!(exits.order_class, OrderClass::Bracket);
+        assert_eq!(exits.take_profit.unwrap().limit_price, dec("100.50"));
+        assert_eq!(exits.stop_loss.unwrap().stop_price, dec("99.00"));
+
+        // too small a percentage on a cheap stock still leaves a cent each way
+        let exits = OrderManager::entry_exits(&dec("1.00"), &policy(StaleLimitAction::Cancel)).unwrap();
+        assert_eq!(exits.take_profit.unwrap().limit_price, dec("1.01"));
+        assert_eq!(exits.stop_loss.unwrap().stop_price, dec("0.99"));
+
+        let mut off = policy(StaleLimitAction::Cancel);
+        off.bracket_enabled = false;
+        assert!(OrderManager::entry_exits(&dec("100"), &off).is_none());
+    }
+
+    #[test]
+    fn entry_exits_with_one_leg_is_an_oto() {
+        let mut stop_only = policy(StaleLimitAction::Cancel);
+        stop_only.bracket_take_profit_per_cent = dec("0");
+        let exits = OrderManager::entry_exits(&dec("100"), &stop_only).unwrap();
+        assert_eq!((exits.order_class, exits.take_profit), (OrderClass::Oto, None));
+        assert_eq!(exits.stop_loss.unwrap().stop_price, dec("99.00"));
+
+        stop_only.bracket_stop_loss_per_cent = dec("0");
+        assert!(OrderManager::entry_exits(&dec("100"), &stop_only).is_none());
+    }
+
+    fn position(side: PositionSide, qty: &str, avg_entry_price: &str) -> Position {
+        Position {
+            dtg: Utc::now(),
+            asset_id: "asset".to_string(),
+            symbol: "aapl".to_string(),
+            exchange: "NASDAQ".to_string(),
+            asset_class: "us_equity".to_string(),
+            avg_entry_price: dec(avg_entry_price),
+            qty: dec(qty),
+            qty_available: dec(qty),
+            side,
+            market_value: dec("0"),
+            cost_basis: dec("0"),
+            unrealized_pl: dec("0"),
+            unrealized_plpc: dec("0"),
+            unrealized_intraday_pl: dec("0"),
+            unrealized_intraday_plpc: dec("0"),
+            current_price: dec(avg_entry_price),
+            lastday_price: dec(avg_entry_price),
+            change_today: dec("0"),
+            dtg_updated: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn oco_exit_serializes_both_legs() {
+        let trade = OrderManager::oco_exit(&position(PositionSide::Long, "10", "100"), &policy(StaleLimitAction::Cancel)).unwrap();
+        let json = serde_json::to_value(&trade).unwrap();
+        assert_eq!(json["order_class"], "oco");
+        assert_eq!(json["side"], "sell");
+        assert_eq!(json["type"], "limit");
+        assert_eq!(json["symbol"], "AAPL");
+        assert_eq!(json["take_profit"]["limit_price"], "100.5");
+        assert_eq!(json["stop_loss"]["stop_price"], "99");
+
+        // a short is covered with a buy: take profit below the entry, stop above
+        let trade = OrderManager::oco_exit(&position(PositionSide::Short, "-10", "100"), &policy(StaleLimitAction::Cancel)).unwrap();
+        assert_eq!((trade.side, trade.qty), (TradeSide::Buy, Some(dec("10"))));
+        assert_eq!(trade.take_profit.unwrap().limit_price, dec("99.50"));
+        assert_eq!(trade.stop_loss.unwrap().stop_price, dec("101.00"));
+    }
+
+    #[test]
+    fn oco_exit_needs_both_legs_and_free_shares() {
+        let mut stop_only = policy(StaleLimitAction::Cancel);
+        stop_only.bracket_take_profit_per_cent = dec("0");
+        assert!(OrderManager::oco_exit(&position(PositionSide::Long, "10", "100"), &stop_only).is_none());
+        assert!(OrderManager::oco_exit(&position(PositionSide::Long, "0", "100"), &policy(StaleLimitAction::Cancel)).is_none());
+    }
+
+    #[test]
+    fn market_orders_are_ignored() {
+        let now = Utc::now();
+        let mut order = limit_sell(now - Duration::seconds(3000), "101.00");
+        order.order_type_v2 = OrderType::Market;
+        assert_eq!(OrderManager::stale_decision(&order, now, &policy(StaleLimitAction::Cancel), Some(&dec("100"))), None);
+
+        // take-profit leg of a bracket
+        let mut order = limit_sell(now - Duration::seconds(3000), "101.00");
+        order.order_class = Some("bracket".to_string());
+        assert_eq!(OrderManager::stale_decision(&order, now, &policy(StaleLimitAction::Cancel), Some(&dec("100"))), None);
+    }
+}
@@ -0,0 +1,124 @@
+//! order_sizing.rs
+//!
+//! How big a buy to send. Whole shares is the original behavior; with a small account or a high-priced
+//! symbol it often comes out to zero. Fractional and notional sizing (t_order_policy.sizing_mode) spend
+//! the same dollars on a fraction of a share, but only for assets Alpaca marks fractionable.
+//!
+
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+/// Alpaca accepts up to 9 decimal places on a fractional qty
+const FRACTIONAL_QTY_SCALE: i64 = 9;
+
+/// Alpaca's smallest notional order
+pub const NOTIONAL_MIN_DOLLARS: i64 = 1;
+
+#[derive(sqlx::Type, Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[sqlx(type_name = "VARCHAR", rename_all = "snake_case")]
+pub enum SizingMode {
+    #[serde(rename = "whole_shares")]
+    WholeShares,
+    #[serde(rename = "fractional")]
+    Fractional,
+    #[serde(rename = "notional")]
+    Notional,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OrderSize {
+    Qty(BigDecimal),
+    Notional(BigDecimal),
+}
+
+impl OrderSize {
+
+    /// Size a buy of up to trade_size shares with cash_available dollars at price.
+    ///
+    /// Non-fractionable assets always fall back to whole shares. Fractional qty is truncated (never
+    /// rounded up past the cash available); notional is truncated to cents.
+    pub fn for_buy(mode: &SizingMode, fractionable: bool, price: &BigDecimal, cash_available: &BigDecimal, trade_size: &BigDecimal) -> OrderSize {
+        let zero = BigDecimal::from(0);
+        if *price <= zero || *cash_available <= zero {
+            return OrderSize::Qty(zero);
+        }
+
+        let qty_affordable = cash_available / price;
+        match (mode, fractionable) {
+            (SizingMode::Fractional, true) => {
+                let qty = qty_affordable.with_scale(FRACTIONAL_QTY_SCALE);
+                OrderSize::Qty(std::cmp::min(qty, trade_size.clone()))
+            },
+            (SizingMode::Notional, true) => {
+                let dollars = std::cmp::min(cash_available.clone(), trade_size * price).with_scale(2);
+                if dollars < BigDecimal::from(NOTIONAL_MIN_DOLLARS) {
+                    OrderSize::Notional(zero)
+                } else {
+                    OrderSize::Notional(dollars)
+                }
+            },
+            _ => OrderSize::Qty(std::cmp::min(qty_affordable.with_scale(0), trade_size.clone())),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            OrderSize::Qty(qty) => *qty <= BigDecimal::from(0),
+            OrderSize::Notional(dollars) => *dollars <= BigDecimal::from(0),
+        }
+    }
+
+    /// whole shares; the only size Alpaca allows on bracket and extended hours orders
+    pub fn is_whole_shares(&self) -> bool {
+        match self {
+            OrderSize::Qty(qty) => qty.is_integer(),
+            OrderSize::Notional(_) => false,
+        }
+    }
+
+    /// approximate shares, for the order log
+    pub fn qty_estimate(&self, price: &BigDecimal) -> BigDecimal {
+        match self {
+            OrderSize::Qty(qty) => qty.clone(),
+            OrderSize::Notional(dollars) if *price > BigDecimal::from(0) => (dollars / price).with_scale(FRACTIONAL_QTY_SCALE),
+            OrderSize::Notional(_) => BigDecimal::from(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn whole_shares_rounds_down() {
+        // $300 at $450 buys nothing in whole shares
+        assert!(OrderSize::for_buy(&SizingMode::WholeShares, true, &dec("450"), &dec("300"), &dec("7")).is_zero());
+        assert_eq!(OrderSize::for_buy(&SizingMode::WholeShares, true, &dec("100"), &dec("350"), &dec("7")), OrderSize::Qty(dec("3")));
+        assert_eq!(OrderSize::for_buy(&SizingMode::WholeShares, true, &dec("10"), &dec("350"), &dec("7")), OrderSize::Qty(dec("7")));
+    }
+
+    #[test]
+    fn fractional_buys_what_cash_allows() {
+        let size = OrderSize::for_buy(&SizingMode::Fractional, true, &dec("450"), &dec("300"), &dec("7"));
+        assert_eq!(size, OrderSize::Qty(dec("0.666666666")));
+        assert!(!size.is_whole_shares());
+
+        // not fractionable: back to whole shares
+        assert!(OrderSize::for_buy(&SizingMode::Fractional, false, &dec("450"), &dec("300"), &dec("7")).is_zero());
+    }
+
+    #[test]
+    fn notional_spends_dollars() {
+        assert_eq!(OrderSize::for_buy(&SizingMode::Notional, true, &dec("450"), &dec("300.129"), &dec("7")), OrderSize::Notional(dec("300.12")));
+        // capped at trade_size shares worth
+        assert_eq!(OrderSize::for_buy(&SizingMode::Notional, true, &dec("10"), &dec("300"), &dec("7")), OrderSize::Notional(dec("70.00")));
+        // below Alpaca's minimum
+        assert!(OrderSize::for_buy(&SizingMode::Notional, true, &dec("450"), &dec("0.50"), &dec("7")).is_zero());
+    }
+}
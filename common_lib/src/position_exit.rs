@@ -0,0 +1,373 @@
+//! position_exit.rs
+//!
+//! Time-based exits, run on a timer by the backend (see backend/src/exit_scheduler.rs):
+//!
+//! - age: close positions, long or short, held longer than Settings.max_position_age_minute
+//!   (t_order_policy.age_exit_enabled)
+//! - end of day: in the last eod_flatten_minutes of the regular session close every position, long or short,
+//!   with a limit order that steps from the last price toward market as the close approaches, then a market
+//!   order in the final eod_flatten_market_minutes (t_order_policy.eod_flatten_enabled)
+//!
+
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use crate::alpaca_api::post_simple_order;
+use crate::alpaca_order::Order;
+use crate::alpaca_position::{Position, PositionSide};
+use crate::clock;
+use crate::db::DbMsg;
+use crate::kill_switch::KillSwitch;
+use crate::market_hours::{MarketHours, Session};
+use crate::order_manager::{OrderManager, OrderPolicy};
+use crate::sell_position::SellPosition;
+use crate::settings::Settings;
+use crate::trade_struct::{JsonReplace, OrderType, TradeSide};
+
+pub struct PositionExit {}
+
+/// What flatten does with an open order on a position it's closing
+#[derive(Debug, PartialEq)]
+pub enum FlattenAction {
+    Keep,
+    Reprice(BigDecimal),
+    Cancel,
+}
+
+/// The next step of an age exit for one position
+#[derive(Debug, PartialEq)]
+pub enum AgeExitAction {
+    Wait,
+    Cancel,
+    Close(BigDecimal),
+}
+
+impl PositionExit {
+
+    /// One pass of both exits; a no-op unless one is enabled and the regular session is open.
+    pub fn run_once(settings: &Settings, tx_db: Sender<DbMsg>) {
+        let policy = match OrderManager::policy(tx_db.clone()) {
+            Ok(policy) => policy,
+            Err(e) => {
+                tracing::error!("[run_once] no order policy: {:?}", &e);
+                return;
+            }
+        };
+        if !policy.age_exit_enabled && !policy.eod_flatten_enabled {
+            return;
+        }
+        if MarketHours::session(tx_db.clone()) != Session::Regular {
+            return;
+        }
+        // the kill switch does its own flattening, and the risk manager would reject these anyway
+        if KillSwitch::status(tx_db.clone()).map(|k| k.engaged).unwrap_or(true) {
+            return;
+        }
+
+        if policy.eod_flatten_enabled {
+            if let Some(close) = MarketHours::regular_close(tx_db.clone()) {
+                let now = clock::now();
+                if now < close && (close - now).num_seconds() <= i64::from(policy.eod_flatten_minutes) * 60 {
+                    PositionExit::flatten(close, now, &policy, settings, tx_db.clone());
+                    return;
+                }
+            }
+        }
+
+        if policy.age_exit_enabled {
+            PositionExit::age_exits(settings, tx_db);
+        }
+    }
+
+    /// market-sell anything held longer than max_position_age_minute, and market-buy to cover shorts held as
+    /// long. Its other orders (the sell-high limit) are canceled first and the exit goes in on a later pass, once
+    /// the cancel has freed the shares.
+    fn age_exits(settings: &Settings, tx_db: Sender<DbMsg>) {
+        if settings.max_position_age_minute <= BigDecimal::from(0) {
+            return;
+        }
+        let longs = match SellPosition::list_older_than(settings.max_position_age_minute.clone(), tx_db.clone()) {
+            Ok(positions) => positions,
+            Err(e) => {
+                tracing::error!("[age_exits] couldn't list positions: {:?}", &e);
+                return;
+            }
+        };
+        let shorts = match SellPosition::list_shorts_older_than(settings.max_position_age_minute.clone(), tx_db.clone()) {
+            Ok(positions) => positions,
+            Err(e) => {
+                tracing::error!("[age_exits] couldn't list short positions: {:?}", &e);
+                return;
+            }
+        };
+        let orders = match OrderManager::open_orders(settings, tx_db.clone()) {
+            Ok(orders) => orders,
+            Err(e) => {
+                tracing::error!("[age_exits] couldn't list open orders: {:?}", &e);
+                return;
+            }
+        };
+        let aged = longs.iter().filter(|p| p.qty > BigDecimal::from(0)).map(|p| (p, TradeSide::Sell))
+            .chain(shorts.iter().filter(|p| p.qty > BigDecimal::from(0)).map(|p| (p, TradeSide::Buy)));
+        for (position, exit_side) in aged {
+            let open: Vec<&Order> = orders.iter().filter(|o| o.symbol.eq_ignore_ascii_case(&position.symbol)).collect();
+            let reason = format!("position older than {} minutes", &settings.max_position_age_minute);
+            match PositionExit::age_exit_action(position, &exit_side, &open) {
+                AgeExitAction::Wait => {},
+                AgeExitAction::Cancel => {
+                    tracing::info!("[age_exits] {} held {} minutes, canceling its orders", &position.symbol, &position.age_minute);
+                    for order in open {
+                        let _ = OrderManager::cancel(order, &reason, settings, tx_db.clone());
+                    }
+                },
+                AgeExitAction::Close(qty) => {
+                    tracing::info!("[age_exits] {} held {} minutes, {:?} {}", &position.symbol, &position.age_minute, &exit_side, &qty);
+                    post_simple_order(&position.symbol, exit_side, qty, None, settings, tx_db.clone());
+                },
+            }
+        }
+    }
+
+    /// Pure; the next step of an age exit given the symbol's open orders. exit_side is Sell for a long and Buy
+    /// for a short; SellPosition carries both as positive shares.
+    ///
+    /// Waits while a market exit is already working, cancels anything else that's open, and otherwise closes
+    /// the shares that aren't held by an order.
+    pub fn age_exit_action(position: &SellPosition, exit_side: &TradeSide, open: &[&Order]) -> AgeExitAction {
+        if open.iter().any(|o| PositionExit::is_market_exit(o, exit_side)) {
+            return AgeExitAction::Wait;
+        }
+        if !open.is_empty() {
+            return AgeExitAction::Cancel;
+        }
+        if position.qty_available > BigDecimal::from(0) {
+            AgeExitAction::Close(position.qty_available.clone())
+        } else {
+            AgeExitAction::Wait
+        }
+    }
+
+    /// Close every position before `close`. Existing limit exits are repriced in place rather than canceled
+    /// and resubmitted, so shares aren't briefly held by a canceled order.
+    fn flatten(close: DateTime<Utc>, now: DateTime<Utc>, policy: &OrderPolicy, settings: &Settings, tx_db: Sender<DbMsg>) {
+        let positions = match Position::get_remote(settings, tx_db.clone()) {
+            Ok(positions) => positions,
+            Err(e) => {
+                tracing::error!("[flatten] couldn't get positions: {:?}", &e);
+                return;
+            }
+        };
+        let orders = OrderManager::open_orders(settings, tx_db.clone()).unwrap_or_default();
+        let seconds_to_close = (close - now).num_seconds();
+        let reason = format!("end of day flatten, {}s to close", seconds_to_close);
+
+        for position in positions.iter().filter(|p| p.qty != BigDecimal::from(0)) {
+            let exit_side = match position.side {
+                PositionSide::Long => TradeSide::Sell,
+                PositionSide::Short => TradeSide::Buy,
+            };
+            let limit_price = PositionExit::flatten_limit_price(&position.current_price, &position.side, seconds_to_close, policy);
+            tracing::info!("[flatten] {} {} {}: {:?}", &position.symbol, &position.side, &position.qty, &limit_price);
+
+            let open: Vec<&Order> = orders.iter().filter(|o| o.symbol.eq_ignore_ascii_case(&position.symbol)).collect();
+            if open.is_empty() {
+                post_simple_order(&position.symbol, exit_side, position.qty.abs(), limit_price, settings, tx_db.clone());
+                continue;
+            }
+
+            for order in open {
+                match PositionExit::flatten_action(order, &exit_side, &limit_price) {
+                    FlattenAction::Keep => {},
+                    FlattenAction::Reprice(limit_price) => {
+                        let json_replace = JsonReplace { limit_price: Some(limit_price), ..Default::default() };
+                        if let Err(e) = OrderManager::replace(order, json_replace, &reason, settings, tx_db.clone()) {
+                            tracing::error!("[flatten] {} not repriced: {:?}", &order.id, &e);
+                        }
+                    },
+                    // the market exit goes in on the next pass once the shares are free
+                    FlattenAction::Cancel => {
+                        let _ = OrderManager::cancel(order, &reason, settings, tx_db.clone());
+                    },
+                }
+            }
+        }
+    }
+
+    /// Pure; what flatten does with an open order when the exit should be at `limit_price` (None for market).
+    ///
+    /// A simple market order on the exit side is left to fill, a simple limit exit is repriced while it's still
+    /// limit time, and anything else (entries, bracket legs, a limit when it's time for market) is canceled.
+    pub fn flatten_action(order: &Order, exit_side: &TradeSide, limit_price: &Option<BigDecimal>) -> FlattenAction {
+        if PositionExit::is_market_exit(order, exit_side) {
+            return FlattenAction::Keep;
+        }
+        let repriceable = order.side == *exit_side && order.order_type_v2 == OrderType::Limit && PositionExit::is_simple(order);
+        match limit_price {
+            Some(limit_price) if repriceable && order.limit_price.as_ref() != Some(limit_price) => FlattenAction::Reprice(limit_price.clone()),
+            Some(_) if repriceable => FlattenAction::Keep,
+            _ => FlattenAction::Cancel,
+        }
+    }
+
+    fn is_market_exit(order: &Order, exit_side: &TradeSide) -> bool {
+        order.side == *exit_side && order.order_type_v2 == OrderType::Market && PositionExit::is_simple(order)
+    }
+
+    fn is_simple(order: &Order) -> bool {
+        order.order_class.as_deref().unwrap_or("").is_empty()
+    }
+
+    /// Pure; the limit price for a flatten order seconds_to_close before the close, or None for market.
+    ///
+    /// Starts at the last price and gives up a straight-line share of eod_flatten_max_concession_per_cent
+    /// (down for a sell, up for a cover) until eod_flatten_market_minutes before the close.
+    pub fn flatten_limit_price(price_last: &BigDecimal, side: &PositionSide, seconds_to_close: i64, policy: &OrderPolicy) -> Option<BigDecimal> {
+        let market_seconds = i64::from(policy.eod_flatten_market_minutes) * 60;
+        if seconds_to_close <= market_seconds || *price_last <= BigDecimal::from(0) {
+            return None;
+        }
+        let window = (i64::from(policy.eod_flatten_minutes) * 60 - market_seconds).max(1);
+        let elapsed = (i64::from(policy.eod_flatten_minutes) * 60 - seconds_to_close).clamp(0, window);
+
+        let concession = price_last * &policy.eod_flatten_max_concession_per_cent * BigDecimal::from(elapsed)
+            / (BigDecimal::from(100) * BigDecimal::from(window));
+        let limit_price = match side {
+            PositionSide::Long => price_last - concession,
+            PositionSide::Short => price_last + concession,
+        };
+        Some(limit_price.round(2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn order(side: TradeSide, order_type: OrderType, limit_price: Option<&str>) -> Order {
+        let now = Utc::now();
+        Order {
+            id: "order-1".to_string(),
+            client_order_id: "client-1".to_string(),
+            created_at: now,
+            updated_at: now,
+            submitted_at: now,
+            filled_at: None,
+            expired_at: None,
+            canceled_at: None,
+            failed_at: None,
+            replaced_at: None,
+            replaced_by: None,
+            replaces: None,
+            asset_id: None,
+            symbol: "AAPL".to_string(),
+            asset_class: None,
+            notional: None,
+            qty: dec("7"),
+            filled_qty: None,
+            filled_avg_price: None,
+            order_class: None,
+            order_type_v2: order_type,
+            side,
+            time_in_force: crate::trade_struct::TimeInForce::Day,
+            limit_price: limit_price.map(dec),
+            stop_price: None,
+            status: "new".to_string(),
+            extended_hours: false,
+            trail_percent: None,
+            trail_price: None,
+            hwm: None,
+        }
+    }
+
+    fn position(qty: &str, qty_available: &str) -> SellPosition {
+        SellPosition {
+            symbol: "AAPL".to_string(),
+            avg_entry_price: dec("100"),
+            qty: dec(qty),
+            qty_available: dec(qty_available),
+            unrealized_pl_per_share: dec("0"),
+            cost_basis: dec("700"),
+            unrealized_pl_total: dec("0"),
+            trade_size: dec("700"),
+            age_minute: dec("90"),
+        }
+    }
+
+    fn policy() -> OrderPolicy {
+        OrderPolicy {
+            dtg: Utc::now(),
+            stale_limit_enabled: false,
+            stale_limit_age_seconds: 300,
+            stale_limit_action: crate::order_manager::StaleLimitAction::Cancel,
+            bracket_enabled: false,
+            bracket_take_profit_per_cent: dec("0.5"),
+            bracket_stop_loss_per_cent: dec("1.0"),
+            sizing_mode: crate::order_sizing::SizingMode::WholeShares,
+            short_enabled: false,
+            short_require_easy_to_borrow: true,
+            age_exit_enabled: false,
+            eod_flatten_enabled: true,
+            eod_flatten_minutes: 15,
+            eod_flatten_max_concession_per_cent: dec("0.5"),
+            eod_flatten_market_minutes: 2,
+        }
+    }
+
+    #[test]
+    fn flatten_steps_toward_market() {
+        let policy = policy();
+        // start of the window: last price
+        assert_eq!(PositionExit::flatten_limit_price(&dec("100"), &PositionSide::Long, 15 * 60, &policy), Some(dec("100.00")));
+        // halfway through the 13 minute stepping window
+        assert_eq!(PositionExit::flatten_limit_price(&dec("100"), &PositionSide::Long, 15 * 60 - 390, &policy), Some(dec("99.75")));
+        assert_eq!(PositionExit::flatten_limit_price(&dec("100"), &PositionSide::Short, 15 * 60 - 390, &policy), Some(dec("100.25")));
+        // last two minutes: market
+        assert_eq!(PositionExit::flatten_limit_price(&dec("100"), &PositionSide::Long, 120, &policy), None);
+    }
+
+    #[test]
+    fn flatten_keeps_its_own_market_exit() {
+        let market_sell = order(TradeSide::Sell, OrderType::Market, None);
+        let limit_sell = order(TradeSide::Sell, OrderType::Limit, Some("100.00"));
+        let entry = order(TradeSide::Buy, OrderType::Limit, Some("99.00"));
+        // market time: the market exit stays, limits and entries go
+        assert_eq!(PositionExit::flatten_action(&market_sell, &TradeSide::Sell, &None), FlattenAction::Keep);
+        assert_eq!(PositionExit::flatten_action(&limit_sell, &TradeSide::Sell, &None), FlattenAction::Cancel);
+        assert_eq!(PositionExit::flatten_action(&entry, &TradeSide::Sell, &None), FlattenAction::Cancel);
+        // limit time: the limit exit is stepped
+        assert_eq!(PositionExit::flatten_action(&limit_sell, &TradeSide::Sell, &Some(dec("99.75"))), FlattenAction::Reprice(dec("99.75")));
+        assert_eq!(PositionExit::flatten_action(&limit_sell, &TradeSide::Sell, &Some(dec("100.00"))), FlattenAction::Keep);
+    }
+
+    #[test]
+    fn age_exit_cancels_then_sells_what_is_available() {
+        let market_sell = order(TradeSide::Sell, OrderType::Market, None);
+        let limit_sell = order(TradeSide::Sell, OrderType::Limit, Some("101.00"));
+        // the sell-high order is canceled first
+        assert_eq!(PositionExit::age_exit_action(&position("7", "0"), &TradeSide::Sell, &[&limit_sell]), AgeExitAction::Cancel);
+        // until the cancel frees the shares there's nothing to sell
+        assert_eq!(PositionExit::age_exit_action(&position("7", "0"), &TradeSide::Sell, &[]), AgeExitAction::Wait);
+        assert_eq!(PositionExit::age_exit_action(&position("7", "7"), &TradeSide::Sell, &[]), AgeExitAction::Close(dec("7")));
+        // an exit already working isn't doubled
+        assert_eq!(PositionExit::age_exit_action(&position("7", "0"), &TradeSide::Sell, &[&market_sell]), AgeExitAction::Wait);
+    }
+
+    #[test]
+    fn age_exit_covers_a_short_with_a_buy() {
+        // shorts come back from fn_positions_to_cover_old as positive shares to cover
+        let short = position("5", "5");
+        let market_buy = order(TradeSide::Buy, OrderType::Market, None);
+        let limit_buy = order(TradeSide::Buy, OrderType::Limit, Some("99.00"));
+        assert_eq!(PositionExit::age_exit_action(&short, &TradeSide::Buy, &[]), AgeExitAction::Close(dec("5")));
+        assert_eq!(PositionExit::age_exit_action(&short, &TradeSide::Buy, &[&limit_buy]), AgeExitAction::Cancel);
+        assert_eq!(PositionExit::age_exit_action(&position("5", "0"), &TradeSide::Buy, &[&market_buy]), AgeExitAction::Wait);
+        // a market sell isn't a cover, so it doesn't count as the exit already working
+        let market_sell = order(TradeSide::Sell, OrderType::Market, None);
+        assert_eq!(PositionExit::age_exit_action(&short, &TradeSide::Buy, &[&market_sell]), AgeExitAction::Cancel);
+    }
+}
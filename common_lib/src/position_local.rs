@@ -5,10 +5,13 @@ use serde::Serialize;
 use tokio::sync::oneshot;
 use crate::db::DbMsg;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, sqlx::FromRow)]
 pub struct PositionLocal{
     pub symbol: String,
+    /// negative when short
     pub qty: BigDecimal,
+    /// long or short
+    pub side: String,
     pub pl: BigDecimal,
     pub pl_per_share: BigDecimal,
     pub basis: BigDecimal,
@@ -0,0 +1,287 @@
+//! profile_schedule.rs
+//!
+//! Switch trade settings profiles at set times in the trading day. Each row of t_trade_settings_schedule
+//! names a session time ("open+5m", "close-30m", "session_close-1h"), the weekdays it applies on, and the
+//! profile to activate. Times come from the market calendar, so nothing fires on holidays and
+//! close-relative times follow early closes.
+//!
+//! The backend checks every so often (backend/src/profile_scheduler.rs). Only the latest entry that has come
+//! due today is applied, so after a restart at noon the morning switch is applied once and a later one isn't
+//! replayed over it. Switches go through Settings::change_trade_profile and land in
+//! t_trade_settings_profile_activation with changed_by "schedule".
+//!
+
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, Duration, Utc, Weekday};
+use chrono_tz::America::New_York;
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use crate::clock;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::market_calendar::{CalendarDay, MarketCalendar};
+use crate::settings::Settings;
+use crate::settings_history::SOURCE_SCHEDULE;
+
+/// who switched, in the activation log
+pub const SCHEDULE_CHANGED_BY: &str = "schedule";
+
+/// a point in the calendar day that session times are measured from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionAnchor {
+    /// regular open, 9:30
+    Open,
+    /// regular close, 16:00 or earlier on a short day
+    Close,
+    /// extended session start, 4:00
+    SessionOpen,
+    /// extended session end, 20:00 or earlier on a short day
+    SessionClose,
+}
+
+impl SessionAnchor {
+
+    fn name(&self) -> &'static str {
+        match self {
+            SessionAnchor::Open => "open",
+            SessionAnchor::Close => "close",
+            SessionAnchor::SessionOpen => "session_open",
+            SessionAnchor::SessionClose => "session_close",
+        }
+    }
+}
+
+/// an anchor plus or minus whole minutes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionTime {
+    pub anchor: SessionAnchor,
+    pub offset_minutes: i64,
+}
+
+impl SessionTime {
+
+    /// when this falls on day, in UTC
+    pub fn on(&self, day: &CalendarDay) -> Option<DateTime<Utc>> {
+        let anchor = match self.anchor {
+            SessionAnchor::Open => day.open_utc(false),
+            SessionAnchor::Close => day.close_utc(false),
+            SessionAnchor::SessionOpen => day.open_utc(true),
+            SessionAnchor::SessionClose => day.close_utc(true),
+        }?;
+        Some(anchor + Duration::minutes(self.offset_minutes))
+    }
+}
+
+impl FromStr for SessionTime {
+    type Err = String;
+
+    /// "open", "open+5m", "close-30m", "session_close-1h"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let (anchor, offset) = match s.find(['+', '-']) {
+            Some(i) => (&s[..i], &s[i..]),
+            None => (s.as_str(), ""),
+        };
+        let anchor = [SessionAnchor::Open, SessionAnchor::Close, SessionAnchor::SessionOpen, SessionAnchor::SessionClose]
+            .into_iter()
+            .find(|a| a.name() == anchor.trim())
+            .ok_or_else(|| format!("unknown session time {}; use open, close, session_open or session_close", &s))?;
+
+        let offset_minutes = if offset.is_empty() {
+            0
+        } else {
+            let (number, unit) = offset[1..].trim().split_at(offset[1..].trim().len().saturating_sub(1));
+            let minutes = match unit {
+                "m" => number.parse::<i64>().ok(),
+                "h" => number.parse::<i64>().ok().map(|h| h * 60),
+                _ => None,
+            }.ok_or_else(|| format!("bad offset in {}; use e.g. +5m or -1h", &s))?;
+            if offset.starts_with('-') { -minutes } else { minutes }
+        };
+        Ok(SessionTime { anchor, offset_minutes })
+    }
+}
+
+impl fmt::Display for SessionTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset_minutes {
+            0 => write!(f, "{}", self.anchor.name()),
+            m if m > 0 => write!(f, "{}+{}m", self.anchor.name(), m),
+            m => write!(f, "{}{}m", self.anchor.name(), m),
+        }
+    }
+}
+
+/// Pure; "mon,tue,wed" into weekdays. Empty or "*" is every day.
+pub fn parse_weekdays(s: &str) -> Result<Vec<Weekday>, String> {
+    let s = s.trim();
+    if s.is_empty() || s == "*" {
+        return Ok(vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri, Weekday::Sat, Weekday::Sun]);
+    }
+    s.split(',')
+        .map(|day| Weekday::from_str(day.trim()).map_err(|_| format!("unknown weekday: {}", day.trim())))
+        .collect()
+}
+
+/// one row of t_trade_settings_schedule
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ProfileSchedule {
+    pub id: i64,
+    pub enabled: bool,
+    /// a SessionTime, e.g. "open+5m"
+    pub session_time: String,
+    /// comma separated, e.g. "mon,tue,wed,thu,fri"
+    pub weekdays: String,
+    pub profile_name: String,
+    pub dtg_last_applied: Option<DateTime<Utc>>,
+}
+
+impl ProfileSchedule {
+
+    /// Pure; when this entry fires on day, if it's enabled, runs that weekday and parses
+    pub fn fire_time(&self, day: &CalendarDay) -> Option<DateTime<Utc>> {
+        if !self.enabled {
+            return None;
+        }
+        let weekdays = parse_weekdays(&self.weekdays).ok()?;
+        if !weekdays.contains(&day.date.weekday()) {
+            return None;
+        }
+        SessionTime::from_str(&self.session_time).ok()?.on(day)
+    }
+
+    /// Pure; the entry to apply now, with its fire time: the one that came due most recently today, unless
+    /// it's already been applied since then
+    pub fn due<'a>(schedules: &'a [ProfileSchedule], calendar: &MarketCalendar, now: DateTime<Utc>) -> Option<(&'a ProfileSchedule, DateTime<Utc>)> {
+        let day = calendar.day(now.with_timezone(&New_York).date_naive())?;
+        let (latest, fire_time) = schedules.iter()
+            .filter_map(|s| s.fire_time(day).map(|t| (s, t)))
+            .filter(|(_, t)| *t <= now)
+            .max_by_key(|(_, t)| *t)?;
+        if latest.dtg_last_applied.is_some_and(|applied| applied >= fire_time) {
+            return None;
+        }
+        Some((latest, fire_time))
+    }
+
+    /// One pass for the backend: apply whatever's due.
+    pub fn run_once(tx_db: Sender<DbMsg>) {
+        let now = clock::now();
+        let calendar = match MarketCalendar::load(tx_db.clone()) {
+            Ok(calendar) if calendar.covers(now) => calendar,
+            Ok(_) => {
+                tracing::debug!("[ProfileSchedule::run_once] calendar doesn't cover {}; waiting for a refresh", &now);
+                return;
+            },
+            Err(e) => {
+                tracing::error!("[ProfileSchedule::run_once] no calendar: {:?}", &e);
+                return;
+            }
+        };
+        let schedules = match ProfileSchedule::list(tx_db.clone()) {
+            Ok(schedules) => schedules,
+            Err(e) => {
+                tracing::error!("[ProfileSchedule::run_once] couldn't load the schedule: {:?}", &e);
+                return;
+            }
+        };
+        let Some((schedule, fire_time)) = ProfileSchedule::due(&schedules, &calendar, now) else { return; };
+
+        tracing::info!("[ProfileSchedule::run_once] {} at {} ({}): switching to {}", &schedule.session_time, &fire_time, schedule.id, &schedule.profile_name);
+        if let Err(e) = Settings::change_trade_profile(&schedule.profile_name, SCHEDULE_CHANGED_BY, SOURCE_SCHEDULE, tx_db.clone()) {
+            tracing::error!("[ProfileSchedule::run_once] couldn't switch to {}: {:?}", &schedule.profile_name, &e);
+        }
+        // marked either way; a missing profile shouldn't be retried every pass
+        let _ = tx_db.send(DbMsg::ProfileScheduleApplied { id: schedule.id, dtg: now });
+    }
+
+    /// every entry, by time of day
+    pub fn list(tx_db: Sender<DbMsg>) -> Result<Vec<ProfileSchedule>, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::ProfileScheduleList { sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// Add an entry; session_time and weekdays are checked here, the profile name by the foreign key.
+    /// Returns the new id.
+    pub fn add(session_time: &str, weekdays: &str, profile_name: &str, tx_db: Sender<DbMsg>) -> Result<i64, String> {
+        let session_time = SessionTime::from_str(session_time)?;
+        let weekdays = parse_weekdays(weekdays)?;
+        let weekdays = weekdays.iter().map(|d| d.to_string().to_lowercase()).collect::<Vec<String>>().join(",");
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::ProfileScheduleAdd { session_time: session_time.to_string(), weekdays, profile_name: profile_name.to_string(), sender: tx })
+            .map_err(|_| "database unavailable".to_string())?;
+        rx.recv().map_err(|_| format!("couldn't add the entry; is there a profile named {}?", profile_name))
+    }
+
+    pub fn delete(id: i64, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::ProfileScheduleDelete { id, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDateTime, TimeZone};
+    use super::*;
+
+    fn calendar() -> MarketCalendar {
+        // a Wednesday and the Friday after Thanksgiving, which closes at 1pm
+        let json = r#"[
+            {"date":"2023-11-22","open":"09:30","close":"16:00","session_open":"0400","session_close":"2000","settlement_date":"2023-11-27"},
+            {"date":"2023-11-24","open":"09:30","close":"13:00","session_open":"0400","session_close":"1700","settlement_date":"2023-11-28"}
+        ]"#;
+        MarketCalendar::new(serde_json::from_str(json).unwrap())
+    }
+
+    fn ny(s: &str) -> DateTime<Utc> {
+        let local = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        New_York.from_local_datetime(&local).unwrap().with_timezone(&Utc)
+    }
+
+    fn schedule(id: i64, session_time: &str, weekdays: &str, profile_name: &str) -> ProfileSchedule {
+        ProfileSchedule { id, enabled: true, session_time: session_time.to_string(), weekdays: weekdays.to_string(), profile_name: profile_name.to_string(), dtg_last_applied: None }
+    }
+
+    #[test]
+    fn session_times_parse_and_print() {
+        assert_eq!(SessionTime::from_str("open+5m").unwrap(), SessionTime { anchor: SessionAnchor::Open, offset_minutes: 5 });
+        assert_eq!(SessionTime::from_str("Close-30m").unwrap(), SessionTime { anchor: SessionAnchor::Close, offset_minutes: -30 });
+        assert_eq!(SessionTime::from_str("session_close-1h").unwrap().offset_minutes, -60);
+        assert_eq!(SessionTime::from_str("close").unwrap().to_string(), "close");
+        assert_eq!(SessionTime::from_str("close - 30m").unwrap().to_string(), "close-30m");
+        assert!(SessionTime::from_str("lunch").is_err());
+        assert!(SessionTime::from_str("open+5s").is_err());
+        assert_eq!(parse_weekdays("mon, fri").unwrap(), vec![Weekday::Mon, Weekday::Fri]);
+        assert!(parse_weekdays("mon,someday").is_err());
+    }
+
+    #[test]
+    fn latest_due_entry_wins_and_follows_early_close() {
+        let calendar = calendar();
+        let mut schedules = vec![
+            schedule(1, "open+5m", "mon,tue,wed,thu,fri", "buy"),
+            schedule(2, "close-30m", "mon,tue,wed,thu,fri", "close"),
+            schedule(3, "close-10m", "fri", "close_with_loss"),
+        ];
+
+        assert!(ProfileSchedule::due(&schedules, &calendar, ny("2023-11-22 09:34")).is_none());
+        let (due, at) = ProfileSchedule::due(&schedules, &calendar, ny("2023-11-22 09:35")).unwrap();
+        assert_eq!((due.id, at), (1, ny("2023-11-22 09:35")));
+
+        // restarted late in the day: only the afternoon switch
+        let (due, _) = ProfileSchedule::due(&schedules, &calendar, ny("2023-11-22 15:45")).unwrap();
+        assert_eq!(due.id, 2);
+        schedules[1].dtg_last_applied = Some(ny("2023-11-22 15:45"));
+        assert!(ProfileSchedule::due(&schedules, &calendar, ny("2023-11-22 15:50")).is_none());
+
+        // the day after Thanksgiving closes at 1pm, and entry 3 only runs Fridays
+        let (due, at) = ProfileSchedule::due(&schedules, &calendar, ny("2023-11-24 12:55")).unwrap();
+        assert_eq!((due.id, at), (3, ny("2023-11-24 12:50")));
+
+        // Thanksgiving itself isn't a trading day
+        assert!(ProfileSchedule::due(&schedules, &calendar, ny("2023-11-23 12:00")).is_none());
+    }
+}
@@ -0,0 +1,441 @@
+//! risk.rs
+//!
+//! Pre-trade risk checks. Every order goes through RiskManager::approve (called from
+//! alpaca_api::post_order) before it reaches the Alpaca orders API. A refused order comes back as a
+//! typed RiskRejection and is written to the risk_event table instead of panicking the caller.
+//!
+
+use std::fmt;
+use bigdecimal::{BigDecimal, FromPrimitive};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use crate::clock;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+use crate::trade_struct::{JsonTrade, TradeSide};
+
+/// catch whatever was causing us to buy 65000 shares
+pub const QTY_SIZE_SAFETY_LIMIT: usize = 1001;
+
+/// reflects the latest row in t_risk_limits
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RiskLimits {
+    pub dtg: DateTime<Utc>,
+    /// max dollars held in any one symbol, including the order being checked
+    pub symbol_notional_cap: BigDecimal,
+    /// max number of symbols with an open position
+    pub max_open_positions: i32,
+    /// stop opening positions once today's realized loss reaches this many dollars
+    pub daily_loss_limit_dollars: BigDecimal,
+}
+
+/// account state the limits are checked against, gathered from the database in one round trip
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct RiskSnapshot {
+    pub cash: BigDecimal,
+    pub position_market_value: BigDecimal,
+    pub open_positions: i64,
+    /// market value already held in the symbol being ordered, long or short
+    pub symbol_market_value: BigDecimal,
+    /// true if the symbol being ordered already has an open position
+    pub symbol_has_position: bool,
+    /// shares held in the symbol, negative when short, so a buy covers it first
+    pub symbol_qty: BigDecimal,
+    /// latest trade price for the symbol; used to price market orders
+    pub price_last: BigDecimal,
+    /// realized P/L since midnight New York time, from longs sold and shorts covered; negative is a loss
+    pub realized_pl_today: BigDecimal,
+    /// latest t_kill_switch state
+    pub kill_switch_engaged: bool,
+}
+
+/// Why an order was refused. Stored in risk_event.reason via code().
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskRejection {
+    KillSwitch,
+    BuyDisabled,
+    QtySafetyLimit { qty: BigDecimal, limit: usize },
+    NoPrice { symbol: String },
+    MaxPositionMarketValue { after_order: BigDecimal, limit: BigDecimal },
+    MinCash { after_order: BigDecimal, limit: BigDecimal },
+    SymbolNotionalCap { symbol: String, after_order: BigDecimal, limit: BigDecimal },
+    MaxOpenPositions { open: i64, limit: i32 },
+    DailyLossLimit { realized_pl: BigDecimal, limit: BigDecimal },
+    DataUnavailable,
+}
+
+impl RiskRejection {
+    /// short stable identifier for the risk_event table
+    pub fn code(&self) -> &'static str {
+        match self {
+            RiskRejection::KillSwitch => "kill_switch",
+            RiskRejection::BuyDisabled => "buy_disabled",
+            RiskRejection::QtySafetyLimit { .. } => "qty_safety_limit",
+            RiskRejection::NoPrice { .. } => "no_price",
+            RiskRejection::MaxPositionMarketValue { .. } => "max_position_market_value",
+            RiskRejection::MinCash { .. } => "min_cash",
+            RiskRejection::SymbolNotionalCap { .. } => "symbol_notional_cap",
+            RiskRejection::MaxOpenPositions { .. } => "max_open_positions",
+            RiskRejection::DailyLossLimit { .. } => "daily_loss_limit",
+            RiskRejection::DataUnavailable => "data_unavailable",
+        }
+    }
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RiskRejection::KillSwitch => write!(f, "kill switch engaged"),
+            RiskRejection::BuyDisabled => write!(f, "trade_enable_buy is off"),
+            RiskRejection::QtySafetyLimit { qty, limit } => write!(f, "qty {} exceeds safety limit {}", qty, limit),
+            RiskRejection::NoPrice { symbol } => write!(f, "no recent price for {}", symbol),
+            RiskRejection::MaxPositionMarketValue { after_order, limit } => write!(f, "position market value would be {} (max {})", after_order, limit),
+            RiskRejection::MinCash { after_order, limit } => write!(f, "cash would drop to {} (min {})", after_order, limit),
+            RiskRejection::SymbolNotionalCap { symbol, after_order, limit } => write!(f, "{} notional would be {} (cap {})", symbol, after_order, limit),
+            RiskRejection::MaxOpenPositions { open, limit } => write!(f, "{} open positions (max {})", open, limit),
+            RiskRejection::DailyLossLimit { realized_pl, limit } => write!(f, "realized P/L today {} (loss limit {})", realized_pl, limit),
+            RiskRejection::DataUnavailable => write!(f, "risk limits or account snapshot unavailable"),
+        }
+    }
+}
+
+/// a refused order, saved to risk_event
+#[derive(Debug, Clone)]
+pub struct RiskEvent {
+    pub dtg: DateTime<Utc>,
+    pub symbol: String,
+    pub side: TradeSide,
+    pub qty: BigDecimal,
+    pub notional: BigDecimal,
+    pub rejection: RiskRejection,
+}
+
+pub struct RiskManager {}
+
+impl RiskManager {
+
+    /// Check an order against the settings, t_risk_limits and the current account snapshot. Rejections
+    /// are logged to risk_event and returned as TradeWebError::RiskRejected.
+    pub fn approve(json_trade: &JsonTrade, settings: &Settings, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+
+        let limits = RiskManager::limits(tx_db.clone());
+        let snapshot = RiskManager::snapshot(&json_trade.symbol, tx_db.clone());
+
+        let result = match (&limits, &snapshot) {
+            (Ok(limits), Ok(snapshot)) => RiskManager::check(json_trade, settings, limits, snapshot),
+            _ => {
+                tracing::error!("[approve] could not load risk data; limits: {:?}, snapshot: {:?}", &limits.is_ok(), &snapshot.is_ok());
+                Err(RiskRejection::DataUnavailable)
+            }
+        };
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(rejection) => {
+                tracing::warn!("[approve] ***** order rejected {} {:?} (${:?}) {}: {}", &json_trade.side, &json_trade.qty, &json_trade.notional, &json_trade.symbol, &rejection);
+                let price = match (&json_trade.limit_price, &snapshot) {
+                    (Some(limit_price), _) => limit_price.clone(),
+                    (None, Ok(snapshot)) => snapshot.price_last.clone(),
+                    (None, Err(_)) => BigDecimal::from(0),
+                };
+                let event = RiskEvent {
+                    dtg: clock::now(),
+                    symbol: json_trade.symbol.clone(),
+                    side: json_trade.side.clone(),
+                    qty: json_trade.qty_or_zero(),
+                    notional: json_trade.notional_at(&price),
+                    rejection: rejection.clone(),
+                };
+                let _ = tx_db.send(DbMsg::RiskEventSave { event });
+                Err(TradeWebError::RiskRejected(rejection))
+            }
+        }
+    }
+
+    /// Pure check; no database access so it can be unit tested.
+    ///
+    /// The kill switch stops everything. Sells and buys that only cover a short reduce exposure, so they
+    /// pass otherwise, whatever their size; a large position has to be closable. Buys and short sells open
+    /// exposure and face every limit, the quantity safety limit included; a buy bigger than the short it
+    /// covers faces them for the shares beyond the short.
+    pub fn check(json_trade: &JsonTrade, settings: &Settings, limits: &RiskLimits, snapshot: &RiskSnapshot) -> Result<(), RiskRejection> {
+
+        if snapshot.kill_switch_engaged {
+            return Err(RiskRejection::KillSwitch);
+        }
+
+        // a buy against a short covers it first; only the shares beyond the short open exposure
+        let zero = BigDecimal::from(0);
+        let qty_short = match json_trade.side {
+            TradeSide::Sell => return Ok(()),
+            TradeSide::Buy if snapshot.symbol_qty < zero => -snapshot.symbol_qty.clone(),
+            _ => zero.clone(),
+        };
+        let qty_opening = json_trade.qty.as_ref().map(|qty| qty - &qty_short);
+        if qty_opening.as_ref().is_some_and(|qty| *qty <= zero) {
+            return Ok(());
+        }
+
+        let qty_limit = BigDecimal::from_usize(QTY_SIZE_SAFETY_LIMIT).unwrap_or_else(|| BigDecimal::from(300));
+        if let Some(qty) = qty_opening {
+            if qty > qty_limit {
+                return Err(RiskRejection::QtySafetyLimit { qty, limit: QTY_SIZE_SAFETY_LIMIT });
+            }
+        }
+
+        if !settings.trade_enable_buy {
+            return Err(RiskRejection::BuyDisabled);
+        }
+
+        // losing day: stop opening anything new
+        if snapshot.realized_pl_today <= -limits.daily_loss_limit_dollars.clone() {
+            return Err(RiskRejection::DailyLossLimit { realized_pl: snapshot.realized_pl_today.clone(), limit: limits.daily_loss_limit_dollars.clone() });
+        }
+
+        if !snapshot.symbol_has_position && snapshot.open_positions >= limits.max_open_positions as i64 {
+            return Err(RiskRejection::MaxOpenPositions { open: snapshot.open_positions, limit: limits.max_open_positions });
+        }
+
+        // a notional order already says how many dollars it spends; a qty order needs a price
+        let price = json_trade.limit_price.clone().unwrap_or_else(|| snapshot.price_last.clone());
+        if json_trade.notional.is_none() && price <= zero {
+            return Err(RiskRejection::NoPrice { symbol: json_trade.symbol.clone() });
+        }
+        let notional = json_trade.notional_at(&price);
+        let notional_opening = &notional - &qty_short * &price;
+        if notional_opening <= zero {
+            return Ok(());
+        }
+        // what's held in the symbol after the order: the short, if this covers one, is gone
+        let symbol_market_value = if qty_short > zero { zero.clone() } else { snapshot.symbol_market_value.clone() };
+
+        let position_market_value_after = &snapshot.position_market_value - &snapshot.symbol_market_value + &symbol_market_value + &notional_opening;
+        if position_market_value_after > settings.acct_max_position_market_value {
+            return Err(RiskRejection::MaxPositionMarketValue { after_order: position_market_value_after, limit: settings.acct_max_position_market_value.clone() });
+        }
+
+        let cash_after = snapshot.cash.clone() - notional;
+        if cash_after < settings.acct_min_cash_dollars {
+            return Err(RiskRejection::MinCash { after_order: cash_after, limit: settings.acct_min_cash_dollars.clone() });
+        }
+
+        let symbol_after = symbol_market_value + notional_opening;
+        if symbol_after > limits.symbol_notional_cap {
+            return Err(RiskRejection::SymbolNotionalCap { symbol: json_trade.symbol.clone(), after_order: symbol_after, limit: limits.symbol_notional_cap.clone() });
+        }
+
+        Ok(())
+    }
+
+    /// latest row of t_risk_limits
+    pub fn limits(tx_db: Sender<DbMsg>) -> Result<RiskLimits, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RiskLimitsGet { sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// account cash, exposure, positions and today's realized P/L as seen from the database
+    pub fn snapshot(symbol: &str, tx_db: Sender<DbMsg>) -> Result<RiskSnapshot, TradeWebError> {
+        let (sender, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::RiskSnapshotGet { symbol: symbol.to_string(), sender }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bigdecimal::BigDecimal;
+    use chrono::Utc;
+    use crate::risk::{RiskLimits, RiskManager, RiskRejection, RiskSnapshot};
+    use crate::secret::Secret;
+    use crate::settings::Settings;
+    use crate::trade_struct::{JsonTrade, OrderType, TimeInForce, TradeSide};
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            dtg: Utc::now(),
+            alpaca_paper_id: Secret::default(),
+            alpaca_paper_secret: Secret::default(),
+            alpaca_live_id: Secret::default(),
+            alpaca_live_secret: Secret::default(),
+            trade_size: dec("10"),
+            trade_enable_buy: true,
+            trade_ema_small_size: 5,
+            trade_ema_large_size: 8,
+            trade_sell_high_per_cent_multiplier: dec("1"),
+            trade_sell_high_upper_limit_cents: dec("10"),
+            finnhub_key: Secret::default(),
+            account_start_value: dec("100000"),
+            max_position_age_minute: dec("60"),
+            upgrade_min_profit: dec("0"),
+            upgrade_sell_elapsed_minutes_min: dec("60"),
+            upgrade_posn_max_elapsed_minutes: dec("60"),
+            upgrade_posn_loss_allowed_dollars: dec("10"),
+            acct_max_position_market_value: dec("5000"),
+            acct_min_cash_dollars: dec("1000"),
+        }
+    }
+
+    fn limits() -> RiskLimits {
+        RiskLimits { dtg: Utc::now(), symbol_notional_cap: dec("1500"), max_open_positions: 3, daily_loss_limit_dollars: dec("200") }
+    }
+
+    fn snapshot() -> RiskSnapshot {
+        RiskSnapshot {
+            cash: dec("10000"),
+            position_market_value: dec("2000"),
+            open_positions: 2,
+            symbol_market_value: dec("0"),
+            symbol_has_position: false,
+            symbol_qty: dec("0"),
+            price_last: dec("100"),
+            realized_pl_today: dec("0"),
+            kill_switch_engaged: false,
+        }
+    }
+
+    fn buy(qty: &str) -> JsonTrade {
+        JsonTrade {
+            symbol: "AAPL".to_string(),
+            side: TradeSide::Buy,
+            time_in_force: TimeInForce::Day,
+            qty: Some(dec(qty)),
+            notional: None,
+            order_type: OrderType::Market,
+            limit_price: None,
+            extended_hours: None,
+            client_order_id: "test".to_string(),
+            stop_price: None,
+            trail_percent: None,
+            trail_price: None,
+            order_class: None,
+            take_profit: None,
+            stop_loss: None,
+        }
+    }
+
+    #[test]
+    fn buy_within_limits_is_approved() {
+        assert_eq!(RiskManager::check(&buy("10"), &settings(), &limits(), &snapshot()), Ok(()));
+    }
+
+    #[test]
+    fn buy_disabled_is_rejected_but_sell_is_not() {
+        let mut s = settings();
+        s.trade_enable_buy = false;
+        assert_eq!(RiskManager::check(&buy("10"), &s, &limits(), &snapshot()), Err(RiskRejection::BuyDisabled));
+
+        let mut sell = buy("10");
+        sell.side = TradeSide::Sell;
+        assert_eq!(RiskManager::check(&sell, &s, &limits(), &snapshot()), Ok(()));
+    }
+
+    #[test]
+    fn kill_switch_blocks_buys_and_sells() {
+        let mut snap = snapshot();
+        snap.kill_switch_engaged = true;
+        assert_eq!(RiskManager::check(&buy("1"), &settings(), &limits(), &snap), Err(RiskRejection::KillSwitch));
+
+        let mut sell = buy("1");
+        sell.side = TradeSide::Sell;
+        assert_eq!(RiskManager::check(&sell, &settings(), &limits(), &snap), Err(RiskRejection::KillSwitch));
+    }
+
+    #[test]
+    fn qty_safety_limit_rejects_instead_of_panicking() {
+        let result = RiskManager::check(&buy("65000"), &settings(), &limits(), &snapshot());
+        assert_eq!(result.unwrap_err().code(), "qty_safety_limit");
+    }
+
+    #[test]
+    fn qty_safety_limit_does_not_stop_closing_a_large_position() {
+        let mut sell = buy("5000");
+        sell.side = TradeSide::Sell;
+        assert_eq!(RiskManager::check(&sell, &settings(), &limits(), &snapshot()), Ok(()));
+
+        let mut snap = snapshot();
+        snap.symbol_has_position = true;
+        snap.symbol_qty = dec("-5000");
+        assert_eq!(RiskManager::check(&buy("5000"), &settings(), &limits(), &snap), Ok(()));
+    }
+
+    #[test]
+    fn short_sale_opens_exposure_and_cover_does_not() {
+        let mut short = buy("40");
+        short.side = TradeSide::SellShort;
+        assert_eq!(RiskManager::check(&short, &settings(), &limits(), &snapshot()).unwrap_err().code(), "max_position_market_value");
+
+        let mut s = settings();
+        s.trade_enable_buy = false;
+        let mut snap = snapshot();
+        snap.symbol_has_position = true;
+        snap.symbol_qty = dec("-40");
+        assert_eq!(RiskManager::check(&buy("40"), &s, &limits(), &snap), Ok(()));
+    }
+
+    #[test]
+    fn buy_past_the_short_faces_the_limits_for_the_rest() {
+        let mut snap = snapshot();
+        snap.symbol_has_position = true;
+        snap.symbol_qty = dec("-10");
+        snap.symbol_market_value = dec("1000");
+        snap.position_market_value = dec("3000");
+
+        // covers 10, then 14 long: 1400 under the 1500 cap, the short's 1000 no longer held
+        assert_eq!(RiskManager::check(&buy("24"), &settings(), &limits(), &snap), Ok(()));
+        // covers 10, then 20 long: 2000 > 1500 cap
+        assert_eq!(RiskManager::check(&buy("30"), &settings(), &limits(), &snap).unwrap_err().code(), "symbol_notional_cap");
+
+        let mut s = settings();
+        s.trade_enable_buy = false;
+        assert_eq!(RiskManager::check(&buy("10"), &s, &limits(), &snap), Ok(()));
+        assert_eq!(RiskManager::check(&buy("11"), &s, &limits(), &snap), Err(RiskRejection::BuyDisabled));
+
+        snap.realized_pl_today = dec("-200");
+        assert_eq!(RiskManager::check(&buy("11"), &settings(), &limits(), &snap).unwrap_err().code(), "daily_loss_limit");
+    }
+
+    #[test]
+    fn notional_buy_is_checked_by_dollars() {
+        let mut order = buy("1");
+        order.qty = None;
+        order.notional = Some(dec("1000"));
+        assert_eq!(RiskManager::check(&order, &settings(), &limits(), &snapshot()), Ok(()));
+
+        // 2000 > 1500 cap regardless of price
+        order.notional = Some(dec("2000"));
+        let mut snap = snapshot();
+        snap.price_last = dec("0");
+        assert_eq!(RiskManager::check(&order, &settings(), &limits(), &snap).unwrap_err().code(), "symbol_notional_cap");
+    }
+
+    #[test]
+    fn each_account_limit_is_enforced() {
+        // 40 * 100 = 4000 on top of 2000 held > 5000
+        assert_eq!(RiskManager::check(&buy("40"), &settings(), &limits(), &snapshot()).unwrap_err().code(), "max_position_market_value");
+
+        let mut snap = snapshot();
+        snap.cash = dec("1500");
+        assert_eq!(RiskManager::check(&buy("10"), &settings(), &limits(), &snap).unwrap_err().code(), "min_cash");
+
+        // 20 * 100 = 2000 > 1500 cap
+        assert_eq!(RiskManager::check(&buy("20"), &settings(), &limits(), &snapshot()).unwrap_err().code(), "symbol_notional_cap");
+
+        let mut snap = snapshot();
+        snap.open_positions = 3;
+        assert_eq!(RiskManager::check(&buy("1"), &settings(), &limits(), &snap).unwrap_err().code(), "max_open_positions");
+        snap.symbol_has_position = true;
+        assert_eq!(RiskManager::check(&buy("1"), &settings(), &limits(), &snap), Ok(()));
+
+        let mut snap = snapshot();
+        snap.realized_pl_today = dec("-200");
+        assert_eq!(RiskManager::check(&buy("1"), &settings(), &limits(), &snap).unwrap_err().code(), "daily_loss_limit");
+    }
+}
@@ -0,0 +1,239 @@
+//! secret.rs
+//!
+//! Credentials at rest. Each value is sealed with its own random data key (AES-256-GCM), and the data key
+//! is sealed with the master key from CREDENTIALS_KEY (base64, 32 bytes; `trader credentials generate-key`
+//! makes one). Rotating the master key only re-seals the data keys.
+//!
+//! Sealed text: enc:v1:<base64 nonce + sealed data key>:<base64 nonce + sealed value>
+//!
+//! Values without the prefix are plaintext from before encryption; they're still read, with a warning,
+//! until `trader credentials encrypt` seals them.
+//!
+
+use std::fmt;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize, Serializer};
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgTypeInfo, PgValueRef};
+use sqlx::Postgres;
+use crate::error::SecretError;
+
+/// the master key
+pub const CREDENTIALS_KEY_ENV: &str = "CREDENTIALS_KEY";
+/// the master key being rotated to
+pub const CREDENTIALS_KEY_NEW_ENV: &str = "CREDENTIALS_KEY_NEW";
+
+const SEALED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+const REDACTED: &str = "[redacted]";
+
+/// A credential. Debug, Display and Serialize show [redacted]; expose() is the only way to the value.
+#[derive(Clone, Default, PartialEq, Deserialize)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: &str) -> Secret {
+        Secret(value.to_string())
+    }
+
+    /// the value, for the request that needs it
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Read a stored value: empty stays empty, plaintext passes through, sealed text needs the key.
+    pub fn unseal(stored: &str, key: Option<&MasterKey>) -> Result<Secret, SecretError> {
+        if stored.is_empty() || !is_sealed(stored) {
+            return Ok(Secret::new(stored));
+        }
+        key.ok_or(SecretError::NoKey)?.open(stored)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// read from a varchar column as stored; see Settings::unseal_credentials
+impl sqlx::Type<Postgres> for Secret {
+    fn type_info() -> PgTypeInfo {
+        <String as sqlx::Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <String as sqlx::Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Postgres> for Secret {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(Secret(<String as sqlx::Decode<Postgres>>::decode(value)?))
+    }
+}
+
+/// whether seal() wrote this
+pub fn is_sealed(stored: &str) -> bool {
+    stored.starts_with(SEALED_PREFIX)
+}
+
+/// the key-encryption key
+#[derive(Clone)]
+pub struct MasterKey(Key<Aes256Gcm>);
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MasterKey({})", REDACTED)
+    }
+}
+
+impl MasterKey {
+
+    pub fn from_base64(text: &str) -> Result<MasterKey, SecretError> {
+        let bytes = STANDARD.decode(text.trim()).map_err(|_| SecretError::BadKey)?;
+        if bytes.len() != 32 {
+            return Err(SecretError::BadKey);
+        }
+        Ok(MasterKey(*Key::<Aes256Gcm>::from_slice(&bytes)))
+    }
+
+    /// the key in the environment variable, None if it isn't set
+    pub fn from_env(var: &str) -> Result<Option<MasterKey>, SecretError> {
+        match std::env::var(var) {
+            Ok(text) if !text.trim().is_empty() => MasterKey::from_base64(&text).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// a new random key, as base64 for the environment
+    pub fn generate() -> String {
+        STANDARD.encode(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// encrypt under a new data key
+    pub fn seal(&self, plaintext: &str) -> String {
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let sealed_key = seal_bytes(&self.0, &data_key);
+        let sealed_value = seal_bytes(&data_key, plaintext.as_bytes());
+        format!("{}{}:{}", SEALED_PREFIX, STANDARD.encode(sealed_key), STANDARD.encode(sealed_value))
+    }
+
+    pub fn open(&self, stored: &str) -> Result<Secret, SecretError> {
+        let (sealed_key, sealed_value) = split_sealed(stored)?;
+        let data_key = self.open_data_key(&sealed_key)?;
+        let plaintext = open_bytes(&data_key, &sealed_value)?;
+        String::from_utf8(plaintext).map(Secret).map_err(|_| SecretError::Malformed)
+    }
+
+    /// the same value sealed for new: only the data key is re-sealed
+    pub fn rewrap(&self, new: &MasterKey, stored: &str) -> Result<String, SecretError> {
+        let (sealed_key, sealed_value) = split_sealed(stored)?;
+        let data_key = self.open_data_key(&sealed_key)?;
+        Ok(format!("{}{}:{}", SEALED_PREFIX, STANDARD.encode(seal_bytes(&new.0, &data_key)), STANDARD.encode(sealed_value)))
+    }
+
+    /// What stored should become under new: plaintext gets sealed, text sealed under old gets rewrapped,
+    /// text new already opens is left alone (None), so a rotation interrupted halfway can be run again.
+    pub fn reseal(stored: &str, old: Option<&MasterKey>, new: &MasterKey) -> Result<Option<String>, SecretError> {
+        if stored.is_empty() {
+            return Ok(None);
+        }
+        if !is_sealed(stored) {
+            return Ok(Some(new.seal(stored)));
+        }
+        if new.open(stored).is_ok() {
+            return Ok(None);
+        }
+        old.ok_or(SecretError::NoKey)?.rewrap(new, stored).map(Some)
+    }
+
+    fn open_data_key(&self, sealed_key: &[u8]) -> Result<Key<Aes256Gcm>, SecretError> {
+        let bytes = open_bytes(&self.0, sealed_key)?;
+        if bytes.len() != 32 {
+            return Err(SecretError::Malformed);
+        }
+        Ok(*Key::<Aes256Gcm>::from_slice(&bytes))
+    }
+}
+
+/// nonce + ciphertext
+fn seal_bytes(key: &Key<Aes256Gcm>, plaintext: &[u8]) -> Vec<u8> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    // only fails for plaintext over 64GB
+    let ciphertext = Aes256Gcm::new(key).encrypt(&nonce, plaintext).expect("[seal_bytes] encrypt");
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    sealed
+}
+
+fn open_bytes(key: &Key<Aes256Gcm>, sealed: &[u8]) -> Result<Vec<u8>, SecretError> {
+    if sealed.len() <= NONCE_LEN {
+        return Err(SecretError::Malformed);
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(key).decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|_| SecretError::Decrypt)
+}
+
+fn split_sealed(stored: &str) -> Result<(Vec<u8>, Vec<u8>), SecretError> {
+    let rest = stored.strip_prefix(SEALED_PREFIX).ok_or(SecretError::Malformed)?;
+    let (sealed_key, sealed_value) = rest.split_once(':').ok_or(SecretError::Malformed)?;
+    let decode = |text: &str| STANDARD.decode(text).map_err(|_| SecretError::Malformed);
+    Ok((decode(sealed_key)?, decode(sealed_value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> MasterKey {
+        MasterKey::from_base64(&MasterKey::generate()).unwrap()
+    }
+
+    #[test]
+    fn seal_open_and_rotate() {
+        let (old, new) = (key(), key());
+        let sealed = old.seal("PKSECRET");
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.contains("PKSECRET"));
+        assert_eq!(old.open(&sealed).unwrap().expose(), "PKSECRET");
+        assert_eq!(new.open(&sealed), Err(SecretError::Decrypt));
+
+        let rotated = MasterKey::reseal(&sealed, Some(&old), &new).unwrap().unwrap();
+        assert_eq!(new.open(&rotated).unwrap().expose(), "PKSECRET");
+        // run again: already under new
+        assert_eq!(MasterKey::reseal(&rotated, Some(&old), &new), Ok(None));
+        // plaintext from before encryption
+        let sealed = MasterKey::reseal("PKID", None, &new).unwrap().unwrap();
+        assert_eq!(Secret::unseal(&sealed, Some(&new)).unwrap().expose(), "PKID");
+        assert_eq!(Secret::unseal(&sealed, None), Err(SecretError::NoKey));
+        assert_eq!(Secret::unseal("PKID", None).unwrap().expose(), "PKID");
+    }
+
+    #[test]
+    fn secret_is_redacted() {
+        let secret = Secret::new("PKSECRET");
+        assert_eq!(format!("{:?} {}", secret, secret), "[redacted] [redacted]");
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"[redacted]\"");
+        assert!(MasterKey::from_base64("too short").is_err());
+    }
+}
@@ -12,7 +12,10 @@ use crate::db::DbMsg;
 use crate::error::{PollerError, TradeWebError};
 
 /// used to receive the output of the SQL function fn_positions_to_sell
-#[derive(Debug, serde::Deserialize)]
+///
+/// Also the output of fn_positions_to_cover for short positions, where qty is the (positive) number of
+/// shares to buy back and unrealized P/L is entry price minus current price.
+#[derive(Debug, Clone, serde::Deserialize, sqlx::FromRow)]
 pub struct SellPosition {
     pub symbol:String,
     pub avg_entry_price:BigDecimal,
@@ -41,9 +44,30 @@ impl SellPosition {
 
     }
 
+    /// short positions that can be bought back lower than they were sold by at least pl_filter per share
+    pub async fn list_to_cover(pl_filter:BigDecimal, sender_tx:Sender<DbMsg>) -> Result<Vec<SellPosition>, TradeWebError> {
+        let (resp_tx, resp_rx) = crossbeam_channel::unbounded();
+        sender_tx.send(DbMsg::PositionListToCover { pl_filter, sender_tx: resp_tx}).map_err(|_| TradeWebError::ChannelError)?;
+        resp_rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
 
 
 
+    /// positions held longer than age_filter_minutes; see PositionExit
+    pub fn list_older_than(age_filter_minutes:BigDecimal, sender_tx:Sender<DbMsg>) -> Result<Vec<SellPosition>, TradeWebError> {
+        let (resp_tx, resp_rx) = crossbeam_channel::unbounded();
+        sender_tx.send(DbMsg::PositionListShowingAge { age_filter_minutes, sender_tx: resp_tx}).map_err(|_| TradeWebError::ChannelError)?;
+        resp_rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// short positions held longer than age_filter_minutes, as positive shares to cover; see PositionExit
+    pub fn list_shorts_older_than(age_filter_minutes:BigDecimal, sender_tx:Sender<DbMsg>) -> Result<Vec<SellPosition>, TradeWebError> {
+        let (resp_tx, resp_rx) = crossbeam_channel::unbounded();
+        sender_tx.send(DbMsg::PositionListToCoverShowingAge { age_filter_minutes, sender_tx: resp_tx}).map_err(|_| TradeWebError::ChannelError)?;
+        resp_rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
     /// positions exceeding the age specified in the parameter filter
     pub async fn list_showing_age(age_filter_minutes:BigDecimal, pool:&PgPool) ->Result<Vec<SellPosition>,PollerError>{
         let result = sqlx::query_as!(SellPosition,r#"
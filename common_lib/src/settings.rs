@@ -2,27 +2,32 @@
 //!
 //! model for settings store in postgres db
 
-use crate::trade_setting_profile::TradeSettingsProfile;
 use bigdecimal::BigDecimal;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
 use crate::db::DbMsg;
+use crate::error::{SecretError, TradeWebError};
+use crate::secret::{MasterKey, Secret};
+use crate::settings_history::ChangeOrigin;
+use crate::trade_setting_profile::SettingsProfile;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// credentials; encrypted in t_settings, never shown, logged or recorded in the settings history
+pub const SECRET_FIELDS: [&str; 5] = ["alpaca_paper_id", "alpaca_paper_secret", "alpaca_live_id", "alpaca_live_secret", "finnhub_key"];
+
+#[derive(Debug, Deserialize, Serialize, Clone, sqlx::FromRow)]
 pub struct Settings {
     pub dtg: DateTime<Utc>,
-    pub alpaca_paper_id: String,
-    pub alpaca_paper_secret: String,
-    pub alpaca_live_id: String,
-    pub alpaca_live_secret: String,
+    pub alpaca_paper_id: Secret,
+    pub alpaca_paper_secret: Secret,
+    pub alpaca_live_id: Secret,
+    pub alpaca_live_secret: Secret,
     pub trade_size: BigDecimal,
     pub trade_enable_buy: bool,
     pub trade_ema_small_size: i32,
     pub trade_ema_large_size: i32,
     pub trade_sell_high_per_cent_multiplier: BigDecimal,
     pub trade_sell_high_upper_limit_cents: BigDecimal,
-    pub finnhub_key: String,
+    pub finnhub_key: Secret,
     pub account_start_value:BigDecimal,
     pub max_position_age_minute:BigDecimal,
     pub upgrade_min_profit:BigDecimal,
@@ -39,9 +44,7 @@ pub struct Settings {
 
 
 impl Settings {
-    ///
-    /// TODO: encrypt alpaca credentials in database and decrypt here using .env
-    ///
+    /// the credentials decrypted with CREDENTIALS_KEY; see secret.rs
     pub fn load_with_secret(tx_db: crossbeam_channel::Sender<DbMsg>) -> Result<Settings, crossbeam_channel::RecvError> {
         tracing::debug!("[load_with_secret]");
         // response channel
@@ -60,42 +63,62 @@ impl Settings {
         settings_result
     }
 
-    /// change the settings and return blank secret for front-end type uses
-    pub async fn change_trade_profile(trade_settings_profile: &TradeSettingsProfile, pool: &PgPool, ) -> Result<Settings, sqlx::Error> {
-        let ts = trade_settings_profile.clone();
-        let ts = ts.to_string(); // .as_str();
-
-        // SQL injection is avoided here by using an enum; failure upon parsing would've happened at the api level
-        let settings_result = sqlx::query_as!(
-            Settings,
-            r#"
-                select
-                    dtg as "dtg!"
-                    , alpaca_paper_id as "alpaca_paper_id!"
-                    , '' as "alpaca_paper_secret!"
-                    , alpaca_live_id as "alpaca_live_id!"
-                    , '' as "alpaca_live_secret!"
-                    , trade_size as "trade_size!"
-                    , trade_enable_buy as "trade_enable_buy!"
-                    , trade_ema_small_size as "trade_ema_small_size!"
-                    , trade_ema_large_size as "trade_ema_large_size!"
-                    , trade_sell_high_per_cent_multiplier as "trade_sell_high_per_cent_multiplier!"
-                    , trade_sell_high_upper_limit_cents as "trade_sell_high_upper_limit_cents!"
-                    , finnhub_key as "finnhub_key!"
-                    ,coalesce(account_start_value,0.0) as "account_start_value!"
-                    ,coalesce(max_position_age_minute,0.0) as "max_position_age_minute!"
-                    ,coalesce(upgrade_min_profit,0.0) as "upgrade_min_profit!"
-                    ,coalesce(upgrade_sell_elapsed_minutes_min,60.0) as "upgrade_sell_elapsed_minutes_min!"
-                    ,coalesce(upgrade_posn_max_elapsed_minutes,60.0) as "upgrade_posn_max_elapsed_minutes!"
-                    ,coalesce(upgrade_posn_loss_allowed_dollars,10.0) as "upgrade_posn_loss_allowed_dollars!"
-                    ,coalesce(acct_max_position_market_value,60.0) as "acct_max_position_market_value!"
-                    ,coalesce(acct_min_cash_dollars,60.0) as "acct_min_cash_dollars!"
-                from fn_set_trade_settings($1);
-            "#,
-            &ts
-        )
-        .fetch_one(pool)
-        .await;
-        settings_result
+    /// the credentials as stored (sealed, or plaintext from before encryption) to usable values
+    pub fn unseal_credentials(mut self, key: Option<&MasterKey>) -> Result<Settings, SecretError> {
+        for secret in [&mut self.alpaca_paper_id, &mut self.alpaca_paper_secret, &mut self.alpaca_live_id, &mut self.alpaca_live_secret, &mut self.finnhub_key] {
+            *secret = Secret::unseal(secret.expose(), key)?;
+        }
+        Ok(self)
+    }
+
+    /// Pure; whether both hold the same credentials, i.e. connections made with one are still good
+    pub fn same_credentials(&self, other: &Settings) -> bool {
+        self.alpaca_paper_id == other.alpaca_paper_id && self.alpaca_paper_secret == other.alpaca_paper_secret
+            && self.alpaca_live_id == other.alpaca_live_id && self.alpaca_live_secret == other.alpaca_live_secret
+            && self.finnhub_key == other.finnhub_key
+    }
+
+    /// Pure; whether the field is a credential
+    pub fn is_secret(field: &str) -> bool {
+        SECRET_FIELDS.contains(&field)
+    }
+
+    /// Pure; every field except the credentials, as JSON
+    pub fn to_json_no_secret(&self) -> serde_json::Value {
+        let mut json = serde_json::to_value(self).unwrap_or_default();
+        if let Some(map) = json.as_object_mut() {
+            for secret in SECRET_FIELDS {
+                map.remove(secret);
+            }
+        }
+        json
+    }
+
+    /// Pure; the profile checks on the trade fields, plus the ones only settings have
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = SettingsProfile::from_settings("settings", "", "", self).validate().err().unwrap_or_default();
+        if self.account_start_value < BigDecimal::from(0) {
+            errors.push("account_start_value can't be negative".to_string());
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// Save the non-secret fields if the row is still the one read at expected_dtg; Conflict if someone
+    /// changed it since. The change is recorded in t_settings_history. Returns the saved settings with blank
+    /// secrets.
+    pub fn update(&self, expected_dtg: DateTime<Utc>, origin: ChangeOrigin, tx_db: crossbeam_channel::Sender<DbMsg>) -> Result<Settings, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsUpdate { settings: Box::new(self.clone()), expected_dtg, origin, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?
+    }
+
+    /// Activate the named profile: copy its values into t_settings and record changed_by in
+    /// t_trade_settings_profile_activation and t_settings_history (source is SOURCE_PROFILE or
+    /// SOURCE_SCHEDULE). Returns the new settings with blank secrets, for front-end type uses.
+    pub fn change_trade_profile(name: &str, changed_by: &str, source: &str, tx_db: crossbeam_channel::Sender<DbMsg>) -> Result<Settings, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let origin = ChangeOrigin::new(changed_by, source, name);
+        tx_db.send(DbMsg::SettingsProfileActivate { name: name.to_string(), origin, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
     }
 }
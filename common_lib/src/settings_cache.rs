@@ -0,0 +1,95 @@
+//! settings_cache.rs
+//!
+//! The current settings for every long-running backend thread. A trigger on t_settings fires
+//! NOTIFY settings_changed on every write (the editor, profile switches, restores, new credentials, or SQL
+//! by hand); SettingsCache listens, reloads with credentials and publishes on a tokio watch channel.
+//! Threads keep the receiver and check has_changed() between units of work; the websockets reconnect when
+//! the credentials change.
+//!
+//! After the listener loses its connection the settings are reloaded, in case a notification was missed.
+//!
+
+use crossbeam_channel::Sender;
+use sqlx::postgres::PgListener;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use crate::config::AppConfig;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+
+/// the NOTIFY channel; fn_notify_settings_changed
+pub const SETTINGS_CHANNEL: &str = "settings_changed";
+
+const LISTEN_RETRY_SECS: u64 = 5;
+
+pub struct SettingsCache;
+
+impl SettingsCache {
+
+    /// Load the settings and keep them current on a thread of their own; the receiver starts with the
+    /// loaded settings.
+    pub fn start(tx_db: Sender<DbMsg>, tokio_handle: Handle) -> Result<watch::Receiver<Settings>, TradeWebError> {
+        let settings = Settings::load_with_secret(tx_db.clone()).map_err(|_| TradeWebError::ChannelError)?;
+        let (tx, rx) = watch::channel(settings);
+        std::thread::spawn(move || {
+            tokio_handle.block_on(SettingsCache::listen(tx, tx_db));
+            tracing::info!("[SettingsCache] no receivers left, stopped listening");
+        });
+        Ok(rx)
+    }
+
+    async fn listen(tx: watch::Sender<Settings>, tx_db: Sender<DbMsg>) {
+        let db_url = AppConfig::get().database_url.expose();
+        while !tx.is_closed() {
+            match PgListener::connect(db_url).await {
+                Ok(mut listener) => match listener.listen(SETTINGS_CHANNEL).await {
+                    Ok(()) => {
+                        // anything written while nobody was listening
+                        SettingsCache::reload(&tx, &tx_db);
+                        while !tx.is_closed() {
+                            match listener.try_recv().await {
+                                Ok(Some(notification)) => {
+                                    tracing::debug!("[SettingsCache] notified: {}", notification.payload());
+                                    SettingsCache::reload(&tx, &tx_db);
+                                },
+                                // lost the connection; the next try_recv reconnects
+                                Ok(None) => {
+                                    tracing::warn!("[SettingsCache] listener reconnecting");
+                                    SettingsCache::reload(&tx, &tx_db);
+                                },
+                                Err(e) => {
+                                    tracing::error!("[SettingsCache] listener error: {:?}", &e);
+                                    break;
+                                },
+                            }
+                        }
+                    },
+                    Err(e) => tracing::error!("[SettingsCache] couldn't listen on {}: {:?}", SETTINGS_CHANNEL, &e),
+                },
+                Err(e) => tracing::error!("[SettingsCache] couldn't connect the listener: {:?}", &e),
+            }
+            // this thread only waits on the listener
+            std::thread::sleep(std::time::Duration::from_secs(LISTEN_RETRY_SECS));
+        }
+    }
+
+    /// publish the latest settings if they're newer than what receivers have
+    fn reload(tx: &watch::Sender<Settings>, tx_db: &Sender<DbMsg>) {
+        match Settings::load_with_secret(tx_db.clone()) {
+            Ok(latest) => {
+                let changed = tx.send_if_modified(|current| {
+                    if current.dtg == latest.dtg {
+                        return false;
+                    }
+                    *current = latest;
+                    true
+                });
+                if changed {
+                    tracing::info!("[SettingsCache] settings reloaded: {}", tx.borrow().dtg);
+                }
+            },
+            Err(e) => tracing::error!("[SettingsCache] couldn't reload settings: {:?}", &e),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! settings_history.rs
+//!
+//! Changes to t_settings, one row of t_settings_history each: who, when, from where, and the non-secret
+//! values before and after (Settings::to_json_no_secret). Every write goes through here: the editor, the
+//! profile buttons, the profile schedule, restores and new credentials (named in the note, never valued).
+//! The row id is the version number. The frontend shows the field-by-field diff at /settings/history and
+//! can restore either side of any change.
+//!
+
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use serde_json::Value;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+
+/// where a change came from, in t_settings_history.source
+pub const SOURCE_EDITOR: &str = "editor";
+pub const SOURCE_PROFILE: &str = "profile";
+pub const SOURCE_SCHEDULE: &str = "schedule";
+pub const SOURCE_RESTORE: &str = "restore";
+pub const SOURCE_CREDENTIALS: &str = "credentials";
+
+/// who made a settings write, from where, and a note for the history (the profile name, the restored version)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeOrigin {
+    pub changed_by: String,
+    pub source: String,
+    pub note: String,
+}
+
+impl ChangeOrigin {
+    pub fn new(changed_by: &str, source: &str, note: &str) -> ChangeOrigin {
+        ChangeOrigin { changed_by: changed_by.to_string(), source: source.to_string(), note: note.to_string() }
+    }
+}
+
+/// one row of t_settings_history
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct SettingsChange {
+    pub id: i64,
+    pub dtg: DateTime<Utc>,
+    pub changed_by: String,
+    pub source: String,
+    pub note: String,
+    /// JSON
+    pub old_values: String,
+    /// JSON
+    pub new_values: String,
+}
+
+/// one field that differs between two versions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl SettingsChange {
+
+    /// the fields this change touched
+    pub fn diff(&self) -> Vec<FieldChange> {
+        let old = serde_json::from_str(&self.old_values).unwrap_or(Value::Null);
+        let new = serde_json::from_str(&self.new_values).unwrap_or(Value::Null);
+        SettingsChange::diff_values(&old, &new)
+    }
+
+    /// Pure; fields that differ between two JSON objects, alphabetical, ignoring dtg (it changes on every
+    /// write). Numbers are compared as written, so 7 and 7.00 count as a change only if the text differs.
+    pub fn diff_values(old: &Value, new: &Value) -> Vec<FieldChange> {
+        let empty = serde_json::Map::new();
+        let old = old.as_object().unwrap_or(&empty);
+        let new = new.as_object().unwrap_or(&empty);
+        let mut fields: Vec<&String> = old.keys().chain(new.keys()).filter(|f| f.as_str() != "dtg").collect();
+        fields.sort();
+        fields.dedup();
+        fields.into_iter()
+            .filter_map(|field| {
+                let text = |v: Option<&Value>| match v {
+                    Some(Value::String(s)) => s.clone(),
+                    Some(v) => v.to_string(),
+                    None => "".to_string(),
+                };
+                let (old, new) = (text(old.get(field)), text(new.get(field)));
+                (old != new).then(|| FieldChange { field: field.clone(), old, new })
+            })
+            .collect()
+    }
+
+    /// the version before (before = true) or after this change, as settings: current with that version's
+    /// non-secret values in place. Credentials and dtg stay as they are in current.
+    pub fn version(&self, before: bool, current: &Settings) -> Result<Settings, String> {
+        let values = if before { &self.old_values } else { &self.new_values };
+        let values: Value = serde_json::from_str(values).map_err(|e| format!("version {} is unreadable: {}", self.id, e))?;
+        SettingsChange::apply_values(current, &values)
+    }
+
+    /// Pure; current with every field in values except dtg and the credentials replaced
+    pub fn apply_values(current: &Settings, values: &Value) -> Result<Settings, String> {
+        let mut merged = serde_json::to_value(current).map_err(|e| e.to_string())?;
+        if let (Some(merged), Some(values)) = (merged.as_object_mut(), values.as_object()) {
+            for (field, value) in values.iter().filter(|(field, _)| field.as_str() != "dtg" && !Settings::is_secret(field)) {
+                if merged.contains_key(field) {
+                    merged.insert(field.clone(), value.clone());
+                }
+            }
+        }
+        let mut restored: Settings = serde_json::from_value(merged).map_err(|e| e.to_string())?;
+        // credentials serialize redacted
+        restored.alpaca_paper_id = current.alpaca_paper_id.clone();
+        restored.alpaca_paper_secret = current.alpaca_paper_secret.clone();
+        restored.alpaca_live_id = current.alpaca_live_id.clone();
+        restored.alpaca_live_secret = current.alpaca_live_secret.clone();
+        restored.finnhub_key = current.finnhub_key.clone();
+        Ok(restored)
+    }
+
+    /// one change by id
+    pub fn load(id: i64, tx_db: Sender<DbMsg>) -> Result<SettingsChange, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsHistoryLoad { id, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// the latest limit changes, newest first
+    pub fn list(limit: i64, tx_db: Sender<DbMsg>) -> Result<Vec<SettingsChange>, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsHistoryList { limit, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn diff_lists_changed_fields_only() {
+        let old = json!({"dtg": "2023-11-22T15:00:00Z", "trade_size": "7", "trade_enable_buy": true, "trade_ema_small_size": 5});
+        let new = json!({"dtg": "2023-11-22T16:00:00Z", "trade_size": "17", "trade_enable_buy": false, "trade_ema_small_size": 5});
+        assert_eq!(SettingsChange::diff_values(&old, &new), vec![
+            FieldChange { field: "trade_enable_buy".to_string(), old: "true".to_string(), new: "false".to_string() },
+            FieldChange { field: "trade_size".to_string(), old: "7".to_string(), new: "17".to_string() },
+        ]);
+        assert!(SettingsChange::diff_values(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn apply_values_keeps_credentials_and_dtg() {
+        let current: Settings = serde_json::from_value(json!({
+            "dtg": "2023-11-22T16:00:00Z", "alpaca_paper_id": "id", "alpaca_paper_secret": "secret", "alpaca_live_id": "",
+            "alpaca_live_secret": "", "trade_size": "17", "trade_enable_buy": false, "trade_ema_small_size": 5,
+            "trade_ema_large_size": 20, "trade_sell_high_per_cent_multiplier": "1.1", "trade_sell_high_upper_limit_cents": "50",
+            "finnhub_key": "key", "account_start_value": "0", "max_position_age_minute": "0", "upgrade_min_profit": "0",
+            "upgrade_sell_elapsed_minutes_min": "60", "upgrade_posn_max_elapsed_minutes": "60", "upgrade_posn_loss_allowed_dollars": "10",
+            "acct_max_position_market_value": "10", "acct_min_cash_dollars": "10"
+        })).unwrap();
+        let version = json!({"dtg": "2023-11-22T15:00:00Z", "trade_size": "7", "trade_enable_buy": true, "alpaca_paper_secret": "", "unknown": 1});
+        let restored = SettingsChange::apply_values(&current, &version).unwrap();
+        assert_eq!(restored.trade_size.to_string(), "7");
+        assert!(restored.trade_enable_buy);
+        assert_eq!(restored.trade_ema_small_size, 5);
+        assert_eq!(restored.alpaca_paper_secret.expose(), "secret");
+        assert_eq!(restored.dtg, current.dtg);
+    }
+}
@@ -0,0 +1,839 @@
+//! sim_broker.rs
+//!
+//! SimulatedBroker runs the Alpaca order lifecycle locally against a price stream, for the backtester and for
+//! shadow trading next to live paper trading: submit a JsonTrade as alpaca_api::post_order would, cancel, list
+//! open orders and positions, read the account. There's no broker trait over it and Alpaca; the interface the
+//! two share is strategy::OrderRouter, with SimRouter here and AlpacaRouter for the real thing.
+//!
+//! The simulation:
+//! - market orders fill at the next price after the latency, moved against the order by the slippage
+//! - limit orders fill once the price reaches the limit; stop and stop limit orders trigger first
+//! - with partial fills on, no fill is bigger than the trade (or bar) it's matched against
+//! - DAY orders expire at the close (8pm Eastern with extended_hours); IOC and FOK at the first price, and a
+//!   FOK order only fills if all of it can
+//! - a sell with no long position opens a short, as on Alpaca
+//! - bracket/OTO/OCO orders and trailing stops are rejected with a 422
+//!
+//! Every state change is queued as the same MesgOrderUpdate the trade_updates websocket sends; drain them
+//! with take_updates.
+//!
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use bigdecimal::{BigDecimal, Signed, Zero};
+use chrono::{DateTime, Datelike, Duration, Utc, Weekday};
+use crate::account::Account;
+use crate::alpaca_api_structs::MesgOrderUpdate;
+use crate::alpaca_order::Order;
+use crate::alpaca_position::{Position, PositionSide};
+use crate::backtest::BacktestTrade;
+use crate::clock::{Clock, SystemClock};
+use crate::error::TradeWebError;
+use crate::market_hours::{MARKET_CLOSE_EXT, MARKET_CLOSE_TIME};
+use crate::sell_position::SellPosition;
+use crate::settings::Settings;
+use crate::strategy::{Intent, MarketEvent, OrderRouter};
+use crate::trade_struct::{JsonTrade, OrderType, TimeInForce, TradeSide};
+
+/// how simulated orders fill
+#[derive(Debug, Clone)]
+pub struct FillModel {
+    pub slippage_bps: BigDecimal,
+    pub latency: Duration,
+    /// cap each fill at the size of the trade it matches
+    pub partial_fills: bool,
+}
+
+impl Default for FillModel {
+    fn default() -> Self {
+        FillModel { slippage_bps: BigDecimal::zero(), latency: Duration::zero(), partial_fills: false }
+    }
+}
+
+impl FillModel {
+
+    /// Pure; a marketable order's fill price, moved against the order by slippage_bps
+    pub fn market_price(&self, side: &TradeSide, price: &BigDecimal) -> BigDecimal {
+        let slippage = price * &self.slippage_bps / BigDecimal::from(10_000);
+        match side {
+            TradeSide::Buy => (price + slippage).round(4),
+            TradeSide::Sell | TradeSide::SellShort => (price - slippage).round(4),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SimOrder {
+    order: Order,
+    /// nothing before this fills it
+    dtg_active: DateTime<Utc>,
+    dtg_expire: Option<DateTime<Utc>>,
+    /// stop and stop limit: the stop price has been reached
+    triggered: bool,
+}
+
+#[derive(Debug, Clone)]
+struct SimPosition {
+    /// negative when short
+    qty: BigDecimal,
+    avg_entry_price: BigDecimal,
+    dtg_open: DateTime<Utc>,
+}
+
+pub struct SimulatedBroker {
+    fill_model: FillModel,
+    cash: BigDecimal,
+    positions: HashMap<String, SimPosition>,
+    orders: Vec<SimOrder>,
+    prices: HashMap<String, BigDecimal>,
+    closed_trades: Vec<BacktestTrade>,
+    updates: Vec<MesgOrderUpdate>,
+    dtg_last: Option<DateTime<Utc>>,
+    /// the time before the first price
+    clock: Arc<dyn Clock>,
+}
+
+impl SimulatedBroker {
+
+    pub fn new(cash_start: BigDecimal, fill_model: FillModel) -> SimulatedBroker {
+        SimulatedBroker {
+            fill_model,
+            cash: cash_start,
+            positions: HashMap::new(),
+            orders: vec![],
+            prices: HashMap::new(),
+            closed_trades: vec![],
+            updates: vec![],
+            dtg_last: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// a replay's simulated time instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> SimulatedBroker {
+        self.clock = clock;
+        self
+    }
+
+    /// the broker's time is the price stream's: the last price seen, or the clock before the first one
+    fn now(&self) -> DateTime<Utc> {
+        self.dtg_last.unwrap_or_else(|| self.clock.now())
+    }
+
+    pub fn cash(&self) -> &BigDecimal {
+        &self.cash
+    }
+
+    pub fn price(&self, symbol: &str) -> Option<&BigDecimal> {
+        self.prices.get(&symbol.to_uppercase())
+    }
+
+    /// signed; zero when flat
+    pub fn position_qty(&self, symbol: &str) -> BigDecimal {
+        self.positions.get(&symbol.to_uppercase()).map(|p| p.qty.clone()).unwrap_or_else(BigDecimal::zero)
+    }
+
+    /// cash plus positions at their last price (shorts count negative)
+    pub fn equity(&self) -> BigDecimal {
+        let market_value = self.positions.iter()
+            .map(|(symbol, p)| &p.qty * self.prices.get(symbol).unwrap_or(&p.avg_entry_price))
+            .fold(BigDecimal::zero(), |a, b| a + b);
+        &self.cash + market_value
+    }
+
+    /// every round trip closed so far, oldest first
+    pub fn closed_trades(&self) -> &[BacktestTrade] {
+        &self.closed_trades
+    }
+
+    /// order updates since the last call, in the order they happened
+    pub fn take_updates(&mut self) -> Vec<MesgOrderUpdate> {
+        std::mem::take(&mut self.updates)
+    }
+
+    /// long positions the way SellPosition::list_showing_profit reports them
+    pub fn sell_positions(&self, now: DateTime<Utc>) -> Vec<SellPosition> {
+        self.exit_positions(now, false)
+    }
+
+    /// short positions the way SellPosition::list_to_cover reports them: positive shares, entry minus price
+    pub fn cover_positions(&self, now: DateTime<Utc>) -> Vec<SellPosition> {
+        self.exit_positions(now, true)
+    }
+
+    fn exit_positions(&self, now: DateTime<Utc>, short: bool) -> Vec<SellPosition> {
+        let exit_side = if short { TradeSide::Buy } else { TradeSide::Sell };
+        self.positions.iter().filter(|(_, p)| p.qty.is_negative() == short && !p.qty.is_zero()).map(|(symbol, p)| {
+            let price = self.prices.get(symbol).unwrap_or(&p.avg_entry_price);
+            let qty = p.qty.abs();
+            let qty_held = self.orders.iter()
+                .filter(|o| o.order.symbol == *symbol && o.order.side == exit_side)
+                .map(|o| &o.order.qty - o.order.filled_qty.clone().unwrap_or_else(BigDecimal::zero))
+                .fold(BigDecimal::zero(), |a, b| a + b);
+            let pl_per_share = if short { &p.avg_entry_price - price } else { price - &p.avg_entry_price };
+            SellPosition {
+                symbol: symbol.to_lowercase(),
+                avg_entry_price: p.avg_entry_price.clone(),
+                qty_available: std::cmp::max(&qty - qty_held, BigDecimal::zero()),
+                cost_basis: &qty * &p.avg_entry_price,
+                unrealized_pl_total: &qty * &pl_per_share,
+                unrealized_pl_per_share: pl_per_share,
+                qty,
+                trade_size: BigDecimal::zero(),
+                age_minute: BigDecimal::from((now - p.dtg_open).num_minutes()),
+            }
+        }).collect()
+    }
+
+    /// A new price: expire what's due, fill whatever the price reaches, then remember it. For a trade, low,
+    /// high and last are all the trade price; a bar's range lets limits and stops fill on it. volume is
+    /// the most that can fill when partial fills are on (zero for unknown).
+    pub fn on_price(&mut self, symbol: &str, dtg: DateTime<Utc>, low: &BigDecimal, high: &BigDecimal, last: &BigDecimal, volume: &BigDecimal) {
+        let symbol = symbol.to_uppercase();
+        self.expire(dtg);
+
+        let mut volume_left = volume.clone();
+        let mut index = 0;
+        while index < self.orders.len() {
+            let sim_order = &mut self.orders[index];
+            if sim_order.order.symbol != symbol || sim_order.dtg_active > dtg {
+                index += 1;
+                continue;
+            }
+            let side = sim_order.order.side.clone();
+            let is_buy = side == TradeSide::Buy;
+
+            if matches!(sim_order.order.order_type_v2, OrderType::Stop | OrderType::StopLimit) && !sim_order.triggered {
+                let stop = sim_order.order.stop_price.clone().unwrap_or_else(BigDecimal::zero);
+                sim_order.triggered = if is_buy { *high >= stop } else { *low <= stop };
+                if !sim_order.triggered {
+                    index += 1;
+                    continue;
+                }
+            }
+
+            let fill_price = match (&sim_order.order.order_type_v2, &sim_order.order.limit_price) {
+                (OrderType::Limit | OrderType::StopLimit, Some(limit)) if is_buy && low <= limit => Some(std::cmp::min(limit, last).clone()),
+                (OrderType::Limit | OrderType::StopLimit, Some(limit)) if !is_buy && high >= limit => Some(std::cmp::max(limit, last).clone()),
+                (OrderType::Limit | OrderType::StopLimit, _) => None,
+                _ => Some(self.fill_model.market_price(&side, last)),
+            };
+            let Some(fill_price) = fill_price else {
+                index += 1;
+                continue;
+            };
+
+            let filled = sim_order.order.filled_qty.clone().unwrap_or_else(BigDecimal::zero);
+            let remaining = match &sim_order.order.notional {
+                Some(notional) if sim_order.order.qty.is_zero() => (notional / &fill_price).with_scale(9),
+                _ => &sim_order.order.qty - &filled,
+            };
+            let fill_qty = if self.fill_model.partial_fills && volume.is_positive() {
+                std::cmp::min(remaining.clone(), volume_left.clone())
+            } else {
+                remaining.clone()
+            };
+            // fill or kill: all of it now or none of it
+            if !fill_qty.is_positive() || sim_order.order.time_in_force == TimeInForce::Fok && fill_qty < remaining {
+                index += 1;
+                continue;
+            }
+            volume_left -= &fill_qty;
+
+            // the order
+            let filled_total = &filled + &fill_qty;
+            let avg_before = sim_order.order.filled_avg_price.clone().unwrap_or_else(BigDecimal::zero);
+            sim_order.order.filled_avg_price = Some(((&avg_before * &filled + &fill_price * &fill_qty) / &filled_total).round(4));
+            sim_order.order.filled_qty = Some(filled_total);
+            sim_order.order.updated_at = dtg;
+            let done = fill_qty == remaining;
+            if sim_order.order.notional.is_some() && sim_order.order.qty.is_zero() {
+                sim_order.order.qty = fill_qty.clone();
+            }
+            let order = if done {
+                sim_order.order.status = "filled".to_string();
+                sim_order.order.filled_at = Some(dtg);
+                self.orders.remove(index).order
+            } else {
+                sim_order.order.status = "partially_filled".to_string();
+                index += 1;
+                sim_order.order.clone()
+            };
+
+            self.apply_fill(&symbol, &side, &fill_qty, &fill_price, dtg);
+            self.updates.push(if done {
+                MesgOrderUpdate::Fill { timestamp: dtg, price: fill_price, qty: fill_qty, order }
+            } else {
+                MesgOrderUpdate::PartialFill { timestamp: dtg, price: fill_price, qty: fill_qty, order }
+            });
+        }
+
+        self.prices.insert(symbol, last.clone());
+        self.dtg_last = Some(std::cmp::max(dtg, self.dtg_last.unwrap_or(dtg)));
+    }
+
+    /// DAY orders past the close, IOC/FOK orders that had their chance
+    fn expire(&mut self, now: DateTime<Utc>) {
+        let (expired, open): (Vec<SimOrder>, Vec<SimOrder>) = std::mem::take(&mut self.orders).into_iter()
+            .partition(|o| o.dtg_expire.is_some_and(|expire| expire <= now));
+        self.orders = open;
+        for mut sim_order in expired {
+            sim_order.order.status = "expired".to_string();
+            sim_order.order.expired_at = Some(now);
+            sim_order.order.updated_at = now;
+            self.updates.push(MesgOrderUpdate::Expired { timestamp: now, order: sim_order.order });
+        }
+    }
+
+    /// cash and position for one fill; closing all or part of a position records a round trip
+    fn apply_fill(&mut self, symbol: &str, side: &TradeSide, qty: &BigDecimal, price: &BigDecimal, dtg: DateTime<Utc>) {
+        let signed_qty = if *side == TradeSide::Buy { qty.clone() } else { -qty.clone() };
+        self.cash -= &signed_qty * price;
+
+        let position = self.positions.entry(symbol.to_string()).or_insert_with(|| SimPosition {
+            qty: BigDecimal::zero(),
+            avg_entry_price: BigDecimal::zero(),
+            dtg_open: dtg,
+        });
+
+        // opening or adding
+        if position.qty.is_zero() || position.qty.is_positive() == signed_qty.is_positive() {
+            let qty_before = position.qty.abs();
+            position.avg_entry_price = ((&qty_before * &position.avg_entry_price + qty * price) / (&qty_before + qty)).round(4);
+            position.qty += &signed_qty;
+            return;
+        }
+
+        // closing, and maybe flipping to the other side
+        let qty_closed = std::cmp::min(qty.clone(), position.qty.abs());
+        let pnl_per_share = if position.qty.is_positive() { price - &position.avg_entry_price } else { &position.avg_entry_price - price };
+        self.closed_trades.push(BacktestTrade {
+            symbol: symbol.to_string(),
+            dtg_open: position.dtg_open,
+            dtg_close: dtg,
+            qty: qty_closed.clone(),
+            price_open: position.avg_entry_price.clone(),
+            price_close: price.clone(),
+            pnl: (&qty_closed * pnl_per_share).round(4),
+        });
+        position.qty += &signed_qty;
+        if position.qty.is_zero() {
+            self.positions.remove(symbol);
+        } else if qty > &qty_closed {
+            position.avg_entry_price = price.clone();
+            position.dtg_open = dtg;
+        }
+    }
+
+    /// Pure; when a DAY order submitted at now expires: today's close, or the next weekday's if that's
+    /// already passed. Holidays and early closes aren't modeled.
+    pub fn day_order_expiry(now: DateTime<Utc>, extended_hours: bool) -> DateTime<Utc> {
+        let close_time = if extended_hours { *MARKET_CLOSE_EXT } else { *MARKET_CLOSE_TIME };
+        let mut date = now.with_timezone(&chrono_tz::America::New_York).date_naive();
+        loop {
+            let is_weekend = matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+            let close = date.and_time(close_time).and_local_timezone(chrono_tz::America::New_York).single().map(|d| d.with_timezone(&Utc));
+            match close {
+                Some(close) if !is_weekend && close > now => return close,
+                _ => date += Duration::days(1),
+            }
+        }
+    }
+
+    /// Pure; why Alpaca would turn the order away with a 422, if it would
+    fn validate(json_trade: &JsonTrade) -> Result<(), String> {
+        let zero = BigDecimal::zero();
+        match (&json_trade.qty, &json_trade.notional) {
+            (Some(qty), None) if *qty > zero => {},
+            (None, Some(notional)) if *notional > zero && json_trade.order_type == OrderType::Market => {},
+            _ => return Err("exactly one of a positive qty or a market notional".to_string()),
+        }
+        if json_trade.order_class.is_some() {
+            return Err("bracket/oto/oco orders aren't simulated".to_string());
+        }
+        match json_trade.order_type {
+            OrderType::TrailingStop => return Err("trailing stops aren't simulated".to_string()),
+            OrderType::Limit | OrderType::StopLimit if json_trade.limit_price.is_none() => return Err("limit_price required".to_string()),
+            _ => {},
+        }
+        if matches!(json_trade.order_type, OrderType::Stop | OrderType::StopLimit) && json_trade.stop_price.is_none() {
+            return Err("stop_price required".to_string());
+        }
+        if matches!(json_trade.time_in_force, TimeInForce::Opg | TimeInForce::Cls) {
+            return Err("opg/cls orders aren't simulated".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// the Alpaca REST calls an order router needs
+impl SimulatedBroker {
+
+    /// accepted orders are queued as New; Alpaca's 422 and 403 (insufficient buying power or qty) come back
+    /// as errors
+    pub fn submit(&mut self, json_trade: JsonTrade) -> Result<Order, TradeWebError> {
+        if let Err(reason) = SimulatedBroker::validate(&json_trade) {
+            tracing::debug!("[SimulatedBroker::submit] rejected {}: {}", &json_trade.symbol, reason);
+            return Err(TradeWebError::Alpaca422);
+        }
+
+        let now = self.now();
+        let symbol = json_trade.symbol.to_uppercase();
+        let side = match json_trade.side {
+            TradeSide::SellShort => TradeSide::Sell,
+            ref side => side.clone(),
+        };
+
+        // buying power: the order's price if it has one, otherwise the last price
+        let price = json_trade.limit_price.clone().or_else(|| json_trade.stop_price.clone()).or_else(|| self.prices.get(&symbol).cloned());
+        let cost = match (&json_trade.notional, &json_trade.qty, &price) {
+            (Some(notional), _, _) => Some(notional.clone()),
+            (None, Some(qty), Some(price)) => Some(qty * price),
+            _ => None,
+        };
+        let position_qty = self.position_qty(&symbol);
+        let opens = side == TradeSide::Buy && !position_qty.is_negative() || side == TradeSide::Sell && !position_qty.is_positive();
+        if opens && cost.as_ref().is_some_and(|cost| *cost > self.cash) {
+            return Err(TradeWebError::Alpaca403);
+        }
+
+        // a sell against a long position can't be for more than isn't already spoken for
+        if side == TradeSide::Sell && position_qty.is_positive() {
+            let qty_held = self.orders.iter()
+                .filter(|o| o.order.symbol == symbol && o.order.side == TradeSide::Sell)
+                .map(|o| &o.order.qty - o.order.filled_qty.clone().unwrap_or_else(BigDecimal::zero))
+                .fold(BigDecimal::zero(), |a, b| a + b);
+            if json_trade.qty.as_ref().is_some_and(|qty| *qty > &position_qty - qty_held) {
+                return Err(TradeWebError::Alpaca403);
+            }
+        }
+
+        let extended_hours = json_trade.extended_hours.unwrap_or(false);
+        let dtg_active = now + self.fill_model.latency;
+        let dtg_expire = match json_trade.time_in_force {
+            TimeInForce::Day => Some(SimulatedBroker::day_order_expiry(now, extended_hours)),
+            TimeInForce::Ioc | TimeInForce::Fok => Some(dtg_active + Duration::milliseconds(1)),
+            _ => None,
+        };
+        let order = Order {
+            id: uuid::Uuid::new_v4().to_string(),
+            client_order_id: json_trade.client_order_id.clone(),
+            created_at: now,
+            updated_at: now,
+            submitted_at: now,
+            filled_at: None,
+            expired_at: None,
+            canceled_at: None,
+            failed_at: None,
+            replaced_at: None,
+            replaced_by: None,
+            replaces: None,
+            asset_id: None,
+            symbol,
+            asset_class: Some("us_equity".to_string()),
+            notional: json_trade.notional.clone(),
+            qty: json_trade.qty.clone().unwrap_or_else(BigDecimal::zero),
+            filled_qty: Some(BigDecimal::zero()),
+            filled_avg_price: None,
+            order_class: Some("".to_string()),
+            order_type_v2: json_trade.order_type.clone(),
+            side,
+            time_in_force: json_trade.time_in_force.clone(),
+            limit_price: json_trade.limit_price.clone(),
+            stop_price: json_trade.stop_price.clone(),
+            status: "new".to_string(),
+            extended_hours,
+            trail_percent: None,
+            trail_price: None,
+            hwm: None,
+        };
+        self.orders.push(SimOrder { order: order.clone(), dtg_active, dtg_expire, triggered: false });
+        self.updates.push(MesgOrderUpdate::New { order: order.clone() });
+        Ok(order)
+    }
+
+    pub fn cancel(&mut self, order_id: &str) -> Result<(), TradeWebError> {
+        let Some(index) = self.orders.iter().position(|o| o.order.id == order_id) else {
+            return Err(TradeWebError::Alpaca422);
+        };
+        let now = self.now();
+        let mut order = self.orders.remove(index).order;
+        order.status = "canceled".to_string();
+        order.canceled_at = Some(now);
+        order.updated_at = now;
+        self.updates.push(MesgOrderUpdate::Canceled { timestamp: now, order });
+        Ok(())
+    }
+
+    pub fn open_orders(&mut self) -> Result<Vec<Order>, TradeWebError> {
+        Ok(self.orders.iter().map(|o| o.order.clone()).collect())
+    }
+
+    pub fn positions(&mut self) -> Result<Vec<Position>, TradeWebError> {
+        let now = self.now();
+        Ok(self.positions.iter().map(|(symbol, p)| {
+            let price = self.prices.get(symbol).cloned().unwrap_or_else(|| p.avg_entry_price.clone());
+            let cost_basis = &p.qty * &p.avg_entry_price;
+            let market_value = &p.qty * &price;
+            let unrealized_pl = &market_value - &cost_basis;
+            let unrealized_plpc = if cost_basis.is_zero() { BigDecimal::zero() } else { (&unrealized_pl / cost_basis.abs()).round(6) };
+            Position {
+                dtg: now,
+                asset_id: "".to_string(),
+                symbol: symbol.clone(),
+                exchange: "".to_string(),
+                asset_class: "us_equity".to_string(),
+                avg_entry_price: p.avg_entry_price.clone(),
+                qty: p.qty.clone(),
+                qty_available: p.qty.clone(),
+                side: if p.qty.is_negative() { PositionSide::Short } else { PositionSide::Long },
+                market_value,
+                cost_basis,
+                unrealized_intraday_pl: unrealized_pl.clone(),
+                unrealized_intraday_plpc: unrealized_plpc.clone(),
+                unrealized_pl,
+                unrealized_plpc,
+                current_price: price.clone(),
+                lastday_price: price,
+                change_today: BigDecimal::zero(),
+                dtg_updated: now,
+            }
+        }).collect())
+    }
+
+    pub fn account(&mut self) -> Result<Account, TradeWebError> {
+        let (long, short) = self.positions.iter().fold((BigDecimal::zero(), BigDecimal::zero()), |(long, short), (symbol, p)| {
+            let market_value = &p.qty * self.prices.get(symbol).unwrap_or(&p.avg_entry_price);
+            if market_value.is_negative() { (long, short + market_value) } else { (long + market_value, short) }
+        });
+        let mut account = Account::blank();
+        account.status = "ACTIVE".to_string();
+        account.currency = "USD".to_string();
+        account.cash = self.cash.clone();
+        account.equity = self.equity();
+        account.portfolio_value = account.equity.clone();
+        account.position_market_value = &long + &short;
+        account.long_market_value = long;
+        account.short_market_value = short;
+        account.buying_power = self.cash.clone();
+        Ok(account)
+    }
+}
+
+/// Strategy intents into a SimulatedBroker, fed prices from the same events the strategies see. Keeps the
+/// live rules: one position and one open order per symbol, sells only close longs and covers only close
+/// shorts.
+pub struct SimRouter {
+    broker: SimulatedBroker,
+    /// shares per buy by symbol, from t_symbol.trade_size
+    trade_sizes: HashMap<String, BigDecimal>,
+    label: &'static str,
+    /// fills and equity at info instead of debug
+    log_fills: bool,
+}
+
+impl SimRouter {
+
+    /// label prefixes the order update log lines, e.g. "backtest" or "shadow"
+    pub fn new(broker: SimulatedBroker, trade_sizes: HashMap<String, BigDecimal>, label: &'static str) -> SimRouter {
+        SimRouter { broker, trade_sizes, label, log_fills: false }
+    }
+
+    /// log fills and equity at info; shadow trading has no other record of them
+    pub fn with_fill_log(mut self) -> SimRouter {
+        self.log_fills = true;
+        self
+    }
+
+    pub fn broker(&self) -> &SimulatedBroker {
+        &self.broker
+    }
+
+    pub fn broker_mut(&mut self) -> &mut SimulatedBroker {
+        &mut self.broker
+    }
+
+    fn has_open_order(&self, symbol: &str) -> bool {
+        self.broker.orders.iter().any(|o| o.order.symbol == symbol)
+    }
+
+    fn with_trade_size(&self, mut positions: Vec<SellPosition>) -> Vec<SellPosition> {
+        for position in positions.iter_mut() {
+            position.trade_size = self.trade_sizes.get(&position.symbol.to_uppercase()).cloned().unwrap_or_else(BigDecimal::zero);
+        }
+        positions
+    }
+
+    /// Whole shares for a new long or short: qty or t_symbol.trade_size, no more than cash covers; None if
+    /// there's already a position or an order.
+    fn entry_qty(&self, symbol: &str, qty: Option<BigDecimal>, limit_price: Option<BigDecimal>, settings: &Settings) -> Option<BigDecimal> {
+        if !self.broker.position_qty(symbol).is_zero() || self.has_open_order(symbol) {
+            return None;
+        }
+        let price = limit_price.or_else(|| self.broker.price(symbol).cloned())?;
+        let trade_size = self.trade_sizes.get(symbol).cloned().unwrap_or_else(|| settings.trade_size.clone());
+        let qty_affordable = if price.is_positive() { (self.broker.cash() / &price).with_scale(0) } else { BigDecimal::zero() };
+        Some(std::cmp::min(qty.unwrap_or(trade_size), qty_affordable)).filter(|qty| qty.is_positive())
+    }
+
+    fn submit(&mut self, symbol: String, side: TradeSide, qty: BigDecimal, limit_price: Option<BigDecimal>) {
+        let json_trade = JsonTrade {
+            symbol,
+            side,
+            time_in_force: TimeInForce::Day,
+            qty: Some(qty),
+            notional: None,
+            order_type: if limit_price.is_some() { OrderType::Limit } else { OrderType::Market },
+            limit_price,
+            extended_hours: Some(true),
+            client_order_id: uuid::Uuid::new_v4().to_string(),
+            stop_price: None,
+            trail_percent: None,
+            trail_price: None,
+            order_class: None,
+            take_profit: None,
+            stop_loss: None,
+        };
+        if let Err(e) = self.broker.submit(json_trade) {
+            tracing::debug!("[{}] order rejected: {:?}", self.label, &e);
+        }
+    }
+}
+
+impl OrderRouter for SimRouter {
+
+    fn execute(&mut self, intent: &Intent, settings: &Settings) {
+        match intent {
+            Intent::Buy { symbol, qty, limit_price, .. } => {
+                let symbol = symbol.to_uppercase();
+                if let Some(qty) = self.entry_qty(&symbol, qty.clone(), limit_price.clone(), settings) {
+                    self.submit(symbol, TradeSide::Buy, qty, limit_price.clone());
+                }
+            },
+            Intent::SellShort { symbol, .. } => {
+                let symbol = symbol.to_uppercase();
+                if let Some(qty) = self.entry_qty(&symbol, None, None, settings) {
+                    self.submit(symbol, TradeSide::SellShort, qty, None);
+                }
+            },
+            Intent::Cover { symbol, qty, limit_price, .. } => {
+                let symbol = symbol.to_uppercase();
+                let position_qty = self.broker.position_qty(&symbol);
+                let covering = self.broker.orders.iter().any(|o| o.order.symbol == symbol && o.order.side == TradeSide::Buy);
+                if !position_qty.is_negative() || covering {
+                    return;
+                }
+                self.submit(symbol, TradeSide::Buy, std::cmp::min(qty.clone(), position_qty.abs()), limit_price.clone());
+            },
+            Intent::Sell { symbol, qty, limit_price, .. } => {
+                let symbol = symbol.to_uppercase();
+                let position_qty = self.broker.position_qty(&symbol);
+                let selling = self.broker.orders.iter().any(|o| o.order.symbol == symbol && o.order.side == TradeSide::Sell);
+                if !position_qty.is_positive() || selling {
+                    return;
+                }
+                self.submit(symbol, TradeSide::Sell, std::cmp::min(qty.clone(), position_qty), limit_price.clone());
+            },
+            Intent::Cancel { symbol, .. } => {
+                let ids: Vec<String> = self.broker.orders.iter().filter(|o| o.order.symbol.eq_ignore_ascii_case(symbol)).map(|o| o.order.id.clone()).collect();
+                for id in ids {
+                    let _ = self.broker.cancel(&id);
+                }
+            },
+        }
+    }
+
+    fn on_event(&mut self, event: &MarketEvent) -> Vec<MarketEvent> {
+        match event {
+            MarketEvent::Trade(trade) => self.broker.on_price(&trade.symbol, trade.dtg, &trade.price, &trade.price, &trade.price, &trade.size),
+            MarketEvent::Bar(bar) => {
+                // a bar is only known once its minute is over
+                let dtg = bar.dtg + Duration::minutes(1);
+                self.broker.on_price(&bar.symbol, dtg, &bar.price_low, &bar.price_high, &bar.price_close, &BigDecimal::from(bar.volume as u64));
+            },
+            _ => {},
+        }
+        let mut fills = vec![];
+        for update in self.broker.take_updates() {
+            match update {
+                MesgOrderUpdate::Fill { price, qty, order, .. } | MesgOrderUpdate::PartialFill { price, qty, order, .. } => {
+                    if self.log_fills {
+                        tracing::info!("[{}] {} {} {} at {}, equity {}", self.label, &order.side, &qty, &order.symbol, &price, self.broker.equity().round(2));
+                    } else {
+                        tracing::debug!("[{}] {} {} {} at {}", self.label, &order.side, &qty, &order.symbol, &price);
+                    }
+                    fills.push(MarketEvent::Fill { order: Box::new(order), price, qty });
+                },
+                update => tracing::debug!("[{}] {:?}", self.label, &update),
+            }
+        }
+        fills
+    }
+
+    fn positions(&self, now: DateTime<Utc>) -> Option<Vec<SellPosition>> {
+        Some(self.with_trade_size(self.broker.sell_positions(now)))
+    }
+
+    fn short_positions(&self, now: DateTime<Utc>) -> Option<Vec<SellPosition>> {
+        Some(self.with_trade_size(self.broker.cover_positions(now)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+    use crate::clock::SimulatedClock;
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn dtg(seconds: i64) -> DateTime<Utc> {
+        // a Wednesday, 10am Eastern
+        DateTime::parse_from_rfc3339("2023-11-22T15:00:00Z").unwrap().with_timezone(&Utc) + Duration::seconds(seconds)
+    }
+
+    fn json_trade(side: TradeSide, qty: &str, order_type: OrderType, limit_price: Option<&str>, stop_price: Option<&str>) -> JsonTrade {
+        JsonTrade {
+            symbol: "AAPL".to_string(),
+            side,
+            time_in_force: TimeInForce::Day,
+            qty: Some(dec(qty)),
+            notional: None,
+            order_type,
+            limit_price: limit_price.map(dec),
+            extended_hours: Some(false),
+            client_order_id: "test".to_string(),
+            stop_price: stop_price.map(dec),
+            trail_percent: None,
+            trail_price: None,
+            order_class: None,
+            take_profit: None,
+            stop_loss: None,
+        }
+    }
+
+    fn trade(broker: &mut SimulatedBroker, seconds: i64, price: &str, size: &str) {
+        broker.on_price("AAPL", dtg(seconds), &dec(price), &dec(price), &dec(price), &dec(size));
+    }
+
+    #[test]
+    fn market_order_waits_out_latency_and_pays_slippage() {
+        let fill_model = FillModel { slippage_bps: dec("10"), latency: Duration::seconds(1), partial_fills: false };
+        let mut broker = SimulatedBroker::new(dec("1000"), fill_model);
+        broker.on_price("AAPL", dtg(0), &dec("100"), &dec("100"), &dec("100"), &dec("0"));
+
+        broker.submit(json_trade(TradeSide::Buy, "5", OrderType::Market, None, None)).unwrap();
+        assert!(matches!(broker.take_updates().as_slice(), [MesgOrderUpdate::New { .. }]));
+        trade(&mut broker, 0, "100", "100");
+        assert!(broker.position_qty("AAPL").is_zero());
+
+        // 10 bps on $100
+        trade(&mut broker, 2, "100", "100");
+        assert_eq!(broker.position_qty("AAPL"), dec("5"));
+        assert_eq!(broker.cash(), &dec("499.5"));
+        match broker.take_updates().as_slice() {
+            [MesgOrderUpdate::Fill { price, qty, order, .. }] => {
+                assert_eq!((price, qty), (&dec("100.1"), &dec("5")));
+                assert_eq!(order.status, "filled");
+            },
+            other => panic!("unexpected updates {:?}", other),
+        }
+    }
+
+    #[test]
+    fn limit_partial_fills_and_round_trip() {
+        let fill_model = FillModel { partial_fills: true, ..Default::default() };
+        let mut broker = SimulatedBroker::new(dec("10000"), fill_model);
+        broker.apply_fill("AAPL", &TradeSide::Buy, &dec("10"), &dec("100"), dtg(0));
+        trade(&mut broker, 0, "100", "1");
+
+        broker.submit(json_trade(TradeSide::Sell, "10", OrderType::Limit, Some("101"), None)).unwrap();
+        // a second sell would oversell the position
+        assert!(broker.submit(json_trade(TradeSide::Sell, "1", OrderType::Market, None, None)).is_err());
+        broker.take_updates();
+
+        trade(&mut broker, 1, "100.5", "100");
+        trade(&mut broker, 2, "101.2", "4");
+        trade(&mut broker, 3, "101", "100");
+        let updates = broker.take_updates();
+        assert!(matches!(&updates[0], MesgOrderUpdate::PartialFill { qty, .. } if *qty == dec("4")));
+        assert!(matches!(&updates[1], MesgOrderUpdate::Fill { qty, order, .. } if *qty == dec("6") && order.filled_avg_price == Some(dec("101.08"))));
+
+        assert!(broker.position_qty("AAPL").is_zero());
+        let pnl: BigDecimal = broker.closed_trades().iter().map(|t| t.pnl.clone()).sum();
+        assert_eq!(pnl, dec("10.8"));
+        assert_eq!(broker.equity(), dec("10010.8"));
+    }
+
+    #[test]
+    fn fok_fills_completely_or_not_at_all() {
+        let fill_model = FillModel { partial_fills: true, ..Default::default() };
+        let mut broker = SimulatedBroker::new(dec("10000"), fill_model);
+        trade(&mut broker, 0, "100", "1");
+
+        let mut fok = json_trade(TradeSide::Buy, "10", OrderType::Market, None, None);
+        fok.time_in_force = TimeInForce::Fok;
+        broker.submit(fok.clone()).unwrap();
+        // 4 shares of volume can't fill 10
+        trade(&mut broker, 0, "100", "4");
+        trade(&mut broker, 1, "100", "100");
+        assert!(broker.position_qty("AAPL").is_zero());
+        assert!(matches!(broker.take_updates().as_slice(), [MesgOrderUpdate::New { .. }, MesgOrderUpdate::Expired { .. }]));
+
+        broker.submit(fok).unwrap();
+        trade(&mut broker, 1, "100", "100");
+        assert_eq!(broker.position_qty("AAPL"), dec("10"));
+    }
+
+    #[test]
+    fn stop_triggers_then_fills_and_short_round_trip() {
+        let mut broker = SimulatedBroker::new(dec("10000"), FillModel::default());
+        trade(&mut broker, 0, "100", "1");
+
+        // no position: the sell opens a short
+        broker.submit(json_trade(TradeSide::Sell, "10", OrderType::Market, None, None)).unwrap();
+        trade(&mut broker, 1, "100", "1");
+        assert_eq!(broker.position_qty("AAPL"), dec("-10"));
+
+        // buy stop to cover above the market
+        broker.submit(json_trade(TradeSide::Buy, "10", OrderType::Stop, None, Some("102"))).unwrap();
+        trade(&mut broker, 2, "101", "1");
+        assert_eq!(broker.position_qty("AAPL"), dec("-10"));
+        trade(&mut broker, 3, "102.5", "1");
+        assert!(broker.position_qty("AAPL").is_zero());
+        assert_eq!(broker.closed_trades()[0].pnl, dec("-25"));
+    }
+
+    #[test]
+    fn orders_before_the_first_price_use_the_brokers_clock() {
+        let sim_clock = SimulatedClock::new(dtg(0));
+        let mut broker = SimulatedBroker::new(dec("10000"), FillModel::default()).with_clock(Arc::new(sim_clock.clone()));
+        let order = broker.submit(json_trade(TradeSide::Buy, "1", OrderType::Limit, Some("90"), None)).unwrap();
+        assert_eq!(order.submitted_at, dtg(0));
+        sim_clock.advance(Duration::hours(7));
+        let order = broker.submit(json_trade(TradeSide::Buy, "1", OrderType::Limit, Some("90"), None)).unwrap();
+        assert_eq!(order.submitted_at, dtg(7 * 3600));
+    }
+
+    #[test]
+    fn day_orders_expire_at_the_close() {
+        // 10am Eastern: today at 4pm, or 8pm with extended hours
+        assert_eq!(SimulatedBroker::day_order_expiry(dtg(0), false), DateTime::parse_from_rfc3339("2023-11-22T21:00:00Z").unwrap());
+        assert_eq!(SimulatedBroker::day_order_expiry(dtg(0), true), DateTime::parse_from_rfc3339("2023-11-23T01:00:00Z").unwrap());
+        // Friday after the close: Monday
+        let friday_evening = DateTime::parse_from_rfc3339("2023-11-24T22:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(SimulatedBroker::day_order_expiry(friday_evening, false), DateTime::parse_from_rfc3339("2023-11-27T21:00:00Z").unwrap());
+
+        let mut broker = SimulatedBroker::new(dec("10000"), FillModel::default());
+        trade(&mut broker, 0, "100", "1");
+        broker.submit(json_trade(TradeSide::Buy, "1", OrderType::Limit, Some("90"), None)).unwrap();
+        trade(&mut broker, 5 * 3600, "95", "1");
+        assert_eq!(broker.open_orders().unwrap().len(), 1);
+        // 4:01pm
+        trade(&mut broker, 6 * 3600 + 60, "95", "1");
+        assert!(broker.open_orders().unwrap().is_empty());
+        assert!(matches!(broker.take_updates().last(), Some(MesgOrderUpdate::Expired { .. })));
+    }
+}
@@ -0,0 +1,235 @@
+//! spool.rs
+//!
+//! Market data and order events that couldn't be written because Postgres was unreachable, one JSON record
+//! per line in spool_path (see config.rs). The backend's spool_replay component moves the file aside to
+//! <spool_path>.replaying and writes it back in order, a chunk at a time, once the database answers again;
+//! anything that fails meanwhile starts a new spool_path behind it.
+//!
+//! A crash mid-chunk replays that chunk again, so up to REPLAY_CHUNK rows can be duplicated.
+//!
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use crossbeam_channel::Sender;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use crate::alpaca_api_structs::{MinuteBar, Ping};
+use crate::alpaca_order_log::AlpacaOrderLogEvent;
+use crate::config::AppConfig;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::finnhub::FinnhubPing;
+use crate::supervisor::Shutdown;
+use crate::trade_batch::TradeBatch;
+
+static SPOOL: OnceCell<Spool> = OnceCell::new();
+
+const REPLAY_SECS: u64 = 10;
+const REPLAY_CHUNK: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "record", rename_all = "snake_case")]
+pub enum SpoolRecord {
+    Trades(TradeBatch),
+    MinuteBar(MinuteBar),
+    PingAlpaca(Ping),
+    PingFinnhub(FinnhubPing),
+    OrderLogEvent(Box<AlpacaOrderLogEvent>),
+}
+
+pub struct Spool {
+    path: PathBuf,
+    replaying: PathBuf,
+    // appends and the replay's file moves don't interleave
+    lock: Mutex<()>,
+}
+
+impl Spool {
+
+    pub fn new(path: &str) -> Spool {
+        Spool { path: PathBuf::from(path), replaying: PathBuf::from(format!("{}.replaying", path)), lock: Mutex::new(()) }
+    }
+
+    /// the one at AppConfig spool_path
+    pub fn get() -> &'static Spool {
+        SPOOL.get_or_init(|| Spool::new(&AppConfig::get().spool_path))
+    }
+
+    /// Spool record if e means the database is unreachable; otherwise it's a bad row that would fail again,
+    /// so it's only logged.
+    pub fn on_error(e: &sqlx::Error, record: SpoolRecord) {
+        if is_unavailable(e) {
+            tracing::warn!("[Spool] database unavailable ({:?}); spooling {}", e, record.describe());
+            Spool::get().append(&record);
+        } else {
+            tracing::error!("[Spool] {} not saved: {:?}", record.describe(), e);
+        }
+    }
+
+    /// append and sync; if even that fails the record is lost, loudly
+    pub fn append(&self, record: &SpoolRecord) {
+        let result = serde_json::to_string(record).map_err(|e| e.to_string()).and_then(|line| {
+            let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.path).map_err(|e| e.to_string())?;
+            writeln!(file, "{}", line).and_then(|_| file.sync_data()).map_err(|e| e.to_string())
+        });
+        if let Err(e) = result {
+            tracing::error!("[Spool::append] {} lost: {}", record.describe(), e);
+        }
+    }
+
+    /// Up to max lines from the front of the replaying file, moving the spool there first if nothing is
+    /// being replayed.
+    pub fn next_chunk(&self, max: usize) -> std::io::Result<Vec<String>> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !self.replaying.exists() {
+            if !self.path.exists() {
+                return Ok(vec![]);
+            }
+            std::fs::rename(&self.path, &self.replaying)?;
+        }
+        BufReader::new(File::open(&self.replaying)?).lines().take(max).collect()
+    }
+
+    /// drop the first lines of the replaying file, once they're in the database
+    pub fn consume(&self, lines: usize) -> std::io::Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let rest: Vec<String> = BufReader::new(File::open(&self.replaying)?).lines().skip(lines).collect::<Result<_, _>>()?;
+        if rest.is_empty() {
+            return std::fs::remove_file(&self.replaying);
+        }
+        let tmp = PathBuf::from(format!("{}.tmp", self.replaying.display()));
+        let mut file = File::create(&tmp)?;
+        for line in rest {
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_data()?;
+        std::fs::rename(&tmp, &self.replaying)
+    }
+
+    /// the spool_replay component: every REPLAY_SECS, write back whatever has been spooled
+    pub fn replay(tx_db: Sender<DbMsg>, shutdown: Shutdown) {
+        while shutdown.wait_for(Duration::from_secs(REPLAY_SECS)) {
+            Spool::get().replay_once(&tx_db, &shutdown);
+        }
+    }
+
+    fn replay_once(&self, tx_db: &Sender<DbMsg>, shutdown: &Shutdown) {
+        while !shutdown.requested() {
+            let lines = match self.next_chunk(REPLAY_CHUNK) {
+                Ok(lines) if lines.is_empty() => return,
+                Ok(lines) => lines,
+                Err(e) => {
+                    tracing::error!("[Spool::replay] couldn't read the spool: {:?}", &e);
+                    return;
+                },
+            };
+
+            // a line torn by a power cut won't parse; it's logged and skipped
+            let mut records = vec![];
+            let mut line_after = vec![];
+            for (i, line) in lines.iter().enumerate() {
+                match serde_json::from_str::<SpoolRecord>(line) {
+                    Ok(record) => {
+                        records.push(record);
+                        line_after.push(i + 1);
+                    },
+                    Err(e) => tracing::error!("[Spool::replay] dropping an unreadable line: {:?}", &e),
+                }
+            }
+
+            let consumed = if records.is_empty() {
+                lines.len()
+            } else {
+                let count = records.len();
+                match Spool::write(records, tx_db.clone()) {
+                    Ok(written) if written == count => lines.len(),
+                    Ok(0) => return,
+                    Ok(written) => line_after[written - 1],
+                    Err(e) => {
+                        tracing::error!("[Spool::replay] {:?}", &e);
+                        return;
+                    },
+                }
+            };
+            if let Err(e) = self.consume(consumed) {
+                tracing::error!("[Spool::replay] couldn't trim the spool: {:?}", &e);
+                return;
+            }
+            tracing::info!("[Spool::replay] {} spooled lines written back", consumed);
+        }
+    }
+
+    /// how many of records, in order, were written before the database went away again
+    fn write(records: Vec<SpoolRecord>, tx_db: Sender<DbMsg>) -> Result<usize, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SpoolReplay { records, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+impl SpoolRecord {
+    fn describe(&self) -> String {
+        match self {
+            SpoolRecord::Trades(batch) => format!("{} trades", batch.len()),
+            SpoolRecord::MinuteBar(bar) => format!("{} bar", bar.symbol),
+            SpoolRecord::PingAlpaca(_) => "alpaca ping".to_string(),
+            SpoolRecord::PingFinnhub(_) => "finnhub ping".to_string(),
+            SpoolRecord::OrderLogEvent(event) => format!("{} {} order event", event.order.symbol, event.event),
+        }
+    }
+}
+
+/// Pure; whether e is the database being unreachable (connection errors, Postgres class 08, shutting down,
+/// out of connections) rather than something wrong with the row
+pub fn is_unavailable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(e) => matches!(e.code().as_deref(), Some(code) if code.starts_with("08") || ["57P01", "57P02", "57P03", "53300"].contains(&code)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use super::*;
+
+    #[test]
+    fn unavailable_is_the_connection_not_the_row() {
+        assert!(is_unavailable(&sqlx::Error::PoolTimedOut));
+        assert!(is_unavailable(&sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused"))));
+        assert!(!is_unavailable(&sqlx::Error::RowNotFound));
+    }
+
+    #[test]
+    fn replays_in_order_across_the_two_files() {
+        let path = std::env::temp_dir().join(format!("spool_test_{}", std::process::id())).join("spool.jsonl");
+        let spool = Spool::new(path.to_str().unwrap());
+        let ping = |second: i64| SpoolRecord::PingAlpaca(Ping { dtg: DateTime::<Utc>::from_timestamp(second, 0).unwrap() });
+        let seconds = |lines: Vec<String>| lines.iter().map(|line| match serde_json::from_str::<SpoolRecord>(line).unwrap() {
+            SpoolRecord::PingAlpaca(ping) => ping.dtg.timestamp(),
+            _ => panic!("not a ping"),
+        }).collect::<Vec<i64>>();
+
+        spool.append(&ping(1));
+        spool.append(&ping(2));
+        spool.append(&ping(3));
+        assert_eq!(seconds(spool.next_chunk(2).unwrap()), vec![1, 2]);
+        spool.consume(1).unwrap();
+        // spooled during the replay; goes after what's being replayed
+        spool.append(&ping(4));
+        assert_eq!(seconds(spool.next_chunk(10).unwrap()), vec![2, 3]);
+        spool.consume(2).unwrap();
+        assert_eq!(seconds(spool.next_chunk(10).unwrap()), vec![4]);
+        spool.consume(1).unwrap();
+        assert!(spool.next_chunk(10).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
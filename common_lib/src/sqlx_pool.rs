@@ -1,13 +1,29 @@
 //! sqlx_pool.rs
 
 use sqlx::{PgPool, Pool, Postgres};
+use crate::config::AppConfig;
+use crate::supervisor::backoff;
 
-/// start up the database pool
+/// start up the database pool, retrying until postgres answers
 pub async fn create_sqlx_pg_pool() -> Pool<Postgres> {
     tracing::debug!("[create_sqlx_pg_pool]");
-    // DB connect; get some data; currently just one stock for the last three days
-    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL not found in environment");
-    let pool = PgPool::connect(&db_url).await.expect("[main] failed to connect to postgres");
-    tracing::debug!("[create_sqlx_pg_pool] connected");
-    pool
+    let db_url = AppConfig::get().database_url.expose();
+    if db_url.is_empty() {
+        panic!("[create_sqlx_pg_pool] DATABASE_URL isn't set");
+    }
+    let mut failures = 0;
+    loop {
+        match PgPool::connect(db_url).await {
+            Ok(pool) => {
+                tracing::debug!("[create_sqlx_pg_pool] connected");
+                return pool;
+            },
+            Err(e) => {
+                failures += 1;
+                let delay = backoff(failures);
+                tracing::error!("[create_sqlx_pg_pool] can't connect to postgres, retrying in {:?}: {:?}", delay, &e);
+                tokio::time::sleep(delay).await;
+            },
+        }
+    }
 }
@@ -0,0 +1,559 @@
+//! strategy.rs
+//!
+//! The open extension point for trading logic. A Strategy sees market events (trades, bars, quotes,
+//! crossovers, fills, positions) and answers with intents (buy, sell, short, cover, cancel). The StrategyRunner feeds
+//! events to every strategy and hands the intents to an OrderRouter: AlpacaRouter for live trading, which
+//! goes through alpaca_api and therefore RiskManager::approve, or SimRouter (sim_broker.rs) for backtests
+//! and shadow trading.
+//!
+//! SellHigh is the reference strategy: the sell-high-by-cents exit driven by
+//! Settings.trade_sell_high_per_cent_multiplier and trade_sell_high_upper_limit_cents, which also covers
+//! shorts that are down by the same amount. EmaCross is the matching entry, buying on an upward crossover
+//! of the small EMA through the large one; EmaCrossShort sells short on a downward one. AgeExit is the
+//! max_position_age_minute exit for replays.
+//!
+
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Receiver, Sender};
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use crate::alpaca_api;
+use crate::alpaca_api_structs::{AlpacaTradeWs, AlpWsQuote, CrossStatus, MinuteBar};
+use crate::alpaca_order::Order;
+use crate::clock;
+use crate::db::DbMsg;
+use crate::indicator::{CrossEvent, IndicatorEngine};
+use crate::order_manager::OrderManager;
+use crate::sell_position::SellPosition;
+use crate::settings::Settings;
+use crate::symbol::Symbol;
+use crate::trade_struct::TradeSide;
+use crate::supervisor::Shutdown;
+
+/// how often the runner refreshes positions for the strategies
+const POSITION_POLL_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuoteEvent {
+    pub symbol: String,
+    pub dtg: DateTime<Utc>,
+    pub price_bid: BigDecimal,
+    pub price_ask: BigDecimal,
+}
+
+impl From<&AlpWsQuote> for QuoteEvent {
+    fn from(quote: &AlpWsQuote) -> Self {
+        QuoteEvent {
+            symbol: quote.symbol.clone(),
+            dtg: quote.dtg,
+            price_bid: quote.price_bid.clone(),
+            price_ask: quote.price_ask.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MarketEvent {
+    Trade(AlpacaTradeWs),
+    Bar(MinuteBar),
+    Quote(QuoteEvent),
+    /// from the indicator engine, which the runner feeds trades and bars
+    Cross(CrossEvent),
+    Fill { order: Box<Order>, price: BigDecimal, qty: BigDecimal },
+    /// every open position with its unrealized P/L (SellPosition::list_showing_profit)
+    Positions(Vec<SellPosition>),
+    /// every open short (SellPosition::list_to_cover): qty is shares to buy back, P/L is entry minus price
+    ShortPositions(Vec<SellPosition>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Intent {
+    /// qty None sizes the buy from t_symbol.trade_size and cash available, like alpaca_api::buy
+    Buy { symbol: String, qty: Option<BigDecimal>, limit_price: Option<BigDecimal>, reason: String },
+    Sell { symbol: String, qty: BigDecimal, limit_price: Option<BigDecimal>, reason: String },
+    /// open a short sized like alpaca_api::sell_short, which also checks t_order_policy.short_enabled
+    SellShort { symbol: String, reason: String },
+    /// buy back qty shares of a short
+    Cover { symbol: String, qty: BigDecimal, limit_price: Option<BigDecimal>, reason: String },
+    /// cancel every open order for the symbol
+    Cancel { symbol: String, reason: String },
+}
+
+pub struct StrategyContext<'a> {
+    pub settings: &'a Settings,
+    pub now: DateTime<Utc>,
+}
+
+pub trait Strategy: Send {
+    fn name(&self) -> &str;
+    fn on_event(&mut self, event: &MarketEvent, ctx: &StrategyContext) -> Vec<Intent>;
+}
+
+/// where intents become orders
+pub trait OrderRouter {
+    fn execute(&mut self, intent: &Intent, settings: &Settings);
+
+    /// every event, before the strategies see it; a simulated router fills its orders from these and returns
+    /// the fills, which the strategies see next
+    fn on_event(&mut self, _event: &MarketEvent) -> Vec<MarketEvent> {
+        vec![]
+    }
+
+    /// positions the router keeps itself; None means the live ones in the database
+    fn positions(&self, _now: DateTime<Utc>) -> Option<Vec<SellPosition>> {
+        None
+    }
+
+    /// short positions the router keeps itself, as SellPosition::list_to_cover reports them
+    fn short_positions(&self, _now: DateTime<Utc>) -> Option<Vec<SellPosition>> {
+        None
+    }
+}
+
+/// live (paper) trading through the Alpaca REST API
+pub struct AlpacaRouter {
+    tx_db: Sender<DbMsg>,
+    tokio_handle: Handle,
+}
+
+impl AlpacaRouter {
+    pub fn new(tx_db: Sender<DbMsg>, tokio_handle: Handle) -> AlpacaRouter {
+        AlpacaRouter { tx_db, tokio_handle }
+    }
+}
+
+impl OrderRouter for AlpacaRouter {
+    fn execute(&mut self, intent: &Intent, settings: &Settings) {
+        tracing::info!("[AlpacaRouter::execute] {:?}", intent);
+        match intent {
+            Intent::Buy { symbol, qty: None, .. } => match Symbol::load_one(symbol.to_lowercase(), self.tx_db.clone()) {
+                Ok(stock_symbol) => self.tokio_handle.block_on(alpaca_api::buy(&stock_symbol, settings, self.tx_db.clone())),
+                Err(e) => tracing::error!("[AlpacaRouter::execute] unknown symbol {}: {:?}", symbol, &e),
+            },
+            Intent::Buy { symbol, qty: Some(qty), limit_price, .. } => {
+                alpaca_api::post_simple_order(symbol, TradeSide::Buy, qty.clone(), limit_price.clone(), settings, self.tx_db.clone());
+            },
+            Intent::Sell { symbol, qty, limit_price, .. } => {
+                alpaca_api::post_simple_order(symbol, TradeSide::Sell, qty.clone(), limit_price.clone(), settings, self.tx_db.clone());
+            },
+            Intent::SellShort { symbol, .. } => match Symbol::load_one(symbol.to_lowercase(), self.tx_db.clone()) {
+                Ok(stock_symbol) => self.tokio_handle.block_on(alpaca_api::sell_short(&stock_symbol, settings, self.tx_db.clone())),
+                Err(e) => tracing::error!("[AlpacaRouter::execute] unknown symbol {}: {:?}", symbol, &e),
+            },
+            Intent::Cover { symbol, qty, limit_price, .. } => {
+                self.tokio_handle.block_on(alpaca_api::buy_to_cover(symbol, qty.clone(), limit_price.clone(), settings, self.tx_db.clone()));
+            },
+            Intent::Cancel { symbol, reason } => {
+                if let Err(e) = OrderManager::cancel_by_symbol(symbol, reason, settings, self.tx_db.clone()) {
+                    tracing::error!("[AlpacaRouter::execute] cancel {}: {:?}", symbol, &e);
+                }
+            },
+        }
+    }
+}
+
+/// Feeds events to strategies and their intents to a router. The indicator engine lives here so every
+/// strategy sees the same crossovers.
+pub struct StrategyRunner<R: OrderRouter> {
+    strategies: Vec<Box<dyn Strategy>>,
+    indicators: IndicatorEngine,
+    router: R,
+}
+
+impl<R: OrderRouter> StrategyRunner<R> {
+
+    pub fn new(strategies: Vec<Box<dyn Strategy>>, settings: &Settings, router: R) -> StrategyRunner<R> {
+        StrategyRunner {
+            strategies,
+            indicators: IndicatorEngine::new(settings.trade_ema_small_size, settings.trade_ema_large_size),
+            router,
+        }
+    }
+
+    pub fn router(&self) -> &R {
+        &self.router
+    }
+
+    pub fn router_mut(&mut self) -> &mut R {
+        &mut self.router
+    }
+
+    /// one event through every strategy; trades and bars also go through the indicator engine, whose
+    /// crossovers are dispatched right after, followed by any fills the router made from the event
+    pub fn dispatch(&mut self, event: &MarketEvent, settings: &Settings, now: DateTime<Utc>) -> Vec<Intent> {
+        let fills = self.router.on_event(event);
+        let cross = match event {
+            MarketEvent::Trade(trade) => self.indicators.on_trade(trade),
+            MarketEvent::Bar(bar) => self.indicators.on_bar(bar),
+            _ => None,
+        };
+
+        let ctx = StrategyContext { settings, now };
+        let mut intents = vec![];
+        for strategy in self.strategies.iter_mut() {
+            intents.extend(strategy.on_event(event, &ctx));
+            if let Some(cross) = &cross {
+                intents.extend(strategy.on_event(&MarketEvent::Cross(cross.clone()), &ctx));
+            }
+            for fill in fills.iter() {
+                intents.extend(strategy.on_event(fill, &ctx));
+            }
+        }
+        for intent in intents.iter() {
+            self.router.execute(intent, settings);
+        }
+        intents
+    }
+
+    /// the averages restart when their lengths change
+    fn update_settings(&mut self, latest: Settings, settings: &Settings) -> Settings {
+        if (latest.trade_ema_small_size, latest.trade_ema_large_size) != (settings.trade_ema_small_size, settings.trade_ema_large_size) {
+            self.indicators = IndicatorEngine::new(latest.trade_ema_small_size, latest.trade_ema_large_size);
+        }
+        latest
+    }
+
+    /// Live loop: events from the websockets on rx_events, positions polled every few seconds, settings from
+    /// the SettingsCache watch; returns on shutdown.
+    pub fn run(mut self, rx_events: Receiver<MarketEvent>, mut settings_rx: watch::Receiver<Settings>, tx_db: Sender<DbMsg>, tokio_handle: Handle, shutdown: Shutdown) {
+        let ticker = crossbeam_channel::tick(std::time::Duration::from_secs(POSITION_POLL_SECS));
+        let mut settings = settings_rx.borrow_and_update().clone();
+
+        loop {
+            crossbeam_channel::select! {
+                recv(rx_events) -> event => match event {
+                    Ok(event) => {
+                        if let Ok(true) = settings_rx.has_changed() {
+                            settings = self.update_settings(settings_rx.borrow_and_update().clone(), &settings);
+                        }
+                        self.dispatch(&event, &settings, clock::now());
+                    },
+                    Err(_) => {
+                        tracing::error!("[StrategyRunner::run] event channel closed");
+                        return;
+                    }
+                },
+                recv(ticker) -> _ => {
+                    if let Ok(true) = settings_rx.has_changed() {
+                        settings = self.update_settings(settings_rx.borrow_and_update().clone(), &settings);
+                    }
+                    let positions = match self.router.positions(clock::now()) {
+                        Some(positions) => Ok(positions),
+                        None => tokio_handle.block_on(SellPosition::list_showing_profit(BigDecimal::from(-1_000_000), tx_db.clone())),
+                    };
+                    match positions {
+                        Ok(positions) => { self.dispatch(&MarketEvent::Positions(positions), &settings, clock::now()); },
+                        Err(e) => tracing::error!("[StrategyRunner::run] positions: {:?}", &e),
+                    }
+                    let short_positions = match self.router.short_positions(clock::now()) {
+                        Some(positions) => Ok(positions),
+                        None => tokio_handle.block_on(SellPosition::list_to_cover(BigDecimal::from(-1_000_000), tx_db.clone())),
+                    };
+                    match short_positions {
+                        Ok(positions) => { self.dispatch(&MarketEvent::ShortPositions(positions), &settings, clock::now()); },
+                        Err(e) => tracing::error!("[StrategyRunner::run] short positions: {:?}", &e),
+                    }
+                },
+                recv(shutdown.receiver()) -> _ => return,
+            }
+        }
+    }
+}
+
+/// Reference strategy: sell a position once it's up by enough per share, or cover a short once it's down
+/// by enough.
+///
+/// Enough is trade_sell_high_per_cent_multiplier percent of the entry price, capped at
+/// trade_sell_high_upper_limit_cents; the sale (or cover) is a limit at the current price. Entries are left
+/// to other strategies.
+#[derive(Debug, Default)]
+pub struct SellHigh {}
+
+impl SellHigh {
+
+    /// minimum profit per share to sell at
+    pub fn target_per_share(avg_entry_price: &BigDecimal, settings: &Settings) -> BigDecimal {
+        let per_cent = avg_entry_price * &settings.trade_sell_high_per_cent_multiplier / BigDecimal::from(100);
+        let cap = &settings.trade_sell_high_upper_limit_cents / BigDecimal::from(100);
+        std::cmp::min(per_cent, cap).round(2)
+    }
+}
+
+impl Strategy for SellHigh {
+
+    fn name(&self) -> &str {
+        "sell_high"
+    }
+
+    fn on_event(&mut self, event: &MarketEvent, ctx: &StrategyContext) -> Vec<Intent> {
+        let (positions, short) = match event {
+            MarketEvent::Positions(positions) => (positions, false),
+            MarketEvent::ShortPositions(positions) => (positions, true),
+            _ => return vec![],
+        };
+        positions.iter()
+            .filter(|p| p.qty_available > BigDecimal::from(0) && p.unrealized_pl_per_share > BigDecimal::from(0))
+            .filter(|p| p.unrealized_pl_per_share >= SellHigh::target_per_share(&p.avg_entry_price, ctx.settings))
+            .map(|p| if short {
+                Intent::Cover {
+                    symbol: p.symbol.to_uppercase(),
+                    qty: p.qty_available.clone(),
+                    limit_price: Some((&p.avg_entry_price - &p.unrealized_pl_per_share).round(2)),
+                    reason: format!("{} short up {} per share", self.name(), &p.unrealized_pl_per_share),
+                }
+            } else {
+                Intent::Sell {
+                    symbol: p.symbol.to_uppercase(),
+                    qty: p.qty_available.clone(),
+                    limit_price: Some((&p.avg_entry_price + &p.unrealized_pl_per_share).round(2)),
+                    reason: format!("{} up {} per share", self.name(), &p.unrealized_pl_per_share),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Entry strategy: buy on an upward EMA crossover, sized like alpaca_api::buy. Exits are left to SellHigh
+/// and the time-based exits.
+#[derive(Debug, Default)]
+pub struct EmaCross {}
+
+impl Strategy for EmaCross {
+
+    fn name(&self) -> &str {
+        "ema_cross"
+    }
+
+    fn on_event(&mut self, event: &MarketEvent, ctx: &StrategyContext) -> Vec<Intent> {
+        match event {
+            MarketEvent::Cross(cross) if cross.status == CrossStatus::Up && ctx.settings.trade_enable_buy => vec![Intent::Buy {
+                symbol: cross.symbol.to_uppercase(),
+                qty: None,
+                limit_price: None,
+                reason: format!("{} up at {}", self.name(), &cross.price),
+            }],
+            _ => vec![],
+        }
+    }
+}
+
+/// Entry strategy: sell short on a downward EMA crossover. Live, alpaca_api::sell_short only goes ahead when
+/// t_order_policy.short_enabled and the asset can be borrowed; SellHigh covers.
+#[derive(Debug, Default)]
+pub struct EmaCrossShort {}
+
+impl Strategy for EmaCrossShort {
+
+    fn name(&self) -> &str {
+        "ema_cross_short"
+    }
+
+    fn on_event(&mut self, event: &MarketEvent, ctx: &StrategyContext) -> Vec<Intent> {
+        match event {
+            MarketEvent::Cross(cross) if cross.status == CrossStatus::Down && ctx.settings.trade_enable_buy => vec![Intent::SellShort {
+                symbol: cross.symbol.to_uppercase(),
+                reason: format!("{} down at {}", self.name(), &cross.price),
+            }],
+            _ => vec![],
+        }
+    }
+}
+
+/// Time exit for replays: market-sell positions held longer than Settings.max_position_age_minute, canceling
+/// their sell-high orders first. Live trading does this in PositionExit::age_exits instead.
+#[derive(Debug, Default)]
+pub struct AgeExit {}
+
+impl Strategy for AgeExit {
+
+    fn name(&self) -> &str {
+        "age_exit"
+    }
+
+    fn on_event(&mut self, event: &MarketEvent, ctx: &StrategyContext) -> Vec<Intent> {
+        let positions = match event {
+            MarketEvent::Positions(positions) if ctx.settings.max_position_age_minute > BigDecimal::from(0) => positions,
+            _ => return vec![],
+        };
+        positions.iter()
+            .filter(|p| p.qty > BigDecimal::from(0) && p.age_minute >= ctx.settings.max_position_age_minute)
+            .flat_map(|p| {
+                let reason = format!("{} held {} minutes", self.name(), &p.age_minute);
+                vec![
+                    Intent::Cancel { symbol: p.symbol.to_uppercase(), reason: reason.clone() },
+                    Intent::Sell { symbol: p.symbol.to_uppercase(), qty: p.qty.clone(), limit_price: None, reason },
+                ]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::collections::HashMap;
+    use super::*;
+    use crate::secret::Secret;
+    use crate::sim_broker::{FillModel, SimRouter, SimulatedBroker};
+
+    fn dec(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            dtg: Utc::now(),
+            alpaca_paper_id: Secret::default(),
+            alpaca_paper_secret: Secret::default(),
+            alpaca_live_id: Secret::default(),
+            alpaca_live_secret: Secret::default(),
+            trade_size: dec("1000"),
+            trade_enable_buy: true,
+            trade_ema_small_size: 4,
+            trade_ema_large_size: 8,
+            trade_sell_high_per_cent_multiplier: dec("0.5"),
+            trade_sell_high_upper_limit_cents: dec("10"),
+            finnhub_key: Secret::default(),
+            account_start_value: dec("100000"),
+            max_position_age_minute: dec("60"),
+            upgrade_min_profit: dec("0"),
+            upgrade_sell_elapsed_minutes_min: dec("0"),
+            upgrade_posn_max_elapsed_minutes: dec("0"),
+            upgrade_posn_loss_allowed_dollars: dec("0"),
+            acct_max_position_market_value: dec("5000"),
+            acct_min_cash_dollars: dec("1000"),
+        }
+    }
+
+    fn position(entry: &str, pl_per_share: &str) -> SellPosition {
+        SellPosition {
+            symbol: "aapl".to_string(),
+            avg_entry_price: dec(entry),
+            qty: dec("10"),
+            qty_available: dec("10"),
+            unrealized_pl_per_share: dec(pl_per_share),
+            cost_basis: dec(entry) * dec("10"),
+            unrealized_pl_total: dec(pl_per_share) * dec("10"),
+            trade_size: dec("10"),
+            age_minute: dec("5"),
+        }
+    }
+
+    #[test]
+    fn quotes_from_the_feed_become_quote_events() {
+        let json = r#"[{"T":"q","S":"AMD","bx":"U","bp":87.66,"bs":1,"ax":"Q","ap":87.68,"as":4,"t":"2021-02-22T15:51:45.335689322Z","c":["R"],"z":"C"}]"#;
+        let messages: Vec<crate::alpaca_api_structs::DataMessage> = serde_json::from_str(json).unwrap();
+        match &messages[0] {
+            crate::alpaca_api_structs::DataMessage::Quote(quote) => {
+                let event = QuoteEvent::from(quote);
+                assert_eq!(event.symbol, "AMD");
+                assert_eq!(event.price_bid.round(2), dec("87.66"));
+                assert_eq!(event.price_ask.round(2), dec("87.68"));
+            },
+            other => panic!("not a quote: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sell_high_target_is_capped() {
+        // 0.5% of $10 is 5 cents; 0.5% of $100 would be 50 cents, capped at 10
+        assert_eq!(SellHigh::target_per_share(&dec("10"), &settings()), dec("0.05"));
+        assert_eq!(SellHigh::target_per_share(&dec("100"), &settings()), dec("0.10"));
+    }
+
+    #[test]
+    fn sell_high_sells_only_past_target() {
+        let settings = settings();
+        let ctx = StrategyContext { settings: &settings, now: Utc::now() };
+        let mut strategy = SellHigh::default();
+
+        let event = MarketEvent::Positions(vec![position("100", "0.09"), position("100", "0.12")]);
+        let intents = strategy.on_event(&event, &ctx);
+        assert_eq!(intents.len(), 1);
+        match &intents[0] {
+            Intent::Sell { symbol, qty, limit_price, .. } => {
+                assert_eq!(symbol, "AAPL");
+                assert_eq!(qty, &dec("10"));
+                assert_eq!(limit_price, &Some(dec("100.12")));
+            },
+            other => panic!("unexpected intent {:?}", other),
+        }
+    }
+
+    #[test]
+    fn short_round_trip_through_the_sim_router() {
+        let settings = settings();
+        let start = Utc::now();
+        let trade = |seconds: i64, price: &str| MarketEvent::Trade(AlpacaTradeWs {
+            symbol: "AAPL".to_string(),
+            id_trade: seconds as usize,
+            exchange: "V".to_string(),
+            price: dec(price),
+            size: dec("100"),
+            dtg: start + chrono::Duration::seconds(seconds),
+            id_tape: "C".to_string(),
+        });
+        let router = SimRouter::new(SimulatedBroker::new(dec("10000"), FillModel::default()), HashMap::from([("AAPL".to_string(), dec("10"))]), "test");
+        let strategies: Vec<Box<dyn Strategy>> = vec![Box::new(EmaCrossShort::default()), Box::new(SellHigh::default())];
+        let mut runner = StrategyRunner::new(strategies, &settings, router);
+
+        runner.dispatch(&trade(0, "100"), &settings, start);
+        let cross = CrossEvent { symbol: "AAPL".to_string(), dtg: start, status: CrossStatus::Down, price: dec("100"), ema_small: dec("99.9"), ema_large: dec("100") };
+        assert!(matches!(runner.dispatch(&MarketEvent::Cross(cross), &settings, start)[..], [Intent::SellShort { .. }]));
+        runner.dispatch(&trade(1, "100"), &settings, start);
+        assert_eq!(runner.router().broker().position_qty("AAPL"), dec("-10"));
+
+        // down a dollar: past the 10 cent target, so SellHigh covers at the current price
+        runner.dispatch(&trade(2, "99"), &settings, start);
+        let short_positions = runner.router().short_positions(start).unwrap();
+        assert_eq!(short_positions[0].unrealized_pl_per_share, dec("1"));
+        let intents = runner.dispatch(&MarketEvent::ShortPositions(short_positions), &settings, start);
+        assert_eq!(intents, vec![Intent::Cover { symbol: "AAPL".to_string(), qty: dec("10"), limit_price: Some(dec("99.00")), reason: "sell_high short up 1 per share".to_string() }]);
+        runner.dispatch(&trade(3, "99"), &settings, start);
+
+        assert_eq!(runner.router().broker().position_qty("AAPL"), dec("0"));
+        assert_eq!(runner.router().broker().closed_trades()[0].pnl, dec("10"));
+    }
+
+    /// remembers the fills it's shown
+    struct FillRecorder(std::sync::Arc<std::sync::Mutex<Vec<BigDecimal>>>);
+
+    impl Strategy for FillRecorder {
+        fn name(&self) -> &str {
+            "fill_recorder"
+        }
+
+        fn on_event(&mut self, event: &MarketEvent, _ctx: &StrategyContext) -> Vec<Intent> {
+            if let MarketEvent::Fill { qty, .. } = event {
+                self.0.lock().unwrap().push(qty.clone());
+            }
+            vec![]
+        }
+    }
+
+    #[test]
+    fn simulated_fills_reach_the_strategies() {
+        let settings = settings();
+        let start = Utc::now();
+        let trade = |seconds: i64| MarketEvent::Trade(AlpacaTradeWs {
+            symbol: "AAPL".to_string(),
+            id_trade: seconds as usize,
+            exchange: "V".to_string(),
+            price: dec("100"),
+            size: dec("100"),
+            dtg: start + chrono::Duration::seconds(seconds),
+            id_tape: "C".to_string(),
+        });
+        let fills = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let router = SimRouter::new(SimulatedBroker::new(dec("10000"), FillModel::default()), HashMap::from([("AAPL".to_string(), dec("10"))]), "test");
+        let mut runner = StrategyRunner::new(vec![Box::new(FillRecorder(fills.clone()))], &settings, router);
+
+        runner.dispatch(&trade(0), &settings, start);
+        runner.router_mut().execute(&Intent::Buy { symbol: "AAPL".to_string(), qty: None, limit_price: None, reason: "test".to_string() }, &settings);
+        assert!(fills.lock().unwrap().is_empty());
+        runner.dispatch(&trade(1), &settings, start);
+        assert_eq!(*fills.lock().unwrap(), vec![dec("10")]);
+    }
+}
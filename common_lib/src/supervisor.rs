@@ -0,0 +1,265 @@
+//! supervisor.rs
+//!
+//! Owns the backend's component threads: each runs under catch_unwind and is restarted with backoff when it
+//! panics or returns. Shutdown is the stop signal every component loop watches; SIGTERM/SIGINT request it.
+//!
+
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use tokio::runtime::Handle;
+use crate::clock;
+
+const BACKOFF_INITIAL_SECS: u64 = 1;
+const BACKOFF_MAX_SECS: u64 = 60;
+// a run this long counts as healthy and the backoff starts over
+const STABLE_SECS: u64 = 120;
+
+/// Nothing is ever sent on the channel; request() drops the only sender, which wakes every clone's receiver.
+#[derive(Clone)]
+pub struct Shutdown {
+    trigger: Arc<Mutex<Option<Sender<()>>>>,
+    rx: Receiver<()>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown::new()
+    }
+}
+
+impl Shutdown {
+
+    pub fn new() -> Shutdown {
+        let (tx, rx) = crossbeam_channel::bounded(0);
+        Shutdown { trigger: Arc::new(Mutex::new(Some(tx))), rx }
+    }
+
+    pub fn request(&self) {
+        if let Ok(mut trigger) = self.trigger.lock() {
+            trigger.take();
+        }
+    }
+
+    pub fn requested(&self) -> bool {
+        matches!(self.rx.try_recv(), Err(TryRecvError::Disconnected))
+    }
+
+    /// sleep for up to duration; false if shutdown was requested meanwhile
+    pub fn wait_for(&self, duration: Duration) -> bool {
+        matches!(self.rx.recv_timeout(duration), Err(RecvTimeoutError::Timeout))
+    }
+
+    /// wait for the next tick; false if shutdown was requested first
+    pub fn tick(&self, ticker: &Receiver<Instant>) -> bool {
+        crossbeam_channel::select! {
+            recv(ticker) -> _ => true,
+            recv(self.rx) -> _ => false,
+        }
+    }
+
+    /// disconnected once shutdown is requested, for a select! of its own
+    pub fn receiver(&self) -> &Receiver<()> {
+        &self.rx
+    }
+
+    /// request shutdown on SIGINT, or SIGTERM (docker stop)
+    pub fn on_signal(&self, tokio_handle: &Handle) {
+        let shutdown = self.clone();
+        tokio_handle.spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                tracing::info!("[Shutdown] SIGINT");
+                shutdown.request();
+            }
+        });
+
+        #[cfg(unix)]
+        {
+            let shutdown = self.clone();
+            tokio_handle.spawn(async move {
+                use tokio::signal::unix::{signal, SignalKind};
+                match signal(SignalKind::terminate()) {
+                    Ok(mut sigterm) => {
+                        sigterm.recv().await;
+                        tracing::info!("[Shutdown] SIGTERM");
+                        shutdown.request();
+                    },
+                    Err(e) => tracing::error!("[Shutdown] couldn't listen for SIGTERM: {:?}", &e),
+                }
+            });
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComponentState {
+    Running,
+    /// waiting out the backoff after a panic or an unexpected return
+    Restarting,
+    Stopped,
+}
+
+#[derive(Debug, Clone)]
+pub struct ComponentHealth {
+    pub name: String,
+    pub state: ComponentState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    pub since: DateTime<Utc>,
+}
+
+pub struct Supervisor {
+    shutdown: Shutdown,
+    health: Arc<Mutex<Vec<ComponentHealth>>>,
+    threads: Vec<(String, JoinHandle<()>)>,
+}
+
+impl Supervisor {
+
+    pub fn new(shutdown: Shutdown) -> Supervisor {
+        Supervisor { shutdown, health: Arc::new(Mutex::new(vec![])), threads: vec![] }
+    }
+
+    /// Run component on its own thread until shutdown. It's called again after a panic or a return, so it
+    /// builds whatever state it needs each time and should return promptly once its Shutdown is requested.
+    pub fn spawn<F>(&mut self, name: &str, component: F) where F: Fn(Shutdown) + Send + 'static {
+        let index = {
+            let mut health = self.health.lock().unwrap();
+            health.push(ComponentHealth { name: name.to_string(), state: ComponentState::Running, restarts: 0, last_error: None, since: clock::now() });
+            health.len() - 1
+        };
+        let shutdown = self.shutdown.clone();
+        let health = self.health.clone();
+        let thread_name = name.to_string();
+
+        let spawned = std::thread::Builder::new().name(name.to_string()).spawn(move || {
+            let set = |state: ComponentState, error: Option<String>| {
+                if let Ok(mut health) = health.lock() {
+                    let component = &mut health[index];
+                    if state == ComponentState::Restarting {
+                        component.restarts += 1;
+                        component.last_error = error;
+                    }
+                    component.state = state;
+                    component.since = clock::now();
+                }
+            };
+
+            let mut failures = 0;
+            loop {
+                let started = Instant::now();
+                let result = catch_unwind(AssertUnwindSafe(|| component(shutdown.clone())));
+                if shutdown.requested() {
+                    break;
+                }
+                let error = match result {
+                    Ok(()) => "returned".to_string(),
+                    Err(panic) => format!("panicked: {}", panic_message(&panic)),
+                };
+                failures = if started.elapsed() >= Duration::from_secs(STABLE_SECS) { 0 } else { failures + 1 };
+                let delay = backoff(failures);
+                tracing::error!("[Supervisor] {} {}; restarting in {:?}", &thread_name, &error, delay);
+                set(ComponentState::Restarting, Some(error));
+                if !shutdown.wait_for(delay) {
+                    break;
+                }
+                set(ComponentState::Running, None);
+            }
+            set(ComponentState::Stopped, None);
+            tracing::info!("[Supervisor] {} stopped", &thread_name);
+        });
+
+        match spawned {
+            Ok(handle) => self.threads.push((name.to_string(), handle)),
+            Err(e) => tracing::error!("[Supervisor] couldn't start {}: {:?}", name, &e),
+        }
+    }
+
+    pub fn health(&self) -> Vec<ComponentHealth> {
+        self.health.lock().map(|health| health.clone()).unwrap_or_default()
+    }
+
+    /// one line per component; anything not running is a warning
+    pub fn log_health(&self) {
+        for component in self.health() {
+            if component.state == ComponentState::Running {
+                tracing::info!("[Supervisor] {}: running since {}, {} restarts", &component.name, component.since, component.restarts);
+            } else {
+                tracing::warn!("[Supervisor] {}: {:?} since {}, {} restarts, last error: {:?}", &component.name, &component.state, component.since, component.restarts, &component.last_error);
+            }
+        }
+    }
+
+    /// Wait up to grace for the components to stop after shutdown is requested; any still going are logged
+    /// and left behind.
+    pub fn join(self, grace: Duration) {
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline && self.threads.iter().any(|(_, handle)| !handle.is_finished()) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        for (name, handle) in self.threads {
+            if handle.is_finished() {
+                let _ = handle.join();
+            } else {
+                tracing::warn!("[Supervisor] {} didn't stop within {:?}", &name, grace);
+            }
+        }
+    }
+}
+
+/// Pure; the wait before restart number failures, doubling up to a minute
+pub fn backoff(failures: u32) -> Duration {
+    let secs = BACKOFF_INITIAL_SECS.saturating_mul(1u64 << failures.saturating_sub(1).min(16));
+    Duration::from_secs(secs.min(BACKOFF_MAX_SECS))
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_to_a_minute() {
+        let delays: Vec<u64> = [1, 2, 3, 4, 6, 7, 8, 40].iter().map(|failures| backoff(*failures).as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 32, 60, 60, 60]);
+    }
+
+    #[test]
+    fn restarts_after_a_panic_and_stops_on_shutdown() {
+        let shutdown = Shutdown::new();
+        let mut supervisor = Supervisor::new(shutdown.clone());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_2 = runs.clone();
+        supervisor.spawn("flaky", move |shutdown| {
+            if runs_2.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run");
+            }
+            while shutdown.wait_for(Duration::from_millis(10)) {}
+        });
+
+        std::thread::sleep(Duration::from_millis(1500));
+        let health = supervisor.health();
+        assert_eq!((health[0].state.clone(), health[0].restarts), (ComponentState::Running, 1));
+        assert_eq!(health[0].last_error.as_deref(), Some("panicked: first run"));
+
+        shutdown.request();
+        let health = supervisor.health.clone();
+        supervisor.join(Duration::from_secs(2));
+        assert_eq!(health.lock().unwrap()[0].state, ComponentState::Stopped);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
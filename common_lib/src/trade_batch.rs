@@ -0,0 +1,241 @@
+//! trade_batch.rs
+//!
+//! The DbActor collects websocket trades here instead of writing each one as it arrives: a batch goes out
+//! when it reaches TRADE_BATCH_SIZE or every TRADE_FLUSH_MILLIS, as one multi-row insert per history table
+//! plus one upsert per latest table (see trade_batch_write in db.rs). A batch the database turns down is
+//! retried a row at a time so one bad row doesn't lose the rest (trade_batch_save). TradeMetrics logs the
+//! throughput.
+//!
+//! `trader bench-trades` times the old row-at-a-time writes against batches on temporary copies of the tables.
+//!
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPoolOptions;
+use crate::alpaca_api_structs::AlpacaTradeWs;
+use crate::clock;
+use crate::config::AppConfig;
+use crate::db::{insert_alpaca_trade, insert_alpaca_trade_latest, insert_finnhub_trade, insert_finnhub_trade_latest, trade_batch_write, DbMsg};
+use crate::error::TradeWebError;
+use crate::finnhub::FinnhubTrade;
+
+pub const TRADE_BATCH_SIZE: usize = 500;
+pub const TRADE_FLUSH_MILLIS: u64 = 500;
+pub const TRADE_METRICS_SECS: u64 = 60;
+
+/// trade_alp has size where trade_fh has volume; qty is either
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeRow {
+    pub dtg: DateTime<Utc>,
+    pub symbol: String,
+    pub price: BigDecimal,
+    pub qty: BigDecimal,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TradeBatch {
+    pub alpaca: Vec<TradeRow>,
+    pub finnhub: Vec<TradeRow>,
+}
+
+impl TradeBatch {
+
+    /// keep a trade message; anything else comes back for the caller to process
+    pub fn add(&mut self, msg: DbMsg) -> Option<DbMsg> {
+        match msg {
+            DbMsg::TradeAlpaca(t) => self.alpaca.push(TradeRow { dtg: t.dtg, symbol: t.symbol, price: t.price, qty: t.size }),
+            DbMsg::TradeFinnhub(t) => self.finnhub.push(TradeRow { dtg: t.dtg, symbol: t.symbol, price: t.price, qty: t.volume }),
+            msg => return Some(msg),
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.alpaca.len() + self.finnhub.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn take(&mut self) -> TradeBatch {
+        std::mem::take(self)
+    }
+
+    /// a batch per row, Alpaca's first, for retrying a batch the database turned down
+    pub fn into_rows(self) -> Vec<TradeBatch> {
+        let alpaca = self.alpaca.into_iter().map(|row| TradeBatch { alpaca: vec![row], finnhub: vec![] });
+        let finnhub = self.finnhub.into_iter().map(|row| TradeBatch { alpaca: vec![], finnhub: vec![row] });
+        alpaca.chain(finnhub).collect()
+    }
+
+    pub fn append(&mut self, mut other: TradeBatch) {
+        self.alpaca.append(&mut other.alpaca);
+        self.finnhub.append(&mut other.finnhub);
+    }
+}
+
+/// Pure; the newest trade for each symbol, by symbol. A later arrival wins a tie on dtg. One row per symbol
+/// because an upsert can't touch the same row twice, and sorted so concurrent upserts lock rows in the same order.
+pub fn latest_per_symbol(rows: &[TradeRow]) -> Vec<&TradeRow> {
+    let mut latest: BTreeMap<&str, &TradeRow> = BTreeMap::new();
+    for row in rows {
+        match latest.get(row.symbol.as_str()) {
+            Some(newest) if newest.dtg > row.dtg => {},
+            _ => { latest.insert(row.symbol.as_str(), row); },
+        }
+    }
+    latest.into_values().collect()
+}
+
+/// counters since the last report
+#[derive(Debug, Default)]
+pub struct TradeMetrics {
+    trades: AtomicU64,
+    batches: AtomicU64,
+    failed: AtomicU64,
+    write_micros: AtomicU64,
+}
+
+impl TradeMetrics {
+
+    /// one batch of trades, failed of which weren't saved
+    pub fn record(&self, trades: usize, elapsed: Duration, failed: usize) {
+        self.trades.fetch_add(trades as u64, Ordering::Relaxed);
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.write_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.failed.fetch_add(failed as u64, Ordering::Relaxed);
+    }
+
+    /// log and reset; quiet when there were no trades and nothing is queued, as in the frontend
+    pub fn report(&self, window: Duration, backlog: usize) {
+        let trades = self.trades.swap(0, Ordering::Relaxed);
+        let batches = self.batches.swap(0, Ordering::Relaxed);
+        let failed = self.failed.swap(0, Ordering::Relaxed);
+        let write_micros = self.write_micros.swap(0, Ordering::Relaxed);
+        if trades == 0 && backlog == 0 {
+            return;
+        }
+        tracing::info!(
+            "[TradeMetrics] {} trades in {} batches over {:?} ({:.1}/s), {:.1}ms per batch, {} failed, {} messages queued",
+            trades, batches, window, trades as f64 / window.as_secs_f64().max(0.001),
+            write_micros as f64 / 1000.0 / batches.max(1) as f64, failed, backlog
+        );
+    }
+}
+
+#[derive(Debug)]
+pub struct TradeBench {
+    pub trades: usize,
+    pub row_at_a_time: Duration,
+    pub batched: Duration,
+}
+
+impl TradeBench {
+    pub fn per_second(trades: usize, elapsed: Duration) -> f64 {
+        trades as f64 / elapsed.as_secs_f64().max(0.000_001)
+    }
+}
+
+/// Write `trades` Alpaca trades and as many Finnhub ones each way over a single connection. They go into
+/// temporary tables that shadow the real ones for that connection only and are gone when it closes.
+pub async fn bench(trades: usize) -> Result<TradeBench, TradeWebError> {
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(AppConfig::get().database_url.expose())
+        .await
+        .map_err(|_| TradeWebError::SqlxError)?;
+    for table in ["trade_alp", "trade_alp_latest", "trade_fh", "trade_fh_latest"] {
+        sqlx::query(&format!("create temp table {} (like {} including all)", table, table))
+            .execute(&pool)
+            .await
+            .map_err(|e| {
+                tracing::error!("[bench] {}: {:?}", table, &e);
+                TradeWebError::SqlxError
+            })?;
+    }
+
+    let start = clock::now();
+    let alpaca: Vec<AlpacaTradeWs> = (0..trades).map(|i| AlpacaTradeWs {
+        symbol: format!("BENCH{}", i % 20),
+        id_trade: i,
+        exchange: "V".to_string(),
+        price: BigDecimal::from(100 + (i % 50) as i64),
+        size: BigDecimal::from(1 + (i % 10) as i64),
+        dtg: start + chrono::Duration::milliseconds(i as i64),
+        id_tape: "C".to_string(),
+    }).collect();
+    let finnhub: Vec<FinnhubTrade> = alpaca.iter().map(|t| FinnhubTrade {
+        dtg: t.dtg,
+        symbol: t.symbol.clone(),
+        price: t.price.clone(),
+        volume: t.size.clone(),
+        conditions: vec![],
+    }).collect();
+
+    // what the DbActor did per message before batching
+    let started = Instant::now();
+    for (a, f) in alpaca.iter().zip(finnhub.iter()) {
+        let _ = insert_alpaca_trade(a, &pool).await;
+        let _ = insert_alpaca_trade_latest(a, &pool).await;
+        let _ = insert_finnhub_trade(f, &pool).await;
+        let _ = insert_finnhub_trade_latest(f, &pool).await;
+    }
+    let row_at_a_time = started.elapsed();
+
+    let mut batch = TradeBatch::default();
+    for (a, f) in alpaca.into_iter().zip(finnhub) {
+        batch.add(DbMsg::TradeAlpaca(a));
+        batch.add(DbMsg::TradeFinnhub(f));
+    }
+    let started = Instant::now();
+    while !batch.is_empty() {
+        let split = |rows: &mut Vec<TradeRow>| rows.drain(..rows.len().min(TRADE_BATCH_SIZE / 2)).collect();
+        let chunk = TradeBatch { alpaca: split(&mut batch.alpaca), finnhub: split(&mut batch.finnhub) };
+        trade_batch_write(&chunk, &pool).await.map_err(|e| {
+            tracing::error!("[bench] batch: {:?}", &e);
+            TradeWebError::SqlxError
+        })?;
+    }
+    let batched = started.elapsed();
+
+    Ok(TradeBench { trades: trades * 2, row_at_a_time, batched })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    fn row(symbol: &str, second: i64, price: &str) -> TradeRow {
+        TradeRow {
+            dtg: DateTime::<Utc>::from_timestamp(1_700_000_000 + second, 0).unwrap(),
+            symbol: symbol.to_string(),
+            price: BigDecimal::from_str(price).unwrap(),
+            qty: BigDecimal::from(1),
+        }
+    }
+
+    #[test]
+    fn latest_per_symbol_keeps_the_newest_in_symbol_order() {
+        let rows = vec![row("msft", 2, "300"), row("aapl", 5, "150"), row("aapl", 3, "149"), row("msft", 2, "301")];
+        let latest: Vec<(&str, String)> = latest_per_symbol(&rows).into_iter().map(|r| (r.symbol.as_str(), r.price.to_string())).collect();
+        assert_eq!(latest, vec![("aapl", "150".to_string()), ("msft", "301".to_string())]);
+    }
+
+    #[test]
+    fn into_rows_and_append_keep_every_row() {
+        let batch = TradeBatch { alpaca: vec![row("aapl", 1, "150"), row("msft", 2, "300")], finnhub: vec![row("aapl", 3, "151")] };
+        let rows = batch.clone().into_rows();
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|r| r.len() == 1));
+        assert_eq!(rows[2].finnhub, vec![row("aapl", 3, "151")]);
+
+        let joined = rows.into_iter().fold(TradeBatch::default(), |mut joined, r| { joined.append(r); joined });
+        assert_eq!((joined.alpaca, joined.finnhub), (batch.alpaca, batch.finnhub));
+    }
+}
@@ -1,25 +1,212 @@
 //! trade_setting_profile.rs
 //!
-//!
+//! Named trade settings profiles, stored as rows of t_trade_settings_profile. Activating one copies its
+//! values into t_settings (Settings::change_trade_profile) and records who did it in
+//! t_trade_settings_profile_activation. Created and edited on /settings; the optimizer exports them too.
+
+use bigdecimal::{BigDecimal, Zero};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use crate::clock;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+use crate::settings::Settings;
+
+/// profile names show up in URLs and the audit log
+const PROFILE_NAME_MAX_LEN: usize = 64;
+
+/// longest EMA in bars (minutes); the indicator engine keeps this much history per symbol
+const EMA_SIZE_MAX: i32 = 1000;
+
+/// trade_sell_high_per_cent_multiplier is a percent of the entry price
+const SELL_HIGH_PER_CENT_MAX: i64 = 100;
+
+/// one row of t_trade_settings_profile: every non-secret trade setting, under a name
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SettingsProfile {
+    pub id: i64,
+    pub name: String,
+    pub dtg: DateTime<Utc>,
+    /// who made it, e.g. "optimizer"
+    pub source: String,
+    pub notes: String,
+    pub trade_size: BigDecimal,
+    pub trade_enable_buy: bool,
+    pub trade_ema_small_size: i32,
+    pub trade_ema_large_size: i32,
+    pub trade_sell_high_per_cent_multiplier: BigDecimal,
+    pub trade_sell_high_upper_limit_cents: BigDecimal,
+    pub max_position_age_minute: BigDecimal,
+    pub upgrade_min_profit: BigDecimal,
+    pub upgrade_sell_elapsed_minutes_min: BigDecimal,
+    pub upgrade_posn_max_elapsed_minutes: BigDecimal,
+    pub upgrade_posn_loss_allowed_dollars: BigDecimal,
+    pub acct_max_position_market_value: BigDecimal,
+    pub acct_min_cash_dollars: BigDecimal,
+}
+
+impl SettingsProfile {
+
+    /// a new (unsaved) profile holding settings' current values
+    pub fn from_settings(name: &str, source: &str, notes: &str, settings: &Settings) -> SettingsProfile {
+        SettingsProfile {
+            id: 0,
+            name: name.to_string(),
+            dtg: clock::now(),
+            source: source.to_string(),
+            notes: notes.to_string(),
+            trade_size: settings.trade_size.clone(),
+            trade_enable_buy: settings.trade_enable_buy,
+            trade_ema_small_size: settings.trade_ema_small_size,
+            trade_ema_large_size: settings.trade_ema_large_size,
+            trade_sell_high_per_cent_multiplier: settings.trade_sell_high_per_cent_multiplier.clone(),
+            trade_sell_high_upper_limit_cents: settings.trade_sell_high_upper_limit_cents.clone(),
+            max_position_age_minute: settings.max_position_age_minute.clone(),
+            upgrade_min_profit: settings.upgrade_min_profit.clone(),
+            upgrade_sell_elapsed_minutes_min: settings.upgrade_sell_elapsed_minutes_min.clone(),
+            upgrade_posn_max_elapsed_minutes: settings.upgrade_posn_max_elapsed_minutes.clone(),
+            upgrade_posn_loss_allowed_dollars: settings.upgrade_posn_loss_allowed_dollars.clone(),
+            acct_max_position_market_value: settings.acct_max_position_market_value.clone(),
+            acct_min_cash_dollars: settings.acct_min_cash_dollars.clone(),
+        }
+    }
+
+    /// Pure; what's wrong with the values, one message per field. Names go in URLs, so they're lowercase
+    /// letters, digits and underscores.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let zero = BigDecimal::zero();
+        let mut errors = vec![];
+        if self.name.is_empty() || self.name.len() > PROFILE_NAME_MAX_LEN || !self.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            errors.push(format!("name must be 1 to {} lowercase letters, digits or underscores", PROFILE_NAME_MAX_LEN));
+        }
+        if self.trade_size <= zero {
+            errors.push("trade_size must be more than 0".to_string());
+        }
+        if self.trade_ema_small_size < 1 {
+            errors.push("trade_ema_small_size must be at least 1".to_string());
+        }
+        if self.trade_ema_large_size <= self.trade_ema_small_size {
+            errors.push("trade_ema_large_size must be more than trade_ema_small_size".to_string());
+        } else if self.trade_ema_large_size > EMA_SIZE_MAX {
+            errors.push(format!("trade_ema_large_size can't be more than {}", EMA_SIZE_MAX));
+        }
+        if self.trade_sell_high_per_cent_multiplier <= zero || self.trade_sell_high_per_cent_multiplier > BigDecimal::from(SELL_HIGH_PER_CENT_MAX) {
+            errors.push(format!("trade_sell_high_per_cent_multiplier must be more than 0 and at most {}", SELL_HIGH_PER_CENT_MAX));
+        }
+        if self.trade_sell_high_upper_limit_cents <= zero {
+            errors.push("trade_sell_high_upper_limit_cents must be more than 0".to_string());
+        }
+        for (field, value) in [
+            ("max_position_age_minute", &self.max_position_age_minute),
+            ("upgrade_min_profit", &self.upgrade_min_profit),
+            ("upgrade_sell_elapsed_minutes_min", &self.upgrade_sell_elapsed_minutes_min),
+            ("upgrade_posn_max_elapsed_minutes", &self.upgrade_posn_max_elapsed_minutes),
+            ("upgrade_posn_loss_allowed_dollars", &self.upgrade_posn_loss_allowed_dollars),
+            ("acct_max_position_market_value", &self.acct_max_position_market_value),
+            ("acct_min_cash_dollars", &self.acct_min_cash_dollars),
+        ] {
+            if *value < zero {
+                errors.push(format!("{} can't be negative", field));
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
+    /// every profile, by name
+    pub fn list(tx_db: Sender<DbMsg>) -> Result<Vec<SettingsProfile>, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsProfileList { sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    pub fn load(id: i64, tx_db: Sender<DbMsg>) -> Result<SettingsProfile, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsProfileLoad { id, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// insert as a new profile; fails if the name is taken. Returns the new id.
+    pub fn save(&self, tx_db: Sender<DbMsg>) -> Result<i64, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsProfileSave { profile: Box::new(self.clone()), sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    /// overwrite the profile with this id; fails if the new name is taken
+    pub fn update(&self, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsProfileUpdate { profile: Box::new(self.clone()), sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+
+    pub fn delete(id: i64, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsProfileDelete { id, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+/// one row of t_trade_settings_profile_activation
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ProfileActivation {
+    pub dtg: DateTime<Utc>,
+    pub profile_name: String,
+    pub changed_by: String,
+}
+
+impl ProfileActivation {
+
+    /// the latest limit activations, newest first
+    pub fn list(limit: i64, tx_db: Sender<DbMsg>) -> Result<Vec<ProfileActivation>, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::SettingsProfileActivations { limit, sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use super::*;
+
+    fn profile() -> SettingsProfile {
+        SettingsProfile {
+            id: 0,
+            name: "buy_three".to_string(),
+            dtg: Utc::now(),
+            source: "frontend".to_string(),
+            notes: "".to_string(),
+            trade_size: BigDecimal::from(7),
+            trade_enable_buy: true,
+            trade_ema_small_size: 5,
+            trade_ema_large_size: 20,
+            trade_sell_high_per_cent_multiplier: BigDecimal::from_str("0.5").unwrap(),
+            trade_sell_high_upper_limit_cents: BigDecimal::from(50),
+            max_position_age_minute: BigDecimal::from(60),
+            upgrade_min_profit: BigDecimal::from(0),
+            upgrade_sell_elapsed_minutes_min: BigDecimal::from(60),
+            upgrade_posn_max_elapsed_minutes: BigDecimal::from(60),
+            upgrade_posn_loss_allowed_dollars: BigDecimal::from(10),
+            acct_max_position_market_value: BigDecimal::from(1000),
+            acct_min_cash_dollars: BigDecimal::from(100),
+        }
+    }
+
+    #[test]
+    fn validate_reports_each_bad_field() {
+        assert_eq!(profile().validate(), Ok(()));
 
-use serde::Deserialize;
-use strum::Display;
-
-/// strongly typed path; fail strongly if someone types anything other than these in the web path
-// #[derive(Deserialize, Debug)]
-#[derive(Display, Deserialize, Debug)]
-#[strum(serialize_all = "snake_case")]
-pub enum TradeSettingsProfile {
-    // these are needed by actix to deserialize the paths from lowercase to the enum; not sure if strum has this capability
-    #[serde(rename = "buy")]
-    Buy,
-    #[serde(rename = "buy_two")]
-    BuyTwo,
-    #[serde(rename = "close")]
-    Close,
-    #[serde(rename = "close_with_loss")]
-    CloseWithLoss,
-    // TODO: manually typing in a path other than one of these results in:
-    // unknown variant `close_with_loss`, expected one of `buy`, `close`, `close_2`
-    // from the deserialization attempt
+        let mut bad = profile();
+        bad.name = "Buy Three".to_string();
+        bad.trade_size = BigDecimal::from(0);
+        bad.trade_ema_large_size = 5;
+        bad.acct_min_cash_dollars = BigDecimal::from(-1);
+        let errors = bad.validate().unwrap_err();
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].starts_with("name"));
+        assert!(errors[1].starts_with("trade_size"));
+        assert!(errors[2].starts_with("trade_ema_large_size"));
+        assert!(errors[3].starts_with("acct_min_cash_dollars"));
+    }
 }
@@ -8,14 +8,91 @@ use strum::Display;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JsonTrade {
     pub  symbol: String,
+    #[serde(serialize_with = "side_for_orders_api")]
     pub  side: TradeSide,
     pub  time_in_force: TimeInForce,
-    pub  qty: BigDecimal,
+    /// shares, possibly fractional; exactly one of qty and notional is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub  qty: Option<BigDecimal>,
+    /// dollar amount for a notional market order (fractionable assets only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notional: Option<BigDecimal>,
     #[serde(rename = "type")]
     pub  order_type: OrderType,
     pub  limit_price: Option<BigDecimal>,
     pub  extended_hours: Option<bool>,
     pub client_order_id: String,
+    /// stop and stop_limit trigger price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_price: Option<BigDecimal>,
+    /// trailing_stop: trail by a percent or a dollar amount (one or the other)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trail_percent: Option<BigDecimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trail_price: Option<BigDecimal>,
+    /// bracket/oto/oco; None is a simple order
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_class: Option<OrderClass>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub take_profit: Option<TakeProfit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_loss: Option<StopLoss>,
+}
+
+/// The orders API only knows buy and sell; a sell with no long position to close opens a short. SellShort
+/// stays on the JsonTrade so the risk checks can tell an opening short from a closing sell.
+fn side_for_orders_api<S>(side: &TradeSide, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+    match side {
+        TradeSide::SellShort => TradeSide::Sell.serialize(serializer),
+        _ => side.serialize(serializer),
+    }
+}
+
+impl JsonTrade {
+    /// shares requested; zero for a notional order since Alpaca decides the quantity at fill
+    pub fn qty_or_zero(&self) -> BigDecimal {
+        self.qty.clone().unwrap_or_else(|| BigDecimal::from(0))
+    }
+
+    /// dollar value of the order at the given price
+    pub fn notional_at(&self, price: &BigDecimal) -> BigDecimal {
+        match &self.notional {
+            Some(notional) => notional.clone(),
+            None => price * self.qty_or_zero(),
+        }
+    }
+}
+
+/// take-profit leg of a bracket/oto/oco order
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TakeProfit {
+    pub limit_price: BigDecimal,
+}
+
+/// stop-loss leg of a bracket/oto/oco order; with a limit_price it becomes a stop_limit
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StopLoss {
+    pub stop_price: BigDecimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_price: Option<BigDecimal>,
+}
+
+/// https://alpaca.markets/docs/trading/orders/#bracket-orders
+///
+/// bracket: entry plus both exits; oto: entry plus one exit; oco: both exits for a position that's already
+/// open, whichever fills first cancels the other
+#[derive(sqlx::Type, Debug, Serialize, Deserialize, PartialEq, Clone, Display)]
+#[sqlx(type_name = "VARCHAR", rename_all = "lowercase")]
+#[strum(serialize_all = "snake_case")]
+pub enum OrderClass {
+    #[serde(rename = "simple")]
+    Simple,
+    #[serde(rename = "bracket")]
+    Bracket,
+    #[serde(rename = "oto")]
+    Oto,
+    #[serde(rename = "oco")]
+    Oco,
 }
 
 /*
@@ -48,6 +125,15 @@ pub enum TimeInForce {
     // Immediate or Cancel
     #[serde(rename = "ioc")]
     Ioc,
+    // Fill or Kill
+    #[serde(rename = "fok")]
+    Fok,
+    // market/limit on open
+    #[serde(rename = "opg")]
+    Opg,
+    // market/limit on close
+    #[serde(rename = "cls")]
+    Cls,
 }
 
 #[derive(sqlx::Type, Debug, Serialize, Deserialize, PartialEq, Clone, Display)]
@@ -58,6 +144,14 @@ pub enum OrderType {
     Market,
     #[serde(rename = "limit")]
     Limit,
+    #[serde(rename = "stop")]
+    Stop,
+    #[serde(rename = "stop_limit")]
+    #[sqlx(rename = "stop_limit")]
+    StopLimit,
+    #[serde(rename = "trailing_stop")]
+    #[sqlx(rename = "trailing_stop")]
+    TrailingStop,
 }
 
 // enable to_string(); print enum in lowercase
@@ -66,3 +160,18 @@ pub enum OrderType {
 //         write!(f, "{}", format!("{:?}", self).to_lowercase())
 //     }
 // }
+
+/// body for PATCH /v2/orders/{id}; only the fields being changed are sent
+///
+/// https://docs.alpaca.markets/reference/patchorderbyorderid
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct JsonReplace {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qty: Option<BigDecimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_in_force: Option<TimeInForce>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_price: Option<BigDecimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+}
@@ -0,0 +1,29 @@
+//! user.rs
+//!
+//! Frontend logins (the users table) for `trader user add` and `trader user passwd`. Passwords arrive
+//! already hashed, argon2 as login.rs verifies them.
+//!
+
+use crossbeam_channel::Sender;
+use uuid::Uuid;
+use crate::db::DbMsg;
+use crate::error::TradeWebError;
+
+pub struct User;
+
+impl User {
+
+    /// a new login; fails if the username is taken
+    pub fn add(username: &str, password_hash: &str, tx_db: Sender<DbMsg>) -> Result<Uuid, TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::UserAdd { username: username.to_string(), password_hash: password_hash.to_string(), sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?
+    }
+
+    /// NotFound if there's no such user
+    pub fn set_password(username: &str, password_hash: &str, tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx_db.send(DbMsg::UserSetPassword { username: username.to_string(), password_hash: password_hash.to_string(), sender: tx }).map_err(|_| TradeWebError::ChannelError)?;
+        rx.recv().map_err(|_| TradeWebError::ChannelError)?
+    }
+}
@@ -0,0 +1,70 @@
+//! backtest.rs
+//!
+//! web pages for the results `trader backtest` saves
+
+use actix_session::Session;
+use actix_web::{web, HttpResponse};
+use crossbeam_channel::Sender;
+use common_lib::backtest::Backtest;
+use common_lib::common_structs::SESSION_USERNAME;
+use common_lib::db::DbMsg;
+use common_lib::http::redirect_home;
+use handlebars::Handlebars;
+use serde_json::json;
+
+/// GET /backtest
+pub async fn get_backtests(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+
+    // require login
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx_db = tx_db.into_inner().as_ref().clone();
+        let runs = Backtest::list_runs(tx_db).unwrap_or_default();
+        let data = json!({
+            "title": "Backtests",
+            "parent": "base0",
+            "is_logged_in": true,
+            "session_username": &session_username,
+            "data": &runs,
+            "message": if runs.is_empty() { "no backtest runs; see `trader backtest`" } else { "" },
+        });
+
+        let body = hb.render("backtest", &data).unwrap();
+        HttpResponse::Ok()
+            .append_header(("cache-control", "no-store"))
+            .body(body)
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /backtest/{run_id}
+pub async fn get_backtest_run(run_id: web::Path<i64>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+
+    // require login
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx_db = tx_db.into_inner().as_ref().clone();
+        match Backtest::load(run_id.into_inner(), tx_db) {
+            Ok(report) => {
+                let data = json!({
+                    "title": format!("Backtest: {}", &report.run.name),
+                    "parent": "base0",
+                    "is_logged_in": true,
+                    "session_username": &session_username,
+                    "data": &report,
+                    "message": "",
+                });
+
+                let body = hb.render("backtest_run", &data).unwrap();
+                HttpResponse::Ok()
+                    .append_header(("cache-control", "no-store"))
+                    .body(body)
+            }
+            Err(e) => {
+                tracing::debug!("[get_backtest_run] error loading run: {:?}", &e);
+                redirect_home().await
+            }
+        }
+    } else {
+        redirect_home().await
+    }
+}
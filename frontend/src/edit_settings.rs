@@ -3,23 +3,90 @@
 //! web form to edit settings
 
 use actix_session::Session;
+use actix_web::web::Form;
 use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
 use crossbeam_channel::Sender;
 use common_lib::common_structs::SESSION_USERNAME;
+use common_lib::credentials::Credentials;
+use common_lib::error::TradeWebError;
 use common_lib::http::redirect_home;
+use common_lib::profile_schedule::ProfileSchedule;
 use common_lib::settings::Settings;
-use common_lib::trade_setting_profile::TradeSettingsProfile;
+use common_lib::settings_history::{ChangeOrigin, SettingsChange, SOURCE_EDITOR, SOURCE_PROFILE, SOURCE_RESTORE};
+use common_lib::trade_setting_profile::{ProfileActivation, SettingsProfile};
 use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sqlx::PgPool;
 use common_lib::db::DbMsg;
+use crate::settings_profile::FieldParser;
+
+/// profile switches shown under the settings
+const ACTIVATIONS_SHOWN: i64 = 20;
+
+/// changes shown on /settings/history
+const HISTORY_SHOWN: i64 = 100;
+
+/// the settings form: every non-secret field, numbers as text. dtg is the version the form was loaded
+/// from; the save only goes through if it's still current.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SettingsForm {
+    dtg: String,
+    trade_size: String,
+    /// a checkbox: present when checked
+    trade_enable_buy: Option<String>,
+    trade_ema_small_size: String,
+    trade_ema_large_size: String,
+    trade_sell_high_per_cent_multiplier: String,
+    trade_sell_high_upper_limit_cents: String,
+    account_start_value: String,
+    max_position_age_minute: String,
+    upgrade_min_profit: String,
+    upgrade_sell_elapsed_minutes_min: String,
+    upgrade_posn_max_elapsed_minutes: String,
+    upgrade_posn_loss_allowed_dollars: String,
+    acct_max_position_market_value: String,
+    acct_min_cash_dollars: String,
+}
+
+impl SettingsForm {
+
+    /// current with the form's values in place, validated; every problem at once
+    fn to_settings(&self, current: &Settings) -> Result<(DateTime<Utc>, Settings), Vec<String>> {
+        let mut parser = FieldParser::default();
+        let mut settings = current.clone();
+        settings.trade_size = parser.decimal("trade_size", &self.trade_size);
+        settings.trade_enable_buy = self.trade_enable_buy.is_some();
+        settings.trade_ema_small_size = parser.integer("trade_ema_small_size", &self.trade_ema_small_size);
+        settings.trade_ema_large_size = parser.integer("trade_ema_large_size", &self.trade_ema_large_size);
+        settings.trade_sell_high_per_cent_multiplier = parser.decimal("trade_sell_high_per_cent_multiplier", &self.trade_sell_high_per_cent_multiplier);
+        settings.trade_sell_high_upper_limit_cents = parser.decimal("trade_sell_high_upper_limit_cents", &self.trade_sell_high_upper_limit_cents);
+        settings.account_start_value = parser.decimal("account_start_value", &self.account_start_value);
+        settings.max_position_age_minute = parser.decimal("max_position_age_minute", &self.max_position_age_minute);
+        settings.upgrade_min_profit = parser.decimal("upgrade_min_profit", &self.upgrade_min_profit);
+        settings.upgrade_sell_elapsed_minutes_min = parser.decimal("upgrade_sell_elapsed_minutes_min", &self.upgrade_sell_elapsed_minutes_min);
+        settings.upgrade_posn_max_elapsed_minutes = parser.decimal("upgrade_posn_max_elapsed_minutes", &self.upgrade_posn_max_elapsed_minutes);
+        settings.upgrade_posn_loss_allowed_dollars = parser.decimal("upgrade_posn_loss_allowed_dollars", &self.upgrade_posn_loss_allowed_dollars);
+        settings.acct_max_position_market_value = parser.decimal("acct_max_position_market_value", &self.acct_max_position_market_value);
+        settings.acct_min_cash_dollars = parser.decimal("acct_min_cash_dollars", &self.acct_min_cash_dollars);
+        let expected_dtg = DateTime::parse_from_rfc3339(self.dtg.trim()).map(|dtg| dtg.with_timezone(&Utc)).unwrap_or_else(|_| {
+            parser.errors.push("the form is missing its version; reload it".to_string());
+            current.dtg
+        });
+        if !parser.errors.is_empty() {
+            return Err(parser.errors);
+        }
+        settings.validate()?;
+        Ok((expected_dtg, settings))
+    }
+}
 
 /// GET /settings
 pub async fn get_settings(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
     get_settings_with_message(tx_db, hb, session, "").await
 }
 
-async fn get_settings_with_message(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session, message: &str) -> HttpResponse {
+pub async fn get_settings_with_message(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session, message: &str) -> HttpResponse {
 
     // require login
     if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
@@ -30,12 +97,20 @@ async fn get_settings_with_message(tx_db: web::Data<Sender<DbMsg>>, hb: web::Dat
         let setting_result = Settings::load_no_secret(tx_db1);
         match setting_result {
             Ok(settings) => {
+                let profiles = SettingsProfile::list(tx_db.clone()).unwrap_or_default();
+                let activations = ProfileActivation::list(ACTIVATIONS_SHOWN, tx_db.clone()).unwrap_or_default();
+                let schedules = ProfileSchedule::list(tx_db.clone()).unwrap_or_default();
+                let credentials = Credentials::status(tx_db).unwrap_or_default();
                 let data = json!({
                     "title": "Settings",
                     "parent": "base0",
                     "is_logged_in": true,
                     "session_username": &session_username,
                     "data": &settings,
+                    "profiles": &profiles,
+                    "activations": &activations,
+                    "schedules": &schedules,
+                    "credentials": &credentials,
                     "message": message,
                 });
 
@@ -55,48 +130,183 @@ async fn get_settings_with_message(tx_db: web::Data<Sender<DbMsg>>, hb: web::Dat
     }
 }
 
-/// select * from fn_set_trade_settings('buy');
-/// select * from fn_set_trade_settings('close');
-/// select * from fn_set_trade_settings('close2');
+/// activate a profile from t_trade_settings_profile; the switch is logged with the session's username
 ///
 /// GET /settings/button/{name}
-pub async fn get_settings_button(path: web::Path<TradeSettingsProfile>, pool: web::Data<PgPool>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
-    // tracing::debug!("[get_settings_button] path: {}", &path);
+pub async fn get_settings_button(path: web::Path<String>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let profile_selected = path.into_inner();
+        tracing::debug!("[get_settings_button] profile_selected: {}", &profile_selected);
+
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let message = match Settings::change_trade_profile(&profile_selected, &session_username, SOURCE_PROFILE, tx) {
+            Ok(_) => format!("{} trade profile selected", &profile_selected),
+            Err(e) => {
+                tracing::debug!("[get_settings_button] error changing profile: {:?}", &e);
+                format!("couldn't select trade profile {}", &profile_selected)
+            }
+        };
+        get_settings_with_message(tx_db, hb, session, &message).await
+    } else {
+        redirect_home().await
+    }
+}
 
+/// GET /settings/edit
+pub async fn get_settings_edit(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
     if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
-        let profile_selected: TradeSettingsProfile = path.into_inner();
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        match Settings::load_no_secret(tx) {
+            Ok(settings) => render_settings_edit(&hb, &session_username, settings_form_data(&settings), &[], ""),
+            Err(_) => get_settings_with_message(tx_db, hb, session, "couldn't load settings").await,
+        }
+    } else {
+        redirect_home().await
+    }
+}
 
-        tracing::debug!(
-            "[get_settings_button] profile_selected enum: {}",
-            &profile_selected.to_string()
-        );
+/// POST /settings/edit
+pub async fn post_settings_edit(form: Form<SettingsForm>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    tracing::debug!("[post_settings_edit] form: {:?}", &form);
 
-        match Settings::change_trade_profile(&profile_selected, &pool).await {
-            // TODO: a little duplicated code here
-            Ok(settings) => {
-                let message = format!("{} trade profile selected", &profile_selected);
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let Ok(current) = Settings::load_no_secret(tx.clone()) else { return get_settings_with_message(tx_db, hb, session, "couldn't load settings").await; };
+        let form = form.into_inner();
 
+        let (expected_dtg, settings) = match form.to_settings(&current) {
+            Ok(parsed) => parsed,
+            // show the form again as typed
+            Err(errors) => return render_settings_edit(&hb, &session_username, json!(&form), &errors, ""),
+        };
+        match settings.update(expected_dtg, ChangeOrigin::new(&session_username, SOURCE_EDITOR, ""), tx) {
+            Ok(_) => get_settings_with_message(tx_db, hb, session, "settings saved").await,
+            Err(TradeWebError::Conflict) => {
+                let message = "settings changed since this form was loaded; these are the current values, make your change again";
+                render_settings_edit(&hb, &session_username, settings_form_data(&current), &[], message)
+            },
+            Err(e) => {
+                tracing::debug!("[post_settings_edit] error saving settings: {:?}", &e);
+                render_settings_edit(&hb, &session_username, json!(&form), &["couldn't save settings".to_string()], "")
+            },
+        }
+    } else {
+        redirect_home().await
+    }
+}
+
+/// the restore buttons on /settings/history: which side of the change, and the settings version the page
+/// was loaded with
+#[derive(Debug, Deserialize)]
+pub struct RestoreForm {
+    dtg: String,
+    /// "before" or "after"
+    side: String,
+}
+
+/// GET /settings/history
+pub async fn get_settings_history(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    get_settings_history_with_message(tx_db, hb, session, "").await
+}
+
+/// the history page with a message above it
+pub async fn get_settings_history_with_message(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session, message: &str) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let Ok(current) = Settings::load_no_secret(tx.clone()) else { return get_settings_with_message(tx_db, hb, session, "couldn't load settings").await; };
+        match SettingsChange::list(HISTORY_SHOWN, tx) {
+            Ok(changes) => {
+                let changes: Vec<serde_json::Value> = changes.iter()
+                    .map(|c| json!({ "id": c.id, "dtg": c.dtg, "changed_by": &c.changed_by, "source": &c.source, "note": &c.note, "diff": c.diff() }))
+                    .collect();
                 let data = json!({
-                    "title": "Settings",
+                    "title": "Settings History",
                     "parent": "base0",
                     "is_logged_in": true,
                     "session_username": &session_username,
-                    "data": &settings,
                     "message": message,
+                    "current_dtg": current.dtg.to_rfc3339(),
+                    "data": &changes,
                 });
-
-                let body = hb.render("settings_table", &data).unwrap();
+                let body = hb.render("settings_history", &data).unwrap();
                 HttpResponse::Ok()
                     .append_header(("cache-control", "no-store"))
                     .body(body)
             }
             Err(e) => {
-                // TODO: redirect to error message
-                tracing::debug!("[get_settings] error getting settings: {:?}", &e);
-                redirect_home().await
+                tracing::debug!("[get_settings_history] error getting history: {:?}", &e);
+                get_settings_with_message(tx_db, hb, session, "couldn't load the settings history").await
             }
         }
     } else {
         redirect_home().await
     }
 }
+
+/// Put back the settings from before or after a recorded change. Goes through the same validation and
+/// version check as the editor and is itself recorded, so a restore can be undone.
+///
+/// POST /settings/history/{id}/restore
+pub async fn post_settings_restore(path: web::Path<i64>, form: Form<RestoreForm>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    tracing::debug!("[post_settings_restore] form: {:?}", &form);
+
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let id = path.into_inner();
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let before = form.side == "before";
+        let Ok(expected_dtg) = DateTime::parse_from_rfc3339(form.dtg.trim()).map(|dtg| dtg.with_timezone(&Utc)) else {
+            return get_settings_history_with_message(tx_db, hb, session, "the page is missing the settings version; reload it").await;
+        };
+        let (Ok(change), Ok(current)) = (SettingsChange::load(id, tx.clone()), Settings::load_no_secret(tx.clone())) else {
+            return get_settings_history_with_message(tx_db, hb, session, &format!("couldn't load version {}", id)).await;
+        };
+        let restored = match change.version(before, &current) {
+            Ok(restored) => restored,
+            Err(e) => return get_settings_history_with_message(tx_db, hb, session, &e).await,
+        };
+        if let Err(errors) = restored.validate() {
+            // limits may have tightened since the version was saved
+            return get_settings_history_with_message(tx_db, hb, session, &format!("version {} doesn't pass validation now: {}", id, errors.join("; "))).await;
+        }
+
+        let side = if before { "before" } else { "after" };
+        let origin = ChangeOrigin::new(&session_username, SOURCE_RESTORE, &format!("{} change {}", side, id));
+        let message = match restored.update(expected_dtg, origin, tx) {
+            Ok(_) => format!("restored the settings from {} change {}", side, id),
+            Err(TradeWebError::Conflict) => "settings changed since this page was loaded; review the history and restore again".to_string(),
+            Err(e) => {
+                tracing::debug!("[post_settings_restore] error restoring {}: {:?}", id, &e);
+                format!("couldn't restore change {}", id)
+            },
+        };
+        get_settings_history_with_message(tx_db, hb, session, &message).await
+    } else {
+        redirect_home().await
+    }
+}
+
+/// settings as the form shows them; dtg keeps full precision so the version check matches
+fn settings_form_data(settings: &Settings) -> serde_json::Value {
+    let mut data = settings.to_json_no_secret();
+    if let Some(map) = data.as_object_mut() {
+        map.insert("dtg".to_string(), json!(settings.dtg.to_rfc3339()));
+    }
+    data
+}
+
+fn render_settings_edit(hb: &Handlebars<'_>, session_username: &str, data: serde_json::Value, errors: &[String], message: &str) -> HttpResponse {
+    let data = json!({
+        "title": "Edit Settings",
+        "parent": "base0",
+        "is_logged_in": true,
+        "session_username": session_username,
+        "data": data,
+        "errors": errors,
+        "message": message,
+    });
+    let body = hb.render("settings_edit", &data).unwrap();
+    HttpResponse::Ok()
+        .append_header(("cache-control", "no-store"))
+        .body(body)
+}
@@ -0,0 +1,94 @@
+//! kill_switch.rs
+//!
+//! web page to engage or clear the global kill switch
+
+use actix_session::Session;
+use actix_web::{web, HttpResponse};
+use crossbeam_channel::Sender;
+use common_lib::common_structs::SESSION_USERNAME;
+use common_lib::db::DbMsg;
+use common_lib::http::redirect_home;
+use common_lib::kill_switch::KillSwitch;
+use common_lib::settings::Settings;
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Deserialize)]
+pub struct KillSwitchQuery {
+    #[serde(default)]
+    flatten: bool,
+    reason: Option<String>,
+}
+
+/// GET /kill_switch
+pub async fn get_kill_switch(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    get_kill_switch_with_message(tx_db, hb, session, "".to_string()).await
+}
+
+/// GET /kill_switch/on?flatten=true&reason=...
+///
+/// persists the halt, cancels every open order, and closes every position if flatten is set
+pub async fn get_kill_switch_on(query: web::Query<KillSwitchQuery>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let reason = query.reason.clone().unwrap_or_else(|| "frontend".to_string());
+
+        let message = match Settings::load_with_secret(tx.clone()) {
+            Ok(settings) => match KillSwitch::engage(query.flatten, &reason, &session_username, &settings, tx) {
+                Ok(result) => format!("kill switch engaged; orders canceled: {:?}, positions closed: {:?}", result.orders_canceled, result.positions_closed),
+                Err(e) => format!("kill switch failed: {:?}", e),
+            },
+            Err(e) => format!("kill switch failed; couldn't load settings: {:?}", e),
+        };
+        get_kill_switch_with_message(tx_db, hb, session, message).await
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /kill_switch/off
+pub async fn get_kill_switch_off(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let message = match KillSwitch::clear(&session_username, tx) {
+            Ok(_) => "kill switch cleared; trading resumed".to_string(),
+            Err(e) => format!("kill switch clear failed: {:?}", e),
+        };
+        get_kill_switch_with_message(tx_db, hb, session, message).await
+    } else {
+        redirect_home().await
+    }
+}
+
+async fn get_kill_switch_with_message(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session, message: String) -> HttpResponse {
+
+    // require login
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx_db = tx_db.into_inner().as_ref().clone();
+
+        match KillSwitch::status(tx_db) {
+            Ok(state) => {
+                let data = json!({
+                    "title": "Kill Switch",
+                    "parent": "base0",
+                    "is_logged_in": true,
+                    "session_username": &session_username,
+                    "data": &state,
+                    "message": message,
+                });
+
+                let body = hb.render("kill_switch", &data).unwrap();
+                HttpResponse::Ok()
+                    .append_header(("cache-control", "no-store"))
+                    .body(body)
+            }
+            Err(e) => {
+                tracing::debug!("[get_kill_switch] error getting kill switch state: {:?}", &e);
+                redirect_home().await
+            }
+        }
+    } else {
+        redirect_home().await
+    }
+}
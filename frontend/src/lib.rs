@@ -0,0 +1,30 @@
+//! frontend/src/lib.rs
+//!
+//! The web interface; `trader frontend` starts it (trader/src/main.rs).
+//!
+#![forbid(unsafe_code)]
+
+mod account;
+mod activities;
+mod backtest;
+mod dashboard;
+mod edit_settings;
+mod kill_switch;
+mod login;
+mod metrics;
+pub mod order;
+mod positions;
+mod profit;
+mod settings_credentials;
+mod settings_profile;
+mod signup;
+mod symbols;
+mod utils;
+pub mod web_server;
+
+use chrono::NaiveTime;
+use once_cell::sync::Lazy;
+
+// https://alpaca.markets/learn/investing-basics/what-is-extended-hours-trading/
+pub static MARKET_OPEN: Lazy<NaiveTime> = Lazy::new(|| NaiveTime::from_hms_opt(9, 30, 0).unwrap()); // 4am Eastern
+pub static MARKET_CLOSE: Lazy<NaiveTime> = Lazy::new(|| NaiveTime::from_hms_opt(16, 0, 0).unwrap()); // 8pm
@@ -6,14 +6,104 @@ use crossbeam_channel::Sender;
 use common_lib::alpaca_order::Order;
 use common_lib::common_structs::SESSION_USERNAME;
 use common_lib::http::redirect_home;
+use common_lib::order_manager::OrderManager;
 use common_lib::settings::Settings;
 use handlebars::Handlebars;
+use serde::Deserialize;
 use serde_json::json;
 use common_lib::db::DbMsg;
+use common_lib::trade_struct::JsonReplace;
+
+#[derive(Debug, Deserialize)]
+pub struct ReplaceQuery {
+    limit_price: bigdecimal::BigDecimal,
+}
 
 /// GET /order
 pub async fn get_order(
     tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    get_order_with_message(tx_db, hb, session, None).await
+}
+
+/// GET /order/cancel/{order_id}
+pub async fn get_order_cancel(path: web::Path<String>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let order_id = path.into_inner();
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let reason = format!("canceled from frontend by {}", &session_username);
+        let message = match Settings::load_with_secret(tx.clone()) {
+            Ok(settings) => match OrderManager::cancel_by_id(&order_id, &reason, &settings, tx) {
+                Ok(()) => format!("order {} canceled", &order_id),
+                Err(e) => format!("order {} not canceled: {:?}", &order_id, e),
+            },
+            Err(e) => format!("couldn't load settings: {:?}", e),
+        };
+        get_order_with_message(tx_db, hb, session, Some(message)).await
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /order/cancel_symbol/{symbol}
+pub async fn get_order_cancel_symbol(path: web::Path<String>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let symbol = path.into_inner();
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let reason = format!("canceled from frontend by {}", &session_username);
+        let message = match Settings::load_with_secret(tx.clone()) {
+            Ok(settings) => match OrderManager::cancel_by_symbol(&symbol, &reason, &settings, tx) {
+                Ok(count) => format!("{} order(s) canceled for {}", count, &symbol),
+                Err(e) => format!("orders for {} not canceled: {:?}", &symbol, e),
+            },
+            Err(e) => format!("couldn't load settings: {:?}", e),
+        };
+        get_order_with_message(tx_db, hb, session, Some(message)).await
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /order/protect/{symbol}: oco take-profit/stop-loss exit for a position that's already open
+pub async fn get_order_protect(path: web::Path<String>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let symbol = path.into_inner();
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        tracing::info!("[get_order_protect] {} by {}", &symbol, &session_username);
+        let message = match Settings::load_with_secret(tx.clone()) {
+            Ok(settings) => match OrderManager::protect(&symbol, &settings, tx) {
+                Ok(order) => format!("oco exit {} posted for {}", &order.id, &symbol),
+                Err(e) => format!("oco exit for {} not posted: {:?}", &symbol, e),
+            },
+            Err(e) => format!("couldn't load settings: {:?}", e),
+        };
+        get_order_with_message(tx_db, hb, session, Some(message)).await
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /order/replace/{order_id}?limit_price=12.34
+pub async fn get_order_replace(path: web::Path<String>, query: web::Query<ReplaceQuery>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let order_id = path.into_inner();
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let reason = format!("replaced from frontend by {}", &session_username);
+        let json_replace = JsonReplace { limit_price: Some(query.limit_price.clone()), ..Default::default() };
+        let message = match Settings::load_with_secret(tx.clone()) {
+            Ok(settings) => match OrderManager::replace_by_id(&order_id, json_replace, &reason, &settings, tx) {
+                Ok(order) => format!("order {} replaced by {} at {:?}", &order_id, &order.id, &order.limit_price),
+                Err(e) => format!("order {} not replaced: {:?}", &order_id, e),
+            },
+            Err(e) => format!("couldn't load settings: {:?}", e),
+        };
+        get_order_with_message(tx_db, hb, session, Some(message)).await
+    } else {
+        redirect_home().await
+    }
+}
+
+async fn get_order_with_message(
+    tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session, message_override: Option<String>) -> HttpResponse {
     // require login
     tracing::debug!("[get_orders]");
     if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
@@ -45,6 +135,8 @@ pub async fn get_order(
                     }
                 };
 
+                let message = message_override.unwrap_or(message);
+
                 let data = json!({
                     "title": "Settings",
                     "parent": "base0",
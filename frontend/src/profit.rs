@@ -85,10 +85,12 @@ pub async fn get_profit(
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct ProfitSummary {
     pub symbol: String,
     pub active: bool,
+    /// long, short, or flat
+    pub side: String,
     pub age_minute: BigDecimal,
     pub closed_pl: BigDecimal,
     pub unrealized_pl: BigDecimal,
@@ -103,6 +105,9 @@ pub struct ProfitSummary {
 
 /// GET /profit_summary
 /// print a table of stocks P/L
+///
+/// v_posn_activity_summary prices every open position as a long; a short's unrealized P/L comes from
+/// alpaca_position instead, where Alpaca reports it with the right sign.
 pub async fn get_profit_summary(hb: web::Data<Handlebars<'_>>, db_pool: web::Data<PgPool>, session: Session) -> HttpResponse {
     tracing::debug!("[get_profit]");
 
@@ -110,24 +115,24 @@ pub async fn get_profit_summary(hb: web::Data<Handlebars<'_>>, db_pool: web::Dat
     if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
         tracing::debug!("session id: {}", &session_username);
 
-        // exclamation point means we're overriding sqlx requiring Option<> on nullables (assuming we know it'll never be null)
-        let profit_vec = match sqlx::query_as!(
-            ProfitSummary,
+        let profit_vec = match sqlx::query_as::<_, ProfitSummary>(
             r#"
                 select
-                    symbol as "symbol!"
-                    ,active as "active!"
-                    ,age_minute as "age_minute!"
-                    , closed_pl as "closed_pl!"
-                    , unrealized_pl as "unrealized_pl!"
-                    , posn_pl as "posn_pl!"
-                    , unrealized_pl_avg as "unrealized_pl_avg!"
-                    , qty_buy_today as "qty_buy_today!"
-                    , qty_sell_today as "qty_sell_today!"
-                    , count_buy_activity_today as "count_buy_activity_today!"
-                    , count_sell_activity_today as "count_sell_activity_today!"
-                    , dtg_latest_buy as "dtg_position"
-                from v_posn_activity_summary
+                    s.symbol
+                    , s.active
+                    , coalesce(p.side, 'flat') as side
+                    , s.age_minute
+                    , s.closed_pl
+                    , case when p.qty < 0.0 then p.unrealized_pl else s.unrealized_pl end as unrealized_pl
+                    , case when p.qty < 0.0 then s.closed_pl + p.unrealized_pl else s.posn_pl end as posn_pl
+                    , case when p.qty < 0.0 then p.unrealized_pl / abs(p.qty) else s.unrealized_pl_avg end as unrealized_pl_avg
+                    , s.qty_buy_today
+                    , s.qty_sell_today
+                    , s.count_buy_activity_today
+                    , s.count_sell_activity_today
+                    , s.dtg_latest_buy as dtg_position
+                from v_posn_activity_summary s
+                left join alpaca_position p on upper(p.symbol) = upper(s.symbol) and p.qty <> 0.0
             "#,
         ).fetch_all(db_pool.as_ref()).await {
             Ok(vec_of_profit) => vec_of_profit,
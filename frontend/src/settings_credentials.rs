@@ -0,0 +1,96 @@
+//! settings_credentials.rs
+//!
+//! set new Alpaca and Finnhub credentials; the form never shows the current values, only whether each one
+//! is set
+
+use actix_session::Session;
+use actix_web::web::Form;
+use actix_web::{web, HttpResponse};
+use crossbeam_channel::Sender;
+use common_lib::common_structs::SESSION_USERNAME;
+use common_lib::credentials::Credentials;
+use common_lib::db::DbMsg;
+use common_lib::error::{SecretError, TradeWebError};
+use common_lib::http::redirect_home;
+use common_lib::secret::{Secret, CREDENTIALS_KEY_ENV};
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::json;
+use crate::edit_settings::get_settings_with_message;
+
+/// blank fields keep the stored value
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct CredentialsForm {
+    alpaca_paper_id: Secret,
+    alpaca_paper_secret: Secret,
+    alpaca_live_id: Secret,
+    alpaca_live_secret: Secret,
+    finnhub_key: Secret,
+}
+
+impl CredentialsForm {
+    /// the filled-in fields
+    fn values(self) -> Vec<(String, Secret)> {
+        [
+            ("alpaca_paper_id", self.alpaca_paper_id),
+            ("alpaca_paper_secret", self.alpaca_paper_secret),
+            ("alpaca_live_id", self.alpaca_live_id),
+            ("alpaca_live_secret", self.alpaca_live_secret),
+            ("finnhub_key", self.finnhub_key),
+        ]
+            .into_iter()
+            .filter(|(_, secret)| !secret.expose().trim().is_empty())
+            .map(|(field, secret)| (field.to_string(), Secret::new(secret.expose().trim())))
+            .collect()
+    }
+}
+
+/// GET /settings/credentials
+pub async fn get_credentials(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        render_credentials(&tx_db, &hb, &session_username, "")
+    } else {
+        redirect_home().await
+    }
+}
+
+/// POST /settings/credentials
+pub async fn post_credentials(form: Form<CredentialsForm>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let values = form.into_inner().values();
+        if values.is_empty() {
+            return render_credentials(&tx_db, &hb, &session_username, "nothing to save; fill in the credentials to replace");
+        }
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        match Credentials::set(&values, &session_username, tx) {
+            Ok(()) => get_settings_with_message(tx_db, hb, session, "credentials saved; restart the backend to use them").await,
+            Err(TradeWebError::Secret(SecretError::NoKey)) => {
+                render_credentials(&tx_db, &hb, &session_username, &format!("{} isn't set; credentials are only stored encrypted", CREDENTIALS_KEY_ENV))
+            },
+            Err(e) => {
+                tracing::debug!("[post_credentials] error saving credentials: {:?}", &e);
+                render_credentials(&tx_db, &hb, &session_username, "couldn't save the credentials")
+            },
+        }
+    } else {
+        redirect_home().await
+    }
+}
+
+fn render_credentials(tx_db: &web::Data<Sender<DbMsg>>, hb: &Handlebars<'_>, session_username: &str, message: &str) -> HttpResponse {
+    let tx = tx_db.clone().into_inner().as_ref().clone();
+    let credentials = Credentials::status(tx).unwrap_or_default();
+    let data = json!({
+        "title": "Credentials",
+        "parent": "base0",
+        "is_logged_in": true,
+        "session_username": session_username,
+        "data": &credentials,
+        "message": message,
+    });
+    let body = hb.render("settings_credentials", &data).unwrap();
+    HttpResponse::Ok()
+        .append_header(("cache-control", "no-store"))
+        .body(body)
+}
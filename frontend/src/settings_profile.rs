@@ -0,0 +1,290 @@
+//! settings_profile.rs
+//!
+//! create, clone, edit and delete the trade settings profiles listed on /settings, and the schedule that
+//! switches between them
+
+use std::str::FromStr;
+use actix_session::Session;
+use actix_web::web::Form;
+use actix_web::{web, HttpResponse};
+use bigdecimal::BigDecimal;
+use chrono::Utc;
+use crossbeam_channel::Sender;
+use common_lib::common_structs::SESSION_USERNAME;
+use common_lib::db::DbMsg;
+use common_lib::http::redirect_home;
+use common_lib::profile_schedule::ProfileSchedule;
+use common_lib::settings::Settings;
+use common_lib::trade_setting_profile::SettingsProfile;
+use handlebars::Handlebars;
+use serde::Deserialize;
+use serde_json::json;
+use crate::edit_settings::get_settings_with_message;
+
+/// collects parse errors so a form can report every bad field at once
+#[derive(Debug, Default)]
+pub(crate) struct FieldParser {
+    pub errors: Vec<String>,
+}
+
+impl FieldParser {
+
+    pub fn decimal(&mut self, field: &str, value: &str) -> BigDecimal {
+        BigDecimal::from_str(value.trim()).unwrap_or_else(|_| {
+            self.errors.push(format!("{} must be a number", field));
+            BigDecimal::from(0)
+        })
+    }
+
+    pub fn integer(&mut self, field: &str, value: &str) -> i32 {
+        value.trim().parse::<i32>().unwrap_or_else(|_| {
+            self.errors.push(format!("{} must be a whole number", field));
+            0
+        })
+    }
+}
+
+/// the profile form; numbers arrive as text so a typo comes back as a message rather than a 400
+#[derive(Debug, Deserialize)]
+pub struct ProfileForm {
+    id: i64,
+    name: String,
+    notes: String,
+    trade_size: String,
+    /// a checkbox: present when checked
+    trade_enable_buy: Option<String>,
+    trade_ema_small_size: String,
+    trade_ema_large_size: String,
+    trade_sell_high_per_cent_multiplier: String,
+    trade_sell_high_upper_limit_cents: String,
+    max_position_age_minute: String,
+    upgrade_min_profit: String,
+    upgrade_sell_elapsed_minutes_min: String,
+    upgrade_posn_max_elapsed_minutes: String,
+    upgrade_posn_loss_allowed_dollars: String,
+    acct_max_position_market_value: String,
+    acct_min_cash_dollars: String,
+}
+
+impl ProfileForm {
+
+    /// parse every field, then SettingsProfile::validate; all the problems at once
+    fn to_profile(&self) -> Result<SettingsProfile, Vec<String>> {
+        let mut parser = FieldParser::default();
+        let trade_size = parser.decimal("trade_size", &self.trade_size);
+        let trade_ema_small_size = parser.integer("trade_ema_small_size", &self.trade_ema_small_size);
+        let trade_ema_large_size = parser.integer("trade_ema_large_size", &self.trade_ema_large_size);
+        let trade_sell_high_per_cent_multiplier = parser.decimal("trade_sell_high_per_cent_multiplier", &self.trade_sell_high_per_cent_multiplier);
+        let trade_sell_high_upper_limit_cents = parser.decimal("trade_sell_high_upper_limit_cents", &self.trade_sell_high_upper_limit_cents);
+        let max_position_age_minute = parser.decimal("max_position_age_minute", &self.max_position_age_minute);
+        let upgrade_min_profit = parser.decimal("upgrade_min_profit", &self.upgrade_min_profit);
+        let upgrade_sell_elapsed_minutes_min = parser.decimal("upgrade_sell_elapsed_minutes_min", &self.upgrade_sell_elapsed_minutes_min);
+        let upgrade_posn_max_elapsed_minutes = parser.decimal("upgrade_posn_max_elapsed_minutes", &self.upgrade_posn_max_elapsed_minutes);
+        let upgrade_posn_loss_allowed_dollars = parser.decimal("upgrade_posn_loss_allowed_dollars", &self.upgrade_posn_loss_allowed_dollars);
+        let acct_max_position_market_value = parser.decimal("acct_max_position_market_value", &self.acct_max_position_market_value);
+        let acct_min_cash_dollars = parser.decimal("acct_min_cash_dollars", &self.acct_min_cash_dollars);
+        if !parser.errors.is_empty() {
+            return Err(parser.errors);
+        }
+
+        let profile = SettingsProfile {
+            id: self.id,
+            name: self.name.trim().to_string(),
+            dtg: Utc::now(),
+            source: "frontend".to_string(),
+            notes: self.notes.trim().to_string(),
+            trade_size,
+            trade_enable_buy: self.trade_enable_buy.is_some(),
+            trade_ema_small_size,
+            trade_ema_large_size,
+            trade_sell_high_per_cent_multiplier,
+            trade_sell_high_upper_limit_cents,
+            max_position_age_minute,
+            upgrade_min_profit,
+            upgrade_sell_elapsed_minutes_min,
+            upgrade_posn_max_elapsed_minutes,
+            upgrade_posn_loss_allowed_dollars,
+            acct_max_position_market_value,
+            acct_min_cash_dollars,
+        };
+        profile.validate()?;
+        Ok(profile)
+    }
+}
+
+/// GET /settings/profile/new; starts from the current settings
+pub async fn get_profile_new(tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        match Settings::load_no_secret(tx) {
+            Ok(settings) => render_form(&hb, &session_username, "New Profile", &SettingsProfile::from_settings("", "frontend", "", &settings), &[]),
+            Err(_) => get_settings_with_message(tx_db, hb, session, "couldn't load settings").await,
+        }
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /settings/profile/{id}/edit
+pub async fn get_profile_edit(path: web::Path<i64>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        match SettingsProfile::load(path.into_inner(), tx) {
+            Ok(profile) => render_form(&hb, &session_username, "Edit Profile", &profile, &[]),
+            Err(_) => get_settings_with_message(tx_db, hb, session, "no such profile").await,
+        }
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /settings/profile/{id}/clone; the copy isn't saved until the form is
+pub async fn get_profile_clone(path: web::Path<i64>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        match SettingsProfile::load(path.into_inner(), tx) {
+            Ok(mut profile) => {
+                profile.id = 0;
+                profile.name = format!("{}_copy", &profile.name);
+                render_form(&hb, &session_username, "Clone Profile", &profile, &[])
+            },
+            Err(_) => get_settings_with_message(tx_db, hb, session, "no such profile").await,
+        }
+    } else {
+        redirect_home().await
+    }
+}
+
+/// POST /settings/profile; id 0 inserts, anything else updates
+pub async fn post_profile(form: Form<ProfileForm>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    tracing::debug!("[post_profile] form: {:?}", &form);
+
+    if let Ok(Some(session_username)) = session.get::<String>(SESSION_USERNAME) {
+        let form = form.into_inner();
+        let profile = match form.to_profile() {
+            Ok(profile) => profile,
+            Err(errors) => {
+                // show the form again with what was typed
+                let tx = tx_db.clone().into_inner().as_ref().clone();
+                let Ok(settings) = Settings::load_no_secret(tx) else { return get_settings_with_message(tx_db, hb, session, "couldn't load settings").await; };
+                let mut typed = SettingsProfile::from_settings(&form.name, "frontend", &form.notes, &settings);
+                typed.id = form.id;
+                return render_form_typed(&hb, &session_username, &typed, &form, &errors);
+            }
+        };
+
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let result = if profile.id == 0 { profile.save(tx).map(|_| ()) } else { profile.update(tx) };
+        let message = match result {
+            Ok(_) => format!("profile {} saved", &profile.name),
+            Err(e) => {
+                tracing::debug!("[post_profile] error saving profile: {:?}", &e);
+                format!("couldn't save profile {}; is the name taken?", &profile.name)
+            }
+        };
+        get_settings_with_message(tx_db, hb, session, &message).await
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /settings/profile/{id}/delete
+pub async fn get_profile_delete(path: web::Path<i64>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if session.get::<String>(SESSION_USERNAME).is_ok_and(|u| u.is_some()) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let message = match SettingsProfile::delete(path.into_inner(), tx) {
+            Ok(_) => "profile deleted".to_string(),
+            Err(e) => format!("couldn't delete profile: {:?}", e),
+        };
+        get_settings_with_message(tx_db, hb, session, &message).await
+    } else {
+        redirect_home().await
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduleForm {
+    session_time: String,
+    weekdays: String,
+    profile_name: String,
+}
+
+/// POST /settings/schedule
+pub async fn post_schedule(form: Form<ScheduleForm>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    tracing::debug!("[post_schedule] form: {:?}", &form);
+
+    if session.get::<String>(SESSION_USERNAME).is_ok_and(|u| u.is_some()) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let message = match ProfileSchedule::add(&form.session_time, &form.weekdays, &form.profile_name, tx) {
+            Ok(_) => format!("{} at {} scheduled", &form.profile_name, &form.session_time),
+            Err(e) => e,
+        };
+        get_settings_with_message(tx_db, hb, session, &message).await
+    } else {
+        redirect_home().await
+    }
+}
+
+/// GET /settings/schedule/{id}/delete
+pub async fn get_schedule_delete(path: web::Path<i64>, tx_db: web::Data<Sender<DbMsg>>, hb: web::Data<Handlebars<'_>>, session: Session) -> HttpResponse {
+    if session.get::<String>(SESSION_USERNAME).is_ok_and(|u| u.is_some()) {
+        let tx = tx_db.clone().into_inner().as_ref().clone();
+        let message = match ProfileSchedule::delete(path.into_inner(), tx) {
+            Ok(_) => "schedule entry deleted".to_string(),
+            Err(e) => format!("couldn't delete schedule entry: {:?}", e),
+        };
+        get_settings_with_message(tx_db, hb, session, &message).await
+    } else {
+        redirect_home().await
+    }
+}
+
+fn render_form(hb: &Handlebars<'_>, session_username: &str, title: &str, profile: &SettingsProfile, errors: &[String]) -> HttpResponse {
+    render(hb, json!({
+        "title": title,
+        "parent": "base0",
+        "is_logged_in": true,
+        "session_username": session_username,
+        "data": profile,
+        "errors": errors,
+    }))
+}
+
+/// the form as submitted, numbers as typed, so the fields to fix are still there
+fn render_form_typed(hb: &Handlebars<'_>, session_username: &str, profile: &SettingsProfile, form: &ProfileForm, errors: &[String]) -> HttpResponse {
+    let mut data = serde_json::to_value(profile).unwrap_or_default();
+    if let Some(map) = data.as_object_mut() {
+        for (field, value) in [
+            ("trade_size", &form.trade_size),
+            ("trade_ema_small_size", &form.trade_ema_small_size),
+            ("trade_ema_large_size", &form.trade_ema_large_size),
+            ("trade_sell_high_per_cent_multiplier", &form.trade_sell_high_per_cent_multiplier),
+            ("trade_sell_high_upper_limit_cents", &form.trade_sell_high_upper_limit_cents),
+            ("max_position_age_minute", &form.max_position_age_minute),
+            ("upgrade_min_profit", &form.upgrade_min_profit),
+            ("upgrade_sell_elapsed_minutes_min", &form.upgrade_sell_elapsed_minutes_min),
+            ("upgrade_posn_max_elapsed_minutes", &form.upgrade_posn_max_elapsed_minutes),
+            ("upgrade_posn_loss_allowed_dollars", &form.upgrade_posn_loss_allowed_dollars),
+            ("acct_max_position_market_value", &form.acct_max_position_market_value),
+            ("acct_min_cash_dollars", &form.acct_min_cash_dollars),
+        ] {
+            map.insert(field.to_string(), json!(value));
+        }
+        map.insert("trade_enable_buy".to_string(), json!(form.trade_enable_buy.is_some()));
+    }
+    render(hb, json!({
+        "title": if form.id == 0 { "New Profile" } else { "Edit Profile" },
+        "parent": "base0",
+        "is_logged_in": true,
+        "session_username": session_username,
+        "data": data,
+        "errors": errors,
+    }))
+}
+
+fn render(hb: &Handlebars<'_>, data: serde_json::Value) -> HttpResponse {
+    let body = hb.render("settings_profile", &data).unwrap();
+    HttpResponse::Ok()
+        .append_header(("cache-control", "no-store"))
+        .body(body)
+}
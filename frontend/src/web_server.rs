@@ -2,23 +2,26 @@
 
 use std::fs;
 use std::io::{BufReader};
-use std::str::FromStr;
-use crate::configuration::get_yaml_configuration;
 use actix_session::storage::CookieSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::{web, App, HttpServer};
 use handlebars::Handlebars;
 use sqlx::PgPool;
 use common_lib::common_structs::ConfigLocation;
+use common_lib::config::AppConfig;
 use common_lib::db::DbMsg;
 use crate::account::get_account;
 use crate::activities::{get_activities, get_activity_for_symbol};
+use crate::backtest::{get_backtest_run, get_backtests};
 use crate::dashboard::{get_dashboard, get_dashboard_with_symbol};
-use crate::edit_settings::{get_settings, get_settings_button};
+use crate::edit_settings::{get_settings, get_settings_button, get_settings_edit, get_settings_history, post_settings_edit, post_settings_restore};
+use crate::kill_switch::{get_kill_switch, get_kill_switch_off, get_kill_switch_on};
 use crate::login::{get_login, get_logout, post_login};
-use crate::order::get_order;
+use crate::order::{get_order, get_order_cancel, get_order_cancel_symbol, get_order_protect, get_order_replace};
 use crate::positions::get_positions;
 use crate::profit::{get_profit, get_profit_summary};
+use crate::settings_credentials::{get_credentials, post_credentials};
+use crate::settings_profile::{get_profile_clone, get_profile_delete, get_profile_edit, get_profile_new, get_schedule_delete, post_profile, post_schedule};
 use crate::symbols::{get_symbols, post_symbols};
 use crate::utils::*;
 
@@ -31,10 +34,7 @@ pub struct WebServer {}
 impl WebServer {
     pub async fn run(tx_db: crossbeam_channel::Sender<DbMsg>) {
 
-        let settings = get_yaml_configuration().expect("no configuration.yaml");
-        let address = format!("{}:{}", settings.database.host, settings.database.port);
-        tracing::debug!("[run] address from config: {}", &address);
-        let web_port = settings.application_port;
+        let web_port = AppConfig::get().application_port;
         tracing::info!("[run] web server starting on port: {}", &web_port);
 
         let _ = WebServer::web_server(web_port, tx_db).await;
@@ -47,8 +47,7 @@ impl WebServer {
     async fn web_server(web_port: u16, tx_db: crossbeam_channel::Sender<DbMsg>) -> std::io::Result<()> {
         tracing::info!("starting HTTP server at http://localhost:8080");
 
-        let configuration = get_yaml_configuration().expect("[web_server] no configuration.yaml?");
-        let pool = PgPool::connect(&configuration.database.connection_string()).await.expect("[frontend][web server] failed to connect to postgres");
+        let pool = PgPool::connect(AppConfig::get().database_url.expose()).await.expect("[frontend][web server] failed to connect to postgres");
 
 
         // handlebars
@@ -56,7 +55,7 @@ impl WebServer {
         // https://github.com/actix/examples/blob/master/templating/handlebars/src/main.rs
         // https://github.com/sunng87/handlebars-rust/tree/master/examples
         let mut handlebars = Handlebars::new();
-        let config_location:ConfigLocation = ConfigLocation::from_str(&std::env::var("CONFIG_LOCATION").unwrap_or_else(|_| "not_docker".to_owned())).expect("CONFIG_LOCATION");
+        let config_location = AppConfig::get().location();
 
         // or CARGO_PKG_NAME
         let package_name = env!("CARGO_MANIFEST_DIR");
@@ -141,12 +140,34 @@ impl WebServer {
                     "/settings/button/{name}",
                     web::get().to(get_settings_button),
                 )
+                .route("/settings/edit", web::get().to(get_settings_edit))
+                .route("/settings/edit", web::post().to(post_settings_edit))
+                .route("/settings/history", web::get().to(get_settings_history))
+                .route("/settings/history/{id}/restore", web::post().to(post_settings_restore))
+                .route("/settings/credentials", web::get().to(get_credentials))
+                .route("/settings/credentials", web::post().to(post_credentials))
+                .route("/settings/profile", web::post().to(post_profile))
+                .route("/settings/profile/new", web::get().to(get_profile_new))
+                .route("/settings/profile/{id}/edit", web::get().to(get_profile_edit))
+                .route("/settings/profile/{id}/clone", web::get().to(get_profile_clone))
+                .route("/settings/profile/{id}/delete", web::get().to(get_profile_delete))
+                .route("/settings/schedule", web::post().to(post_schedule))
+                .route("/settings/schedule/{id}/delete", web::get().to(get_schedule_delete))
                 .route("/dashboard", web::get().to(get_dashboard))
                 .route(
                     "/dashboard/{symbol}",
                     web::get().to(get_dashboard_with_symbol),
                 )
                 .route("/order", web::get().to(get_order))
+                .route("/order/cancel/{order_id}", web::get().to(get_order_cancel))
+                .route("/order/cancel_symbol/{symbol}", web::get().to(get_order_cancel_symbol))
+                .route("/order/replace/{order_id}", web::get().to(get_order_replace))
+                .route("/order/protect/{symbol}", web::get().to(get_order_protect))
+                .route("/kill_switch", web::get().to(get_kill_switch))
+                .route("/kill_switch/on", web::get().to(get_kill_switch_on))
+                .route("/kill_switch/off", web::get().to(get_kill_switch_off))
+                .route("/backtest", web::get().to(get_backtests))
+                .route("/backtest/{run_id}", web::get().to(get_backtest_run))
             // .route("/order/{symbol}", web::get().to(get_orders))
         })
         .bind_rustls(("0.0.0.0", web_port), config)?
@@ -0,0 +1,34 @@
+-- account-level risk limits checked by common_lib::risk before every order
+
+create table if not exists t_risk_limits
+(
+    dtg timestamptz not null default now(),
+    symbol_notional_cap numeric(20,10) not null default 1000.0,
+    max_open_positions integer not null default 10,
+    daily_loss_limit_dollars numeric(20,10) not null default 100.0
+);
+
+alter table t_risk_limits owner to postgres;
+
+insert into t_risk_limits(dtg)
+select now() where not exists (select 1 from t_risk_limits);
+
+-- every order the risk manager refuses
+
+create table if not exists risk_event
+(
+    id bigserial
+        constraint risk_event_pk
+            primary key,
+    dtg timestamptz not null default now(),
+    symbol varchar,
+    side varchar,
+    qty numeric(20,10),
+    notional numeric(20,10),
+    reason varchar not null,
+    detail varchar
+);
+
+alter table risk_event owner to postgres;
+
+create index if not exists idx_risk_event_dtg on risk_event (dtg);
@@ -0,0 +1,15 @@
+-- kill switch history; the latest row is the current state and survives restarts until cleared
+
+create table if not exists t_kill_switch
+(
+    id bigserial
+        constraint t_kill_switch_pk
+            primary key,
+    dtg timestamptz not null default now(),
+    engaged boolean not null,
+    flatten boolean not null default false,
+    reason varchar,
+    changed_by varchar
+);
+
+alter table t_kill_switch owner to postgres;
@@ -0,0 +1,39 @@
+-- how long an unfilled limit order may sit before order_manager reprices or cancels it
+
+create table if not exists t_order_policy
+(
+    dtg timestamptz not null default now(),
+    stale_limit_enabled boolean not null default true,
+    stale_limit_age_seconds integer not null default 300,
+    -- 'reprice' moves the limit to the latest trade price; 'cancel' frees the shares for a new order
+    stale_limit_action varchar not null default 'reprice'
+);
+
+alter table t_order_policy owner to postgres;
+
+insert into t_order_policy(dtg)
+select now() where not exists (select 1 from t_order_policy);
+
+-- every cancel and replace sent to alpaca
+
+create table if not exists log_order_change
+(
+    id bigserial
+        constraint log_order_change_pk
+            primary key,
+    dtg timestamptz not null default now(),
+    action varchar not null,
+    order_id varchar not null,
+    client_order_id varchar,
+    symbol varchar,
+    side varchar,
+    qty numeric(20,10),
+    limit_price_old numeric(20,10),
+    limit_price_new numeric(20,10),
+    order_id_new varchar,
+    reason varchar
+);
+
+alter table log_order_change owner to postgres;
+
+create index if not exists idx_log_order_change_dtg on log_order_change (dtg);
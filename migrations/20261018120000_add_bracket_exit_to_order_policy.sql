@@ -0,0 +1,5 @@
+-- optional exits attached to every buy as a bracket order, instead of waiting for the sell-high poller
+
+alter table t_order_policy add column if not exists bracket_enabled boolean not null default false;
+alter table t_order_policy add column if not exists bracket_take_profit_per_cent numeric(20,10) not null default 0.5;
+alter table t_order_policy add column if not exists bracket_stop_loss_per_cent numeric(20,10) not null default 1.0;
@@ -0,0 +1,8 @@
+-- buy sizing: 'whole_shares', 'fractional' or 'notional' (the last two only for fractionable assets)
+
+alter table t_order_policy add column if not exists sizing_mode varchar not null default 'whole_shares';
+
+-- fractional share quantities end to end
+
+alter table if exists alpaca_transaction_status alter column posn_shares type numeric(20,10);
+alter table if exists log_orders alter column qty type numeric(20,10);
@@ -0,0 +1,44 @@
+-- short selling is off unless enabled here; easy_to_borrow avoids hard-to-borrow fees and recalls
+
+alter table t_order_policy add column if not exists short_enabled boolean not null default false;
+alter table t_order_policy add column if not exists short_require_easy_to_borrow boolean not null default true;
+
+-- short positions showing a profit of at least pl_filter per share, in the same shape as fn_positions_to_sell_high
+-- so both come back as common_lib::sell_position::SellPosition. Alpaca reports short qty and market_value as
+-- negative; everything here is returned as positive shares to cover and profit-positive P/L.
+
+create or replace function fn_positions_to_cover(pl_filter numeric)
+    returns table
+            (
+                stock_symbol varchar,
+                price numeric,
+                sell_qty numeric,
+                sell_qty_available numeric,
+                unrealized_pl_per_share numeric,
+                cost numeric,
+                unrealized_pl_total numeric,
+                age_min numeric
+            )
+    language sql
+as
+$$
+select
+    p.symbol
+    , p.avg_entry_price
+    , abs(p.qty)
+    , abs(p.qty_available)
+    , p.avg_entry_price - coalesce(t.price, p.current_price)
+    , abs(p.cost_basis)
+    , (p.avg_entry_price - coalesce(t.price, p.current_price)) * abs(p.qty)
+    , coalesce((
+        select extract(epoch from now() - max(a.dtg)) / 60.0
+        from alpaca_activity a
+        where upper(a.symbol) = upper(p.symbol) and a.side = 'sell_short'
+    ), 0.0)::numeric
+from alpaca_position p
+left join trade_alp_latest t on upper(t.symbol) = upper(p.symbol)
+where p.side = 'short'
+    and p.avg_entry_price - coalesce(t.price, p.current_price) >= pl_filter
+$$;
+
+alter function fn_positions_to_cover(numeric) owner to postgres;
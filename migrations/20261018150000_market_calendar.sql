@@ -0,0 +1,27 @@
+-- Alpaca /v2/calendar and /v2/clock, cached so every process answers "is the market open" the same way;
+-- times are New York local, days the market is closed have no row
+
+create table if not exists alpaca_calendar
+(
+    date date not null
+        constraint alpaca_calendar_pk
+            primary key,
+    open time not null,
+    close time not null,
+    session_open time not null,
+    session_close time not null,
+    dtg timestamptz not null default now()
+);
+
+alter table alpaca_calendar owner to postgres;
+
+create table if not exists alpaca_clock
+(
+    dtg timestamptz not null default now(),
+    timestamp timestamptz not null,
+    is_open boolean not null,
+    next_open timestamptz not null,
+    next_close timestamptz not null
+);
+
+alter table alpaca_clock owner to postgres;
@@ -0,0 +1,8 @@
+-- time-based exits: sell positions older than t_settings.max_position_age_minute, and flatten everything
+-- shortly before the regular close so nothing is held overnight by accident; both off by default
+
+alter table t_order_policy add column if not exists age_exit_enabled boolean not null default false;
+alter table t_order_policy add column if not exists eod_flatten_enabled boolean not null default false;
+alter table t_order_policy add column if not exists eod_flatten_minutes integer not null default 15;
+alter table t_order_policy add column if not exists eod_flatten_max_concession_per_cent numeric(20,10) not null default 0.5;
+alter table t_order_policy add column if not exists eod_flatten_market_minutes integer not null default 2;
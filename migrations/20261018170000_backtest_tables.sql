@@ -0,0 +1,69 @@
+-- results of the backtest binary; one backtest_run per replay, the rest keyed by run_id
+
+create table if not exists backtest_run
+(
+    id                      bigserial primary key,
+    dtg                     timestamptz not null default now(),
+    name                    varchar not null,
+    dtg_start               timestamptz not null,
+    dtg_end                 timestamptz not null,
+    symbols                 varchar not null,
+    source                  varchar not null,
+    slippage_bps            numeric not null,
+    latency_ms              bigint not null,
+    cash_start              numeric not null,
+    equity_end              numeric not null,
+    pnl                     numeric not null,
+    max_drawdown            numeric not null,
+    max_drawdown_per_cent   numeric not null,
+    trade_count             bigint not null,
+    win_rate                numeric not null,
+    settings                varchar not null
+);
+
+alter table backtest_run
+    owner to postgres;
+
+create table if not exists backtest_trade
+(
+    id          bigserial primary key,
+    run_id      bigint not null references backtest_run (id) on delete cascade,
+    symbol      varchar not null,
+    dtg_open    timestamptz not null,
+    dtg_close   timestamptz not null,
+    qty         numeric not null,
+    price_open  numeric not null,
+    price_close numeric not null,
+    pnl         numeric not null
+);
+
+alter table backtest_trade
+    owner to postgres;
+
+create index if not exists backtest_trade_run_id on backtest_trade (run_id);
+
+create table if not exists backtest_equity
+(
+    run_id      bigint not null references backtest_run (id) on delete cascade,
+    dtg         timestamptz not null,
+    equity      numeric not null,
+    drawdown    numeric not null
+);
+
+alter table backtest_equity
+    owner to postgres;
+
+create index if not exists backtest_equity_run_id on backtest_equity (run_id, dtg);
+
+create table if not exists backtest_symbol
+(
+    run_id      bigint not null references backtest_run (id) on delete cascade,
+    symbol      varchar not null,
+    trades      bigint not null,
+    wins        bigint not null,
+    pnl         numeric not null,
+    primary key (run_id, symbol)
+);
+
+alter table backtest_symbol
+    owner to postgres;
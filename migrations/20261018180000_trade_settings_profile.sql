@@ -0,0 +1,26 @@
+-- named sets of trade setting values; the optimizer binary exports its best configuration as one
+
+create table if not exists t_trade_settings_profile
+(
+    id                                  bigserial primary key,
+    name                                varchar not null unique,
+    dtg                                 timestamptz not null default now(),
+    source                              varchar not null,
+    notes                               varchar not null default '',
+    trade_size                          numeric not null,
+    trade_enable_buy                    boolean not null,
+    trade_ema_small_size                integer not null,
+    trade_ema_large_size                integer not null,
+    trade_sell_high_per_cent_multiplier numeric not null,
+    trade_sell_high_upper_limit_cents   numeric not null,
+    max_position_age_minute             numeric not null,
+    upgrade_min_profit                  numeric not null,
+    upgrade_sell_elapsed_minutes_min    numeric not null,
+    upgrade_posn_max_elapsed_minutes    numeric not null,
+    upgrade_posn_loss_allowed_dollars   numeric not null,
+    acct_max_position_market_value      numeric not null,
+    acct_min_cash_dollars               numeric not null
+);
+
+alter table t_trade_settings_profile
+    owner to postgres;
@@ -0,0 +1,35 @@
+-- trade settings profiles as data: who activated which profile when, and the four profiles that used to be
+-- hard coded in fn_set_trade_settings
+
+create table if not exists t_trade_settings_profile_activation
+(
+    id              bigserial primary key,
+    dtg             timestamptz not null default now(),
+    profile_name    varchar not null,
+    changed_by      varchar not null
+);
+
+alter table t_trade_settings_profile_activation
+    owner to postgres;
+
+create index if not exists t_trade_settings_profile_activation_dtg_idx
+    on t_trade_settings_profile_activation (dtg desc);
+
+-- seeded from the current settings with the differences the /settings buttons advertised; compare them with
+-- fn_set_trade_settings and edit them on /settings before relying on them
+insert into t_trade_settings_profile(name, source, notes, trade_size, trade_enable_buy, trade_ema_small_size
+    , trade_ema_large_size, trade_sell_high_per_cent_multiplier, trade_sell_high_upper_limit_cents, max_position_age_minute
+    , upgrade_min_profit, upgrade_sell_elapsed_minutes_min, upgrade_posn_max_elapsed_minutes, upgrade_posn_loss_allowed_dollars
+    , acct_max_position_market_value, acct_min_cash_dollars)
+select p.name, 'built_in', p.notes, coalesce(p.trade_size, s.trade_size), p.trade_enable_buy, s.trade_ema_small_size
+    , s.trade_ema_large_size, s.trade_sell_high_per_cent_multiplier, s.trade_sell_high_upper_limit_cents, coalesce(s.max_position_age_minute, 0.0)
+    , coalesce(s.upgrade_min_profit, 0.0), coalesce(s.upgrade_sell_elapsed_minutes_min, 60.0), coalesce(s.upgrade_posn_max_elapsed_minutes, 60.0)
+    , coalesce(s.upgrade_posn_loss_allowed_dollars, 10.0), coalesce(s.acct_max_position_market_value, 10.0), coalesce(s.acct_min_cash_dollars, 10.0)
+from (values
+    ('buy', 'buying, 7 shares', 7.0, true),
+    ('buy_two', 'buying, 17 shares', 17.0, true),
+    ('close', 'no new buys', null, false),
+    ('close_with_loss', 'no new buys; exits allowed at a loss', null, false)
+) as p(name, notes, trade_size, trade_enable_buy)
+cross join (select * from t_settings order by dtg desc limit 1) s
+on conflict (name) do nothing;
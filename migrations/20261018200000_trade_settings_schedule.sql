@@ -0,0 +1,15 @@
+-- profile switches at session times, e.g. buy at open+5m and close at close-30m on weekdays; see
+-- common_lib/src/profile_schedule.rs
+
+create table if not exists t_trade_settings_schedule
+(
+    id                  bigserial primary key,
+    enabled             boolean not null default true,
+    session_time        varchar not null,
+    weekdays            varchar not null default 'mon,tue,wed,thu,fri',
+    profile_name        varchar not null references t_trade_settings_profile (name) on update cascade on delete cascade,
+    dtg_last_applied    timestamptz
+);
+
+alter table t_trade_settings_schedule
+    owner to postgres;
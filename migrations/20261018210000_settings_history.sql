@@ -0,0 +1,17 @@
+-- one row per change to t_settings: the non-secret values before and after, as JSON text
+
+create table if not exists t_settings_history
+(
+    id          bigserial primary key,
+    dtg         timestamptz not null default now(),
+    changed_by  varchar not null,
+    source      varchar not null,
+    old_values  varchar not null,
+    new_values  varchar not null
+);
+
+alter table t_settings_history
+    owner to postgres;
+
+create index if not exists t_settings_history_dtg_idx
+    on t_settings_history (dtg desc);
@@ -0,0 +1,5 @@
+-- every settings write is recorded now, not just the editor's; note says which profile was switched to or
+-- which version was restored
+
+alter table t_settings_history
+    add column if not exists note varchar not null default '';
@@ -0,0 +1,16 @@
+-- every write to t_settings tells the running services to reload their settings; see settings_cache.rs
+
+create or replace function fn_notify_settings_changed() returns trigger as $$
+begin
+    perform pg_notify('settings_changed', new.dtg::text);
+    return new;
+end;
+$$ language plpgsql;
+
+alter function fn_notify_settings_changed() owner to postgres;
+
+drop trigger if exists tr_settings_changed on t_settings;
+
+create trigger tr_settings_changed
+    after insert or update on t_settings
+    for each row execute function fn_notify_settings_changed();
@@ -0,0 +1,49 @@
+-- the four built-in profiles were seeded with guessed values; replace them with what fn_set_trade_settings
+-- actually sets. The function writes t_settings, so each call is made in a block that's rolled back afterwards;
+-- the row it returned survives in r. Databases without the function keep the seeded values.
+
+do $$
+declare
+    profile_name varchar;
+    r record;
+    returned boolean;
+begin
+    if not exists (select 1 from pg_proc where proname = 'fn_set_trade_settings') then
+        raise notice 'fn_set_trade_settings not found; built-in profiles left as seeded';
+        return;
+    end if;
+
+    foreach profile_name in array array['buy', 'buy_two', 'close', 'close_with_loss'] loop
+        returned := false;
+        begin
+            select * into r from fn_set_trade_settings(profile_name);
+            returned := found;
+            raise exception using errcode = 'P0001', message = 'undo fn_set_trade_settings';
+        exception
+            when raise_exception then null;
+        end;
+        if not returned then
+            continue;
+        end if;
+
+        update t_trade_settings_profile
+        set dtg                                 = now()
+          , notes                               = 'from fn_set_trade_settings(''' || profile_name || ''')'
+          , trade_size                          = r.trade_size
+          , trade_enable_buy                    = r.trade_enable_buy
+          , trade_ema_small_size                = r.trade_ema_small_size
+          , trade_ema_large_size                = r.trade_ema_large_size
+          , trade_sell_high_per_cent_multiplier = r.trade_sell_high_per_cent_multiplier
+          , trade_sell_high_upper_limit_cents   = r.trade_sell_high_upper_limit_cents
+          , max_position_age_minute             = coalesce(r.max_position_age_minute, 0.0)
+          , upgrade_min_profit                  = coalesce(r.upgrade_min_profit, 0.0)
+          , upgrade_sell_elapsed_minutes_min    = coalesce(r.upgrade_sell_elapsed_minutes_min, 60.0)
+          , upgrade_posn_max_elapsed_minutes    = coalesce(r.upgrade_posn_max_elapsed_minutes, 60.0)
+          , upgrade_posn_loss_allowed_dollars   = coalesce(r.upgrade_posn_loss_allowed_dollars, 10.0)
+          , acct_max_position_market_value      = coalesce(r.acct_max_position_market_value, 60.0)
+          , acct_min_cash_dollars               = coalesce(r.acct_min_cash_dollars, 60.0)
+        where name = profile_name
+          and source = 'built_in';
+    end loop;
+end;
+$$;
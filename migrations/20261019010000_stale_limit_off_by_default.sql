@@ -0,0 +1,14 @@
+-- handling stale limit orders is something the operator turns on: off by default, and cancel rather than
+-- reprice when it is on, so running the migrations doesn't start moving the sell-high limits
+
+alter table t_order_policy alter column stale_limit_enabled set default false;
+alter table t_order_policy alter column stale_limit_action set default 'cancel';
+
+-- the row 20261018110000 seeded, if no policy has been added since
+update t_order_policy
+set stale_limit_enabled = false
+  , stale_limit_action  = 'cancel'
+where stale_limit_enabled
+  and stale_limit_action = 'reprice'
+  and stale_limit_age_seconds = 300
+  and (select count(*) from t_order_policy) = 1;
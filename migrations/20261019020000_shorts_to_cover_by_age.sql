@@ -0,0 +1,41 @@
+-- short positions held longer than age_filter_minutes, for the age exit; the same shape as fn_positions_to_cover
+-- (positive shares to cover, profit-positive P/L) with the age of the latest sell_short instead of a P/L filter.
+
+create or replace function fn_positions_to_cover_old(age_filter_minutes numeric)
+    returns table
+            (
+                stock_symbol varchar,
+                price numeric,
+                sell_qty numeric,
+                sell_qty_available numeric,
+                unrealized_pl_per_share numeric,
+                cost numeric,
+                unrealized_pl_total numeric,
+                age_min numeric
+            )
+    language sql
+as
+$$
+select *
+from (
+    select
+        p.symbol
+        , p.avg_entry_price
+        , abs(p.qty)
+        , abs(p.qty_available)
+        , p.avg_entry_price - coalesce(t.price, p.current_price)
+        , abs(p.cost_basis)
+        , (p.avg_entry_price - coalesce(t.price, p.current_price)) * abs(p.qty)
+        , coalesce((
+            select extract(epoch from now() - max(a.dtg)) / 60.0
+            from alpaca_activity a
+            where upper(a.symbol) = upper(p.symbol) and a.side = 'sell_short'
+        ), 0.0)::numeric as age_min
+    from alpaca_position p
+    left join trade_alp_latest t on upper(t.symbol) = upper(p.symbol)
+    where p.side = 'short'
+) shorts
+where shorts.age_min > age_filter_minutes
+$$;
+
+alter function fn_positions_to_cover_old(numeric) owner to postgres;
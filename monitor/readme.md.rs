@@ -1,5 +0,0 @@
-## Under Construction
-
-All of this needs much better monitoring for when it goes live. This will be a project to monitor
-the liveness of the other projects and to report out to Signal, email and external logging
-and reporting APIs.
\ No newline at end of file
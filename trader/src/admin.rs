@@ -0,0 +1,145 @@
+//! admin.rs
+//!
+//! migrate, user, kill-switch, settings and bench-trades.
+//!
+
+use std::io::BufRead;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHasher};
+use common_lib::config::AppConfig;
+use common_lib::init::init;
+use common_lib::kill_switch::KillSwitch;
+use common_lib::settings::Settings;
+use common_lib::sqlx_pool::create_sqlx_pg_pool;
+use common_lib::trade_batch::{self, TradeBench};
+use common_lib::user::User;
+use crate::credentials::print_status;
+use crate::{arg_map, fail, start_db, usage, BACKEND_DIR, FRONTEND_DIR};
+
+const USER_USAGE: &str = "usage: trader user add <username> | user passwd <username>; the password is read from stdin";
+const KILL_SWITCH_USAGE: &str = "usage: trader kill-switch on [--flatten] [reason...] | off | status";
+const SETTINGS_USAGE: &str = "usage: trader settings show";
+const BENCH_USAGE: &str = "usage: trader bench-trades [--trades 2000]";
+
+/// apply whatever in migrations/ the database hasn't seen; they're built into the binary
+pub fn migrate() {
+    init(BACKEND_DIR);
+
+    let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name("migrate")
+        .enable_all()
+        .build()
+        .expect("Tokio runtime didn't start");
+    let pool = tokio_runtime.block_on(create_sqlx_pg_pool());
+    match tokio_runtime.block_on(sqlx::migrate!("../migrations").run(&pool)) {
+        Ok(()) => println!("migrations applied"),
+        Err(e) => fail("migrate", e),
+    }
+}
+
+/// add a frontend login or change its password
+pub fn user(args: &[String]) {
+    init(FRONTEND_DIR);
+
+    let (command, username) = match args {
+        [command, username] if command == "add" || command == "passwd" => (command.as_str(), username.as_str()),
+        _ => usage(USER_USAGE),
+    };
+
+    eprintln!("password for {}:", username);
+    let mut password = String::new();
+    if std::io::stdin().lock().read_line(&mut password).is_err() || password.trim_end_matches(['\r', '\n']).is_empty() {
+        usage(USER_USAGE);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::default().hash_password(password.as_bytes(), &salt).unwrap_or_else(|e| fail("hashing", e)).to_string();
+
+    let (_tokio_runtime, tx_db) = start_db("user");
+    match command {
+        "add" => match User::add(username, &password_hash, tx_db) {
+            Ok(user_id) => println!("added {} ({})", username, user_id),
+            Err(e) => fail("add (is the name taken?)", e),
+        },
+        _ => match User::set_password(username, &password_hash, tx_db) {
+            Ok(()) => println!("password changed for {}", username),
+            Err(e) => fail("passwd", e),
+        },
+    }
+}
+
+/// for when the frontend isn't reachable
+pub fn kill_switch(args: &[String]) {
+    init(BACKEND_DIR);
+
+    let Some(command) = args.first() else { usage(KILL_SWITCH_USAGE) };
+    if !["on", "off", "status"].contains(&command.as_str()) {
+        usage(KILL_SWITCH_USAGE);
+    }
+
+    let (_tokio_runtime, tx_db) = start_db("kill_switch");
+    let changed_by = format!("cli:{}", std::env::var("USER").unwrap_or_else(|_| "unknown".to_owned()));
+
+    match command.as_str() {
+        "on" => {
+            let flatten = args.iter().any(|a| a == "--flatten");
+            let reason = args.iter().skip(1).filter(|a| *a != "--flatten").cloned().collect::<Vec<String>>().join(" ");
+            let reason = if reason.is_empty() { "command line".to_string() } else { reason };
+
+            let settings = Settings::load_with_secret(tx_db.clone()).expect("[kill_switch] couldn't load settings");
+            match KillSwitch::engage(flatten, &reason, &changed_by, &settings, tx_db) {
+                Ok(result) => println!("engaged; orders canceled: {:?}, positions closed: {:?}", result.orders_canceled, result.positions_closed),
+                Err(e) => fail("engage", e),
+            }
+        },
+        "off" => match KillSwitch::clear(&changed_by, tx_db) {
+            Ok(_) => println!("cleared; trading resumed"),
+            Err(e) => fail("clear", e),
+        },
+        _ => match KillSwitch::status(tx_db) {
+            Ok(state) => println!("{:?}", state),
+            Err(e) => fail("status", e),
+        },
+    }
+}
+
+/// the configuration and the current trade settings, credentials redacted
+pub fn settings(args: &[String]) {
+    init(BACKEND_DIR);
+
+    if args.first().map(|a| a.as_str()) != Some("show") {
+        usage(SETTINGS_USAGE);
+    }
+    let (_tokio_runtime, tx_db) = start_db("settings");
+    println!("configuration:\n{}\n", AppConfig::get());
+    match Settings::load_no_secret(tx_db.clone()) {
+        Ok(settings) => println!("trade settings:\n{}\n", serde_json::to_string_pretty(&settings.to_json_no_secret()).unwrap_or_default()),
+        Err(e) => fail("loading settings", e),
+    }
+    if let Err(e) = print_status(tx_db) {
+        fail("credential status", e);
+    }
+}
+
+/// trade write throughput one row at a time, as before batching, and batched; temporary tables, nothing kept
+pub fn bench_trades(args: &[String]) {
+    init(BACKEND_DIR);
+
+    let trades = match arg_map(args).get("trades") {
+        None => 2000,
+        Some(n) => n.parse::<usize>().ok().filter(|n| *n > 0).unwrap_or_else(|| usage(BENCH_USAGE)),
+    };
+    let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name("bench")
+        .enable_all()
+        .build()
+        .expect("Tokio runtime didn't start");
+    match tokio_runtime.block_on(trade_batch::bench(trades)) {
+        Ok(bench) => {
+            println!("{} trades", bench.trades);
+            println!("row at a time: {:>8.1?} {:>10.1}/s", bench.row_at_a_time, TradeBench::per_second(bench.trades, bench.row_at_a_time));
+            println!("batched:       {:>8.1?} {:>10.1}/s", bench.batched, TradeBench::per_second(bench.trades, bench.batched));
+        },
+        Err(e) => fail("bench-trades", e),
+    }
+}
@@ -0,0 +1,55 @@
+//! backfill.rs
+//!
+//! Load Alpaca's historical minute bars into bar_minute; see common_lib/src/backfill.rs.
+//!
+//! trader backfill --start 2023-06-01 --end 2023-06-30 [--symbols aapl,msft] [--feed iex|sip]
+//!
+//! Dates are UTC days, end inclusive, a day per request. Symbols default to the active ones in t_symbol.
+//!
+
+use chrono::Duration;
+use common_lib::backfill::Backfill;
+use common_lib::init::init;
+use common_lib::settings::Settings;
+use crate::backtest::symbols;
+use crate::{arg_date, arg_map, start_db, usage, BACKEND_DIR};
+
+const USAGE: &str = "usage: trader backfill --start YYYY-MM-DD --end YYYY-MM-DD [--symbols a,b] [--feed iex|sip]";
+
+pub fn run(args: &[String]) {
+    init(BACKEND_DIR);
+
+    let args = arg_map(args);
+    let (start, end) = match (arg_date(&args, "start"), arg_date(&args, "end")) {
+        (Some(start), Some(end)) if start <= end => (start, end + Duration::days(1)),
+        _ => usage(USAGE),
+    };
+    let feed = match args.get("feed").map(|s| s.as_str()) {
+        None | Some("iex") => "iex",
+        Some("sip") => "sip",
+        Some(_) => usage(USAGE),
+    };
+
+    let (tokio_runtime, tx_db) = start_db("backfill");
+    let settings = Settings::load_with_secret(tx_db.clone()).expect("[backfill] couldn't load settings");
+    let symbols = symbols(args.get("symbols"), &tokio_runtime, tx_db.clone());
+
+    let mut failed = 0;
+    for symbol in symbols.iter() {
+        let mut day = start;
+        while day < end {
+            let day_end = std::cmp::min(day + Duration::days(1), end);
+            match Backfill::minute_bars(symbol, day, day_end, feed, &settings, tx_db.clone()) {
+                Ok(saved) => println!("{} {}: {} bars", symbol, day.date_naive(), saved),
+                Err(e) => {
+                    eprintln!("{} {}: failed: {:?}", symbol, day.date_naive(), e);
+                    failed += 1;
+                },
+            }
+            day = day_end;
+        }
+    }
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,131 @@
+//! backtest.rs
+//!
+//! Replay stored trades and minute bars through the strategies and save the results for the frontend's
+//! /backtest page.
+//!
+//! trader backtest --start 2023-06-01 --end 2023-06-30 [--symbols aapl,msft] [--source alp|fh]
+//!     [--slippage-bps 5] [--latency-ms 250] [--partial-fills true] [--cash 100000] [--name label]
+//! trader replay 12 [--partial-fills true] [--current-settings]
+//!
+//! Dates are UTC days, end inclusive. Symbols default to the active ones in t_symbol. replay runs a saved
+//! backtest again over the same range, symbols and fill model, with the settings it used unless
+//! --current-settings, and saves it as a new run.
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use crossbeam_channel::Sender;
+use tokio::runtime::Runtime;
+use common_lib::backtest::{Backtest, BacktestConfig, BacktestReport, TradeSource};
+use common_lib::db::DbMsg;
+use common_lib::init::init;
+use common_lib::settings::Settings;
+use common_lib::settings_history::SettingsChange;
+use common_lib::strategy::{AgeExit, EmaCross, SellHigh, Strategy};
+use common_lib::symbol::Symbol;
+use common_lib::symbol_list::SymbolList;
+use crate::{arg_date, arg_map, fail, start_db, usage, BACKEND_DIR};
+
+const USAGE: &str = "usage: trader backtest --start YYYY-MM-DD --end YYYY-MM-DD [--symbols a,b] [--source alp|fh] [--slippage-bps N] [--latency-ms N] [--partial-fills true] [--cash N] [--name label]";
+const REPLAY_USAGE: &str = "usage: trader replay <backtest run id> [--partial-fills true] [--current-settings]";
+
+pub fn run(args: &[String]) {
+    init(BACKEND_DIR);
+
+    let args = arg_map(args);
+    let (start, end) = match (arg_date(&args, "start"), arg_date(&args, "end")) {
+        (Some(start), Some(end)) if start <= end => (start, end + Duration::days(1)),
+        _ => usage(USAGE),
+    };
+
+    let (tokio_runtime, tx_db) = start_db("backtest");
+    let settings = Settings::load_with_secret(tx_db.clone()).expect("[backtest] couldn't load settings");
+    let symbols = symbols(args.get("symbols"), &tokio_runtime, tx_db.clone());
+    let trade_sizes = trade_sizes(&symbols, &settings, tx_db.clone());
+
+    let config = BacktestConfig {
+        name: args.get("name").cloned().unwrap_or_else(|| format!("{} to {}", start.date_naive(), (end - Duration::days(1)).date_naive())),
+        start,
+        end,
+        symbols,
+        source: args.get("source").map(|s| TradeSource::from_str(s).unwrap_or_else(|_| usage(USAGE))).unwrap_or(TradeSource::Alpaca),
+        cash_start: arg_decimal(&args, "cash").unwrap_or_else(|| BigDecimal::from(100_000)),
+        slippage_bps: arg_decimal(&args, "slippage-bps").unwrap_or_else(|| BigDecimal::from(0)),
+        latency_ms: args.get("latency-ms").map(|s| s.parse::<i64>().unwrap_or_else(|_| usage(USAGE))).unwrap_or(0),
+        partial_fills: args.get("partial-fills").is_some_and(|s| s == "true"),
+    };
+    run_and_save(&config, &settings, trade_sizes, tx_db);
+}
+
+/// a saved run again, as a new run
+pub fn replay(args: &[String]) {
+    init(BACKEND_DIR);
+
+    let Some(run_id) = args.first().and_then(|id| id.parse::<i64>().ok()) else { usage(REPLAY_USAGE) };
+    let current_settings = args.iter().any(|a| a == "--current-settings");
+    let options: Vec<String> = args.iter().skip(1).filter(|a| *a != "--current-settings").cloned().collect();
+    let options = arg_map(&options);
+
+    let (_tokio_runtime, tx_db) = start_db("replay");
+    let saved = Backtest::load(run_id, tx_db.clone()).unwrap_or_else(|e| fail("loading the run", e)).run;
+    let config = BacktestConfig::from_run(&saved, &format!("replay of {}: {}", saved.id, saved.name), options.get("partial-fills").is_some_and(|s| s == "true"))
+        .unwrap_or_else(|e| fail("reading the run", e));
+
+    let current = Settings::load_with_secret(tx_db.clone()).expect("[replay] couldn't load settings");
+    let settings = if current_settings {
+        current
+    } else {
+        // the run's settings were saved without credentials, which a replay doesn't use anyway
+        let values = serde_json::from_str(&saved.settings).unwrap_or_else(|e| fail("reading the run's settings", e));
+        SettingsChange::apply_values(&current, &values).unwrap_or_else(|e| fail("reading the run's settings", e))
+    };
+    let trade_sizes = trade_sizes(&config.symbols, &settings, tx_db.clone());
+    run_and_save(&config, &settings, trade_sizes, tx_db);
+}
+
+fn run_and_save(config: &BacktestConfig, settings: &Settings, trade_sizes: HashMap<String, BigDecimal>, tx_db: Sender<DbMsg>) {
+    println!("{:?}", config);
+
+    let strategies: Vec<Box<dyn Strategy>> = vec![Box::new(EmaCross::default()), Box::new(SellHigh::default()), Box::new(AgeExit::default())];
+    let report = Backtest::run(config, settings, strategies, trade_sizes, tx_db.clone()).unwrap_or_else(|e| fail("backtest", e));
+    print_report(&report);
+
+    match Backtest::save(report, tx_db) {
+        Ok(run_id) => println!("saved as run {}", run_id),
+        Err(e) => fail("save", e),
+    }
+}
+
+fn print_report(report: &BacktestReport) {
+    let run = &report.run;
+    println!("trades: {}, win rate: {}%, P&L: {}, max drawdown: {} ({}%)", run.trade_count, &run.win_rate, &run.pnl, &run.max_drawdown, &run.max_drawdown_per_cent);
+    for symbol in report.symbols.iter() {
+        println!("  {}: {} trades, {} wins, P&L {}", &symbol.symbol, symbol.trades, symbol.wins, &symbol.pnl);
+    }
+}
+
+/// --symbols, uppercase; the active ones in t_symbol if not given
+pub fn symbols(arg: Option<&String>, tokio_runtime: &Runtime, tx_db: Sender<DbMsg>) -> Vec<String> {
+    match arg {
+        Some(symbols) => symbols.split(',').map(|s| s.trim().to_uppercase()).filter(|s| !s.is_empty()).collect(),
+        None => tokio_runtime.block_on(SymbolList::get_active_symbols(tx_db))
+            .unwrap_or_else(|e| fail("loading symbols", e))
+            .iter().map(|s| s.to_uppercase()).collect(),
+    }
+}
+
+/// shares per buy, as in live trading
+pub fn trade_sizes(symbols: &[String], settings: &Settings, tx_db: Sender<DbMsg>) -> HashMap<String, BigDecimal> {
+    symbols.iter()
+        .map(|s| {
+            let trade_size = Symbol::load_one(s.to_lowercase(), tx_db.clone()).map(|symbol| symbol.trade_size).unwrap_or_else(|_| settings.trade_size.clone());
+            (s.clone(), trade_size)
+        })
+        .collect()
+}
+
+fn arg_decimal(args: &HashMap<String, String>, key: &str) -> Option<BigDecimal> {
+    args.get(key).map(|s| BigDecimal::from_str(s).unwrap_or_else(|_| usage(USAGE)))
+}
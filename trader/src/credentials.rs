@@ -0,0 +1,81 @@
+//! credentials.rs
+//!
+//! Manage the encryption of the credentials in t_settings; see common_lib/src/secret.rs.
+//!
+//! trader credentials generate-key
+//! trader credentials status
+//! trader credentials encrypt
+//! trader credentials rotate
+//!
+//! encrypt seals any plaintext credentials with CREDENTIALS_KEY. rotate re-seals everything from
+//! CREDENTIALS_KEY to CREDENTIALS_KEY_NEW in one transaction (plaintext included); afterwards set
+//! CREDENTIALS_KEY to the new key everywhere and restart. Running either again is harmless.
+//!
+
+use crossbeam_channel::Sender;
+use common_lib::credentials::Credentials;
+use common_lib::db::DbMsg;
+use common_lib::error::TradeWebError;
+use common_lib::init::init;
+use common_lib::secret::{MasterKey, CREDENTIALS_KEY_ENV, CREDENTIALS_KEY_NEW_ENV};
+use crate::{fail, start_db, usage, BACKEND_DIR};
+
+const USAGE: &str = "usage: trader credentials generate-key | status | encrypt | rotate";
+
+pub fn run(args: &[String]) {
+    init(BACKEND_DIR);
+
+    let Some(command) = args.first() else { usage(USAGE) };
+    if command == "generate-key" {
+        println!("{}", MasterKey::generate());
+        return;
+    }
+    if !["status", "encrypt", "rotate"].contains(&command.as_str()) {
+        usage(USAGE);
+    }
+
+    let (_tokio_runtime, tx_db) = start_db("credentials");
+
+    let result = match command.as_str() {
+        "status" => print_status(tx_db),
+        "encrypt" => {
+            let key = required_key(CREDENTIALS_KEY_ENV);
+            Credentials::reseal(None, key, tx_db).map(|changed| println!("encrypted {} values", changed))
+        },
+        _ => {
+            let (old, new) = (required_key(CREDENTIALS_KEY_ENV), required_key(CREDENTIALS_KEY_NEW_ENV));
+            Credentials::reseal(Some(old), new, tx_db).map(|changed| {
+                println!("re-sealed {} values; set {} to the value of {} and restart", changed, CREDENTIALS_KEY_ENV, CREDENTIALS_KEY_NEW_ENV);
+            })
+        },
+    };
+    if let Err(e) = result {
+        fail(command, e);
+    }
+}
+
+/// set or not, encrypted or not; never the values
+pub fn print_status(tx_db: Sender<DbMsg>) -> Result<(), TradeWebError> {
+    for credential in Credentials::status(tx_db)? {
+        println!("{}: {}", credential.field, match (credential.is_set, credential.is_encrypted) {
+            (false, _) => "not set",
+            (true, false) => "set, plaintext",
+            (true, true) => "set, encrypted",
+        });
+    }
+    Ok(())
+}
+
+fn required_key(var: &str) -> MasterKey {
+    match MasterKey::from_env(var) {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            eprintln!("{} isn't set; `trader credentials generate-key` makes one", var);
+            std::process::exit(2);
+        },
+        Err(e) => {
+            eprintln!("{} is unusable: {:?}", var, e);
+            std::process::exit(2);
+        },
+    }
+}
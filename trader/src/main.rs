@@ -0,0 +1,110 @@
+//! trader/src/main.rs
+//!
+//! One binary for the services and the admin tasks:
+//!
+//! trader backend [--alpaca-rest-on true ...]     the trading backend; options override the configuration
+//! trader frontend [--application-port 8000 ...]  the web interface
+//! trader rest                                    only the Alpaca REST poller (leave ALPACA_REST_ON off in backend)
+//! trader monitor [--max-age-secs 300] [--once]   warn when a market data websocket stops pinging
+//! trader migrate                                 apply the migrations in migrations/
+//! trader backfill --start YYYY-MM-DD --end YYYY-MM-DD [--symbols a,b] [--feed iex|sip]
+//! trader backtest --start YYYY-MM-DD --end YYYY-MM-DD [...]
+//! trader replay <backtest run id> [--partial-fills true] [--current-settings]
+//! trader optimize --start YYYY-MM-DD --end YYYY-MM-DD --params "..." [...]
+//! trader user add <username> | user passwd <username>   the password is read from stdin
+//! trader kill-switch on [--flatten] [reason...] | off | status
+//! trader settings show
+//! trader bench-trades [--trades 2000]            trade write throughput, row at a time v batched
+//! trader credentials generate-key | status | encrypt | rotate
+//!
+//! Every command goes through common_lib::init, so .env and config/configuration.yaml come from the backend
+//! directory, or the frontend's for frontend and user; see common_lib/src/config.rs.
+//!
+#![forbid(unsafe_code)]
+
+mod admin;
+mod backfill;
+mod backtest;
+mod credentials;
+mod optimize;
+mod services;
+
+use std::collections::HashMap;
+use chrono::{DateTime, NaiveDate, Utc};
+use crossbeam_channel::Sender;
+use tokio::runtime::Runtime;
+use common_lib::db::{DbActor, DbMsg};
+
+const USAGE: &str = "usage: trader backend | frontend | rest | monitor | migrate | backfill | backtest | replay | optimize | user add|passwd <username> | kill-switch on|off|status | settings show | bench-trades | credentials generate-key|status|encrypt|rotate";
+
+/// .env and config/ for each service outside docker
+const BACKEND_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../backend");
+const FRONTEND_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../frontend");
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(command) = args.first() else { usage(USAGE) };
+    let args = &args[1..];
+
+    match command.as_str() {
+        "backend" => services::backend(args),
+        "frontend" => services::frontend(args),
+        "rest" => services::rest(args),
+        "monitor" => services::monitor(args),
+        "migrate" => admin::migrate(),
+        "backfill" => backfill::run(args),
+        "backtest" => backtest::run(args),
+        "replay" => backtest::replay(args),
+        "optimize" => optimize::run(args),
+        "user" => admin::user(args),
+        "kill-switch" => admin::kill_switch(args),
+        "settings" => admin::settings(args),
+        "bench-trades" => admin::bench_trades(args),
+        "credentials" => credentials::run(args),
+        _ => usage(USAGE),
+    }
+}
+
+/// a runtime and the database actor on its own thread
+fn start_db(thread_name: &str) -> (Runtime, Sender<DbMsg>) {
+    let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name(thread_name)
+        .enable_all()
+        .build()
+        .expect("Tokio runtime didn't start");
+
+    let db_actor = tokio_runtime.block_on(DbActor::new());
+    let tx_db = db_actor.tx.clone();
+    let rt = tokio_runtime.handle().clone();
+    std::thread::spawn(move || {
+        db_actor.run(rt);
+    });
+    (tokio_runtime, tx_db)
+}
+
+/// --key value pairs; anything else is dropped
+fn arg_map(args: &[String]) -> HashMap<String, String> {
+    args.chunks(2)
+        .filter_map(|pair| match pair {
+            [key, value] if key.starts_with("--") => Some((key.trim_start_matches("--").to_string(), value.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// a UTC day
+fn arg_date(args: &HashMap<String, String>, key: &str) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(args.get(key)?, "%Y-%m-%d").ok()?;
+    Some(DateTime::<Utc>::from_naive_utc_and_offset(date.and_hms_opt(0, 0, 0)?, Utc))
+}
+
+fn usage(usage: &str) -> ! {
+    eprintln!("{}", usage);
+    std::process::exit(2);
+}
+
+/// print what failed and exit 1
+fn fail<E: std::fmt::Debug>(what: &str, e: E) -> ! {
+    eprintln!("{} failed: {:?}", what, e);
+    std::process::exit(1);
+}
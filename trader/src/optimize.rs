@@ -0,0 +1,155 @@
+//! optimize.rs
+//!
+//! Sweep the Settings tunables over stored history and rank the combinations by return over drawdown;
+//! see common_lib/src/optimizer.rs.
+//!
+//! trader optimize --start 2023-06-01 --end 2023-06-30
+//!     --params "trade_ema_small_size=3,5,8;trade_sell_high_per_cent_multiplier=0.5:2:0.5"
+//!     [--search grid|random] [--samples 50] [--seed 1] [--train-days 10 --test-days 5] [--top 10]
+//!     [--export profile_name] [--symbols aapl,msft] [--source alp|fh] [--slippage-bps 5] [--latency-ms 250]
+//!     [--partial-fills true] [--cash 100000]
+//!
+//! Dates are UTC days, end inclusive. Without --train-days and --test-days the whole range is one training
+//! window and there's no out-of-sample test. --export saves the top-ranked settings to
+//! t_trade_settings_profile under that name.
+//!
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use bigdecimal::BigDecimal;
+use chrono::{Duration, Utc};
+use common_lib::backtest::{Backtest, BacktestConfig, HistoryRow, TradeSource};
+use common_lib::init::init;
+use common_lib::optimizer::{Optimizer, ParamRange, Search, SearchSpace, Window};
+use common_lib::settings::Settings;
+use common_lib::strategy::{AgeExit, EmaCross, SellHigh, Strategy};
+use common_lib::trade_setting_profile::SettingsProfile;
+use crate::backtest::{symbols, trade_sizes};
+use crate::{arg_date, arg_map, fail, start_db, BACKEND_DIR};
+
+const USAGE: &str = "usage: trader optimize --start YYYY-MM-DD --end YYYY-MM-DD --params \"name=a,b,c;name=first:last:step\" [--search grid|random] [--samples N] [--seed N] [--train-days N --test-days N] [--top N] [--export name] [--symbols a,b] [--source alp|fh] [--slippage-bps N] [--latency-ms N] [--partial-fills true] [--cash N]";
+
+/// history is read from the database a day at a time
+const HISTORY_CHUNK_DAYS: i64 = 1;
+
+pub fn run(args: &[String]) {
+    init(BACKEND_DIR);
+
+    let args = arg_map(args);
+
+    let (start, end) = match (arg_date(&args, "start"), arg_date(&args, "end")) {
+        (Some(start), Some(end)) if start <= end => (start, end + Duration::days(1)),
+        _ => usage(),
+    };
+
+    let space = match args.get("params") {
+        Some(params) => match params.split(';').filter(|p| !p.trim().is_empty()).map(ParamRange::from_str).collect::<Result<Vec<ParamRange>, String>>() {
+            Ok(ranges) if !ranges.is_empty() => SearchSpace { ranges },
+            Ok(_) => usage(),
+            Err(e) => {
+                eprintln!("{}", e);
+                usage();
+            }
+        },
+        None => usage(),
+    };
+    let search = match args.get("search").map(|s| s.as_str()) {
+        None | Some("grid") => Search::Grid,
+        Some("random") => Search::Random {
+            samples: arg_number(&args, "samples").unwrap_or(50),
+            seed: arg_number(&args, "seed").unwrap_or_else(|| Utc::now().timestamp_nanos_opt().unwrap_or(1) as u64),
+        },
+        Some(_) => usage(),
+    };
+    let windows = match (arg_number::<i64>(&args, "train-days"), arg_number::<i64>(&args, "test-days")) {
+        (Some(train), Some(test)) => Window::walk_forward(start, end, Duration::days(train), Duration::days(test)),
+        (None, None) => vec![Window::whole(start, end)],
+        _ => usage(),
+    };
+    if windows.is_empty() {
+        eprintln!("the train and test windows don't fit between --start and --end");
+        usage();
+    }
+    let top: usize = arg_number(&args, "top").unwrap_or(10);
+
+    let (tokio_runtime, tx_db) = start_db("optimize");
+    let settings = Settings::load_with_secret(tx_db.clone()).expect("[optimize] couldn't load settings");
+    let symbols = symbols(args.get("symbols"), &tokio_runtime, tx_db.clone());
+    let trade_sizes = trade_sizes(&symbols, &settings, tx_db.clone());
+
+    let config = BacktestConfig {
+        name: "optimize".to_string(),
+        start,
+        end,
+        symbols,
+        source: args.get("source").map(|s| TradeSource::from_str(s).unwrap_or_else(|_| usage())).unwrap_or(TradeSource::Alpaca),
+        cash_start: arg_decimal(&args, "cash").unwrap_or_else(|| BigDecimal::from(100_000)),
+        slippage_bps: arg_decimal(&args, "slippage-bps").unwrap_or_else(|| BigDecimal::from(0)),
+        latency_ms: args.get("latency-ms").map(|s| s.parse::<i64>().unwrap_or_else(|_| usage())).unwrap_or(0),
+        partial_fills: args.get("partial-fills").is_some_and(|s| s == "true"),
+    };
+    println!("{:?}", &config);
+
+    // every replay reads the same history, so it's loaded once
+    let mut rows: Vec<HistoryRow> = vec![];
+    let mut chunk_start = start;
+    while chunk_start < end {
+        let chunk_end = std::cmp::min(chunk_start + Duration::days(HISTORY_CHUNK_DAYS), end);
+        match Backtest::history(&config.source, &config.symbols, chunk_start, chunk_end, tx_db.clone()) {
+            Ok(chunk) => rows.extend(chunk),
+            Err(e) => fail("loading history", e),
+        }
+        chunk_start = chunk_end;
+    }
+
+    let candidates = space.candidates(&search);
+    println!("{} rows, {} candidates of {}, {} windows", rows.len(), candidates.len(), space.size(), windows.len());
+
+    let strategies = || -> Vec<Box<dyn Strategy>> { vec![Box::new(EmaCross::default()), Box::new(SellHigh::default()), Box::new(AgeExit::default())] };
+    let optimizer = Optimizer { config: config.clone(), settings: settings.clone(), trade_sizes, strategies: &strategies };
+    let report = optimizer.run(candidates, &windows, &rows);
+
+    println!("rank  mean train score  trades  P&L  candidate");
+    for (rank, result) in report.ranked.iter().take(top).enumerate() {
+        let trades: i64 = result.train_runs.iter().map(|r| r.trade_count).sum();
+        let pnl: BigDecimal = result.train_runs.iter().map(|r| r.pnl.clone()).sum();
+        println!("{:>4}  {:>16.4}  {:>6}  {}  {}", rank + 1, result.mean_train_score, trades, pnl, result.candidate.label());
+    }
+    for window in report.windows.iter().filter(|w| w.window.has_test()) {
+        println!("train {} to {}: best {} ({:.4}); test to {}: {:.4}",
+            window.window.train_start.date_naive(), window.window.train_end.date_naive(), report.ranked[window.best].candidate.label(),
+            window.train_score, window.window.test_end.date_naive(), window.test_score.unwrap_or(0.0));
+    }
+    if let Some(mean_test_score) = report.mean_test_score() {
+        println!("walk-forward mean test score: {:.4}", mean_test_score);
+    }
+
+    let Some(name) = args.get("export") else { return; };
+    let Some(best) = report.ranked.first() else {
+        eprintln!("no valid candidates to export");
+        std::process::exit(1);
+    };
+    let notes = format!("{}; {} to {}, {}; mean train score {:.4} over {} windows{}",
+        best.candidate.label(), start.date_naive(), (end - Duration::days(1)).date_naive(), config.symbols.join(","),
+        best.mean_train_score, windows.len(),
+        report.mean_test_score().map(|s| format!(", walk-forward mean test score {:.4}", s)).unwrap_or_default());
+    match SettingsProfile::from_settings(name, "optimizer", &notes, &best.settings).save(tx_db) {
+        Ok(id) => println!("saved as trade settings profile {} ({})", name, id),
+        Err(e) => {
+            eprintln!("export failed (is the name taken?): {:?}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    crate::usage(USAGE)
+}
+
+fn arg_decimal(args: &HashMap<String, String>, key: &str) -> Option<BigDecimal> {
+    args.get(key).map(|s| BigDecimal::from_str(s).unwrap_or_else(|_| usage()))
+}
+
+fn arg_number<T: FromStr>(args: &HashMap<String, String>, key: &str) -> Option<T> {
+    args.get(key).map(|s| s.parse::<T>().unwrap_or_else(|_| usage()))
+}
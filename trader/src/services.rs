@@ -0,0 +1,114 @@
+//! services.rs
+//!
+//! The long-running commands: backend, frontend, rest and monitor.
+//!
+
+use std::time::Duration;
+use tokio::runtime::Handle;
+use backend::alpaca_rest::AlpacaRest;
+use common_lib::clock;
+use common_lib::config::{AppConfig, Service};
+use common_lib::feed_monitor::FeedHeartbeat;
+use common_lib::init::init_service;
+use common_lib::settings_cache::SettingsCache;
+use common_lib::supervisor::{Shutdown, Supervisor};
+use frontend::web_server::WebServer;
+use crate::{arg_map, fail, start_db, usage, BACKEND_DIR, FRONTEND_DIR};
+
+const MONITOR_USAGE: &str = "usage: trader monitor [--max-age-secs N] [--interval-secs N] [--once]";
+
+pub fn backend(args: &[String]) {
+    init_service(BACKEND_DIR, Service::Backend, args);
+
+    let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
+        // default to number of cores
+        .thread_name("alpaca")
+        .enable_all()
+        .build()
+        .expect("Tokio runtime didn't start");
+
+    // spawn an non-worker thread as the main runtime thread
+    // https://ryhl.io/blog/async-what-is-blocking/
+    tokio_runtime.block_on(async {
+        let tokio_handle = Handle::current();
+        backend::backend::run(tokio_handle).await;
+    });
+    // run() returns after a signal; don't wait on anything left in the runtime
+    tokio_runtime.shutdown_timeout(Duration::from_secs(5));
+}
+
+pub fn frontend(args: &[String]) {
+    init_service(FRONTEND_DIR, Service::Frontend, args);
+
+    let tokio_runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(8)
+        .thread_name("alpaca")
+        .enable_all()
+        .build()
+        .expect("Tokio runtime didn't start");
+
+    tokio_runtime.block_on(async {
+        let db_actor = common_lib::db::DbActor::new().await;
+        let tx_db = db_actor.tx.clone();
+        let rt = Handle::current();
+        std::thread::spawn(move || {
+            db_actor.run(rt);
+        });
+        WebServer::run(tx_db).await;
+    });
+}
+
+/// the REST poller on its own, for running it apart from the backend; restarted if it panics
+pub fn rest(args: &[String]) {
+    init_service(BACKEND_DIR, Service::Tool, args);
+
+    let (tokio_runtime, tx_db) = start_db("rest");
+    let settings_rx = SettingsCache::start(tx_db.clone(), tokio_runtime.handle().clone()).unwrap_or_else(|e| fail("loading settings", e));
+
+    let shutdown = Shutdown::new();
+    shutdown.on_signal(tokio_runtime.handle());
+    let mut supervisor = Supervisor::new(shutdown.clone());
+    let tokio_handle = tokio_runtime.handle().clone();
+    supervisor.spawn("alpaca_rest", move |shutdown| {
+        AlpacaRest::run(tx_db.clone(), settings_rx.clone(), tokio_handle.clone(), shutdown);
+    });
+    while shutdown.wait_for(Duration::from_secs(60)) {
+        supervisor.log_health();
+    }
+    supervisor.join(Duration::from_secs(10));
+    tokio_runtime.shutdown_timeout(Duration::from_secs(5));
+}
+
+/// Log an error whenever a feed the configuration turns on hasn't pinged in max-age-secs. With --once,
+/// check a single time and exit 1 if anything is stale, for cron or a container health check.
+pub fn monitor(args: &[String]) {
+    init_service(BACKEND_DIR, Service::Tool, &[]);
+
+    let once = args.iter().any(|a| a == "--once");
+    let args: Vec<String> = args.iter().filter(|a| *a != "--once").cloned().collect();
+    let args = arg_map(&args);
+    let seconds = |key: &str, default: i64| args.get(key).map(|s| s.parse::<i64>().unwrap_or_else(|_| usage(MONITOR_USAGE))).unwrap_or(default);
+    let max_age = chrono::Duration::seconds(seconds("max-age-secs", 300));
+    let interval = Duration::from_secs(seconds("interval-secs", 60).max(1) as u64);
+
+    let (_tokio_runtime, tx_db) = start_db("monitor");
+    let config = AppConfig::get();
+    loop {
+        match FeedHeartbeat::load(tx_db.clone()) {
+            Ok(heartbeat) => {
+                let stale = heartbeat.stale(clock::now(), max_age, config.alpaca_websocket_on, config.finnhub_on);
+                if stale.is_empty() {
+                    tracing::info!("[monitor] feeds up: {:?}", &heartbeat);
+                } else {
+                    tracing::error!("[monitor] no ping within {}s from: {}; last pings {:?}", max_age.num_seconds(), stale.join(", "), &heartbeat);
+                }
+                if once {
+                    std::process::exit(if stale.is_empty() { 0 } else { 1 });
+                }
+            },
+            Err(e) if once => fail("monitor", e),
+            Err(e) => tracing::error!("[monitor] couldn't read the pings: {:?}", &e),
+        }
+        std::thread::sleep(interval);
+    }
+}